@@ -1,7 +1,33 @@
 use std::error::Error;
-use std::process::Command;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// the layout checks only run in the crate
+#[allow(dead_code)]
+#[path = "src/shaders/layout.rs"]
+#[macro_use]
+mod layout;
+
+#[path = "src/shaders/compiler.rs"]
+mod compiler;
+
+// only the GLSL side of the shared structs, their Rust types are left unresolved
+mod shared_types {
+    use crate::layout::{BlockLayout, GlslField, GlslStruct};
+
+    macro_rules! shared_structs {
+        ($($tokens:tt)*) => {
+            glsl_structs!(glsl; $($tokens)*);
+        };
+    }
+
+    include!("src/shaders/shared_types.rs");
+}
+
+use compiler::{shader_files, stage, ShaderCompiler, SHADER_DIRECTORY};
+use layout::{glsl_declarations, SHARED_TYPES_INCLUDE};
+use shared_types::GLSL_STRUCTS;
 
-#[allow(unused)]
 macro_rules! p {
     ($($tokens: tt)*) => {
         println!("cargo:warning={}", format!($($tokens)*))
@@ -9,15 +35,61 @@ macro_rules! p {
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let rt = Command::new("shaders/compile.bat").status()?;
-    let ao = Command::new("shaders/AO/compile.bat").status()?;
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+
+    let mut files = vec![];
+    shader_files(Path::new(SHADER_DIRECTORY), &mut files)?;
+    files.sort();
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/shaders/compiler.rs");
+    println!("cargo:rerun-if-changed=src/shaders/layout.rs");
+    println!("cargo:rerun-if-changed=src/shaders/shared_types.rs");
+    println!("cargo:rerun-if-changed={}", SHADER_DIRECTORY);
+    for file in &files {
+        println!("cargo:rerun-if-changed={}", file.display());
+    }
+
+    // written out only to be inspected, the compiler resolves the include itself
+    let shared_types = glsl_declarations(GLSL_STRUCTS);
+    fs::write(out_dir.join(SHARED_TYPES_INCLUDE), &shared_types)?;
+
+    let mut compiler = ShaderCompiler::new(shared_types)?;
+
+    let mut outputs: Vec<String> = vec![];
+    let mut failed = 0;
+
+    for file in &files {
+        let Some((output, _)) = stage(file) else {
+            continue;
+        };
+
+        if outputs.contains(&output) {
+            return Err(format!("Two shaders compile to {}.", output).into());
+        }
+
+        match compiler.compile(file) {
+            Ok((code, warnings)) => {
+                for warning in warnings {
+                    p!("{}", warning);
+                }
+
+                let bytes: Vec<u8> = code.iter().flat_map(|word| word.to_ne_bytes()).collect();
+                fs::write(out_dir.join(&output), bytes)?;
+            }
+            Err(errors) => {
+                for error in errors {
+                    p!("{}", error);
+                }
+                failed += 1;
+            }
+        }
 
-    if !rt.success() {
-        panic!("rt failed {}", rt);
+        outputs.push(output);
     }
 
-    if !ao.success() {
-        panic!("ao failed {}", ao);
+    if failed > 0 {
+        return Err(format!("{} shader(s) failed to compile.", failed).into());
     }
 
     Ok(())
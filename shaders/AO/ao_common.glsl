@@ -1,13 +1,4 @@
-struct MainPassPayload {
-    vec3 color;
-    vec3 normal;
-    float t;
-};
-
-struct AOPayload {
-    float t;
-};
+#include "shared_types.glsl"
+#include <constants.glsl>
 
 #define FLT_MIN 1.175494351e-38
-const uint AO_SPP = 1;
-const float PI = 3.14159265358979323;
\ No newline at end of file
@@ -0,0 +1,116 @@
+#ifndef DEBUG_VIEW_GLSL
+#define DEBUG_VIEW_GLSL
+
+// Debug views of the primary hits, written by the AO preview ray generation shader instead of the
+// frame. Included after the `main_payload`, `debug_image` and `settings` declarations
+
+#include <common.glsl>
+#include <random.glsl>
+#include <voxel.glsl>
+
+// Values of `TracePushConstants::debug_view`
+const uint DEBUG_VIEW_NORMALS = 1u;
+const uint DEBUG_VIEW_DEPTH = 2u;
+const uint DEBUG_VIEW_HIT_KIND = 3u;
+const uint DEBUG_VIEW_INSTANCE_ID = 4u;
+const uint DEBUG_VIEW_PRIMITIVE_ID = 5u;
+const uint DEBUG_VIEW_PALETTE_INDEX = 6u;
+const uint DEBUG_VIEW_TRAVERSAL_COST = 7u;
+
+// Kind of the faces of the cube in the order of `FACE_NORMALS`, named like the intersection
+// shader of the AABB voxels names them
+const uint FACE_KINDS[6] = uint[](
+    uint(KIND_BACK_FACE), // +z
+    uint(KIND_RIGHT_FACE), // +x
+    uint(KIND_FRONT_FACE), // -z
+    uint(KIND_LEFT_FACE), // -x
+    uint(KIND_BOTTOM_FACE), // -y
+    uint(KIND_TOP_FACE) // +y
+);
+
+const vec3 KIND_COLORS[6] = vec3[](
+    vec3(0.2, 0.8, 0.2), // top
+    vec3(0.8, 0.2, 0.2), // left
+    vec3(0.2, 0.2, 0.8), // back
+    vec3(0.8, 0.8, 0.2), // right
+    vec3(0.2, 0.8, 0.8), // front
+    vec3(0.8, 0.5, 0.2) // bottom
+);
+
+// Distinct color for every id
+vec3 id_color(uint id) {
+    const uint hash = pcg_hash(id);
+    return vec3(hash & 0xFFu, (hash >> 8) & 0xFFu, (hash >> 16) & 0xFFu) / 255.0;
+}
+
+// Blue to green to yellow to red over [0, 1], white above
+vec3 heatmap(float x) {
+    if (x > 1.0) {
+        return vec3(1.0);
+    }
+
+    return clamp(vec3(x * 4.0 - 2.0, x < 0.5 ? x * 4.0 - 0.5 : 3.5 - x * 4.0, 1.5 - x * 4.0), 0.0, 1.0);
+}
+
+// Kind of the hit face, KIND_UNKNOWN when the triangle is not one of the cube or the ray hit it
+// from inside the voxel
+uint hit_kind(vec3 direction) {
+    const uint face = main_payload.primitive_index / 2u;
+
+    if (face >= 6u || dot(main_payload.normal, direction) >= 0.0) {
+        return uint(KIND_UNKNOWN);
+    }
+
+    return FACE_KINDS[face];
+}
+
+vec3 debug_view_color(vec3 origin, vec3 direction) {
+    // the traversal cost counts the candidate hits in the any-hit shader, which the opaque voxels
+    // only run when the ray forces them to be non-opaque
+    const uint ray_flags = settings.debug_view == DEBUG_VIEW_TRAVERSAL_COST ? gl_RayFlagsNoOpaqueEXT : gl_RayFlagsNoneEXT;
+
+    main_payload.color = vec3(0.0);
+    main_payload.t = -1.0;
+    main_payload.candidate_hits = 0u;
+
+    traceRayEXT(scene_as, ray_flags, SURFACE_MASK, 0u, 0u, 0u, origin, 0.0001, direction, 1000.0, 0);
+
+    if (settings.debug_view == DEBUG_VIEW_TRAVERSAL_COST) {
+        return heatmap(float(main_payload.candidate_hits) / float(max(settings.debug_max_cost, 1u)));
+    }
+
+    // the sky is black, or white beyond any depth
+    if (main_payload.t <= 0.0) {
+        return vec3(settings.debug_view == DEBUG_VIEW_DEPTH ? 1.0 : 0.0);
+    }
+
+    switch (settings.debug_view) {
+        case DEBUG_VIEW_NORMALS:
+            return main_payload.normal * 0.5 + 0.5;
+        case DEBUG_VIEW_DEPTH: {
+            // along the view axis rather than along the ray
+            const vec3 forward = -globals.view_inverse[2].xyz;
+            return vec3(clamp(main_payload.t * dot(direction, forward) / settings.debug_max_depth, 0.0, 1.0));
+        }
+        case DEBUG_VIEW_HIT_KIND: {
+            const uint kind = hit_kind(direction);
+
+            // blinks between magenta and white
+            if (kind == uint(KIND_UNKNOWN)) {
+                return (globals.frame_index / 16u) % 2u == 0u ? vec3(1.0, 0.0, 1.0) : vec3(1.0);
+            }
+
+            return KIND_COLORS[kind];
+        }
+        case DEBUG_VIEW_INSTANCE_ID:
+            return id_color(main_payload.voxel_index);
+        case DEBUG_VIEW_PRIMITIVE_ID:
+            return id_color(main_payload.primitive_index);
+        case DEBUG_VIEW_PALETTE_INDEX:
+            return id_color(main_payload.material_index);
+    }
+
+    return vec3(0.0);
+}
+
+#endif
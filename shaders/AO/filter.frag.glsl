@@ -1,32 +1,37 @@
 #version 460
 
+#extension GL_EXT_scalar_block_layout : enable
+#extension GL_GOOGLE_include_directive : enable
+
+#include "ao_common.glsl"
+
 // https://github.com/boksajak/RTAO
 
 // RTAO - Low-pass filtering shader, Jakub Boksansky 2018
 
 // ========================================================================
-// Constant buffer inputs
+// Inputs
 // ========================================================================
 
-struct LowPassFilterInfo
-{
-    mat4 normalMatrix;
-	vec2 texelSize;
-    int outputMode;
-};
-
 layout(location = 0) in vec3 vertex_tex_coords;
 layout(location = 0) out vec4 frag_color;
 
-layout(set = 0, binding = 0) uniform _LowPassFilterInfo { LowPassFilterInfo filterInfo; };
+layout(push_constant, scalar) uniform _FilterPushConstants { FilterPushConstants filter_info; };
 
 // ========================================================================
 // Textures and Samplers
 // ========================================================================
 
-layout(set = 0, binding = 1) uniform sampler2D depth_normals_texture;
-layout(set = 0, binding = 2) uniform sampler2D ao_texture;
-layout(set = 0, binding = 3) uniform sampler2D color_texture;
+layout(set = 0, binding = 0) uniform sampler2D depth_normals_texture;
+layout(set = 0, binding = 1) uniform sampler2D ao_texture;
+layout(set = 0, binding = 2) uniform sampler2D color_texture;
+layout(set = 0, binding = 3) uniform _GlobalUniforms { GlobalUniforms globals; };
+layout(set = 0, binding = 4) uniform sampler2D environment_map;
+layout(set = 0, binding = 5) uniform sampler2D reflection_texture;
+// light scattered by the fog, and the light behind it let through in alpha
+layout(set = 0, binding = 6) uniform sampler2D fog_texture;
+
+#include <sky.glsl>
 
 // ========================================================================
 // Pixel Shaders 
@@ -51,30 +56,30 @@ bool isValidTap(float tapDepth, float centerDepth, vec3 tapNormal, vec3 centerNo
 	return true;
 }
 
-// Filter ambient occlusion using low-pass tent filter and mix it with color buffer
-float lowPassFilter(vec2 filterDirection, const bool doTemporalFilter) {
+// Filter ambient occlusion using low-pass tent filter
+float lowPassFilter(vec2 uv, vec4 centerDepthNormal) {
     float ao = 0.0;
 	float weight = 1.0;
 
-	vec4 centerDepthNormal = texture(depth_normals_texture, gl_FragCoord.xy);
 	float centerDepth = centerDepthNormal.a;
 	vec3 centerNormal = normalize(centerDepthNormal.rgb);
-    float dotViewNormal = abs((filterInfo.normalMatrix * vec4(centerNormal, 0.0)).z);
+    float dotViewNormal = abs(dot(filter_info.view_z, centerNormal));
 
-    vec2 offsetScale = filterInfo.texelSize * filterDirection;
+    vec2 offsetScale = filter_info.texel_size * filter_info.direction * filter_info.radius;
 
     for (int i = -2; i <= 2; ++i) {
         vec2 offset = float(i) * offsetScale;
 
-		vec4 tapDepthNormal = texture(depth_normals_texture , gl_FragCoord.xy + offset);
-		vec4 tapAO = texture(ao_texture, gl_FragCoord.xy + offset);
+		vec4 tapDepthNormal = texture(depth_normals_texture, uv + offset);
+		float tapAO = texture(ao_texture, uv + offset).r;
 		float tapDepth = tapDepthNormal.a;
 		vec3 tapNormal = normalize(tapDepthNormal.rgb);
 
 		float tapWeight = gauss[i + 2];
 
-        if (isValidTap(tapDepth, centerDepth, tapNormal, centerNormal, dotViewNormal)) {
-            ao += (doTemporalFilter ? dot(tapAO, vec4(0.25)) : tapAO.r) * tapWeight;
+        // background taps have a zero depth
+        if (tapDepth > 0.0 && isValidTap(tapDepth, centerDepth, tapNormal, centerNormal, dotViewNormal)) {
+            ao += tapAO * tapWeight;
 		} else {
 			weight -= tapWeight;
 		}
@@ -85,28 +90,36 @@ float lowPassFilter(vec2 filterDirection, const bool doTemporalFilter) {
 	return ao;
 }
 
-vec4 low_pass_filter_x()
-{
-    return lowPassFilter(vec2(1.0, 0.0), true).xxxx;
-}
-
-vec4 low_pass_filter_y()
-{
-    float ao = lowPassFilter(vec2(0.0, 1.0), false);
-
-	vec4 color = texture(color_texture, gl_FragCoord.xy);
-
-    if (filterInfo.outputMode == 0) return vec4(ao);
-    if (filterInfo.outputMode == 2) return color;
-
-	return vec4(color.rgb * ao, 1.0);
-}
-
 void main() {
-	// vec4 x_pass = low_pass_filter_x();
-	// vec4 y_pass = low_pass_filter_y();
-
-	// frag_color = mix(x_pass, y_pass, 0.5);
+    const vec2 uv = vertex_tex_coords.xy;
+
+	const vec4 centerDepthNormal = texture(depth_normals_texture, uv);
+	const vec4 color = texture(color_texture, uv);
+
+    const vec4 fog = texture(fog_texture, uv);
+
+    // nothing was hit, there is nothing to occlude and the color is the sky
+    if (centerDepthNormal.a <= 0.0) {
+        if (filter_info.output_mode == 0u) {
+            frag_color = vec4(1.0);
+        } else if (filter_info.output_mode == 2u) {
+            frag_color = vec4(color.rgb, 1.0);
+        } else {
+            frag_color = vec4(color.rgb * fog.a + fog.rgb, 1.0);
+        }
+        return;
+    }
 
-	frag_color = vec4(vertex_tex_coords, 1.0);
-}
\ No newline at end of file
+    const float ao = lowPassFilter(uv, centerDepthNormal);
+
+    if (filter_info.output_mode == 0u) {
+        frag_color = vec4(ao);
+    } else if (filter_info.output_mode == 2u) {
+        frag_color = vec4(color.rgb, 1.0);
+    } else {
+        // the occluded sky and the sun, whose light was traced in the alpha of the albedo
+        const vec3 light = sky_ambient(normalize(centerDepthNormal.rgb)) * ao + globals.sun_color * color.a;
+        const vec4 reflection = texture(reflection_texture, uv);
+        frag_color = vec4((color.rgb * light * reflection.a + reflection.rgb) * fog.a + fog.rgb, 1.0);
+    }
+}
@@ -1,10 +1,11 @@
 #version 460
 
-layout(location = 0) in mat2x3 vertex_attributes;
 layout(location = 0) out vec3 vertex_tex_coords;
 
+// fullscreen triangle, drawn without vertex buffer
 void main() {
-	vec3 vertex_position = vertex_attributes[0];
-    gl_Position = vec4(vertex_position, 1.0);
-	vertex_tex_coords = vertex_attributes[1]; 
-}
\ No newline at end of file
+    const vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+
+    gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+    vertex_tex_coords = vec3(uv, 0.0);
+}
@@ -0,0 +1,71 @@
+#ifndef RESTIR_GLSL
+#define RESTIR_GLSL
+
+// Reservoirs of light samples, resampled over the candidates of a pixel, then reused over frames
+// and neighbouring pixels
+// "Spatiotemporal reservoir resampling for real-time ray tracing with dynamic direct lighting",
+// Bitterli et al. 2020
+
+#include <lights.glsl>
+
+struct Reservoir {
+    LightSample light_sample;
+    // sum of the resampling weights
+    float weight_sum;
+    // target function of the kept sample
+    float target;
+    // candidates seen
+    float count;
+};
+
+Reservoir empty_reservoir() {
+    return Reservoir(LightSample(0u, 6u, vec2(0.0)), 0.0, 0.0, 0.0);
+}
+
+// Streams a candidate through the reservoir, it replaces the kept sample in proportion to its
+// weight. `count` is the number of candidates it stands for.
+void update_reservoir(inout Reservoir reservoir, LightSample light_sample, float weight, float target, float count, float u) {
+    reservoir.weight_sum += weight;
+    reservoir.count += count;
+
+    if (weight > 0.0 && u * reservoir.weight_sum < weight) {
+        reservoir.light_sample = light_sample;
+        reservoir.target = target;
+    }
+}
+
+// Weight of the kept sample making its contribution an estimate of the direct light
+float contribution_weight(Reservoir reservoir) {
+    return reservoir.target > 0.0 ? reservoir.weight_sum / (reservoir.count * reservoir.target) : 0.0;
+}
+
+// Streams the sample of another reservoir, `target` evaluated at the surface of this one
+void merge_reservoir(inout Reservoir reservoir, LightSample light_sample, float weight, float count, float target, float u) {
+    update_reservoir(reservoir, light_sample, target * weight * count, target, count, u);
+}
+
+// The sample, then the contribution weight and the count
+uvec4 encode_reservoir(Reservoir reservoir) {
+    return uvec4(
+        (reservoir.light_sample.light << 3u) | reservoir.light_sample.face,
+        packUnorm2x16(reservoir.light_sample.uv),
+        floatBitsToUint(contribution_weight(reservoir)),
+        floatBitsToUint(reservoir.count)
+    );
+}
+
+LightSample decode_reservoir(uvec4 texel, out float weight, out float count) {
+    weight = uintBitsToFloat(texel.z);
+    count = uintBitsToFloat(texel.w);
+    return LightSample(texel.x >> 3u, texel.x & 7u, unpackUnorm2x16(texel.y));
+}
+
+// Resampling target, the unshadowed light reflected by the surface
+float light_target(LightSample light_sample, Material material, vec3 position, vec3 normal, vec3 view) {
+    vec3 direction;
+    float light_distance;
+    float cos_light;
+    return luminance(light_contribution(light_sample, material, position, normal, view, direction, light_distance, cos_light));
+}
+
+#endif
@@ -0,0 +1,22 @@
+#ifndef HISTOGRAM_GLSL
+#define HISTOGRAM_GLSL
+
+// Luminance histogram of the HDR image, shared by the exposure passes
+
+#extension GL_EXT_scalar_block_layout : enable
+
+#include <shared_types.glsl>
+
+// bin 0 counts the pixels darker than the range, which the average leaves out
+const uint HISTOGRAM_BINS = 256u;
+
+layout(set = 0, binding = 1) buffer _Histogram { uint bins[HISTOGRAM_BINS]; };
+
+layout(push_constant, scalar) uniform _TonemapPushConstants { TonemapPushConstants settings; };
+
+// Log2 luminance at the middle of a bin, from 1 to `HISTOGRAM_BINS - 1`
+float bin_log_luminance(float bin) {
+    return (bin - 1.0) / float(HISTOGRAM_BINS - 2u) * settings.log_luminance_range + settings.min_log_luminance;
+}
+
+#endif
@@ -0,0 +1,121 @@
+#version 460
+
+#extension GL_EXT_scalar_block_layout : enable
+#extension GL_GOOGLE_include_directive : enable
+
+#include <shared_types.glsl>
+#include <brdf.glsl>
+
+// Exposes the HDR image, maps it to the range of the display and encodes it, ready for the
+// upscaling to the swapchain
+
+layout(location = 0) in vec3 vertex_tex_coords;
+layout(location = 0) out vec4 frag_color;
+
+layout(set = 0, binding = 0) uniform sampler2D hdr_texture;
+layout(set = 0, binding = 2, scalar) readonly buffer _Exposure { ExposureState exposure_state; };
+
+layout(push_constant, scalar) uniform _TonemapPushConstants { TonemapPushConstants settings; };
+
+const uint OPERATOR_ACES = 0u;
+const uint OPERATOR_AGX = 1u;
+// the debug views are shown as they are
+const uint OPERATOR_NONE = 3u;
+
+const uint TRANSFER_SRGB = 1u;
+const uint TRANSFER_HDR10 = 2u;
+
+// Fit of the ACES reference rendering and output transforms, Stephen Hill
+const mat3 ACES_INPUT = mat3(0.59719, 0.07600, 0.02840, 0.35458, 0.90834, 0.13383, 0.04823, 0.01566, 0.83777);
+const mat3 ACES_OUTPUT = mat3(1.60475, -0.10208, -0.00327, -0.53108, 1.10813, -0.07276, -0.07367, -0.00605, 1.07602);
+
+vec3 aces(vec3 color) {
+    const vec3 v = ACES_INPUT * color;
+    const vec3 a = v * (v + 0.0245786) - 0.000090537;
+    const vec3 b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(ACES_OUTPUT * (a / b), 0.0, 1.0);
+}
+
+// Polynomial approximation of the AgX base contrast, Benjamin Wrensch
+const mat3 AGX_INSET = mat3(0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+                            0.0784335999999992, 0.878468636469772, 0.0784336,
+                            0.0792237451477643, 0.0791661274605434, 0.879142973793104);
+const mat3 AGX_OUTSET = mat3(1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+                             -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+                             -0.0990297440797205, -0.0989611768448433, 1.15107367264116);
+
+vec3 agx(vec3 color) {
+    const float min_ev = -12.47393;
+    const float max_ev = 4.026069;
+
+    vec3 x = clamp(log2(max(AGX_INSET * color, vec3(1e-10))), min_ev, max_ev);
+    x = (x - min_ev) / (max_ev - min_ev);
+
+    const vec3 x2 = x * x;
+    const vec3 x4 = x2 * x2;
+    x = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+
+    // the curve outputs display values with a 2.2 gamma
+    return pow(clamp(AGX_OUTSET * x, 0.0, 1.0), vec3(2.2));
+}
+
+// On the luminance, which keeps the hue
+vec3 reinhard(vec3 color) {
+    return color / (1.0 + luminance(color));
+}
+
+vec3 srgb_encode(vec3 color) {
+    return mix(12.92 * color, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+}
+
+const mat3 REC709_TO_REC2020 = mat3(0.6274, 0.0691, 0.0164, 0.3293, 0.9195, 0.0880, 0.0433, 0.0114, 0.8956);
+
+// SMPTE ST 2084 perceptual quantizer
+vec3 pq_encode(vec3 nits) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+
+    const vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+// Linear up to a diffuse white, then rolls off smoothly towards the peak brightness
+vec3 hdr10(vec3 color) {
+    const float peak = max(settings.peak_brightness / settings.paper_white, 1.0001);
+    const float color_luminance = luminance(color);
+
+    if (color_luminance > 1.0) {
+        const float mapped = 1.0 + (peak - 1.0) * (1.0 - exp(-(color_luminance - 1.0) / (peak - 1.0)));
+        color *= mapped / color_luminance;
+    }
+
+    return pq_encode(max(REC709_TO_REC2020 * color, 0.0) * settings.paper_white);
+}
+
+void main() {
+    const float exposure = settings.tonemap_operator == OPERATOR_NONE ? 1.0 : exposure_state.exposure;
+    const vec3 color = texture(hdr_texture, vertex_tex_coords.xy).rgb * exposure;
+
+    if (settings.output_transfer == TRANSFER_HDR10) {
+        frag_color = vec4(hdr10(color), 1.0);
+        return;
+    }
+
+    vec3 mapped;
+
+    if (settings.tonemap_operator == OPERATOR_ACES) {
+        mapped = aces(color);
+    } else if (settings.tonemap_operator == OPERATOR_AGX) {
+        mapped = agx(color);
+    } else if (settings.tonemap_operator == OPERATOR_NONE) {
+        mapped = clamp(color, 0.0, 1.0);
+    } else {
+        mapped = reinhard(color);
+    }
+
+    // encoded before the upscaling, which works on perceptual values
+    frag_color = vec4(settings.output_transfer == TRANSFER_SRGB ? srgb_encode(mapped) : mapped, 1.0);
+}
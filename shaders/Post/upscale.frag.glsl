@@ -0,0 +1,88 @@
+#version 460
+
+#extension GL_EXT_scalar_block_layout : enable
+#extension GL_GOOGLE_include_directive : enable
+
+#include <shared_types.glsl>
+
+// Writes the frame to the output: samples the tonemapped frame bilinearly, sharpens its EASU
+// upscale with the robust contrast adaptive sharpening of AMD FidelityFX Super Resolution 1, or
+// averages the texels of a supersampled frame covered by the pixel
+
+layout(location = 0) in vec3 vertex_tex_coords;
+layout(location = 0) out vec4 frag_color;
+
+layout(set = 0, binding = 0) uniform sampler2D source_texture;
+layout(set = 0, binding = 2) uniform sampler2D upscaled_texture;
+
+layout(push_constant, scalar) uniform _UpscalePushConstants { UpscalePushConstants settings; };
+
+const uint FILTER_FSR = 1u;
+const uint FILTER_BOX = 2u;
+
+// Strongest negative lobe, beyond it the sharpening would clip
+const float RCAS_LIMIT = 0.25 - 1.0 / 16.0;
+
+vec3 fetch(ivec2 texel) {
+    return texelFetch(upscaled_texture, clamp(texel, ivec2(0), ivec2(settings.output_size) - 1), 0).rgb;
+}
+
+// The center e and its ring
+//   b
+// d e f
+//   h
+vec3 rcas(ivec2 pixel) {
+    const vec3 b = fetch(pixel + ivec2(0, -1));
+    const vec3 d = fetch(pixel + ivec2(-1, 0));
+    const vec3 e = fetch(pixel);
+    const vec3 f = fetch(pixel + ivec2(1, 0));
+    const vec3 h = fetch(pixel + ivec2(0, 1));
+
+    const vec3 ring_min = min(min(b, d), min(f, h));
+    const vec3 ring_max = max(max(b, d), max(f, h));
+
+    // largest lobe keeping the sharpened pixel within [0, 1] on each channel
+    const vec3 hit_min = min(ring_min, e) / max(4.0 * ring_max, 1e-5);
+    const vec3 hit_max = (1.0 - max(ring_max, e)) / min(4.0 * ring_min - 4.0, -1e-5);
+    const vec3 channel_lobes = max(-hit_min, hit_max);
+    const float lobe = max(-RCAS_LIMIT, min(max(channel_lobes.r, max(channel_lobes.g, channel_lobes.b)), 0.0)) * exp2(-settings.sharpness);
+
+    return (lobe * (b + d + f + h) + e) / (4.0 * lobe + 1.0);
+}
+
+// Mean of the texels of the frame under the pixel, as displayed, the frame is larger than the output
+vec3 box_downsample(ivec2 pixel) {
+    const vec2 ratio = settings.input_size / settings.output_size;
+    const ivec2 first = ivec2(floor(vec2(pixel) * ratio));
+    const ivec2 last = min(ivec2(ceil(vec2(pixel + 1) * ratio)), ivec2(settings.input_size)) - 1;
+
+    vec3 sum = vec3(0.0);
+
+    for (int y = first.y; y <= last.y; y++) {
+        for (int x = first.x; x <= last.x; x++) {
+            sum += texelFetch(source_texture, ivec2(x, y), 0).rgb;
+        }
+    }
+
+    const ivec2 count = max(last - first + 1, ivec2(1));
+    return sum / float(count.x * count.y);
+}
+
+vec3 srgb_decode(vec3 color) {
+    return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), step(0.04045, color));
+}
+
+void main() {
+    vec3 color;
+
+    if (settings.upscale_filter == FILTER_FSR) {
+        color = rcas(ivec2(gl_FragCoord.xy));
+    } else if (settings.upscale_filter == FILTER_BOX) {
+        color = box_downsample(ivec2(gl_FragCoord.xy));
+    } else {
+        color = texture(source_texture, vertex_tex_coords.xy).rgb;
+    }
+
+    // the hardware encodes the linear values again
+    frag_color = vec4(settings.srgb_output != 0u ? srgb_decode(clamp(color, 0.0, 1.0)) : color, 1.0);
+}
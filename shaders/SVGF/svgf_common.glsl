@@ -0,0 +1,69 @@
+#ifndef SVGF_COMMON_GLSL
+#define SVGF_COMMON_GLSL
+
+// Interface shared by the stages of the denoiser, every stage declares the same bindings
+// "Spatiotemporal Variance-Guided Filtering", Schied et al. 2017
+
+#extension GL_EXT_scalar_block_layout : enable
+
+#include <shared_types.glsl>
+#include <brdf.glsl>
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 0, binding = 0) uniform _GlobalUniforms { GlobalUniforms globals; };
+// path traced radiance, or the occlusion in red
+layout(set = 0, binding = 1) uniform sampler2D signal_texture;
+layout(set = 0, binding = 2) uniform sampler2D depth_normal_texture;
+layout(set = 0, binding = 3) uniform sampler2D prev_depth_normal_texture;
+layout(set = 0, binding = 4) uniform sampler2D albedo_texture;
+// accumulated signal and history length
+layout(set = 0, binding = 5, rgba32f) uniform image2D history_image;
+layout(set = 0, binding = 6, rgba32f) uniform readonly image2D prev_history_image;
+// first and second moments of the luminance
+layout(set = 0, binding = 7, rg32f) uniform image2D moments_image;
+layout(set = 0, binding = 8, rg32f) uniform readonly image2D prev_moments_image;
+// signal and variance, the wavelet iterations alternate between the two
+layout(set = 0, binding = 9, rgba32f) uniform image2D filtered_image_0;
+layout(set = 0, binding = 10, rgba32f) uniform image2D filtered_image_1;
+layout(set = 0, binding = 11, rgba16f) uniform writeonly image2D denoised_image;
+
+layout(push_constant, scalar) uniform _DenoisePushConstants { DenoisePushConstants settings; };
+
+const uint SIGNAL_RADIANCE = 0u;
+const uint SIGNAL_OCCLUSION = 1u;
+
+bool is_outside(ivec2 pixel) {
+    return any(greaterThanEqual(pixel, textureSize(depth_normal_texture, 0)));
+}
+
+// The radiance is filtered without the texture of the surfaces, which the modulation puts back.
+// Dark albedos are clamped so that the demodulation does not blow the noise up.
+vec3 demodulation_albedo(ivec2 pixel) {
+    return max(texelFetch(albedo_texture, pixel, 0).rgb, vec3(0.01));
+}
+
+vec3 demodulated_signal(ivec2 pixel) {
+    const vec4 signal = texelFetch(signal_texture, pixel, 0);
+
+    if (settings.signal == SIGNAL_OCCLUSION) {
+        return vec3(signal.r);
+    }
+
+    return signal.rgb / demodulation_albedo(pixel);
+}
+
+// Input of the wavelet iteration, whose output goes to the other image
+vec4 load_filtered(uint iteration, ivec2 pixel) {
+    return iteration % 2u == 0u ? imageLoad(filtered_image_0, pixel) : imageLoad(filtered_image_1, pixel);
+}
+
+void store_filtered(uint iteration, ivec2 pixel, vec4 value) {
+    if (iteration % 2u == 0u) {
+        imageStore(filtered_image_0, pixel, value);
+    } else {
+        imageStore(filtered_image_1, pixel, value);
+    }
+}
+
+#endif
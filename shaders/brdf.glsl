@@ -0,0 +1,167 @@
+#ifndef BRDF_GLSL
+#define BRDF_GLSL
+
+#include <constants.glsl>
+#include <random.glsl>
+#include <shared_types.glsl>
+
+// Orthonormal basis whose z axis is `n`
+// "Building an Orthonormal Basis, Revisited", Duff et al. 2017
+mat3 tangent_frame(vec3 n) {
+    const float s = n.z >= 0.0 ? 1.0 : -1.0;
+    const float a = -1.0 / (s + n.z);
+    const float b = n.x * n.y * a;
+    const vec3 tangent = vec3(1.0 + s * n.x * n.x * a, s * b, -s * n.x);
+    const vec3 bitangent = vec3(b, s + n.y * n.y * a, -n.y);
+    return mat3(tangent, bitangent, n);
+}
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+vec3 sample_cosine_hemisphere(vec2 u) {
+    const float r = sqrt(u.x);
+    const float phi = 2.0 * PI * u.y;
+    return vec3(r * cos(phi), r * sin(phi), sqrt(max(1.0 - u.x, 0.0)));
+}
+
+vec3 fresnel_schlick(vec3 f0, float cos_theta) {
+    return f0 + (1.0 - f0) * pow(1.0 - clamp(cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Fresnel reflectance of an unpolarized ray arriving with `cos_i` at an interface, `eta` is the
+// ratio of the indices of refraction on its side and on the other. 1 past the critical angle.
+float fresnel_dielectric(float cos_i, float eta) {
+    const float sin_t2 = eta * eta * (1.0 - cos_i * cos_i);
+
+    if (sin_t2 >= 1.0) {
+        return 1.0;
+    }
+
+    const float cos_t = sqrt(1.0 - sin_t2);
+    const float parallel = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    const float perpendicular = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    return 0.5 * (parallel * parallel + perpendicular * perpendicular);
+}
+
+// Glass takes its color after this many voxels
+const float GLASS_COLOR_DISTANCE = 4.0;
+
+// Beer-Lambert absorption over `distance` through glass of `color`
+vec3 glass_transmittance(vec3 color, float distance) {
+    return pow(max(color, vec3(1e-3)), vec3(distance / GLASS_COLOR_DISTANCE));
+}
+
+// Smith masking of the GGX distribution in one direction
+float smith_g1(float alpha, float n_dot_x) {
+    const float a2 = alpha * alpha;
+    return 2.0 * n_dot_x / (n_dot_x + sqrt(a2 + (1.0 - a2) * n_dot_x * n_dot_x));
+}
+
+// Half vector of the GGX distribution seen from `v`, both in the tangent frame
+// "Sampling the GGX Distribution of Visible Normals", Heitz 2018
+vec3 sample_ggx_vndf(vec3 v, float alpha, vec2 u) {
+    const vec3 vh = normalize(vec3(alpha * v.x, alpha * v.y, v.z));
+
+    const float length2 = vh.x * vh.x + vh.y * vh.y;
+    const vec3 t1 = length2 > 0.0 ? vec3(-vh.y, vh.x, 0.0) * inversesqrt(length2) : vec3(1.0, 0.0, 0.0);
+    const vec3 t2 = cross(vh, t1);
+
+    const float r = sqrt(u.x);
+    const float phi = 2.0 * PI * u.y;
+    const float p1 = r * cos(phi);
+    const float s = 0.5 * (1.0 + vh.z);
+    const float p2 = (1.0 - s) * sqrt(1.0 - p1 * p1) + s * r * sin(phi);
+
+    const vec3 nh = p1 * t1 + p2 * t2 + sqrt(max(0.0, 1.0 - p1 * p1 - p2 * p2)) * vh;
+    return normalize(vec3(alpha * nh.x, alpha * nh.y, max(0.0, nh.z)));
+}
+
+// BRDF times the cosine for light arriving from `light` and leaving towards `view`, with the
+// lobes of `sample_brdf`
+vec3 evaluate_brdf(Material material, vec3 normal, vec3 view, vec3 light) {
+    const float n_dot_l = dot(normal, light);
+
+    if (n_dot_l <= 0.0) {
+        return vec3(0.0);
+    }
+
+    const float n_dot_v = max(dot(normal, view), 1e-4);
+    const vec3 h = normalize(view + light);
+    const float n_dot_h = max(dot(normal, h), 0.0);
+
+    const vec3 f0 = mix(vec3(0.04), material.albedo, material.metalness);
+    const float alpha = max(material.roughness * material.roughness, 1e-3);
+    const float a2 = alpha * alpha;
+    const float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    const float distribution = a2 / (PI * d * d);
+
+    const vec3 specular = fresnel_schlick(f0, dot(view, h)) * distribution * smith_g1(alpha, n_dot_l) *
+        smith_g1(alpha, n_dot_v) / (4.0 * n_dot_l * n_dot_v);
+    const vec3 diffuse = material.albedo * (1.0 - material.metalness) * (1.0 - fresnel_schlick(f0, n_dot_v)) / PI;
+
+    return (diffuse + specular) * n_dot_l;
+}
+
+// Density of the directions `sample_brdf` picks, over the solid angle, for `light`
+float brdf_pdf(Material material, vec3 normal, vec3 view, vec3 light) {
+    const float n_dot_l = dot(normal, light);
+
+    if (n_dot_l <= 0.0) {
+        return 0.0;
+    }
+
+    const float n_dot_v = max(dot(normal, view), 1e-4);
+    const vec3 h = normalize(view + light);
+    const float n_dot_h = max(dot(normal, h), 0.0);
+
+    const vec3 f0 = mix(vec3(0.04), material.albedo, material.metalness);
+    const float specular_probability = clamp(mix(luminance(fresnel_schlick(f0, n_dot_v)), 1.0, material.metalness), 0.05, 0.95);
+
+    const float alpha = max(material.roughness * material.roughness, 1e-3);
+    const float a2 = alpha * alpha;
+    const float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    const float distribution = a2 / (PI * d * d);
+
+    // the visible normals, over the solid angle of the reflected direction
+    const float specular = smith_g1(alpha, n_dot_v) * distribution / (4.0 * n_dot_v);
+
+    return specular_probability * specular + (1.0 - specular_probability) * n_dot_l / PI;
+}
+
+// Picks the Lambert or the GGX lobe of the material at random, then samples the direction the
+// path continues in from the surface seen from `view`. `weight` is the BRDF times the cosine
+// over the probability of the direction, zero when the path is absorbed.
+vec3 sample_brdf(Material material, vec3 normal, vec3 view, inout uint rng, out vec3 weight) {
+    const mat3 frame = tangent_frame(normal);
+    const vec3 v = view * frame;
+    const float n_dot_v = max(v.z, 1e-4);
+
+    const vec3 f0 = mix(vec3(0.04), material.albedo, material.metalness);
+    const vec3 fresnel = fresnel_schlick(f0, n_dot_v);
+    const float specular_probability = clamp(mix(luminance(fresnel), 1.0, material.metalness), 0.05, 0.95);
+
+    const vec2 u = vec2(next_random(rng), next_random(rng));
+
+    if (next_random(rng) < specular_probability) {
+        const float alpha = max(material.roughness * material.roughness, 1e-3);
+        const vec3 h = sample_ggx_vndf(vec3(v.xy, n_dot_v), alpha, u);
+        const vec3 l = reflect(-v, h);
+
+        if (l.z <= 0.0) {
+            weight = vec3(0.0);
+            return normal;
+        }
+
+        // the visible normal density cancels the distribution and the masking towards the view
+        weight = fresnel_schlick(f0, dot(v, h)) * smith_g1(alpha, l.z) / specular_probability;
+        return frame * l;
+    }
+
+    // the cosine density cancels the cosine and the 1 / PI of the Lambert BRDF
+    weight = material.albedo * (1.0 - material.metalness) * (1.0 - fresnel) / (1.0 - specular_probability);
+    return frame * sample_cosine_hemisphere(u);
+}
+
+#endif
@@ -0,0 +1,123 @@
+#ifndef CAMERA_GLSL
+#define CAMERA_GLSL
+
+// Primary rays of the thin lens camera and their reprojection into the previous frame, needs
+// `globals`
+
+#include <constants.glsl>
+#include <random.glsl>
+
+// Motion of the pixels whose previous position is unknown, the largest half float, which
+// reprojects outside of any image
+const float INVALID_MOTION = 65504.0;
+
+// Point of the aperture for a uniform `u` in [0, 1)^2, in a disk of radius 1 or in the regular
+// polygon of `aperture_blades` sides inscribed in it, which shapes the bokeh
+vec2 aperture_point(vec2 u) {
+    const uint blades = globals.aperture_blades;
+
+    if (blades < 3u) {
+        // concentric mapping of the square to the disk, "A Low Distortion Map Between Disk and
+        // Square", Shirley and Chiu 1997
+        const vec2 offset = u * 2.0 - 1.0;
+
+        if (all(equal(offset, vec2(0.0)))) {
+            return vec2(0.0);
+        }
+
+        const bool horizontal = abs(offset.x) > abs(offset.y);
+        const float radius = horizontal ? offset.x : offset.y;
+        const float theta = horizontal ? PI / 4.0 * (offset.y / offset.x) : PI / 2.0 - PI / 4.0 * (offset.x / offset.y);
+        return radius * vec2(cos(theta), sin(theta));
+    }
+
+    // a triangle between the center and one side, then a uniform point in it
+    const float sector = floor(u.x * float(blades));
+    const float along = u.x * float(blades) - sector;
+    const float step_angle = 2.0 * PI / float(blades);
+    const float angle = sector * step_angle;
+
+    const vec2 first = vec2(cos(angle), sin(angle));
+    const vec2 second = vec2(cos(angle + step_angle), sin(angle + step_angle));
+    return sqrt(u.y) * mix(first, second, along);
+}
+
+// Camera ray through `position` of the thin lens camera, from the point of the aperture picked by
+// `lens_sample` and at the time of the shutter picked by `time_sample`, both in [0, 1). The
+// shutter closes at the current camera, the camera moves from its previous position while it is
+// open.
+void camera_ray(vec2 position, vec2 size, vec2 lens_sample, float time_sample, out vec3 origin, out vec3 direction) {
+    const vec2 d = position / size * 2.0 - 1.0;
+    vec3 view_direction = normalize((globals.proj_inverse * vec4(d.x, -d.y, 1.0, 1.0)).xyz);
+    vec3 view_origin = vec3(0.0);
+
+    // the rays through the same pixel meet on the focal plane, in front of the camera along -z
+    if (globals.aperture_radius > 0.0) {
+        const vec3 focus = view_direction * (globals.focal_distance / -view_direction.z);
+        view_origin = vec3(aperture_point(lens_sample) * globals.aperture_radius, 0.0);
+        view_direction = normalize(focus - view_origin);
+    }
+
+    origin = (globals.view_inverse * vec4(view_origin, 1.0)).xyz;
+    direction = (globals.view_inverse * vec4(view_direction, 0.0)).xyz;
+
+    if (globals.shutter > 0.0) {
+        // the view matrices are rigid transforms
+        const mat3 prev_rotation = transpose(mat3(globals.prev_view));
+        const vec3 prev_origin = prev_rotation * (view_origin - globals.prev_view[3].xyz);
+        const vec3 prev_direction = prev_rotation * view_direction;
+
+        const float time = 1.0 - globals.shutter * time_sample;
+        origin = mix(prev_origin, origin, time);
+        direction = normalize(mix(prev_direction, direction, time));
+    }
+}
+
+// Primary ray of `pixel` as the AO preview and the G-buffers trace it, through the jitter of the
+// frame and from a lens sample and a shutter time of their own, the same every time for a pixel
+// in a frame
+void primary_ray(ivec2 pixel, vec2 size, out vec3 origin, out vec3 direction) {
+    // a stream apart from the one of the paths of the pixel
+    uint rng = pcg_hash(random_seed(uvec2(pixel), globals.frame_index));
+    const vec2 lens_sample = vec2(next_random(rng), next_random(rng));
+    const float time_sample = next_random(rng);
+
+    camera_ray(vec2(pixel) + 0.5 + globals.jitter, size, lens_sample, time_sample, origin, direction);
+}
+
+// Position in pixels of `point` in the previous frame, a position (w = 1) or a direction (w = 0).
+// False when it was behind the camera.
+bool previous_screen_position(vec4 point, vec2 size, out vec2 prev_position) {
+    const vec4 prev_clip = globals.prev_proj * globals.prev_view * point;
+
+    prev_position = vec2(0.0);
+
+    if (prev_clip.w <= 0.0) {
+        return false;
+    }
+
+    // the inverse of the mapping from pixels to rays
+    const vec2 prev_ndc = prev_clip.xy / prev_clip.w;
+    prev_position = (vec2(prev_ndc.x, -prev_ndc.y) * 0.5 + 0.5) * size;
+
+    return true;
+}
+
+// Motion in pixels since the previous frame of the point at `position` in the current one, seen
+// at `point`
+vec2 motion_vector(vec2 position, vec4 point, vec2 size) {
+    vec2 prev_position;
+
+    if (!previous_screen_position(point, size, prev_position)) {
+        return vec2(INVALID_MOTION);
+    }
+
+    return position - prev_position;
+}
+
+// Camera position of the previous frame, the view matrix is a rigid transform
+vec3 previous_camera_origin() {
+    return -transpose(mat3(globals.prev_view)) * globals.prev_view[3].xyz;
+}
+
+#endif
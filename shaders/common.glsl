@@ -6,10 +6,4 @@
 #define KIND_BOTTOM_FACE 5
 #define KIND_UNKNOWN 6
 
-struct RayPayload {
-    vec3 color;
-    vec3 origin;
-    vec3 direction;
-    float attenuation;
-    float t;
-};
\ No newline at end of file
+#include "shared_types.glsl"
\ No newline at end of file
@@ -0,0 +1,6 @@
+#ifndef CONSTANTS_GLSL
+#define CONSTANTS_GLSL
+
+const float PI = 3.14159265358979323;
+
+#endif
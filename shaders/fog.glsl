@@ -0,0 +1,115 @@
+#ifndef FOG_GLSL
+#define FOG_GLSL
+
+// Single scattering of the sun and the sky in the height fog and in the media voxels, marched
+// along the primary rays. Included by ray generation shaders after `shadow.glsl`, `sky.glsl` and
+// their `materials` declaration
+
+#include <constants.glsl>
+#include <random.glsl>
+#include <voxel.glsl>
+
+layout(location = 3) rayPayloadEXT MainPassPayload media_payload;
+
+// Distance the fog is marched to along the rays that leave the scene
+const float FOG_MAX_DISTANCE = 1000.0;
+
+// Faces of the blocks of media a ray is split at, the media past them are left out
+const uint MAX_MEDIA_CROSSINGS = 8u;
+
+// Henyey-Greenstein phase function, the share of the light scattered by `cos_theta`
+float henyey_greenstein(float cos_theta, float g) {
+    const float denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+    return (1.0 - g * g) / (4.0 * PI * denominator * sqrt(denominator));
+}
+
+// Extinction of the height fog at `position`, constant below `fog_height` and falling off above
+float height_fog_density(vec3 position) {
+    return globals.fog_density * exp(-globals.fog_height_falloff * max(position.y - globals.fog_height, 0.0));
+}
+
+// Marches the ray from `t_start` to `t_end` through the height fog and a media of `density` and
+// `albedo`, adding the light they scatter towards its origin to `scattered` and attenuating
+// `transmittance`. `offset` places the samples in their steps.
+void march_fog_segment(vec3 origin, vec3 direction, float t_start, float t_end, float density, vec3 albedo, float offset, vec3 sky_light, inout vec3 scattered, inout float transmittance) {
+    const float step_length = (t_end - t_start) / float(globals.fog_steps);
+    const float sun_phase = henyey_greenstein(dot(direction, globals.sun_direction), globals.fog_anisotropy);
+    const bool sunlit = any(greaterThan(globals.sun_color, vec3(0.0)));
+
+    for (uint i = 0; i < globals.fog_steps; i++) {
+        const vec3 position = origin + direction * (t_start + (float(i) + offset) * step_length);
+        const float height_density = height_fog_density(position);
+        const float extinction = height_density + density;
+
+        if (extinction <= 1e-6) {
+            continue;
+        }
+
+        vec3 light = sky_light;
+
+        if (sunlit) {
+            light += globals.sun_color * sun_phase * shadow_transmittance(position, globals.sun_direction, 10000.0);
+        }
+
+        // the light scattered over the step is attenuated within it, "Physically Based and
+        // Unified Volumetric Rendering in Frostbite", Hillaire 2015
+        const vec3 source = (globals.fog_albedo * height_density + albedo * density) * light;
+        const float step_transmittance = exp(-extinction * step_length);
+
+        scattered += transmittance * source * (1.0 - step_transmittance) / extinction;
+        transmittance *= step_transmittance;
+    }
+}
+
+// Light scattered towards `origin` by the fog along `direction` up to `t_end`, with the share of
+// the light from `t_end` it lets through in `transmittance`. The faces of the blocks of media
+// split the ray into pieces of a constant media, found in order by tracing to the next one.
+vec3 march_fog(vec3 origin, vec3 direction, float t_end, inout uint rng, out float transmittance) {
+    vec3 scattered = vec3(0.0);
+    transmittance = 1.0;
+
+    if (globals.fog_steps == 0u) {
+        return scattered;
+    }
+
+    const float offset = next_random(rng);
+    // the sky lights the fog evenly from above, the ground below is left dark
+    const vec3 sky_light = 0.5 * sky_ambient(vec3(0.0, 1.0, 0.0));
+
+    float t = 0.0;
+    float density = 0.0;
+    vec3 albedo = vec3(0.0);
+
+    for (uint crossing = 0; crossing <= MAX_MEDIA_CROSSINGS; crossing++) {
+        media_payload.t = 0.0;
+
+        if (crossing < MAX_MEDIA_CROSSINGS) {
+            traceRayEXT(scene_as, gl_RayFlagsNoneEXT, MEDIA_MASK, 0u, 0u, 0u, origin, t + 0.0001, direction, t_end, 3);
+        }
+
+        const bool crossed = media_payload.t > 0.0;
+        const float t_next = crossed ? media_payload.t : t_end;
+
+        march_fog_segment(origin, direction, t, t_next, density, albedo, offset, sky_light, scattered, transmittance);
+
+        if (!crossed) {
+            break;
+        }
+
+        // the ray enters a block by its front faces and leaves it by its back faces, a camera
+        // inside a block only sees the media past it
+        if (dot(media_payload.normal, direction) < 0.0) {
+            const Material material = materials[media_payload.material_index];
+            density = material.density;
+            albedo = material.albedo;
+        } else {
+            density = 0.0;
+        }
+
+        t = t_next;
+    }
+
+    return scattered;
+}
+
+#endif
@@ -0,0 +1,131 @@
+#ifndef LIGHTS_GLSL
+#define LIGHTS_GLSL
+
+// Sampling of the emissive voxels, included after the `voxels`, `materials`, `lights` and
+// `settings` declarations
+
+#include <brdf.glsl>
+#include <random.glsl>
+#include <shared_types.glsl>
+#include <voxel.glsl>
+
+const float VOXEL_FACE_AREA = 4.0 * VOXEL_HALF_SIZE * VOXEL_HALF_SIZE;
+
+// A point on an emissive voxel: the entry of the light list, the face and the position on it
+struct LightSample {
+    uint light;
+    uint face;
+    vec2 uv;
+};
+
+vec3 voxel_emission(uint voxel_index) {
+    return materials[voxels[voxel_index].palette_index].emission;
+}
+
+// Probability of picking the voxel, lights are picked in proportion to their power
+float voxel_selection_pdf(uint voxel_index) {
+    return luminance(voxel_emission(voxel_index)) / settings.light_power;
+}
+
+// Alias table lookup: an entry drawn uniformly is kept with its probability, else replaced by
+// its alias
+uint sample_light_index(inout uint rng) {
+    const uint index = min(uint(next_random(rng) * float(settings.light_count)), settings.light_count - 1u);
+    const EmissiveLight light = lights[index];
+    return next_random(rng) < light.probability ? index : light.alias;
+}
+
+// One bit per face of the voxel at `center` that `position` sees the front of
+uint visible_faces(vec3 center, vec3 position) {
+    uint faces = 0u;
+
+    for (uint face = 0u; face < 6u; face++) {
+        if (dot(FACE_NORMALS[face], position - center) > VOXEL_HALF_SIZE) {
+            faces |= 1u << face;
+        }
+    }
+
+    return faces;
+}
+
+vec3 light_point(vec3 center, uint face, vec2 uv) {
+    const vec3 normal = FACE_NORMALS[face];
+    const mat3 frame = tangent_frame(normal);
+    return center + VOXEL_HALF_SIZE * (normal + frame[0] * (uv.x * 2.0 - 1.0) + frame[1] * (uv.y * 2.0 - 1.0));
+}
+
+// Picks a light in proportion to its power, then a point on the faces of its voxel that
+// `position` sees. The face is 6 when there is none.
+LightSample sample_light(vec3 position, inout uint rng) {
+    LightSample light_sample;
+    light_sample.light = sample_light_index(rng);
+    light_sample.uv = vec2(next_random(rng), next_random(rng));
+
+    const uint faces = visible_faces(voxels[lights[light_sample.light].voxel_index].position, position);
+    uint n = min(uint(next_random(rng) * float(bitCount(faces))), uint(bitCount(faces)) - 1u);
+
+    light_sample.face = 6u;
+
+    for (uint face = 0u; face < 6u && light_sample.face == 6u; face++) {
+        if ((faces & (1u << face)) != 0u) {
+            if (n == 0u) {
+                light_sample.face = face;
+            }
+
+            n--;
+        }
+    }
+
+    return light_sample;
+}
+
+// Density of `sample_light` over the area of the lights
+float light_sample_pdf(LightSample light_sample, vec3 position) {
+    const uint voxel_index = lights[light_sample.light].voxel_index;
+    const uint faces = visible_faces(voxels[voxel_index].position, position);
+    return voxel_selection_pdf(voxel_index) / (float(bitCount(faces)) * VOXEL_FACE_AREA);
+}
+
+// Density of `sample_light` over the solid angle seen from `origin`, for the point of the voxel
+// hit at `t` with `normal`
+float emitter_pdf(uint voxel_index, vec3 origin, vec3 normal, vec3 direction, float t) {
+    const uint faces = visible_faces(voxels[voxel_index].position, origin);
+    const float cos_light = dot(normal, -direction);
+
+    if (faces == 0u || cos_light <= 0.0) {
+        return 0.0;
+    }
+
+    return voxel_selection_pdf(voxel_index) / (float(bitCount(faces)) * VOXEL_FACE_AREA) * t * t / cos_light;
+}
+
+// Light arriving from the sample at the surface at `position`, reflected towards `view`,
+// without the shadowing. It is over the area of the light, `direction` and `light_distance`
+// lead to the point on the light and `cos_light` is the cosine at it.
+vec3 light_contribution(LightSample light_sample, Material material, vec3 position, vec3 normal, vec3 view,
+                        out vec3 direction, out float light_distance, out float cos_light) {
+    direction = vec3(0.0);
+    light_distance = 0.0;
+    cos_light = 0.0;
+
+    if (light_sample.face >= 6u || light_sample.light >= settings.light_count) {
+        return vec3(0.0);
+    }
+
+    const uint voxel_index = lights[light_sample.light].voxel_index;
+    const vec3 center = voxels[voxel_index].position;
+
+    // reused samples may come from a point seeing other faces
+    if (dot(FACE_NORMALS[light_sample.face], position - center) <= VOXEL_HALF_SIZE) {
+        return vec3(0.0);
+    }
+
+    const vec3 to_light = light_point(center, light_sample.face, light_sample.uv) - position;
+    light_distance = length(to_light);
+    direction = to_light / light_distance;
+    cos_light = max(dot(FACE_NORMALS[light_sample.face], -direction), 0.0);
+
+    return voxel_emission(voxel_index) * evaluate_brdf(material, normal, view, direction) * cos_light / (light_distance * light_distance);
+}
+
+#endif
@@ -0,0 +1,41 @@
+#ifndef RANDOM_GLSL
+#define RANDOM_GLSL
+
+// https://www.pcg-random.org, hash variant from "Hash Functions for GPU Rendering", Jarzynski and Olano 2020
+uint pcg_hash(uint v) {
+    uint state = v * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Seed of the random numbers of a pixel in a frame, advanced by `next_random`
+uint random_seed(uvec2 pixel, uint frame_index) {
+    return pcg_hash(pixel.x + pcg_hash(pixel.y + pcg_hash(frame_index)));
+}
+
+// Uniform white noise in [0, 1)
+float next_random(inout uint state) {
+    state = pcg_hash(state);
+    return float(state >> 8u) / 16777216.0;
+}
+
+// Uniform white noise in [0, 1) for every pixel
+vec2 pixel_noise(uvec2 pixel) {
+    uint hash = pcg_hash(pixel.x + pcg_hash(pixel.y));
+    return vec2(hash & 0xFFFFu, hash >> 16u) / 65536.0;
+}
+
+// n-th point of the R2 low discrepancy sequence in [0, 1)
+// http://extremelearning.com.au/unreasonable-effectiveness-of-quasirandom-sequences/
+vec2 r2_sequence(uint n) {
+    const vec2 alpha = vec2(0.7548776662466927, 0.5698402909980532);
+    return fract(0.5 + float(n) * alpha);
+}
+
+// Sample `sample_index` of a pixel: the R2 sequence shifted by the pixel noise, so neighbouring
+// pixels decorrelate while the samples of one pixel stay well distributed across frames
+vec2 pixel_sample(uvec2 pixel, uint sample_index) {
+    return fract(r2_sequence(sample_index) + pixel_noise(pixel));
+}
+
+#endif
@@ -0,0 +1,37 @@
+#ifndef SHADOW_GLSL
+#define SHADOW_GLSL
+
+// Shadow rays towards the sun and the lights, included by ray generation shaders after their `scene_as` and
+// `globals` declarations
+
+#include <shared_types.glsl>
+#include <voxel.glsl>
+
+layout(location = 2) rayPayloadEXT ShadowPayload shadow_payload;
+
+// Light let through from `position` along `direction` up to `t_max`: 1 when nothing is in the
+// way, tinted by the glass and dimmed by the media in between, 0 behind opaque voxels
+vec3 shadow_transmittance(vec3 position, vec3 direction, float t_max) {
+    // any opaque hit is enough, the any-hit shader absorbs through the glass and the media, and
+    // only the shadow miss shader sets the visibility
+    const uint ray_flags = gl_RayFlagsTerminateOnFirstHitEXT | gl_RayFlagsSkipClosestHitShaderEXT;
+    shadow_payload.transmittance = vec3(1.0);
+    shadow_payload.visibility = 0.0;
+    shadow_payload.optical_depth = 0.0;
+
+    traceRayEXT(scene_as, ray_flags, SURFACE_MASK | MEDIA_MASK, 2u, 0u, 2u, position, 0.001, direction, t_max, 2);
+
+    // a ray that ends inside a block of media misses the face it would leave it by
+    return shadow_payload.transmittance * shadow_payload.visibility * exp(-max(shadow_payload.optical_depth, 0.0));
+}
+
+// Sun light let through to `position`, a point offset from a surface with `normal`
+vec3 sun_visibility(vec3 position, vec3 normal) {
+    if (dot(normal, globals.sun_direction) <= 0.0 || all(equal(globals.sun_color, vec3(0.0)))) {
+        return vec3(0.0);
+    }
+
+    return shadow_transmittance(position, globals.sun_direction, 10000.0);
+}
+
+#endif
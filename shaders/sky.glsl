@@ -0,0 +1,101 @@
+#ifndef SKY_GLSL
+#define SKY_GLSL
+
+// Sky seen by every render mode, included after the `globals` and `environment_map` declarations
+
+#include <constants.glsl>
+#include <shared_types.glsl>
+
+// Preetham luminances are in kcd/m², brought to the range of the rest of the lighting
+const float SKY_LUMINANCE_SCALE = 0.08;
+
+// Perez distribution of the sky luminance, `cos_theta` from the zenith and `gamma` from the sun
+float perez(float cos_theta, float gamma, float A, float B, float C, float D, float E) {
+    const float cos_gamma = cos(gamma);
+    return (1.0 + A * exp(B / max(cos_theta, 0.01))) * (1.0 + C * exp(D * gamma) + E * cos_gamma * cos_gamma);
+}
+
+// "A Practical Analytic Model for Daylight", Preetham et al. 1999
+vec3 preetham_sky(vec3 direction, vec3 sun_direction, float turbidity) {
+    const float T = turbidity;
+
+    // the model is only defined for a sun above the horizon
+    const vec3 sun = normalize(vec3(sun_direction.x, max(sun_direction.y, 0.01), sun_direction.z));
+    const float theta_s = acos(sun.y);
+    const float theta_s2 = theta_s * theta_s;
+    const float theta_s3 = theta_s2 * theta_s;
+
+    const float chi = (4.0 / 9.0 - T / 120.0) * (PI - 2.0 * theta_s);
+    const float zenith_Y = (4.0453 * T - 4.9710) * tan(chi) - 0.2155 * T + 2.4192;
+    const float zenith_x = T * T * (0.00166 * theta_s3 - 0.00375 * theta_s2 + 0.00209 * theta_s) +
+        T * (-0.02903 * theta_s3 + 0.06377 * theta_s2 - 0.03202 * theta_s + 0.00394) +
+        (0.11693 * theta_s3 - 0.21196 * theta_s2 + 0.06052 * theta_s + 0.25886);
+    const float zenith_y = T * T * (0.00275 * theta_s3 - 0.00610 * theta_s2 + 0.00317 * theta_s) +
+        T * (-0.04214 * theta_s3 + 0.08970 * theta_s2 - 0.04153 * theta_s + 0.00516) +
+        (0.15346 * theta_s3 - 0.26756 * theta_s2 + 0.06670 * theta_s + 0.26688);
+
+    // below the horizon, the ground reflects the horizon
+    const float cos_theta = max(direction.y, 0.0);
+    const float gamma = acos(clamp(dot(direction, sun), -1.0, 1.0));
+
+    const float Y = zenith_Y *
+        perez(cos_theta, gamma, 0.1787 * T - 1.4630, -0.3554 * T + 0.4275, -0.0227 * T + 5.3251, 0.1206 * T - 2.5771, -0.0670 * T + 0.3703) /
+        perez(1.0, theta_s, 0.1787 * T - 1.4630, -0.3554 * T + 0.4275, -0.0227 * T + 5.3251, 0.1206 * T - 2.5771, -0.0670 * T + 0.3703);
+    const float x = zenith_x *
+        perez(cos_theta, gamma, -0.0193 * T - 0.2592, -0.0665 * T + 0.0008, -0.0004 * T + 0.2125, -0.0641 * T - 0.8989, -0.0033 * T + 0.0452) /
+        perez(1.0, theta_s, -0.0193 * T - 0.2592, -0.0665 * T + 0.0008, -0.0004 * T + 0.2125, -0.0641 * T - 0.8989, -0.0033 * T + 0.0452);
+    const float y = zenith_y *
+        perez(cos_theta, gamma, -0.0167 * T - 0.2608, -0.0950 * T + 0.0092, -0.0079 * T + 0.2102, -0.0441 * T - 1.6537, -0.0109 * T + 0.0529) /
+        perez(1.0, theta_s, -0.0167 * T - 0.2608, -0.0950 * T + 0.0092, -0.0079 * T + 0.2102, -0.0441 * T - 1.6537, -0.0109 * T + 0.0529);
+
+    // xyY to XYZ to linear sRGB
+    const vec3 XYZ = vec3(x * Y / y, Y, (1.0 - x - y) * Y / y);
+    const mat3 XYZ_to_sRGB = mat3(
+        3.2404542, -0.9692660, 0.0556434,
+        -1.5371385, 1.8760108, -0.2040259,
+        -0.4985314, 0.0415560, 1.0572252
+    );
+    vec3 radiance = max(XYZ_to_sRGB * XYZ, vec3(0.0)) * SKY_LUMINANCE_SCALE;
+
+    if (direction.y < 0.0) {
+        radiance *= mix(1.0, 0.3, sqrt(-direction.y));
+    }
+
+    return radiance;
+}
+
+// Equirectangular lookup, +y up, turned by `environment_rotation` around it
+vec3 environment_radiance(vec3 direction) {
+    const float azimuth = atan(direction.z, direction.x) + globals.environment_rotation;
+    const vec2 uv = vec2(azimuth / (2.0 * PI) + 0.5, acos(clamp(direction.y, -1.0, 1.0)) / PI);
+    return textureLod(environment_map, uv, 0.0).rgb * globals.environment_intensity;
+}
+
+// Radiance of the sky in `direction`, at night the moon stands in for the sun
+vec3 sky_radiance(vec3 direction) {
+    if (globals.use_environment_map != 0u) {
+        return environment_radiance(direction);
+    }
+
+    return preetham_sky(direction, globals.sun_direction, globals.sky_turbidity) * globals.sky_intensity;
+}
+
+// Mean radiance of the sky over the hemisphere around `normal`, roughly estimated from a few
+// directions: a Lambert surface reflects its albedo times it
+vec3 sky_ambient(vec3 normal) {
+    const vec3 tangent = normalize(abs(normal.y) < 0.99 ? cross(normal, vec3(0.0, 1.0, 0.0)) : cross(normal, vec3(1.0, 0.0, 0.0)));
+    const vec3 bitangent = cross(normal, tangent);
+
+    vec3 ambient = sky_radiance(normal);
+
+    // 60 degrees away from the normal
+    for (int i = 0; i < 4; i++) {
+        const float phi = float(i) * 0.5 * PI;
+        const vec3 direction = normal * 0.5 + (tangent * cos(phi) + bitangent * sin(phi)) * 0.8660254;
+        ambient += sky_radiance(direction);
+    }
+
+    return ambient / 5.0;
+}
+
+#endif
@@ -0,0 +1,31 @@
+#ifndef TRANSLUCENT_GLSL
+#define TRANSLUCENT_GLSL
+
+// Any-hit side of the translucent voxels, included after the `voxels`, `materials` and `globals`
+// declarations
+
+#include <random.glsl>
+#include <shared_types.glsl>
+#include <voxel.glsl>
+
+uint face_index() {
+    return uint(gl_PrimitiveID) / 2u;
+}
+
+// Faces shared with a voxel of the same glass, which a block of it has no interface at
+bool is_interior_face() {
+    return (voxels[gl_InstanceID].interior_faces & (1u << face_index())) != 0u;
+}
+
+// Random number of the ray for the voxel it hits, the same for both faces of the voxel
+float voxel_random(uint dimension) {
+    uint rng = random_seed(gl_LaunchIDEXT.xy, globals.frame_index) ^ pcg_hash(uint(gl_InstanceID) * 4u + dimension);
+    return next_random(rng);
+}
+
+// Stochastic alpha test, the accumulation over frames resolves the coverage
+bool passes_alpha_test(Material material) {
+    return material.opacity >= 1.0 || voxel_random(0u) < material.opacity;
+}
+
+#endif
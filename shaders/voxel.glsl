@@ -0,0 +1,23 @@
+#ifndef VOXEL_GLSL
+#define VOXEL_GLSL
+
+// Geometry of the voxel cube, whose BLAS spans -1 to 1 around the position of the voxel
+
+// Outward normals of the cube faces, two triangles each
+const vec3 FACE_NORMALS[6] = vec3[](
+    vec3(0., 0., 1.), // +z
+    vec3(1., 0., 0.), // +x
+    vec3(0., 0., -1.), // -z
+    vec3(-1., 0., 0.), // -x
+    vec3(0., -1., 0.), // -y
+    vec3(0., 1., 0.) // +y
+);
+
+// Distance from the center of the cube to its faces
+const float VOXEL_HALF_SIZE = 1.0;
+
+// Instance masks of the voxels hit by the surface rays and of the media voxels, the fog volumes
+const uint SURFACE_MASK = 0x01u;
+const uint MEDIA_MASK = 0x02u;
+
+#endif
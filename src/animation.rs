@@ -0,0 +1,135 @@
+use ash::vk;
+
+use crate::uniform_types::VoxelInfos;
+
+/// Copy of a voxel of the model circling above it, its instance moves every frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrbitingVoxel {
+    /// Index of its instance, after the instances of the model.
+    pub index: usize,
+    pub center: glm::Vec3,
+    pub radius: f32,
+    /// Seconds per turn.
+    pub period: f32,
+}
+
+impl OrbitingVoxel {
+    /// Orbit over the bounds of `voxels`, for the instance appended after them.
+    pub fn around(voxels: &[VoxelInfos], period: f32) -> Self {
+        let (min, max) = voxels.iter().fold(
+            (glm::Vec3::repeat(f32::MAX), glm::Vec3::repeat(f32::MIN)),
+            |(min, max), voxel| {
+                (
+                    glm::min2(&min, &voxel.position),
+                    glm::max2(&max, &voxel.position),
+                )
+            },
+        );
+        let extent = max - min;
+
+        Self {
+            index: voxels.len(),
+            center: glm::vec3((min.x + max.x) * 0.5, max.y + 4.0, (min.z + max.z) * 0.5),
+            radius: extent.x.max(extent.z) * 0.5,
+            period,
+        }
+    }
+
+    pub fn position(&self, time: f32) -> glm::Vec3 {
+        let angle = time / self.period * glm::two_pi::<f32>();
+        self.center + glm::vec3(angle.cos(), 0.0, angle.sin()) * self.radius
+    }
+
+    pub fn transform(&self, time: f32) -> vk::TransformMatrixKHR {
+        translation_transform(&self.position(time))
+    }
+}
+
+/// Row major 3x4 transform of an instance at `position`.
+pub fn translation_transform(position: &glm::Vec3) -> vk::TransformMatrixKHR {
+    vk::TransformMatrixKHR {
+        matrix: [
+            1.0, 0.0, 0.0, position.x, 0.0, 1.0, 0.0, position.y, 0.0, 0.0, 1.0, position.z,
+        ],
+    }
+}
+
+/// Transform between `from` at 0 and `to` at 1, exact for translations.
+pub fn interpolate_transform(
+    from: &vk::TransformMatrixKHR,
+    to: &vk::TransformMatrixKHR,
+    t: f32,
+) -> vk::TransformMatrixKHR {
+    vk::TransformMatrixKHR {
+        matrix: std::array::from_fn(|i| from.matrix[i] + (to.matrix[i] - from.matrix[i]) * t),
+    }
+}
+
+/// Translation of a row major 3x4 instance transform.
+pub fn transform_translation(transform: &vk::TransformMatrixKHR) -> glm::Vec3 {
+    glm::vec3(
+        transform.matrix[3],
+        transform.matrix[7],
+        transform.matrix[11],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voxel(x: f32, y: f32, z: f32) -> VoxelInfos {
+        VoxelInfos {
+            position: glm::vec3(x, y, z),
+            palette_index: 1,
+            interior_faces: 0,
+        }
+    }
+
+    #[test]
+    fn orbits_above_the_bounds() {
+        let voxels = [voxel(0.0, 0.0, 0.0), voxel(10.0, 6.0, 4.0)];
+        let orbit = OrbitingVoxel::around(&voxels, 8.0);
+
+        assert_eq!(orbit.index, 2);
+        assert_eq!(orbit.center, glm::vec3(5.0, 10.0, 2.0));
+        assert_eq!(orbit.radius, 5.0);
+
+        for time in [0.0, 1.0, 3.5] {
+            let offset = orbit.position(time) - orbit.center;
+            assert!((offset.norm() - orbit.radius).abs() < 1e-4);
+            assert_eq!(offset.y, 0.0);
+        }
+    }
+
+    #[test]
+    fn comes_back_after_a_period() {
+        let orbit = OrbitingVoxel::around(&[voxel(0.0, 0.0, 0.0), voxel(8.0, 0.0, 8.0)], 4.0);
+
+        assert!((orbit.position(1.0) - orbit.position(5.0)).norm() < 1e-4);
+        assert!((orbit.position(0.0) - orbit.position(2.0)).norm() > orbit.radius);
+    }
+
+    #[test]
+    fn interpolates_between_transforms() {
+        let from = translation_transform(&glm::vec3(0.0, 2.0, 0.0));
+        let to = translation_transform(&glm::vec3(4.0, 2.0, -8.0));
+
+        assert_eq!(interpolate_transform(&from, &to, 0.0).matrix, from.matrix);
+        assert_eq!(interpolate_transform(&from, &to, 1.0).matrix, to.matrix);
+        assert_eq!(
+            transform_translation(&interpolate_transform(&from, &to, 0.25)),
+            glm::vec3(1.0, 2.0, -2.0)
+        );
+    }
+
+    #[test]
+    fn transform_round_trips_the_translation() {
+        let position = glm::vec3(1.0, -2.0, 3.5);
+
+        assert_eq!(
+            transform_translation(&translation_transform(&position)),
+            position
+        );
+    }
+}
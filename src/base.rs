@@ -1,17 +1,49 @@
-use ash::vk;
+use ash::{vk, Device};
 use bevy_transform::components::Transform;
 use bytemuck::bytes_of;
-use std::default::Default;
+use std::{default::Default, path::Path};
 
 use winit::event_loop::ActiveEventLoop;
 
 use crate::{
+    animation::{interpolate_transform, OrbitingVoxel},
+    camera_path::CameraPath,
+    config::{
+        AnimationConfig, AntiAliasing, AntiAliasingConfig, AoConfig, AoOutput, Config, DebugConfig,
+        DebugView, DenoiserConfig, DisplayConfig, FogConfig, HeadlessConfig, LensConfig,
+        LightsConfig, PathTracingConfig, ReflectionConfig, RenderScaleConfig, SkyConfig, SunConfig,
+        TemporalConfig,
+    },
+    headless::HeadlessSchedule,
+    io::ppm::write_ppm,
+    memory::staging::upload_dst_stages,
     player_controller::PlayerController,
-    uniform_types::{CameraTransform, GlobalUniforms},
-    utils::WIDTH,
+    render::{
+        ao_filter::{DENOISED_VERTICAL_SET, HORIZONTAL_PASS, VERTICAL_PASS},
+        frame::{FrameImage, FRAMES_IN_FLIGHT},
+        graph::{ImageId, RenderGraph, ResourceState},
+        pass::{Access, Pass},
+        svgf::{SvgfImages, OCCLUSION_SIGNAL, RADIANCE_SIGNAL},
+        tonemap::{HDR_SET, RESOLVED_SET, UNMAPPED_OPERATOR},
+        transient::TransientResources,
+        upscale::BOX_FILTER,
+    },
+    sun::{advance_time_of_day, SunLight},
+    uniform_types::{
+        CameraTransform, DenoisePushConstants, FilterPushConstants, GlobalUniforms,
+        TaaPushConstants, TonemapPushConstants, TracePushConstants, UpscalePushConstants,
+        VoxelInfos,
+    },
+    utils::{halton, WIDTH},
     vk_controller::VkController,
 };
 
+/// Time between two changes of the dynamic render scale, each one recreates the frame images.
+const DYNAMIC_SCALE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Steps of the dynamic render scale, smaller changes are ignored.
+const DYNAMIC_SCALE_STEP: f32 = 0.05;
+
 pub struct AppBase<'a> {
     pub vk_controller: VkController<'a>,
 
@@ -27,6 +59,52 @@ pub struct AppBase<'a> {
     pub player_controller: PlayerController,
     pub camera: CameraTransform,
     pub sensitivity: f64,
+    pub camera_path: Option<CameraPath>,
+    /// Time on the camera path while it plays, the camera follows it.
+    pub path_time: Option<f32>,
+
+    pub ao_settings: AoConfig,
+    pub reflection_settings: ReflectionConfig,
+    pub temporal_settings: TemporalConfig,
+    pub path_tracing_settings: PathTracingConfig,
+    /// Path traced samples per pixel accumulated since the view last changed.
+    pub accumulated_samples: u32,
+    pub sun_settings: SunConfig,
+    /// Hours since midnight, drives the sun.
+    pub time_of_day: f32,
+    pub sun: SunLight,
+    pub sky_settings: SkyConfig,
+    pub lights_settings: LightsConfig,
+    pub denoiser_settings: DenoiserConfig,
+    pub display_settings: DisplayConfig,
+    pub render_scale_settings: RenderScaleConfig,
+    /// GPU time of the frames smoothed over the last ones, in milliseconds.
+    pub average_gpu_frame_time: Option<f32>,
+    pub last_scale_change: std::time::Instant,
+    pub anti_aliasing_settings: AntiAliasingConfig,
+    pub fog_settings: FogConfig,
+    pub debug_settings: DebugConfig,
+    pub animation_settings: AnimationConfig,
+    /// Seconds of animation of the moving instances, at the current frame and at the previous
+    /// one, between which the motion blur spreads them.
+    pub animation_time: f32,
+    pub previous_animation_time: f32,
+    pub orbiting_voxel: Option<OrbitingVoxel>,
+    pub headless_settings: HeadlessConfig,
+    /// Progress of the headless render, rendering instead of presenting when set.
+    pub headless: Option<HeadlessSchedule>,
+    /// The headless render wrote its last frame.
+    pub finished: bool,
+
+    /// Matrices of the previous frame, to reproject the accumulated history.
+    pub previous_view: bevy_math::Mat4,
+    pub previous_proj: glm::Mat4,
+    /// Jitter of the primary rays of the previous frame, in pixels.
+    pub previous_jitter: glm::Vec2,
+    pub frame_index: u32,
+
+    /// Transient images of the frame graphs, one set per frame in flight.
+    pub transient_resources: Vec<TransientResources>,
 }
 
 impl AppBase<'_> {
@@ -94,7 +172,374 @@ impl AppBase<'_> {
         }
     }
 
-    pub fn update_camera(&mut self) {
+    pub fn cycle_ao_output(&mut self) {
+        self.ao_settings.output = self.ao_settings.output.next();
+        println!("AO output: {:?}", self.ao_settings.output);
+    }
+
+    pub fn cycle_tonemapper(&mut self) {
+        self.display_settings.tonemapper = self.display_settings.tonemapper.next();
+        println!("Tonemapper: {:?}", self.display_settings.tonemapper);
+    }
+
+    pub fn cycle_upscaler(&mut self) {
+        self.render_scale_settings.upscaler = self.render_scale_settings.upscaler.next();
+        println!("Upscaler: {:?}", self.render_scale_settings.upscaler);
+    }
+
+    /// Supersampling renders at its own scale, the other modes at the configured one.
+    pub fn cycle_anti_aliasing(&mut self) {
+        let mode = self.anti_aliasing_settings.mode.next();
+        self.anti_aliasing_settings.mode = mode;
+
+        let scale = self
+            .anti_aliasing_settings
+            .render_scale(mode, self.render_scale_settings.scale);
+        self.vk_controller.set_render_scale(scale).unwrap();
+        self.average_gpu_frame_time = None;
+        // the TAA history was not resolved in the meantime
+        self.vk_controller.reset_history = true;
+        println!("Anti-aliasing: {:?}", mode);
+    }
+
+    /// Moves the render scale towards the one that would keep the GPU time of a frame at the
+    /// target, assuming that time is proportional to the rendered pixels.
+    pub fn update_render_scale(&mut self) {
+        let settings = self.render_scale_settings;
+        let supersampling = self.anti_aliasing_settings.mode == AntiAliasing::Supersampling;
+
+        let Some(frame_time) = self
+            .vk_controller
+            .gpu_frame_time
+            .filter(|_| settings.dynamic && !supersampling)
+        else {
+            return;
+        };
+
+        let average = match self.average_gpu_frame_time {
+            Some(average) => average + (frame_time - average) * 0.1,
+            None => frame_time,
+        };
+        self.average_gpu_frame_time = Some(average);
+
+        if self.frame_start.duration_since(self.last_scale_change) < DYNAMIC_SCALE_INTERVAL {
+            return;
+        }
+
+        let current = self.vk_controller.render_scale;
+        let ideal = current * (settings.target_frame_time / average.max(0.01)).sqrt();
+        let scale = ((ideal / DYNAMIC_SCALE_STEP).floor() * DYNAMIC_SCALE_STEP)
+            .clamp(settings.min_scale, settings.max_scale);
+
+        if (scale - current).abs() < DYNAMIC_SCALE_STEP * 0.5 {
+            return;
+        }
+
+        self.vk_controller.set_render_scale(scale).unwrap();
+        // the new resolution has its own frame time
+        self.average_gpu_frame_time = None;
+        self.last_scale_change = self.frame_start;
+    }
+
+    pub fn toggle_path_tracing(&mut self) {
+        self.path_tracing_settings.enabled = !self.path_tracing_settings.enabled;
+        // the images of the other mode were not updated in the meantime
+        self.vk_controller.reset_history = true;
+        println!(
+            "Path tracing: {}",
+            if self.path_tracing_settings.enabled {
+                "on"
+            } else {
+                "off"
+            }
+        );
+    }
+
+    pub fn toggle_restir(&mut self) {
+        self.lights_settings.restir = !self.lights_settings.restir;
+        // the reservoirs were not updated in the meantime
+        self.vk_controller.reset_history = true;
+        println!(
+            "ReSTIR: {}",
+            if self.lights_settings.restir {
+                "on"
+            } else {
+                "off"
+            }
+        );
+    }
+
+    /// Toggles the denoiser of the current mode.
+    pub fn toggle_denoiser(&mut self) {
+        let enabled = if self.path_tracing_settings.enabled {
+            &mut self.denoiser_settings.path_tracing
+        } else {
+            &mut self.denoiser_settings.ao
+        };
+        *enabled = !*enabled;
+        // the accumulation of the mode changes hands
+        self.vk_controller.reset_history = true;
+        println!("Denoiser: {}", if *enabled { "on" } else { "off" });
+    }
+
+    pub fn toggle_lens(&mut self) {
+        self.camera.lens.enabled = !self.camera.lens.enabled;
+        // the accumulated samples were traced through the other camera
+        self.vk_controller.reset_history = true;
+        println!(
+            "Depth of field and motion blur: {}",
+            if self.camera.lens.enabled {
+                "on"
+            } else {
+                "off"
+            }
+        );
+    }
+
+    pub fn toggle_fog(&mut self) {
+        self.fog_settings.enabled = !self.fog_settings.enabled;
+        // the accumulated samples were traced without the fog
+        self.vk_controller.reset_history = true;
+        println!(
+            "Fog: {}",
+            if self.fog_settings.enabled {
+                "on"
+            } else {
+                "off"
+            }
+        );
+    }
+
+    pub fn cycle_debug_view(&mut self) {
+        self.debug_settings.view = self.debug_settings.view.next();
+        // the history was accumulated from the frame, not from the debug view
+        self.vk_controller.reset_history = true;
+        println!("Debug view: {:?}", self.debug_settings.view);
+    }
+
+    /// Adds a copy of the first voxel circling the model, or removes it. The instance count
+    /// changes, so the TLAS is rebuilt rather than refitted.
+    pub fn toggle_orbiting_voxel(&mut self) {
+        let mut instances = self.vk_controller.instances.clone();
+        let mut voxels_infos = self.vk_controller.voxels_infos.clone().unwrap();
+
+        if let Some(orbit) = self.orbiting_voxel.take() {
+            instances.truncate(orbit.index);
+            voxels_infos.truncate(orbit.index);
+        } else {
+            let orbit = OrbitingVoxel::around(&voxels_infos, self.animation_settings.orbit_period);
+
+            instances.push(vk::AccelerationStructureInstanceKHR {
+                transform: orbit.transform(self.animation_time),
+                ..instances[0]
+            });
+            // alone in the air, none of its faces is shared
+            voxels_infos.push(VoxelInfos {
+                position: orbit.position(self.animation_time),
+                interior_faces: 0,
+                ..voxels_infos[0]
+            });
+
+            self.orbiting_voxel = Some(orbit);
+        }
+
+        self.vk_controller.set_instances(instances, voxels_infos);
+        println!(
+            "Orbiting voxel: {}",
+            if self.orbiting_voxel.is_some() {
+                "on"
+            } else {
+                "off"
+            }
+        );
+    }
+
+    /// Plays the camera path from its start, or gives the camera back.
+    pub fn toggle_camera_path(&mut self) {
+        let Some(path) = &self.camera_path else {
+            println!("No camera path, add keyframes to the [camera_path] section of config.toml");
+            return;
+        };
+
+        self.path_time = match self.path_time {
+            Some(_) => None,
+            None => Some(path.start()),
+        };
+        println!(
+            "Camera path: {}",
+            if self.path_time.is_some() {
+                "on"
+            } else {
+                "off"
+            }
+        );
+    }
+
+    /// Moves the animated instances to `shutter_time`, from their transforms of the previous
+    /// frame at 0 to their current ones at 1. The TLAS is refitted.
+    pub fn update_instances(&mut self, shutter_time: f32) {
+        if let Some(orbit) = self.orbiting_voxel {
+            let transform = interpolate_transform(
+                &orbit.transform(self.previous_animation_time),
+                &orbit.transform(self.animation_time),
+                shutter_time,
+            );
+            self.vk_controller
+                .set_instance_transform(orbit.index, transform);
+        }
+    }
+
+    /// Time of the shutter the instances are held at this frame. A preview of their motion
+    /// blur: the frame sees them at a single time, the history averages the times of the
+    /// frames.
+    fn instance_shutter_time(&self) -> f32 {
+        let lens = self.camera.lens;
+
+        if !lens.enabled || self.debug_settings.view != DebugView::Off {
+            return 1.0;
+        }
+
+        1.0 - lens.shutter * halton(self.frame_index % 16 + 1, 5)
+    }
+
+    /// Renders the camera path with the path tracer, the frames are converged rather than
+    /// denoised or resolved over time.
+    fn start_headless(&mut self) {
+        let settings = &self.headless_settings;
+        let (start, end) = self
+            .camera_path
+            .as_ref()
+            .map_or((0.0, 0.0), |path| (path.start(), path.end()));
+
+        if let Err(error) = std::fs::create_dir_all(&settings.output_directory) {
+            println!("Failed to create {}: {}", settings.output_directory, error);
+            self.finished = true;
+            return;
+        }
+
+        self.path_tracing_settings.enabled = true;
+        self.path_tracing_settings.max_samples = settings.samples_per_pixel;
+        self.denoiser_settings.path_tracing = false;
+        self.render_scale_settings.dynamic = false;
+        self.debug_settings.view = DebugView::Off;
+        if self.anti_aliasing_settings.mode == AntiAliasing::Taa {
+            self.anti_aliasing_settings.mode = AntiAliasing::Off;
+        }
+
+        let schedule = HeadlessSchedule::new(
+            start,
+            end,
+            settings.frames_per_second,
+            settings.samples_per_pixel,
+            self.path_tracing_settings.samples_per_pixel,
+        );
+        println!(
+            "Rendering {} frames of {} samples per pixel to {}",
+            schedule.frame_count, settings.samples_per_pixel, settings.output_directory
+        );
+
+        self.headless = Some(schedule);
+    }
+
+    /// Places the camera and the instances over the piece of the shutter of the current
+    /// sub-frame, and returns the time of the shutter the instances are held at.
+    fn prepare_headless_sub_frame(&mut self) -> f32 {
+        let schedule = self.headless.unwrap();
+
+        // the clocks follow the path rather than the time the frames take
+        self.delta_time = std::time::Duration::from_secs_f32(
+            schedule.frame_interval() / schedule.sub_frame_count as f32,
+        );
+        self.previous_animation_time = schedule.time_at(0.0);
+        self.animation_time = schedule.frame_time();
+
+        if schedule.first_sub_frame() {
+            self.accumulated_samples = 0;
+        }
+
+        let enabled = self.camera.lens.enabled;
+        let Some(path) = &self.camera_path else {
+            // a still, only the instances move
+            let shutter = if enabled {
+                self.camera.lens.shutter
+            } else {
+                0.0
+            };
+            self.previous_view = self.camera.transform.compute_matrix().inverse();
+            return schedule.shutter_interval(shutter).middle();
+        };
+
+        let frame_camera = path.sample(schedule.frame_time());
+        let shutter = if enabled {
+            frame_camera.lens.shutter
+        } else {
+            0.0
+        };
+        let interval = schedule.shutter_interval(shutter);
+
+        // the camera rays move from the view of the opening to the one of the closing
+        let open_camera = path.sample(schedule.time_at(interval.open));
+        self.previous_view = open_camera.transform.compute_matrix().inverse();
+        self.camera = path.sample(schedule.time_at(interval.close));
+        self.camera.lens.enabled = enabled;
+
+        interval.middle()
+    }
+
+    /// Writes the frame once its last sub-frame is traced, then moves to the next sub-frame.
+    fn finish_headless_sub_frame(&mut self) {
+        let mut schedule = self.headless.unwrap();
+
+        if schedule.last_sub_frame() {
+            let extent = self.vk_controller.surface_resolution;
+            let path = Path::new(&self.headless_settings.output_directory)
+                .join(format!("frame_{:04}.ppm", schedule.frame));
+
+            match self
+                .vk_controller
+                .read_back_output()
+                .and_then(|pixels| write_ppm(&path, extent.width, extent.height, &pixels))
+            {
+                Ok(()) => println!("Wrote {}", path.display()),
+                Err(error) => {
+                    println!("Failed to write {}: {}", path.display(), error);
+                    self.finished = true;
+                }
+            }
+        } else {
+            self.vk_controller.skip_present();
+        }
+
+        self.vk_controller.end_frame();
+
+        if !schedule.advance() {
+            println!("Headless render done");
+            self.finished = true;
+        }
+        self.headless = Some(schedule);
+    }
+
+    pub fn update_camera(&mut self) -> GlobalUniforms {
+        if let (Some(path), Some(time)) = (&self.camera_path, self.path_time.as_mut()) {
+            *time += self.delta_time.as_secs_f32();
+
+            // loops, cutting back to the start
+            if *time > path.end() {
+                *time = path.start();
+                self.vk_controller.reset_history = true;
+            }
+
+            let enabled = self.camera.lens.enabled;
+            self.camera = path.sample(*time);
+            self.camera.lens.enabled = enabled;
+        } else if self.headless.is_none() {
+            self.move_camera();
+        }
+
+        self.camera_uniforms()
+    }
+
+    fn move_camera(&mut self) {
         let local_z = self.camera.transform.local_z();
         let forward = -bevy_math::Vec3::new(local_z.x, 0.0, local_z.z);
         let right = bevy_math::Vec3::new(local_z.z, 0.0, -local_z.x);
@@ -119,9 +564,16 @@ impl AppBase<'_> {
 
         velocity = velocity.normalize_or_zero();
 
-        self.camera.transform.translation +=
-            velocity * self.delta_time.as_secs_f32() * self.player_controller.speed;
+        let movement = velocity * self.delta_time.as_secs_f32() * self.player_controller.speed;
+        self.camera.transform.translation += movement;
 
+        // too far for the history to reproject usefully
+        if movement.length() > self.temporal_settings.camera_cut_distance {
+            self.vk_controller.reset_history = true;
+        }
+    }
+
+    fn camera_uniforms(&mut self) -> GlobalUniforms {
         let view_inverse = self.camera.transform.compute_matrix();
 
         let proj_matrix =
@@ -129,69 +581,113 @@ impl AppBase<'_> {
 
         let proj_inverse = glm::inverse(&proj_matrix);
 
-        let uniform_buffer_data = GlobalUniforms {
+        self.time_of_day = advance_time_of_day(
+            self.time_of_day,
+            self.delta_time.as_secs_f32(),
+            &self.sun_settings,
+        );
+        let sun = SunLight::at_time(self.time_of_day, &self.sun_settings);
+
+        let history_valid = !std::mem::take(&mut self.vk_controller.reset_history);
+        let view = view_inverse.inverse();
+
+        // the debug views show the hits through the pixel centers of a pinhole camera
+        let debugging = self.debug_settings.view != DebugView::Off;
+        let lens = LensConfig {
+            enabled: self.camera.lens.enabled && !debugging,
+            ..self.camera.lens
+        };
+        let fog = self.fog_settings;
+
+        // the path tracer jitters its own samples within the pixels
+        let jitter = if self.anti_aliasing_settings.mode == AntiAliasing::Taa
+            && !self.path_tracing_settings.enabled
+            && !debugging
+        {
+            let index = self.frame_index % self.anti_aliasing_settings.jitter_samples.max(1) + 1;
+            glm::vec2(halton(index, 2) - 0.5, halton(index, 3) - 0.5)
+        } else {
+            glm::Vec2::zeros()
+        };
+
+        // the path traced samples are only valid for one exact view and lighting, the headless
+        // render accumulates over the moving shutter on purpose
+        if self.headless.is_none()
+            && (!history_valid
+                || view != self.previous_view
+                || proj_matrix != self.previous_proj
+                || sun != self.sun)
+        {
+            self.accumulated_samples = 0;
+        }
+
+        // the previous camera is unknown after a cut, the headless sub-frames spread the rays
+        // over their whole piece of the shutter
+        let shutter = match self.headless {
+            _ if !lens.enabled => 0.0,
+            Some(_) => 1.0,
+            None if history_valid => lens.shutter,
+            None => 0.0,
+        };
+
+        let uniforms = GlobalUniforms {
             view_inverse,
             proj_inverse,
+            prev_view: self.previous_view,
+            prev_proj: self.previous_proj,
+            frame_index: self.frame_index,
+            history_valid: history_valid as u32,
+            max_history: self.temporal_settings.max_history,
+            history_depth_tolerance: self.temporal_settings.depth_tolerance,
+            sun_direction: sun.direction,
+            time_of_day: self.time_of_day,
+            sun_color: sun.color,
+            sky_intensity: sun.sky_intensity,
+            sky_turbidity: self.sky_settings.turbidity,
+            use_environment_map: self.vk_controller.environment_map.as_ref().unwrap().loaded as u32,
+            environment_intensity: self.sky_settings.environment_intensity,
+            environment_rotation: self.sky_settings.environment_rotation.to_radians(),
+            jitter,
+            prev_jitter: self.previous_jitter,
+            aperture_radius: if lens.enabled { lens.aperture } else { 0.0 },
+            focal_distance: lens.focal_distance,
+            aperture_blades: lens.blades,
+            shutter,
+            fog_albedo: glm::Vec3::from(fog.albedo),
+            fog_density: fog.density,
+            fog_height: fog.height,
+            fog_height_falloff: fog.height_falloff,
+            fog_anisotropy: fog.anisotropy.clamp(-0.99, 0.99),
+            fog_steps: if fog.enabled { fog.steps } else { 0 },
         };
 
-        let uniforms_buffer = self.vk_controller.uniforms_buffer.as_mut().unwrap();
+        self.sun = sun;
+        self.previous_jitter = jitter;
 
-        unsafe {
-            let buffer_barrier = vk::BufferMemoryBarrier::default()
-                .src_access_mask(vk::AccessFlags::SHADER_READ)
-                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                .buffer(uniforms_buffer.buffer)
-                .size(uniforms_buffer.size);
-
-            self.vk_controller.device.cmd_pipeline_barrier(
-                self.vk_controller.rt_command_buffer,
-                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::DependencyFlags::DEVICE_GROUP,
-                &[],
-                &[buffer_barrier],
-                &[],
-            );
-        }
-
-        unsafe {
-            self.vk_controller.device.cmd_update_buffer(
-                self.vk_controller.rt_command_buffer,
-                uniforms_buffer.buffer,
-                0,
-                bytes_of(&uniform_buffer_data),
-            )
-        }
+        self.previous_view = view;
+        self.previous_proj = proj_matrix;
+        self.frame_index = self.frame_index.wrapping_add(1);
 
-        unsafe {
-            let buffer_barrier = vk::BufferMemoryBarrier::default()
-                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                .dst_access_mask(vk::AccessFlags::SHADER_READ)
-                .buffer(uniforms_buffer.buffer)
-                .size(uniforms_buffer.size);
-
-            self.vk_controller.device.cmd_pipeline_barrier(
-                self.vk_controller.rt_command_buffer,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
-                vk::DependencyFlags::DEVICE_GROUP,
-                &[],
-                &[buffer_barrier],
-                &[],
-            );
-        }
+        uniforms
     }
 
     pub fn new(event_loop: &ActiveEventLoop, window_width: u32, window_height: u32) -> Self {
+        let config = Config::load();
+
         let camera = CameraTransform {
             transform: Transform::from_xyz(160.0, 64.0, -32.0)
                 .looking_at(bevy_math::Vec3::new(64.0, 48.0, 64.0), bevy_math::Vec3::Y),
+            lens: config.lens,
         };
 
-        let mut vk_controller = VkController::new(event_loop, window_width, window_height);
+        let mut vk_controller = VkController::new(event_loop, window_width, window_height, &config);
         vk_controller.init();
 
-        AppBase {
+        let transient_resources = (0..FRAMES_IN_FLIGHT)
+            .map(|_| TransientResources::new(vk_controller.allocator.clone()))
+            .collect();
+
+        let mut app = AppBase {
             vk_controller,
             current_frames_counter: 0,
             frame_start: std::time::Instant::now(),
@@ -201,30 +697,54 @@ impl AppBase<'_> {
             player_controller: PlayerController::default(),
             camera,
             sensitivity: 0.001,
+            camera_path: CameraPath::new(&config.camera_path.keyframes, config.lens),
+            path_time: None,
+            ao_settings: config.ao,
+            reflection_settings: config.reflections,
+            temporal_settings: config.temporal,
+            path_tracing_settings: config.path_tracing,
+            accumulated_samples: 0,
+            sun_settings: config.sun,
+            time_of_day: config.sun.time_of_day,
+            sun: SunLight::at_time(config.sun.time_of_day, &config.sun),
+            sky_settings: config.sky.clone(),
+            lights_settings: config.lights,
+            denoiser_settings: config.denoiser,
+            display_settings: config.display,
+            render_scale_settings: config.render_scale,
+            average_gpu_frame_time: None,
+            last_scale_change: std::time::Instant::now(),
+            anti_aliasing_settings: config.anti_aliasing,
+            fog_settings: config.fog,
+            debug_settings: config.debug,
+            animation_settings: config.animation,
+            animation_time: 0.0,
+            previous_animation_time: 0.0,
+            orbiting_voxel: None,
+            headless_settings: config.headless.clone(),
+            headless: None,
+            finished: false,
+            previous_view: bevy_math::Mat4::IDENTITY,
+            previous_proj: glm::Mat4::identity(),
+            previous_jitter: glm::Vec2::zeros(),
+            frame_index: 0,
             resized: false,
             focused: false,
-        }
-    }
-
-    pub fn main_loop(&mut self) {
-        let rt_command_buffer = self.vk_controller.rt_command_buffer;
+            transient_resources,
+        };
 
-        unsafe {
-            self.vk_controller
-                .device
-                .wait_for_fences(
-                    &[self.vk_controller.swapchain_acquire_fence],
-                    true,
-                    std::u64::MAX,
-                )
-                .expect("Wait for fence failed.");
+        if app.animation_settings.orbiting_voxel {
+            app.toggle_orbiting_voxel();
+        }
 
-            self.vk_controller
-                .device
-                .reset_fences(&[self.vk_controller.swapchain_acquire_fence])
-                .expect("Reset fences failed.");
+        if app.headless_settings.enabled {
+            app.start_headless();
         }
 
+        app
+    }
+
+    pub fn main_loop(&mut self) {
         if self.resized {
             self.vk_controller.recreate_swapchain().unwrap();
             self.resized = false;
@@ -234,261 +754,931 @@ impl AppBase<'_> {
 
         self.frame_start = std::time::Instant::now();
 
-        self.update_delta_time();
+        let shutter_time = if self.headless.is_some() {
+            self.prepare_headless_sub_frame()
+        } else {
+            self.update_delta_time();
+            self.previous_animation_time = self.animation_time;
+            self.animation_time += self.delta_time.as_secs_f32();
+            self.instance_shutter_time()
+        };
 
         if self.frame_start.duration_since(self.last_second).as_secs() > 0 {
             // println!("{}fps", self.current_frames_counter);
             self.reset_fps_counter();
         }
 
-        let (present_index, _) = unsafe {
-            self.vk_controller.swapchain_loader.acquire_next_image(
-                self.vk_controller.swapchain.unwrap(),
-                std::u64::MAX,
-                self.vk_controller.present_complete_semaphore,
-                self.vk_controller.swapchain_acquire_fence,
-            )
-        }
-        .unwrap();
+        self.vk_controller.reload_changed_shaders();
+        self.update_render_scale();
+        self.update_instances(shutter_time);
 
-        let current_swapchain_image =
-            self.vk_controller.present_images.as_ref().unwrap()[present_index as usize];
+        let frame_index = self.vk_controller.begin_frame();
+        let command_buffer = self.vk_controller.frames[frame_index].command_buffer;
 
         unsafe {
-            self.vk_controller
-                .device
-                .wait_for_fences(
-                    &[self.vk_controller.draw_commands_reuse_fence],
-                    true,
-                    std::u64::MAX,
-                )
-                .expect("Wait for fence failed.");
-
-            self.vk_controller
-                .device
-                .reset_fences(&[self.vk_controller.draw_commands_reuse_fence])
-                .expect("Reset fences failed.");
-
             self.vk_controller
                 .device
                 .reset_command_buffer(
-                    rt_command_buffer,
+                    command_buffer,
                     vk::CommandBufferResetFlags::RELEASE_RESOURCES,
                 )
                 .expect("Reset command buffer failed.");
 
-            let rt_command_buffer_begin_info = vk::CommandBufferBeginInfo::default()
+            let command_buffer_begin_info = vk::CommandBufferBeginInfo::default()
                 .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
 
             self.vk_controller
                 .device
-                .begin_command_buffer(rt_command_buffer, &rt_command_buffer_begin_info)
+                .begin_command_buffer(command_buffer, &command_buffer_begin_info)
                 .expect("Begin commandbuffer");
 
-            self.update_camera();
+            self.vk_controller
+                .record_frame_start_timestamp(command_buffer);
+            // the paths traced so far saw the instances elsewhere
+            if self.vk_controller.record_tlas_update(command_buffer) && self.headless.is_none() {
+                self.accumulated_samples = 0;
+            }
 
-            // full rt pass
-            {
-                self.vk_controller.device.cmd_bind_pipeline(
-                    rt_command_buffer,
-                    vk::PipelineBindPoint::RAY_TRACING_KHR,
-                    self.vk_controller.pipeline.unwrap(),
-                );
-                self.vk_controller.device.cmd_bind_descriptor_sets(
-                    rt_command_buffer,
-                    vk::PipelineBindPoint::RAY_TRACING_KHR,
-                    self.vk_controller.pipeline_layout.unwrap(),
-                    0,
-                    &[
-                        self.vk_controller.rt_descriptor_set.unwrap(),
-                        self.vk_controller.uniforms_descriptor_set.unwrap(),
-                    ],
-                    &[],
-                );
-                self.vk_controller
-                    .ray_tracing_pipeline_loader
-                    .cmd_trace_rays(
-                        rt_command_buffer,
-                        &self.vk_controller.sbt_raygen_region.unwrap(),
-                        &self.vk_controller.sbt_miss_region.unwrap(),
-                        &self.vk_controller.sbt_hit_region.unwrap(),
-                        &self.vk_controller.sbt_call_region.unwrap(),
-                        self.vk_controller.surface_resolution.width,
-                        self.vk_controller.surface_resolution.height,
-                        1,
+            let uniform_buffer_data = self.update_camera();
+
+            let ao_settings = self.ao_settings;
+            let debug_settings = self.debug_settings;
+            // the debug views replace the frame, none of the modes is traced
+            let debugging = debug_settings.view != DebugView::Off;
+            let path_tracing_settings = self.path_tracing_settings;
+            let path_tracing = path_tracing_settings.enabled && !debugging;
+            let lights_settings = self.lights_settings;
+            let restir = lights_settings.restir && self.vk_controller.light_count > 0;
+            let denoiser_settings = self.denoiser_settings;
+            let denoise = if path_tracing {
+                denoiser_settings.path_tracing
+            } else {
+                denoiser_settings.ao
+            };
+            let anti_aliasing_settings = self.anti_aliasing_settings;
+            let taa = anti_aliasing_settings.mode == AntiAliasing::Taa && !debugging;
+
+            // a still view keeps accumulating until it reaches `max_samples`
+            let remaining_samples = match path_tracing_settings.max_samples {
+                0 => u32::MAX,
+                max_samples => max_samples.saturating_sub(self.accumulated_samples),
+            };
+            let new_samples = path_tracing_settings
+                .samples_per_pixel
+                .min(remaining_samples);
+
+            let trace_push_constants = TracePushConstants {
+                ao_samples_per_pixel: ao_settings.samples_per_pixel,
+                ao_ray_length: ao_settings.ray_length,
+                pt_samples_per_pixel: new_samples,
+                pt_max_bounces: path_tracing_settings.max_bounces,
+                pt_accumulated_samples: self.accumulated_samples,
+                reflection_max_depth: self.reflection_settings.max_depth,
+                light_count: self.vk_controller.light_count,
+                light_power: self.vk_controller.light_power,
+                light_candidates: lights_settings.candidates,
+                restir_enabled: restir as u32,
+                restir_spatial_samples: lights_settings.spatial_samples,
+                restir_spatial_radius: lights_settings.spatial_radius,
+                restir_max_history: lights_settings.max_history,
+                denoiser_enabled: denoise as u32,
+                taa_enabled: taa as u32,
+                debug_view: debug_settings.view.view(),
+                debug_max_depth: debug_settings.max_depth,
+                debug_max_cost: debug_settings.max_cost,
+            };
+
+            if path_tracing {
+                self.accumulated_samples += new_samples;
+
+                if new_samples > 0 && self.accumulated_samples == path_tracing_settings.max_samples
+                {
+                    println!(
+                        "Path tracing reached {} samples per pixel",
+                        self.accumulated_samples
                     );
+                }
             }
 
-            // current swapchain to dst layout
-            {
-                let image_barrier = vk::ImageMemoryBarrier::default()
-                    .src_access_mask(vk::AccessFlags::empty())
-                    .dst_access_mask(
-                        vk::AccessFlags::TRANSFER_WRITE | vk::AccessFlags::TRANSFER_READ,
+            let vk_controller = &self.vk_controller;
+            let frame = &vk_controller.frames[frame_index];
+            let mut graph =
+                RenderGraph::with_transients(&mut self.transient_resources[frame_index]);
+
+            let uniforms_buffer = graph.import_buffer(
+                frame.uniforms_buffer.buffer,
+                ResourceState::idle(vk::ImageLayout::UNDEFINED),
+            );
+            // every image is overwritten, the output is left for the copy of the present commands
+            let output_image = graph.import_image(
+                frame.images.output.image,
+                ResourceState::idle(vk::ImageLayout::UNDEFINED),
+                Some(ResourceState {
+                    stage: vk::PipelineStageFlags::TRANSFER,
+                    access: vk::AccessFlags::TRANSFER_READ,
+                    layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                }),
+            );
+            let hdr_image = graph.import_image(
+                frame.images.hdr.image,
+                ResourceState::idle(vk::ImageLayout::UNDEFINED),
+                None,
+            );
+            let tonemapped_image = graph.import_image(
+                frame.images.tonemapped.image,
+                ResourceState::idle(vk::ImageLayout::UNDEFINED),
+                None,
+            );
+            // shared by the frames, last written by the exposure passes of the previous frame
+            // and read by its tonemapping
+            let exposure_state = ResourceState {
+                stage: vk::PipelineStageFlags::COMPUTE_SHADER
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                access: vk::AccessFlags::SHADER_WRITE,
+                layout: vk::ImageLayout::UNDEFINED,
+            };
+            let histogram_buffer = graph.import_buffer(
+                vk_controller.histogram_buffer.as_ref().unwrap().buffer,
+                exposure_state,
+            );
+            let exposure_buffer = graph.import_buffer(
+                vk_controller.exposure_buffer.as_ref().unwrap().buffer,
+                exposure_state,
+            );
+
+            // the history images are left for the ray tracing and the denoiser of the next frame,
+            // which read them while the frame after overwrites them
+            let history_state = ResourceState {
+                stage: vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR
+                    | vk::PipelineStageFlags::COMPUTE_SHADER,
+                access: vk::AccessFlags::empty(),
+                layout: vk::ImageLayout::GENERAL,
+            };
+            let history_final_state = ResourceState {
+                access: vk::AccessFlags::SHADER_READ,
+                ..history_state
+            };
+            let overwritten_history_state = ResourceState {
+                layout: vk::ImageLayout::UNDEFINED,
+                ..history_state
+            };
+            // discarded histories may not have been written yet
+            let prev_history_state = if uniform_buffer_data.history_valid != 0 {
+                history_state
+            } else {
+                overwritten_history_state
+            };
+
+            let prev_frame =
+                &vk_controller.frames[(frame_index + FRAMES_IN_FLIGHT - 1) % FRAMES_IN_FLIGHT];
+
+            let import_history =
+                |graph: &mut RenderGraph, image: &FrameImage, prev_image: &FrameImage| {
+                    (
+                        graph.import_image(
+                            image.image,
+                            overwritten_history_state,
+                            Some(history_final_state),
+                        ),
+                        graph.import_image(prev_image.image, prev_history_state, None),
                     )
-                    .old_layout(vk::ImageLayout::UNDEFINED)
-                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                    .image(current_swapchain_image)
-                    .subresource_range(
-                        vk::ImageSubresourceRange::default()
-                            .aspect_mask(vk::ImageAspectFlags::COLOR)
-                            .base_mip_level(0)
-                            .level_count(1)
-                            .base_array_layer(0)
-                            .layer_count(1),
+                };
+
+            // the resolved image is left for the resolve of the next frame, which samples it as its
+            // history while the frame after overwrites it
+            let resolved_state = ResourceState {
+                stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                access: vk::AccessFlags::empty(),
+                layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            };
+            let taa_images = taa.then(|| {
+                let motion_image = graph.import_image(
+                    frame.images.motion.image,
+                    ResourceState::idle(vk::ImageLayout::UNDEFINED),
+                    None,
+                );
+                let resolved_image = graph.import_image(
+                    frame.images.taa_resolved.image,
+                    ResourceState {
+                        layout: vk::ImageLayout::UNDEFINED,
+                        ..resolved_state
+                    },
+                    Some(ResourceState {
+                        access: vk::AccessFlags::SHADER_READ,
+                        ..resolved_state
+                    }),
+                );
+                let prev_resolved_image = graph.import_image(
+                    prev_frame.images.taa_resolved.image,
+                    if uniform_buffer_data.history_valid != 0 {
+                        resolved_state
+                    } else {
+                        ResourceState {
+                            layout: vk::ImageLayout::UNDEFINED,
+                            ..resolved_state
+                        }
+                    },
+                    None,
+                );
+                (motion_image, resolved_image, prev_resolved_image)
+            });
+
+            let extent = vk_controller.render_resolution;
+
+            let svgf = vk_controller.svgf.as_ref().unwrap();
+            let denoise_push_constants = DenoisePushConstants {
+                signal: RADIANCE_SIGNAL as u32,
+                iteration: denoiser_settings.iterations,
+                alpha: denoiser_settings.alpha,
+                moments_alpha: denoiser_settings.moments_alpha,
+                phi_color: denoiser_settings.phi_color,
+                phi_normal: denoiser_settings.phi_normal,
+                phi_depth: denoiser_settings.phi_depth,
+                phi_albedo: denoiser_settings.phi_albedo,
+            };
+
+            // the images of the denoiser around the signal and the G-buffer of the mode
+            let import_svgf_images =
+                |graph: &mut RenderGraph, signal, depth_normal, prev_depth_normal, albedo| {
+                    let (history, prev_history) = import_history(
+                        graph,
+                        &frame.images.svgf_history,
+                        &prev_frame.images.svgf_history,
                     );
+                    let (moments, prev_moments) = import_history(
+                        graph,
+                        &frame.images.svgf_moments,
+                        &prev_frame.images.svgf_moments,
+                    );
+                    let [filtered_0, filtered_1, denoised] = [
+                        &frame.images.svgf_filtered[0],
+                        &frame.images.svgf_filtered[1],
+                        &frame.images.denoised,
+                    ]
+                    .map(|image| {
+                        graph.import_image(
+                            image.image,
+                            ResourceState::idle(vk::ImageLayout::UNDEFINED),
+                            None,
+                        )
+                    });
+
+                    SvgfImages {
+                        signal,
+                        depth_normal,
+                        prev_depth_normal,
+                        albedo,
+                        history,
+                        prev_history,
+                        moments,
+                        prev_moments,
+                        filtered: [filtered_0, filtered_1],
+                        denoised,
+                    }
+                };
+
+            let trace_rays =
+                move |device: &Device,
+                      command_buffer: vk::CommandBuffer,
+                      raygen_region: vk::StridedDeviceAddressRegionKHR| {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::RAY_TRACING_KHR,
+                        vk_controller.pipeline.unwrap(),
+                    );
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::RAY_TRACING_KHR,
+                        vk_controller.pipeline_layout.unwrap(),
+                        0,
+                        &[frame.rt_descriptor_set, frame.uniforms_descriptor_set],
+                        &[],
+                    );
+                    device.cmd_push_constants(
+                        command_buffer,
+                        vk_controller.pipeline_layout.unwrap(),
+                        vk::ShaderStageFlags::RAYGEN_KHR,
+                        0,
+                        bytes_of(&trace_push_constants),
+                    );
+                    vk_controller.ray_tracing_pipeline_loader.cmd_trace_rays(
+                        command_buffer,
+                        &raygen_region,
+                        &vk_controller.sbt_miss_region.unwrap(),
+                        &vk_controller.sbt_hit_region.unwrap(),
+                        &vk_controller.sbt_call_region.unwrap(),
+                        extent.width,
+                        extent.height,
+                        1,
+                    );
+                };
+
+            // converts a traced image to the format of the HDR image
+            let add_display_pass = |graph: &mut RenderGraph, name, image: ImageId| {
+                graph.add_pass(
+                    Pass::new(name)
+                        .image(image, Access::TransferRead)
+                        .image(hdr_image, Access::TransferWrite)
+                        .record(move |device, command_buffer, resources| {
+                            let subresource = vk::ImageSubresourceLayers::default()
+                                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                .layer_count(1);
+                            let offsets = [
+                                vk::Offset3D::default(),
+                                vk::Offset3D {
+                                    x: extent.width as i32,
+                                    y: extent.height as i32,
+                                    z: 1,
+                                },
+                            ];
+                            let blit = vk::ImageBlit::default()
+                                .src_subresource(subresource)
+                                .src_offsets(offsets)
+                                .dst_subresource(subresource)
+                                .dst_offsets(offsets);
+
+                            device.cmd_blit_image(
+                                command_buffer,
+                                resources.image(image),
+                                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                                resources.image(hdr_image),
+                                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                                &[blit],
+                                vk::Filter::NEAREST,
+                            );
+                        }),
+                );
+            };
+
+            graph.add_pass(
+                Pass::new("update uniforms")
+                    .buffer(uniforms_buffer, Access::TransferWrite)
+                    .record(move |device, command_buffer, resources| {
+                        device.cmd_update_buffer(
+                            command_buffer,
+                            resources.buffer(uniforms_buffer),
+                            0,
+                            bytes_of(&uniform_buffer_data),
+                        );
+                    }),
+            );
 
-                self.vk_controller.device.cmd_pipeline_barrier(
-                    rt_command_buffer,
-                    vk::PipelineStageFlags::TOP_OF_PIPE,
-                    vk::PipelineStageFlags::TRANSFER,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[image_barrier],
+            if debugging {
+                let debug_image = graph.import_image(
+                    frame.images.debug.image,
+                    ResourceState::idle(vk::ImageLayout::UNDEFINED),
+                    None,
                 );
-            }
 
-            // rt image to src layout
-            {
-                let image_barrier = vk::ImageMemoryBarrier::default()
-                    .src_access_mask(vk::AccessFlags::empty())
-                    .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                    .old_layout(vk::ImageLayout::UNDEFINED)
-                    .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-                    .image(self.vk_controller.rt_image)
-                    .subresource_range(
-                        vk::ImageSubresourceRange::default()
-                            .aspect_mask(vk::ImageAspectFlags::COLOR)
-                            .base_mip_level(0)
-                            .level_count(1)
-                            .base_array_layer(0)
-                            .layer_count(1),
-                    );
+                graph.add_pass(
+                    Pass::new("trace debug view")
+                        .buffer(uniforms_buffer, Access::RayTracingUniformRead)
+                        .image(debug_image, Access::RayTracingStorageWrite)
+                        .record(move |device, command_buffer, _| {
+                            trace_rays(
+                                device,
+                                command_buffer,
+                                vk_controller.sbt_raygen_region.unwrap(),
+                            );
+                        }),
+                );
 
-                self.vk_controller.device.cmd_pipeline_barrier(
-                    rt_command_buffer,
-                    vk::PipelineStageFlags::TRANSFER,
-                    vk::PipelineStageFlags::TRANSFER,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[image_barrier],
+                add_display_pass(&mut graph, "display debug view", debug_image);
+            } else if path_tracing {
+                let (accumulation_image, prev_accumulation_image) = import_history(
+                    &mut graph,
+                    &frame.images.accumulation,
+                    &prev_frame.images.accumulation,
                 );
-            }
 
-            // copy rt image to swapchain
-            {
-                let copy_region = vk::ImageCopy::default()
-                    .src_subresource(
-                        vk::ImageSubresourceLayers::default()
-                            .aspect_mask(vk::ImageAspectFlags::COLOR)
-                            .layer_count(1),
+                let mut path_trace_pass = Pass::new("path trace")
+                    .buffer(uniforms_buffer, Access::RayTracingUniformRead)
+                    .image(accumulation_image, Access::RayTracingStorageWrite)
+                    .image(prev_accumulation_image, Access::RayTracingStorageRead);
+
+                // the denoiser is guided by the G-buffer of the first hits, which is traced along
+                // with their motion
+                let gbuffer_images = (denoise || taa).then(|| {
+                    let (depth_normal_image, prev_depth_normal_image) = import_history(
+                        &mut graph,
+                        &frame.images.depth_normal,
+                        &prev_frame.images.depth_normal,
+                    );
+                    let albedo_image = graph.import_image(
+                        frame.images.albedo.image,
+                        ResourceState::idle(vk::ImageLayout::UNDEFINED),
+                        None,
+                    );
+                    (depth_normal_image, prev_depth_normal_image, albedo_image)
+                });
+
+                if let Some((depth_normal_image, _, albedo_image)) = gbuffer_images {
+                    path_trace_pass = path_trace_pass
+                        .image(depth_normal_image, Access::RayTracingStorageWrite)
+                        .image(albedo_image, Access::RayTracingStorageWrite);
+                }
+
+                if let Some((motion_image, _, _)) = taa_images {
+                    path_trace_pass =
+                        path_trace_pass.image(motion_image, Access::RayTracingStorageWrite);
+                }
+
+                graph.add_pass(path_trace_pass.record(move |device, command_buffer, _| {
+                    trace_rays(
+                        device,
+                        command_buffer,
+                        vk_controller.sbt_path_tracing_raygen_region.unwrap(),
+                    );
+                }));
+
+                let radiance_image = match gbuffer_images.filter(|_| denoise) {
+                    Some((depth_normal_image, prev_depth_normal_image, albedo_image)) => {
+                        let images = import_svgf_images(
+                            &mut graph,
+                            accumulation_image,
+                            depth_normal_image,
+                            prev_depth_normal_image,
+                            albedo_image,
+                        );
+                        svgf.add_passes(
+                            &mut graph,
+                            images,
+                            uniforms_buffer,
+                            frame.svgf_descriptor_sets[RADIANCE_SIGNAL],
+                            extent,
+                            denoise_push_constants,
+                        );
+                        images.denoised
+                    }
+                    None => accumulation_image,
+                };
+
+                add_display_pass(&mut graph, "display path tracing", radiance_image);
+            } else {
+                let [albedo_image, filtered_ao_image, reflection_image, fog_image] = [
+                    &frame.images.albedo,
+                    &frame.images.filtered_ao,
+                    &frame.images.reflection,
+                    &frame.images.fog,
+                ]
+                .map(|image| {
+                    graph.import_image(
+                        image.image,
+                        ResourceState::idle(vk::ImageLayout::UNDEFINED),
+                        None,
                     )
-                    .dst_subresource(
-                        vk::ImageSubresourceLayers::default()
-                            .aspect_mask(vk::ImageAspectFlags::COLOR)
-                            .layer_count(1),
+                });
+                let (ao_image, prev_ao_image) =
+                    import_history(&mut graph, &frame.images.ao, &prev_frame.images.ao);
+                let (depth_normal_image, prev_depth_normal_image) = import_history(
+                    &mut graph,
+                    &frame.images.depth_normal,
+                    &prev_frame.images.depth_normal,
+                );
+
+                let local_z = self.camera.transform.local_z();
+                let filter_push_constants = FilterPushConstants {
+                    view_z: glm::vec3(local_z.x, local_z.y, local_z.z),
+                    radius: ao_settings.filter_radius,
+                    texel_size: glm::vec2(1.0 / extent.width as f32, 1.0 / extent.height as f32),
+                    direction: glm::vec2(1.0, 0.0),
+                    output_mode: AoOutput::Occlusion.output_mode(),
+                };
+                let ao_filter = vk_controller.ao_filter.as_ref().unwrap();
+
+                let mut trace_pass = Pass::new("trace rays")
+                    .buffer(uniforms_buffer, Access::RayTracingUniformRead)
+                    .image(ao_image, Access::RayTracingStorageWrite)
+                    .image(depth_normal_image, Access::RayTracingStorageWrite)
+                    .image(albedo_image, Access::RayTracingStorageWrite)
+                    .image(reflection_image, Access::RayTracingStorageWrite)
+                    .image(fog_image, Access::RayTracingStorageWrite)
+                    .image(prev_ao_image, Access::RayTracingStorageRead)
+                    .image(prev_depth_normal_image, Access::RayTracingStorageRead);
+
+                // the spatial reuse shades the direct light of the emissive voxels
+                let restir_images = restir.then(|| {
+                    let reservoir_image = graph.import_image(
+                        frame.images.reservoir.image,
+                        ResourceState::idle(vk::ImageLayout::UNDEFINED),
+                        None,
+                    );
+                    let (spatial_reservoir_image, prev_spatial_reservoir_image) = import_history(
+                        &mut graph,
+                        &frame.images.spatial_reservoir,
+                        &prev_frame.images.spatial_reservoir,
+                    );
+                    (
+                        reservoir_image,
+                        spatial_reservoir_image,
+                        prev_spatial_reservoir_image,
                     )
-                    .extent(
-                        vk::Extent3D::default()
-                            .width(self.vk_controller.surface_resolution.width)
-                            .height(self.vk_controller.surface_resolution.height)
-                            .depth(1),
+                });
+
+                if let Some((reservoir_image, _, prev_spatial_reservoir_image)) = restir_images {
+                    trace_pass = trace_pass
+                        .image(reservoir_image, Access::RayTracingStorageWrite)
+                        .image(prev_spatial_reservoir_image, Access::RayTracingStorageRead);
+                }
+
+                if let Some((motion_image, _, _)) = taa_images {
+                    trace_pass = trace_pass.image(motion_image, Access::RayTracingStorageWrite);
+                }
+
+                graph.add_pass(trace_pass.record(move |device, command_buffer, _| {
+                    trace_rays(
+                        device,
+                        command_buffer,
+                        vk_controller.sbt_raygen_region.unwrap(),
+                    );
+                }));
+
+                if let Some((reservoir_image, spatial_reservoir_image, _)) = restir_images {
+                    graph.add_pass(
+                        Pass::new("reuse light samples")
+                            .buffer(uniforms_buffer, Access::RayTracingUniformRead)
+                            .image(reservoir_image, Access::RayTracingStorageRead)
+                            .image(depth_normal_image, Access::RayTracingStorageRead)
+                            .image(spatial_reservoir_image, Access::RayTracingStorageWrite)
+                            .image(reflection_image, Access::RayTracingStorageReadWrite)
+                            .record(move |device, command_buffer, _| {
+                                trace_rays(
+                                    device,
+                                    command_buffer,
+                                    vk_controller.sbt_restir_spatial_raygen_region.unwrap(),
+                                );
+                            }),
                     );
+                }
 
-                self.vk_controller.device.cmd_copy_image(
-                    rt_command_buffer,
-                    self.vk_controller.rt_image,
-                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                    current_swapchain_image,
-                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    &[copy_region],
-                );
-            }
+                if denoise {
+                    let images = import_svgf_images(
+                        &mut graph,
+                        ao_image,
+                        depth_normal_image,
+                        prev_depth_normal_image,
+                        albedo_image,
+                    );
+                    svgf.add_passes(
+                        &mut graph,
+                        images,
+                        uniforms_buffer,
+                        frame.svgf_descriptor_sets[OCCLUSION_SIGNAL],
+                        extent,
+                        DenoisePushConstants {
+                            signal: OCCLUSION_SIGNAL as u32,
+                            ..denoise_push_constants
+                        },
+                    );
 
-            // current swapchain to present src layout
-            {
-                let image_barrier = vk::ImageMemoryBarrier::default()
-                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                    .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ)
-                    .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                    .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-                    .image(current_swapchain_image)
-                    .subresource_range(
-                        vk::ImageSubresourceRange::default()
-                            .aspect_mask(vk::ImageAspectFlags::COLOR)
-                            .base_mip_level(0)
-                            .level_count(1)
-                            .base_array_layer(0)
-                            .layer_count(1),
+                    // a zero radius composites the denoised occlusion as it is
+                    graph.add_pass(
+                        Pass::new("composite denoised ao")
+                            .buffer(uniforms_buffer, Access::FragmentUniformRead)
+                            .image(depth_normal_image, Access::FragmentSampledRead)
+                            .image(images.denoised, Access::FragmentSampledRead)
+                            .image(albedo_image, Access::FragmentSampledRead)
+                            .image(reflection_image, Access::FragmentSampledRead)
+                            .image(fog_image, Access::FragmentSampledRead)
+                            .image(hdr_image, Access::ColorAttachmentWrite)
+                            .record(move |device, command_buffer, _| {
+                                ao_filter.record_pass(
+                                    device,
+                                    command_buffer,
+                                    VERTICAL_PASS,
+                                    frame.images.filter_framebuffers[VERTICAL_PASS],
+                                    frame.filter_descriptor_sets[DENOISED_VERTICAL_SET],
+                                    extent,
+                                    &FilterPushConstants {
+                                        radius: 0.0,
+                                        output_mode: ao_settings.output.output_mode(),
+                                        ..filter_push_constants
+                                    },
+                                );
+                            }),
+                    );
+                } else {
+                    graph.add_pass(
+                        Pass::new("filter ao horizontally")
+                            .buffer(uniforms_buffer, Access::FragmentUniformRead)
+                            .image(depth_normal_image, Access::FragmentSampledRead)
+                            .image(ao_image, Access::FragmentSampledRead)
+                            .image(albedo_image, Access::FragmentSampledRead)
+                            .image(filtered_ao_image, Access::ColorAttachmentWrite)
+                            .record(move |device, command_buffer, _| {
+                                ao_filter.record_pass(
+                                    device,
+                                    command_buffer,
+                                    HORIZONTAL_PASS,
+                                    frame.images.filter_framebuffers[HORIZONTAL_PASS],
+                                    frame.filter_descriptor_sets[HORIZONTAL_PASS],
+                                    extent,
+                                    &filter_push_constants,
+                                );
+                            }),
                     );
 
-                self.vk_controller.device.cmd_pipeline_barrier(
-                    rt_command_buffer,
-                    vk::PipelineStageFlags::TRANSFER,
-                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[image_barrier],
-                );
+                    graph.add_pass(
+                        Pass::new("filter ao vertically")
+                            .buffer(uniforms_buffer, Access::FragmentUniformRead)
+                            .image(depth_normal_image, Access::FragmentSampledRead)
+                            .image(filtered_ao_image, Access::FragmentSampledRead)
+                            .image(albedo_image, Access::FragmentSampledRead)
+                            .image(reflection_image, Access::FragmentSampledRead)
+                            .image(fog_image, Access::FragmentSampledRead)
+                            .image(hdr_image, Access::ColorAttachmentWrite)
+                            .record(move |device, command_buffer, _| {
+                                ao_filter.record_pass(
+                                    device,
+                                    command_buffer,
+                                    VERTICAL_PASS,
+                                    frame.images.filter_framebuffers[VERTICAL_PASS],
+                                    frame.filter_descriptor_sets[VERTICAL_PASS],
+                                    extent,
+                                    &FilterPushConstants {
+                                        direction: glm::vec2(0.0, 1.0),
+                                        output_mode: ao_settings.output.output_mode(),
+                                        ..filter_push_constants
+                                    },
+                                );
+                            }),
+                    );
+                }
             }
 
-            // rt image back to general layout
-            {
-                let image_barrier = vk::ImageMemoryBarrier::default()
-                    .src_access_mask(vk::AccessFlags::TRANSFER_READ)
-                    .dst_access_mask(vk::AccessFlags::MEMORY_WRITE)
-                    .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-                    .new_layout(vk::ImageLayout::GENERAL)
-                    .image(self.vk_controller.rt_image)
-                    .subresource_range(
-                        vk::ImageSubresourceRange::default()
-                            .aspect_mask(vk::ImageAspectFlags::COLOR)
-                            .base_mip_level(0)
-                            .level_count(1)
-                            .base_array_layer(0)
-                            .layer_count(1),
+            // the tonemapping reads the resolved image instead of the frame
+            let (tonemap_input_image, tonemap_descriptor_set) = match taa_images {
+                Some((motion_image, resolved_image, prev_resolved_image)) => {
+                    vk_controller.taa.as_ref().unwrap().add_pass(
+                        &mut graph,
+                        hdr_image,
+                        motion_image,
+                        prev_resolved_image,
+                        resolved_image,
+                        frame.taa_descriptor_set,
+                        extent,
+                        TaaPushConstants {
+                            alpha: anti_aliasing_settings.taa_alpha,
+                            history_valid: uniform_buffer_data.history_valid,
+                        },
                     );
+                    (resolved_image, frame.tonemap_descriptor_sets[RESOLVED_SET])
+                }
+                None => (hdr_image, frame.tonemap_descriptor_sets[HDR_SET]),
+            };
+
+            let display_settings = self.display_settings;
+            // the exposure of the frame is kept for when the debug view is left
+            let adaptation = if debugging {
+                0.0
+            } else {
+                1.0 - (-self.delta_time.as_secs_f32() * display_settings.adaptation_speed).exp()
+            };
+            let tonemap_operator = if debugging {
+                UNMAPPED_OPERATOR
+            } else {
+                display_settings.tonemapper.operator()
+            };
+
+            vk_controller.tonemapper.as_ref().unwrap().add_passes(
+                &mut graph,
+                tonemap_input_image,
+                tonemapped_image,
+                histogram_buffer,
+                exposure_buffer,
+                tonemap_descriptor_set,
+                frame.images.tonemap_framebuffer,
+                extent,
+                TonemapPushConstants {
+                    min_log_luminance: display_settings.min_log_luminance,
+                    log_luminance_range: display_settings.max_log_luminance
+                        - display_settings.min_log_luminance,
+                    adaptation,
+                    auto_exposure: display_settings.auto_exposure as u32,
+                    exposure_compensation: display_settings.exposure_compensation,
+                    tonemap_operator,
+                    output_transfer: vk_controller.output_transfer(),
+                    paper_white: display_settings.paper_white,
+                    peak_brightness: display_settings.peak_brightness,
+                },
+            );
 
-                self.vk_controller.device.cmd_pipeline_barrier(
-                    rt_command_buffer,
-                    vk::PipelineStageFlags::TRANSFER,
-                    vk::PipelineStageFlags::TRANSFER,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[image_barrier],
-                );
+            let render_scale_settings = self.render_scale_settings;
+            let output_extent = vk_controller.surface_resolution;
+            // the supersampled frame is larger than the output
+            let upscale_filter = match anti_aliasing_settings.mode {
+                AntiAliasing::Supersampling => BOX_FILTER,
+                AntiAliasing::Off | AntiAliasing::Taa => render_scale_settings.upscaler.filter(),
+            };
+
+            vk_controller.upscaler.as_ref().unwrap().add_passes(
+                &mut graph,
+                tonemapped_image,
+                output_image,
+                frame.upscale_descriptor_set,
+                frame.images.upscale_framebuffer,
+                output_extent,
+                UpscalePushConstants {
+                    input_size: glm::vec2(extent.width as f32, extent.height as f32),
+                    output_size: glm::vec2(output_extent.width as f32, output_extent.height as f32),
+                    upscale_filter,
+                    sharpness: render_scale_settings.sharpness,
+                    srgb_output: vk_controller.srgb_output() as u32,
+                },
+            );
+
+            graph.execute(&vk_controller.device, command_buffer);
+
+            self.vk_controller
+                .record_frame_end_timestamp(command_buffer);
+
+            self.vk_controller
+                .device
+                .end_command_buffer(command_buffer)
+                .expect("End commandbuffer");
+
+            let command_buffers = [command_buffer];
+            let mut wait_semaphores = vec![];
+            let mut wait_dst_stage_mask = vec![];
+            let mut wait_values = vec![];
+
+            let staging = self.vk_controller.staging.as_mut().unwrap();
+
+            if let Some(upload_value) = staging.take_wait_value() {
+                wait_semaphores.push(staging.timeline());
+                wait_dst_stage_mask.push(upload_dst_stages());
+                wait_values.push(upload_value);
+            }
+
+            let mut timeline_submit_info =
+                vk::TimelineSemaphoreSubmitInfo::default().wait_semaphore_values(&wait_values);
+
+            let submit_info = vk::SubmitInfo::default()
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_dst_stage_mask)
+                .command_buffers(&command_buffers)
+                .push_next(&mut timeline_submit_info);
+
+            self.vk_controller
+                .device
+                .queue_submit(
+                    self.vk_controller.present_queue,
+                    &[submit_info],
+                    vk::Fence::null(),
+                )
+                .expect("queue submit failed.");
+        }
+
+        if self.headless.is_some() {
+            self.finish_headless_sub_frame();
+            return;
+        }
+
+        // the swapchain image is only needed by the final copy, the tracing above is already
+        // queued when acquiring blocks
+        let frame = &self.vk_controller.frames[frame_index];
+
+        let acquire_result = unsafe {
+            self.vk_controller.swapchain_loader.acquire_next_image(
+                self.vk_controller.swapchain.unwrap(),
+                u64::MAX,
+                frame.image_available_semaphore,
+                vk::Fence::null(),
+            )
+        };
+
+        let present_index = match acquire_result {
+            Ok((present_index, _)) => present_index,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                // the frame fence must still signal before the frame resources are reused
+                unsafe {
+                    self.vk_controller
+                        .device
+                        .queue_submit(self.vk_controller.present_queue, &[], frame.in_flight_fence)
+                        .expect("queue submit failed.");
+                }
+
+                self.vk_controller.recreate_swapchain().unwrap();
+                self.vk_controller.end_frame();
+                return;
             }
+            Err(error) => panic!("Failed to acquire swapchain image: {}", error),
+        };
+
+        let current_swapchain_image =
+            self.vk_controller.present_images.as_ref().unwrap()[present_index as usize];
+        let rendering_complete_semaphore =
+            self.vk_controller.rendering_complete_semaphores[present_index as usize];
+        let present_command_buffer = frame.present_command_buffer;
+
+        unsafe {
+            self.vk_controller
+                .device
+                .reset_command_buffer(
+                    present_command_buffer,
+                    vk::CommandBufferResetFlags::RELEASE_RESOURCES,
+                )
+                .expect("Reset command buffer failed.");
+
+            let command_buffer_begin_info = vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+            self.vk_controller
+                .device
+                .begin_command_buffer(present_command_buffer, &command_buffer_begin_info)
+                .expect("Begin commandbuffer");
+
+            let vk_controller = &self.vk_controller;
+            let mut graph = RenderGraph::new();
+
+            // made visible to the copy at the end of the frame commands
+            let output_image = graph.import_image(
+                frame.images.output.image,
+                ResourceState {
+                    stage: vk::PipelineStageFlags::TRANSFER,
+                    access: vk::AccessFlags::empty(),
+                    layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                },
+                None,
+            );
+            // the acquire semaphore is waited on at the transfer stage
+            let swapchain_image = graph.import_image(
+                current_swapchain_image,
+                ResourceState {
+                    stage: vk::PipelineStageFlags::TRANSFER,
+                    access: vk::AccessFlags::empty(),
+                    layout: vk::ImageLayout::UNDEFINED,
+                },
+                Some(ResourceState {
+                    stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    access: vk::AccessFlags::empty(),
+                    layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                }),
+            );
+
+            graph.add_pass(
+                Pass::new("copy to swapchain")
+                    .image(output_image, Access::TransferRead)
+                    .image(swapchain_image, Access::TransferWrite)
+                    .record(|device, command_buffer, resources| {
+                        let copy_region = vk::ImageCopy::default()
+                            .src_subresource(
+                                vk::ImageSubresourceLayers::default()
+                                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                    .layer_count(1),
+                            )
+                            .dst_subresource(
+                                vk::ImageSubresourceLayers::default()
+                                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                    .layer_count(1),
+                            )
+                            .extent(
+                                vk::Extent3D::default()
+                                    .width(vk_controller.surface_resolution.width)
+                                    .height(vk_controller.surface_resolution.height)
+                                    .depth(1),
+                            );
+
+                        device.cmd_copy_image(
+                            command_buffer,
+                            resources.image(output_image),
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            resources.image(swapchain_image),
+                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            &[copy_region],
+                        );
+                    }),
+            );
+
+            graph.execute(&vk_controller.device, present_command_buffer);
 
             self.vk_controller
                 .device
-                .end_command_buffer(rt_command_buffer)
+                .end_command_buffer(present_command_buffer)
                 .expect("End commandbuffer");
 
-            let command_buffers = vec![rt_command_buffer];
-            let wait_semaphores = &[self.vk_controller.present_complete_semaphore];
-            let signal_semaphores = &[self.vk_controller.rendering_complete_semaphore];
+            let command_buffers = [present_command_buffer];
+            let wait_semaphores = [frame.image_available_semaphore];
+            let wait_dst_stage_mask = [vk::PipelineStageFlags::TRANSFER];
+            let signal_semaphores = [rendering_complete_semaphore];
 
             let submit_info = vk::SubmitInfo::default()
-                .wait_semaphores(wait_semaphores)
-                .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_dst_stage_mask)
                 .command_buffers(&command_buffers)
-                .signal_semaphores(signal_semaphores);
+                .signal_semaphores(&signal_semaphores);
 
             self.vk_controller
                 .device
                 .queue_submit(
                     self.vk_controller.present_queue,
                     &[submit_info],
-                    self.vk_controller.draw_commands_reuse_fence,
+                    frame.in_flight_fence,
                 )
                 .expect("queue submit failed.");
         }
 
-        let wait_semaphors = [self.vk_controller.rendering_complete_semaphore];
+        let wait_semaphors = [rendering_complete_semaphore];
         let swapchains = [self.vk_controller.swapchain.unwrap()];
         let image_indices = [present_index];
         let present_info = vk::PresentInfoKHR::default()
@@ -496,19 +1686,27 @@ impl AppBase<'_> {
             .swapchains(&swapchains)
             .image_indices(&image_indices);
 
+        self.vk_controller.end_frame();
+
         unsafe {
-            if let Err(code) = self
+            if let Err(vk::Result::ERROR_OUT_OF_DATE_KHR) = self
                 .vk_controller
                 .swapchain_loader
                 .queue_present(self.vk_controller.present_queue, &present_info)
             {
-                match code {
-                    vk::Result::ERROR_OUT_OF_DATE_KHR => {
-                        self.vk_controller.recreate_swapchain().unwrap()
-                    }
-                    _ => (),
-                }
+                self.vk_controller.recreate_swapchain().unwrap()
             }
         }
     }
 }
+
+impl Drop for AppBase<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            self.vk_controller.device.device_wait_idle().unwrap();
+        }
+        for transient_resources in &mut self.transient_resources {
+            transient_resources.destroy(&self.vk_controller.device);
+        }
+    }
+}
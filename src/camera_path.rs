@@ -0,0 +1,266 @@
+use bevy_math::Vec3;
+use bevy_transform::components::Transform;
+
+use crate::{
+    config::{CameraKeyframe, LensConfig},
+    uniform_types::CameraTransform,
+};
+
+#[derive(Clone, Copy, Debug)]
+struct Keyframe {
+    time: f32,
+    position: Vec3,
+    look_at: Vec3,
+    lens: LensConfig,
+}
+
+/// Camera moving through keyframes: the positions and the points looked at follow Catmull-Rom
+/// splines through them, the lens values are interpolated linearly and the blades step.
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    /// None without keyframes. The keyframes are sorted by time, the values of their lens left
+    /// out are carried over from the previous keyframe, starting from `lens`.
+    pub fn new(keyframes: &[CameraKeyframe], lens: LensConfig) -> Option<Self> {
+        let mut sorted = keyframes.to_vec();
+        sorted.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let mut lens = lens;
+        let keyframes: Vec<Keyframe> = sorted
+            .iter()
+            .map(|keyframe| {
+                lens = LensConfig {
+                    aperture: keyframe.aperture.unwrap_or(lens.aperture),
+                    focal_distance: keyframe.focal_distance.unwrap_or(lens.focal_distance),
+                    blades: keyframe.blades.unwrap_or(lens.blades),
+                    shutter: keyframe.shutter.unwrap_or(lens.shutter),
+                    ..lens
+                };
+
+                Keyframe {
+                    time: keyframe.time,
+                    position: Vec3::from(keyframe.position),
+                    look_at: Vec3::from(keyframe.look_at),
+                    lens,
+                }
+            })
+            .collect();
+
+        (!keyframes.is_empty()).then_some(Self { keyframes })
+    }
+
+    pub fn start(&self) -> f32 {
+        self.keyframes.first().unwrap().time
+    }
+
+    pub fn end(&self) -> f32 {
+        self.keyframes.last().unwrap().time
+    }
+
+    /// Camera at `time`, held at the first and the last keyframes outside of the path.
+    pub fn sample(&self, time: f32) -> CameraTransform {
+        let time = time.clamp(self.start(), self.end());
+        let last = self.keyframes.len() - 1;
+
+        // the segment from the keyframe `i` to the next one
+        let i = self
+            .keyframes
+            .iter()
+            .rposition(|keyframe| keyframe.time <= time)
+            .unwrap_or(0)
+            .min(last.saturating_sub(1));
+        let key = |index: isize| self.keyframes[index.clamp(0, last as isize) as usize];
+        let (previous, from, to, next) = (
+            key(i as isize - 1),
+            key(i as isize),
+            key(i as isize + 1),
+            key(i as isize + 2),
+        );
+
+        let duration = to.time - from.time;
+        let u = if duration > 0.0 {
+            ((time - from.time) / duration).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let position = catmull_rom(
+            previous.position,
+            from.position,
+            to.position,
+            next.position,
+            u,
+        );
+        let look_at = catmull_rom(previous.look_at, from.look_at, to.look_at, next.look_at, u);
+
+        let lerp = |a: f32, b: f32| a + (b - a) * u;
+        let lens = LensConfig {
+            aperture: lerp(from.lens.aperture, to.lens.aperture),
+            focal_distance: lerp(from.lens.focal_distance, to.lens.focal_distance),
+            shutter: lerp(from.lens.shutter, to.lens.shutter),
+            ..if u < 1.0 { from.lens } else { to.lens }
+        };
+
+        CameraTransform {
+            transform: Transform::from_translation(position).looking_at(look_at, Vec3::Y),
+            lens,
+        }
+    }
+}
+
+/// Uniform Catmull-Rom spline from `p1` to `p2` at `u` in [0, 1].
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, u: f32) -> Vec3 {
+    let u2 = u * u;
+    let u3 = u2 * u;
+
+    0.5 * (2.0 * p1
+        + (p2 - p0) * u
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * u2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * u3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, position: [f32; 3]) -> CameraKeyframe {
+        CameraKeyframe {
+            time,
+            position,
+            look_at: [0.0, 0.0, -100.0],
+            aperture: None,
+            focal_distance: None,
+            blades: None,
+            shutter: None,
+        }
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    #[test]
+    fn passes_through_the_keyframes() {
+        let keyframes = [
+            keyframe(0.0, [0.0, 0.0, 0.0]),
+            keyframe(1.0, [4.0, 1.0, 0.0]),
+            keyframe(3.0, [4.0, 5.0, 2.0]),
+            keyframe(4.0, [0.0, 2.0, 2.0]),
+        ];
+        let path = CameraPath::new(&keyframes, LensConfig::default()).unwrap();
+
+        for keyframe in keyframes {
+            let camera = path.sample(keyframe.time);
+            assert!(close(
+                camera.transform.translation,
+                Vec3::from(keyframe.position)
+            ));
+        }
+    }
+
+    #[test]
+    fn two_keyframes_are_a_line() {
+        let path = CameraPath::new(
+            &[
+                keyframe(2.0, [0.0, 0.0, 0.0]),
+                keyframe(4.0, [8.0, 0.0, 0.0]),
+            ],
+            LensConfig::default(),
+        )
+        .unwrap();
+
+        assert!(close(
+            path.sample(3.0).transform.translation,
+            Vec3::new(4.0, 0.0, 0.0)
+        ));
+        // held outside of the path
+        assert!(close(path.sample(0.0).transform.translation, Vec3::ZERO));
+        assert!(close(
+            path.sample(10.0).transform.translation,
+            Vec3::new(8.0, 0.0, 0.0)
+        ));
+    }
+
+    #[test]
+    fn looks_at_the_target() {
+        let path =
+            CameraPath::new(&[keyframe(0.0, [0.0, 0.0, 0.0])], LensConfig::default()).unwrap();
+
+        // the camera looks along -z
+        assert!(close(
+            path.sample(0.0).transform.forward().into(),
+            Vec3::new(0.0, 0.0, -1.0)
+        ));
+    }
+
+    #[test]
+    fn keyframes_are_sorted() {
+        let path = CameraPath::new(
+            &[
+                keyframe(1.0, [2.0, 0.0, 0.0]),
+                keyframe(0.0, [0.0, 0.0, 0.0]),
+            ],
+            LensConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(path.start(), 0.0);
+        assert_eq!(path.end(), 1.0);
+        assert!(close(
+            path.sample(0.5).transform.translation,
+            Vec3::new(1.0, 0.0, 0.0)
+        ));
+    }
+
+    #[test]
+    fn lens_values_carry_over_and_interpolate() {
+        let lens = LensConfig {
+            enabled: true,
+            aperture: 0.5,
+            focal_distance: 100.0,
+            blades: 6,
+            shutter: 0.5,
+        };
+        let path = CameraPath::new(
+            &[
+                CameraKeyframe {
+                    focal_distance: Some(10.0),
+                    ..keyframe(0.0, [0.0, 0.0, 0.0])
+                },
+                CameraKeyframe {
+                    aperture: Some(1.5),
+                    blades: Some(0),
+                    ..keyframe(1.0, [1.0, 0.0, 0.0])
+                },
+                keyframe(2.0, [2.0, 0.0, 0.0]),
+            ],
+            lens,
+        )
+        .unwrap();
+
+        let start = path.sample(0.0).lens;
+        assert_eq!(start.focal_distance, 10.0);
+        assert_eq!(start.aperture, 0.5);
+        assert!(start.enabled);
+
+        let middle = path.sample(0.5).lens;
+        assert!((middle.aperture - 1.0).abs() < 1e-6);
+        assert_eq!(middle.focal_distance, 10.0);
+        assert_eq!(middle.blades, 6);
+
+        // the second keyframe keeps the focal distance of the first one
+        let end = path.sample(2.0).lens;
+        assert_eq!(end.aperture, 1.5);
+        assert_eq!(end.focal_distance, 10.0);
+        assert_eq!(end.blades, 0);
+        assert_eq!(end.shutter, 0.5);
+    }
+
+    #[test]
+    fn no_keyframes_no_path() {
+        assert!(CameraPath::new(&[], LensConfig::default()).is_none());
+    }
+}
@@ -0,0 +1,694 @@
+use serde::Deserialize;
+
+const CONFIG_PATH: &str = "config.toml";
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub acceleration_structures: AccelerationStructureConfig,
+    pub shaders: ShaderConfig,
+    pub ao: AoConfig,
+    pub temporal: TemporalConfig,
+    pub path_tracing: PathTracingConfig,
+    pub sun: SunConfig,
+    pub sky: SkyConfig,
+    pub reflections: ReflectionConfig,
+    pub lights: LightsConfig,
+    pub denoiser: DenoiserConfig,
+    pub display: DisplayConfig,
+    pub render_scale: RenderScaleConfig,
+    pub anti_aliasing: AntiAliasingConfig,
+    pub lens: LensConfig,
+    pub camera_path: CameraPathConfig,
+    pub animation: AnimationConfig,
+    pub headless: HeadlessConfig,
+    pub fog: FogConfig,
+    pub debug: DebugConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AccelerationStructureConfig {
+    /// Build with `ALLOW_COMPACTION` and copy into a compacted structure afterwards.
+    pub compaction: bool,
+    /// Serialize built structures to disk and reuse them on the next launch.
+    pub cache: bool,
+    pub cache_directory: String,
+}
+
+impl Default for AccelerationStructureConfig {
+    fn default() -> Self {
+        Self {
+            compaction: true,
+            cache: true,
+            cache_directory: String::from("cache"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ShaderConfig {
+    /// Recompile the ray tracing shaders and rebuild the pipeline when `shaders/` changes.
+    pub hot_reload: bool,
+}
+
+impl Default for ShaderConfig {
+    fn default() -> Self {
+        Self { hot_reload: true }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct AoConfig {
+    /// Occlusion rays traced per pixel and frame.
+    pub samples_per_pixel: u32,
+    /// Length of the occlusion rays, farther geometry does not occlude.
+    pub ray_length: f32,
+    /// Distance in pixels between the taps of the filter, 0 disables it.
+    pub filter_radius: f32,
+    pub output: AoOutput,
+}
+
+impl Default for AoConfig {
+    fn default() -> Self {
+        Self {
+            samples_per_pixel: 4,
+            ray_length: 4.0,
+            filter_radius: 2.0,
+            output: AoOutput::Composite,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct TemporalConfig {
+    /// Frames accumulated at most, 1 disables the accumulation.
+    pub max_history: u32,
+    /// Relative depth difference above which a reprojected sample is rejected.
+    pub depth_tolerance: f32,
+    /// Camera movement in one frame treated as a cut, which discards the history.
+    pub camera_cut_distance: f32,
+}
+
+impl Default for TemporalConfig {
+    fn default() -> Self {
+        Self {
+            max_history: 32,
+            depth_tolerance: 0.05,
+            camera_cut_distance: 8.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct PathTracingConfig {
+    /// Start in the path tracing mode instead of the AO preview.
+    pub enabled: bool,
+    /// Bounces before a path is cut, Russian roulette usually ends them earlier.
+    pub max_bounces: u32,
+    /// Samples traced per pixel and frame.
+    pub samples_per_pixel: u32,
+    /// Samples per pixel after which the accumulation stops, 0 never stops.
+    pub max_samples: u32,
+}
+
+impl Default for PathTracingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_bounces: 8,
+            samples_per_pixel: 1,
+            max_samples: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct SunConfig {
+    /// Hours since midnight at launch, the sun rises at 6 and sets at 18.
+    pub time_of_day: f32,
+    /// Real seconds for a full day, 0 stops the time.
+    pub day_duration: f32,
+    /// Angle in degrees between the path of the sun and the zenith.
+    pub latitude: f32,
+    /// Rotation in degrees of the sunrise direction around the up axis, 0 rises towards +x.
+    pub azimuth: f32,
+    /// Scale of the sun light at noon, the moon is much dimmer.
+    pub intensity: f32,
+}
+
+impl Default for SunConfig {
+    fn default() -> Self {
+        Self {
+            time_of_day: 10.0,
+            day_duration: 0.0,
+            latitude: 35.0,
+            azimuth: 30.0,
+            intensity: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SkyConfig {
+    /// Haziness of the analytic sky, from 2 for a clear day to 10 for a hazy one.
+    pub turbidity: f32,
+    /// Equirectangular Radiance `.hdr` image replacing the analytic sky. The sun still follows
+    /// the time of day.
+    pub environment_map: Option<String>,
+    /// Scale of the environment map radiance.
+    pub environment_intensity: f32,
+    /// Rotation in degrees of the environment map around the up axis.
+    pub environment_rotation: f32,
+}
+
+impl Default for SkyConfig {
+    fn default() -> Self {
+        Self {
+            turbidity: 2.5,
+            environment_map: None,
+            environment_intensity: 1.0,
+            environment_rotation: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct ReflectionConfig {
+    /// Reflection bounces traced in the AO preview, 0 disables the reflections. The bounces are
+    /// traced in a loop, the device ray recursion limit does not apply.
+    pub max_depth: u32,
+}
+
+impl Default for ReflectionConfig {
+    fn default() -> Self {
+        Self { max_depth: 3 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct LightsConfig {
+    /// Emissive voxels drawn per pixel and frame, of which one lights the pixel in the AO
+    /// preview.
+    pub candidates: u32,
+    /// Reuse the light samples of the previous frame and of the neighbouring pixels (ReSTIR).
+    pub restir: bool,
+    /// Neighbouring pixels whose samples are reused.
+    pub spatial_samples: u32,
+    /// Distance in pixels of the reused neighbours.
+    pub spatial_radius: f32,
+    /// Samples carried over from the previous frames, in frames worth of candidates.
+    pub max_history: u32,
+}
+
+impl Default for LightsConfig {
+    fn default() -> Self {
+        Self {
+            candidates: 8,
+            restir: true,
+            spatial_samples: 4,
+            spatial_radius: 16.0,
+            max_history: 20,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct DenoiserConfig {
+    /// Denoise the path traced radiance.
+    pub path_tracing: bool,
+    /// Denoise the occlusion of the AO preview, in place of its low-pass filter.
+    pub ao: bool,
+    /// Wavelet iterations, the taps of the last one are `1 << iterations` pixels away at most.
+    pub iterations: u32,
+    /// Weight of the new frame in the accumulated signal.
+    pub alpha: f32,
+    /// Weight of the new frame in the accumulated moments, which estimate the variance.
+    pub moments_alpha: f32,
+    /// Luminance difference, in standard deviations, past which the wavelets stop.
+    pub phi_color: f32,
+    /// Sharpness of the edges between normals.
+    pub phi_normal: f32,
+    /// Relative depth difference past which the wavelets stop.
+    pub phi_depth: f32,
+    /// Albedo difference past which the wavelets stop, between textures of the surfaces.
+    pub phi_albedo: f32,
+}
+
+impl Default for DenoiserConfig {
+    fn default() -> Self {
+        Self {
+            path_tracing: true,
+            ao: false,
+            iterations: 5,
+            alpha: 0.2,
+            moments_alpha: 0.2,
+            phi_color: 4.0,
+            phi_normal: 128.0,
+            phi_depth: 0.1,
+            phi_albedo: 0.1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct DisplayConfig {
+    /// Operator mapping the HDR radiance to the SDR output.
+    pub tonemapper: TonemapOperator,
+    /// Adapt the exposure to the average luminance of the frame.
+    pub auto_exposure: bool,
+    /// Exposure compensation in stops, on top of the automatic exposure.
+    pub exposure_compensation: f32,
+    /// Speed at which the exposure adapts, per second.
+    pub adaptation_speed: f32,
+    /// Range of the luminance histogram, in log2 of the luminance.
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    /// Present in HDR10 (Rec. 2020 primaries, PQ transfer) when the surface supports it.
+    pub hdr10: bool,
+    /// Brightness of a diffuse white in HDR10, in nits.
+    pub paper_white: f32,
+    /// Brightness the HDR10 output rolls off to, in nits.
+    pub peak_brightness: f32,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            tonemapper: TonemapOperator::Aces,
+            auto_exposure: true,
+            exposure_compensation: 0.0,
+            adaptation_speed: 1.5,
+            min_log_luminance: -8.0,
+            max_log_luminance: 8.0,
+            hdr10: false,
+            paper_white: 200.0,
+            peak_brightness: 1000.0,
+        }
+    }
+}
+
+/// Tonemapping operator of the SDR output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TonemapOperator {
+    Aces,
+    Agx,
+    Reinhard,
+}
+
+impl TonemapOperator {
+    /// Value of `TonemapPushConstants::tonemap_operator`.
+    pub fn operator(self) -> u32 {
+        match self {
+            TonemapOperator::Aces => 0,
+            TonemapOperator::Agx => 1,
+            TonemapOperator::Reinhard => 2,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            TonemapOperator::Aces => TonemapOperator::Agx,
+            TonemapOperator::Agx => TonemapOperator::Reinhard,
+            TonemapOperator::Reinhard => TonemapOperator::Aces,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct RenderScaleConfig {
+    /// Fraction of the window resolution the frame is rendered at.
+    pub scale: f32,
+    /// Filter upscaling the rendered frame to the window.
+    pub upscaler: UpscaleFilter,
+    /// Sharpening of the FSR upscaler in stops, 0 is the sharpest.
+    pub sharpness: f32,
+    /// Adjust the scale to keep the GPU time of a frame under `target_frame_time`.
+    pub dynamic: bool,
+    /// In milliseconds.
+    pub target_frame_time: f32,
+    /// Range of the dynamic scale.
+    pub min_scale: f32,
+    pub max_scale: f32,
+}
+
+impl Default for RenderScaleConfig {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            upscaler: UpscaleFilter::Fsr,
+            sharpness: 0.25,
+            dynamic: false,
+            target_frame_time: 16.0,
+            min_scale: 0.5,
+            max_scale: 1.0,
+        }
+    }
+}
+
+/// Filter upscaling the rendered frame to the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpscaleFilter {
+    Bilinear,
+    /// Edge adaptive spatial upsampling followed by contrast adaptive sharpening, as in AMD
+    /// FidelityFX Super Resolution 1.
+    Fsr,
+}
+
+impl UpscaleFilter {
+    /// Value of `UpscalePushConstants::upscale_filter`.
+    pub fn filter(self) -> u32 {
+        match self {
+            UpscaleFilter::Bilinear => 0,
+            UpscaleFilter::Fsr => 1,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            UpscaleFilter::Bilinear => UpscaleFilter::Fsr,
+            UpscaleFilter::Fsr => UpscaleFilter::Bilinear,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct AntiAliasingConfig {
+    pub mode: AntiAliasing,
+    /// Weight of the new frame in the TAA history.
+    pub taa_alpha: f32,
+    /// Points of the Halton sequence the primary rays are jittered by before it repeats.
+    pub jitter_samples: u32,
+    /// Rays per pixel along each axis in the supersampling mode, which renders at this
+    /// multiple of the window resolution.
+    pub supersampling: u32,
+}
+
+impl Default for AntiAliasingConfig {
+    fn default() -> Self {
+        Self {
+            mode: AntiAliasing::Taa,
+            taa_alpha: 0.1,
+            jitter_samples: 8,
+            supersampling: 2,
+        }
+    }
+}
+
+impl AntiAliasingConfig {
+    /// Render scale of `mode`, the supersampling factor overrides `scale`.
+    pub fn render_scale(&self, mode: AntiAliasing, scale: f32) -> f32 {
+        match mode {
+            AntiAliasing::Supersampling => self.supersampling.max(1) as f32,
+            AntiAliasing::Off | AntiAliasing::Taa => scale,
+        }
+    }
+}
+
+/// Thin lens and shutter of the camera, for depth of field and motion blur.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LensConfig {
+    /// Start with the depth of field and the motion blur.
+    pub enabled: bool,
+    /// Radius of the lens, larger apertures blur more out of the focal plane.
+    pub aperture: f32,
+    /// Distance from the camera of the plane in focus.
+    pub focal_distance: f32,
+    /// Sides of the aperture, which shapes the bokeh. Below 3 the aperture is round.
+    pub blades: u32,
+    /// Part of the interval between two frames the shutter is open for, 0 disables the motion
+    /// blur.
+    pub shutter: f32,
+}
+
+impl Default for LensConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            aperture: 0.5,
+            focal_distance: 100.0,
+            blades: 6,
+            shutter: 0.5,
+        }
+    }
+}
+
+/// Keyframes of a camera path, played with C and rendered by the headless mode.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CameraPathConfig {
+    pub keyframes: Vec<CameraKeyframe>,
+}
+
+/// Camera at a time of the path. The lens values left out keep those of the previous keyframe,
+/// or of `[lens]` for the first one.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct CameraKeyframe {
+    /// Seconds since the start of the path.
+    pub time: f32,
+    pub position: [f32; 3],
+    /// Point the camera looks at.
+    pub look_at: [f32; 3],
+    pub aperture: Option<f32>,
+    pub focal_distance: Option<f32>,
+    pub blades: Option<u32>,
+    pub shutter: Option<f32>,
+}
+
+/// Moving instances of the scene.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct AnimationConfig {
+    /// Start with a copy of a voxel circling the model, toggled with I.
+    pub orbiting_voxel: bool,
+    /// Seconds the orbiting voxel takes to circle the model.
+    pub orbit_period: f32,
+}
+
+impl Default for AnimationConfig {
+    fn default() -> Self {
+        Self {
+            orbiting_voxel: false,
+            orbit_period: 12.0,
+        }
+    }
+}
+
+/// Offline render of the camera path with the path tracer, started with `--headless`. The
+/// window is hidden but still created along with its swapchain, so a display is required.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct HeadlessConfig {
+    /// Set by the `--headless` argument rather than by the file.
+    #[serde(skip)]
+    pub enabled: bool,
+    /// Directory the frames are written to, as binary PPM images.
+    pub output_directory: String,
+    pub frames_per_second: f32,
+    /// Path traced samples accumulated for every frame, spread over the time the shutter is open.
+    pub samples_per_pixel: u32,
+}
+
+impl Default for HeadlessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            output_directory: String::from("renders"),
+            frames_per_second: 24.0,
+            samples_per_pixel: 256,
+        }
+    }
+}
+
+/// Height fog, and the media voxels the fog march also crosses.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct FogConfig {
+    /// Start with the fog, its steps are not marched otherwise.
+    pub enabled: bool,
+    /// Extinction per unit of length of the height fog below `height`, 0 leaves only the media
+    /// voxels.
+    pub density: f32,
+    /// Height up to which the density of the fog is constant.
+    pub height: f32,
+    /// Rate at which the density decreases above `height`, 0 for a homogeneous fog.
+    pub height_falloff: f32,
+    /// Color of the light scattered by the height fog.
+    pub albedo: [f32; 3],
+    /// Henyey-Greenstein asymmetry of the scattering, from -1 backwards to 1 forwards.
+    pub anisotropy: f32,
+    /// Samples marched along every piece of the primary rays, each with a shadow ray towards the
+    /// sun.
+    pub steps: u32,
+}
+
+impl Default for FogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            density: 0.005,
+            height: 40.0,
+            height_falloff: 0.1,
+            albedo: [0.9, 0.9, 0.9],
+            anisotropy: 0.5,
+            steps: 8,
+        }
+    }
+}
+
+/// Debug views of the primary hits, drawn instead of the frame.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct DebugConfig {
+    /// View to start with, cycled with V.
+    pub view: DebugView,
+    /// Depth along the view axis shown as white in the depth view.
+    pub max_depth: f32,
+    /// Candidate hits of a primary ray shown as red in the traversal cost view, white above.
+    pub max_cost: u32,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            view: DebugView::Off,
+            max_depth: 500.0,
+            max_cost: 32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DebugView {
+    Off,
+    Normals,
+    /// Linear depth along the view axis.
+    Depth,
+    /// Colors of the faces by their hit kind, blinking where it is unknown.
+    HitKind,
+    InstanceId,
+    PrimitiveId,
+    PaletteIndex,
+    /// Candidate hits found by the traversal of the primary ray, a proxy of its cost.
+    TraversalCost,
+}
+
+impl DebugView {
+    /// Value of `TracePushConstants::debug_view`.
+    pub fn view(self) -> u32 {
+        match self {
+            DebugView::Off => 0,
+            DebugView::Normals => 1,
+            DebugView::Depth => 2,
+            DebugView::HitKind => 3,
+            DebugView::InstanceId => 4,
+            DebugView::PrimitiveId => 5,
+            DebugView::PaletteIndex => 6,
+            DebugView::TraversalCost => 7,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            DebugView::Off => DebugView::Normals,
+            DebugView::Normals => DebugView::Depth,
+            DebugView::Depth => DebugView::HitKind,
+            DebugView::HitKind => DebugView::InstanceId,
+            DebugView::InstanceId => DebugView::PrimitiveId,
+            DebugView::PrimitiveId => DebugView::PaletteIndex,
+            DebugView::PaletteIndex => DebugView::TraversalCost,
+            DebugView::TraversalCost => DebugView::Off,
+        }
+    }
+}
+
+/// Anti-aliasing of the voxel edges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AntiAliasing {
+    Off,
+    /// Jitters the primary rays every frame and resolves them against the reprojected history.
+    Taa,
+    /// Traces several rays per pixel and averages them, for offline renders.
+    Supersampling,
+}
+
+impl AntiAliasing {
+    pub fn next(self) -> Self {
+        match self {
+            AntiAliasing::Off => AntiAliasing::Taa,
+            AntiAliasing::Taa => AntiAliasing::Supersampling,
+            AntiAliasing::Supersampling => AntiAliasing::Off,
+        }
+    }
+}
+
+/// What the AO filter writes to the frame output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AoOutput {
+    Occlusion,
+    /// The albedo lit by the sun and by the sky, dimmed by the occlusion.
+    Composite,
+    Albedo,
+}
+
+impl AoOutput {
+    /// Value of `FilterPushConstants::output_mode`.
+    pub fn output_mode(self) -> u32 {
+        match self {
+            AoOutput::Occlusion => 0,
+            AoOutput::Composite => 1,
+            AoOutput::Albedo => 2,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            AoOutput::Occlusion => AoOutput::Composite,
+            AoOutput::Composite => AoOutput::Albedo,
+            AoOutput::Albedo => AoOutput::Occlusion,
+        }
+    }
+}
+
+impl Config {
+    /// Reads `config.toml` from the working directory, falling back to defaults when it is
+    /// missing or invalid, then applies the command line arguments.
+    pub fn load() -> Self {
+        let mut config = match std::fs::read_to_string(CONFIG_PATH) {
+            Ok(source) => match toml::from_str(&source) {
+                Ok(config) => config,
+                Err(error) => {
+                    println!("Invalid {}, using defaults: {}", CONFIG_PATH, error);
+                    Config::default()
+                }
+            },
+            Err(_) => Config::default(),
+        };
+
+        if std::env::args().any(|argument| argument == "--headless") {
+            config.headless.enabled = true;
+            // the frames are written with 8 bits per channel
+            config.display.hdr10 = false;
+        }
+
+        config
+    }
+}
@@ -0,0 +1,170 @@
+//! Schedule of the headless renders of the camera path.
+//!
+//! Headless only means the window is never shown: the renderer still creates it, with its
+//! surface and swapchain, and submits the frames on the present queue, it just does not present
+//! them. A display and a device able to present to it are still required.
+
+/// Part of the interval between the previous frame (0) and the current one (1) a sub-frame is
+/// traced over.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShutterInterval {
+    pub open: f32,
+    pub close: f32,
+}
+
+impl ShutterInterval {
+    pub fn middle(&self) -> f32 {
+        (self.open + self.close) * 0.5
+    }
+}
+
+/// Frames of a headless render of the camera path and the sub-frames each one is accumulated
+/// over. Every sub-frame traces the samples of one dispatch of the path tracer through its own
+/// piece of the shutter interval, so the instances blur even though the acceleration structure
+/// holds them at a single time per sub-frame.
+#[derive(Clone, Copy, Debug)]
+pub struct HeadlessSchedule {
+    start: f32,
+    frames_per_second: f32,
+    pub frame_count: u32,
+    pub sub_frame_count: u32,
+    pub frame: u32,
+    pub sub_frame: u32,
+}
+
+impl HeadlessSchedule {
+    /// Frames from `start` to `end` seconds, each of `samples_per_pixel` samples traced
+    /// `samples_per_sub_frame` at a time.
+    pub fn new(
+        start: f32,
+        end: f32,
+        frames_per_second: f32,
+        samples_per_pixel: u32,
+        samples_per_sub_frame: u32,
+    ) -> Self {
+        let frames_per_second = frames_per_second.max(f32::EPSILON);
+
+        Self {
+            start,
+            frames_per_second,
+            frame_count: ((end - start).max(0.0) * frames_per_second).floor() as u32 + 1,
+            sub_frame_count: samples_per_pixel
+                .div_ceil(samples_per_sub_frame.max(1))
+                .max(1),
+            frame: 0,
+            sub_frame: 0,
+        }
+    }
+
+    /// Seconds between two frames.
+    pub fn frame_interval(&self) -> f32 {
+        1.0 / self.frames_per_second
+    }
+
+    /// Time of the current frame, on the clock of the camera path.
+    pub fn frame_time(&self) -> f32 {
+        self.start + self.frame as f32 * self.frame_interval()
+    }
+
+    /// Time at `fraction` of the interval from the previous frame to the current one.
+    pub fn time_at(&self, fraction: f32) -> f32 {
+        self.frame_time() - (1.0 - fraction) * self.frame_interval()
+    }
+
+    /// Piece of the interval the current sub-frame is traced over. The shutter closes at the
+    /// frame and stays open for the part `shutter` of the interval, like the real time camera.
+    pub fn shutter_interval(&self, shutter: f32) -> ShutterInterval {
+        let shutter = shutter.clamp(0.0, 1.0);
+        let count = self.sub_frame_count as f32;
+        let index = self.sub_frame as f32;
+
+        ShutterInterval {
+            open: 1.0 - shutter * (1.0 - index / count),
+            close: 1.0 - shutter * (1.0 - (index + 1.0) / count),
+        }
+    }
+
+    pub fn first_sub_frame(&self) -> bool {
+        self.sub_frame == 0
+    }
+
+    pub fn last_sub_frame(&self) -> bool {
+        self.sub_frame + 1 == self.sub_frame_count
+    }
+
+    /// Moves to the next sub-frame, false once the last frame is done.
+    pub fn advance(&mut self) -> bool {
+        self.sub_frame += 1;
+
+        if self.sub_frame == self.sub_frame_count {
+            self.sub_frame = 0;
+            self.frame += 1;
+        }
+
+        self.frame < self.frame_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_the_frames_and_sub_frames() {
+        let schedule = HeadlessSchedule::new(1.0, 3.0, 24.0, 100, 16);
+
+        // both ends of the path are rendered
+        assert_eq!(schedule.frame_count, 49);
+        assert_eq!(schedule.sub_frame_count, 7);
+
+        // a still, a single dispatch
+        let still = HeadlessSchedule::new(0.0, 0.0, 24.0, 4, 16);
+        assert_eq!(still.frame_count, 1);
+        assert_eq!(still.sub_frame_count, 1);
+    }
+
+    #[test]
+    fn advances_through_every_sub_frame() {
+        let mut schedule = HeadlessSchedule::new(0.0, 1.0, 2.0, 8, 4);
+        let mut visited = vec![(schedule.frame, schedule.sub_frame)];
+
+        while schedule.advance() {
+            visited.push((schedule.frame, schedule.sub_frame));
+        }
+
+        assert_eq!(visited, [(0, 0), (0, 1), (1, 0), (1, 1), (2, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn times_follow_the_frames() {
+        let mut schedule = HeadlessSchedule::new(2.0, 4.0, 4.0, 1, 1);
+        schedule.advance();
+        schedule.advance();
+
+        assert_eq!(schedule.frame_time(), 2.5);
+        assert_eq!(schedule.time_at(1.0), 2.5);
+        assert_eq!(schedule.time_at(0.0), 2.25);
+    }
+
+    #[test]
+    fn sub_frames_split_the_open_shutter() {
+        let mut schedule = HeadlessSchedule::new(0.0, 1.0, 24.0, 16, 4);
+        let mut intervals = vec![schedule.shutter_interval(0.5)];
+
+        while !schedule.last_sub_frame() {
+            schedule.advance();
+            intervals.push(schedule.shutter_interval(0.5));
+        }
+
+        assert_eq!(intervals.first().unwrap().open, 0.5);
+        assert_eq!(intervals.last().unwrap().close, 1.0);
+
+        for pair in intervals.windows(2) {
+            assert_eq!(pair[0].close, pair[1].open);
+        }
+
+        // a closed shutter traces every sub-frame at the frame
+        let closed = schedule.shutter_interval(0.0);
+        assert_eq!((closed.open, closed.close), (1.0, 1.0));
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use ash::vk;
+
+const MAGIC: &[u8; 4] = b"ASRT";
+const FORMAT_VERSION: u32 = 2;
+// magic, version and count of the bottom level addresses that follow
+const FILE_HEADER_SIZE: usize = 16;
+
+// Layout of the header written by vkCmdCopyAccelerationStructureToMemoryKHR
+const VERSION_DATA_SIZE: usize = 2 * vk::UUID_SIZE;
+const SERIALIZED_SIZE_OFFSET: usize = VERSION_DATA_SIZE;
+const DESERIALIZED_SIZE_OFFSET: usize = SERIALIZED_SIZE_OFFSET + 8;
+const HANDLE_COUNT_OFFSET: usize = DESERIALIZED_SIZE_OFFSET + 8;
+const HANDLES_OFFSET: usize = HANDLE_COUNT_OFFSET + 8;
+
+/// Directory of serialized acceleration structures, one file per key.
+pub struct AccelerationStructureCache {
+    directory: PathBuf,
+}
+
+impl AccelerationStructureCache {
+    pub fn new(directory: impl AsRef<Path>) -> Self {
+        Self {
+            directory: directory.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Builds a stable file name from a label and the bytes the structure was built from.
+    pub fn key(label: &str, inputs: &[u8]) -> String {
+        // FNV-1a, stable across runs and compiler versions unlike DefaultHasher
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in inputs {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+
+        format!("{}_{:016x}", label, hash)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{}.bin", key))
+    }
+
+    pub fn load(&self, key: &str) -> Option<CacheEntry> {
+        let data = fs::read(self.path(key)).ok()?;
+
+        let entry = CacheEntry::parse(data);
+        if entry.is_none() {
+            println!(
+                "Ignoring invalid acceleration structure cache entry {}",
+                key
+            );
+        }
+
+        entry
+    }
+
+    /// `bottom_level_addresses` are the addresses of the bottom level structures a top level
+    /// one was built from, in the order the caller will give them back to
+    /// `patch_bottom_level_handles`.
+    pub fn store(
+        &self,
+        key: &str,
+        bottom_level_addresses: &[vk::DeviceAddress],
+        serialized: &[u8],
+    ) -> std::io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+
+        let mut file = fs::File::create(self.path(key))?;
+        file.write_all(MAGIC)?;
+        file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        file.write_all(&(bottom_level_addresses.len() as u64).to_le_bytes())?;
+        for address in bottom_level_addresses {
+            file.write_all(&address.to_le_bytes())?;
+        }
+        file.write_all(serialized)?;
+
+        Ok(())
+    }
+}
+
+/// A cached structure and the addresses its bottom level structures had when it was stored.
+pub struct CacheEntry {
+    pub bottom_level_addresses: Vec<vk::DeviceAddress>,
+    pub serialized: Vec<u8>,
+}
+
+impl CacheEntry {
+    fn parse(mut data: Vec<u8>) -> Option<Self> {
+        if data.len() < FILE_HEADER_SIZE
+            || &data[0..4] != MAGIC
+            || u32::from_le_bytes(data[4..8].try_into().unwrap()) != FORMAT_VERSION
+        {
+            return None;
+        }
+
+        let address_count = u64::from_le_bytes(data[8..16].try_into().unwrap());
+        let addresses_end = address_count
+            .checked_mul(8)?
+            .checked_add(FILE_HEADER_SIZE as u64)?;
+        if addresses_end > data.len() as u64 {
+            return None;
+        }
+
+        let serialized = data.split_off(addresses_end as usize);
+        let bottom_level_addresses = data[FILE_HEADER_SIZE..]
+            .chunks_exact(8)
+            .map(|address| u64::from_le_bytes(address.try_into().unwrap()))
+            .collect();
+
+        SerializedHeader::parse(&serialized)?;
+
+        Some(CacheEntry {
+            bottom_level_addresses,
+            serialized,
+        })
+    }
+}
+
+/// Fixed part of a serialized acceleration structure, as defined by the Vulkan spec.
+pub struct SerializedHeader {
+    pub version_data: [u8; VERSION_DATA_SIZE],
+    pub serialized_size: u64,
+    pub deserialized_size: u64,
+    pub handle_count: u64,
+}
+
+impl SerializedHeader {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < HANDLES_OFFSET {
+            return None;
+        }
+
+        let read_u64 =
+            |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+
+        let header = SerializedHeader {
+            version_data: data[0..VERSION_DATA_SIZE].try_into().unwrap(),
+            serialized_size: read_u64(SERIALIZED_SIZE_OFFSET),
+            deserialized_size: read_u64(DESERIALIZED_SIZE_OFFSET),
+            handle_count: read_u64(HANDLE_COUNT_OFFSET),
+        };
+
+        let handles_end = header
+            .handle_count
+            .checked_mul(8)?
+            .checked_add(HANDLES_OFFSET as u64)?;
+        if header.serialized_size != data.len() as u64 || handles_end > header.serialized_size {
+            return None;
+        }
+
+        Some(header)
+    }
+}
+
+/// Top level structures store the addresses of the bottom level structures they reference.
+/// Those addresses change between runs, so every handle is rewritten from the address its
+/// structure had in `stored` to the address at the same index in `current` before
+/// deserialization. Returns false when the handles do not belong to the stored structures or
+/// the number of structures changed, the entry must be rebuilt then.
+pub fn patch_bottom_level_handles(
+    serialized: &mut [u8],
+    stored: &[vk::DeviceAddress],
+    current: &[vk::DeviceAddress],
+) -> bool {
+    let Some(header) = SerializedHeader::parse(serialized) else {
+        return false;
+    };
+
+    if stored.len() != current.len() {
+        return false;
+    }
+
+    for i in 0..header.handle_count as usize {
+        let offset = HANDLES_OFFSET + i * 8;
+        let handle = u64::from_le_bytes(serialized[offset..offset + 8].try_into().unwrap());
+
+        let Some(index) = stored.iter().position(|address| *address == handle) else {
+            return false;
+        };
+
+        serialized[offset..offset + 8].copy_from_slice(&current[index].to_le_bytes());
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serialized structure referencing `handles`, followed by some opaque bytes.
+    fn serialized(handles: &[vk::DeviceAddress]) -> Vec<u8> {
+        let size = HANDLES_OFFSET + handles.len() * 8 + 16;
+
+        let mut data = vec![7; VERSION_DATA_SIZE];
+        data.extend((size as u64).to_le_bytes());
+        data.extend(1024u64.to_le_bytes());
+        data.extend((handles.len() as u64).to_le_bytes());
+        for handle in handles {
+            data.extend(handle.to_le_bytes());
+        }
+        data.extend([0xAB; 16]);
+
+        data
+    }
+
+    fn handles(serialized: &[u8]) -> Vec<vk::DeviceAddress> {
+        let header = SerializedHeader::parse(serialized).unwrap();
+
+        (0..header.handle_count as usize)
+            .map(|i| {
+                let offset = HANDLES_OFFSET + i * 8;
+                u64::from_le_bytes(serialized[offset..offset + 8].try_into().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn patches_every_handle_to_its_own_structure() {
+        let mut data = serialized(&[0x2000, 0x1000, 0x2000, 0x3000]);
+
+        assert!(patch_bottom_level_handles(
+            &mut data,
+            &[0x1000, 0x2000, 0x3000],
+            &[0xA000, 0xB000, 0xC000]
+        ));
+        assert_eq!(handles(&data), [0xB000, 0xA000, 0xB000, 0xC000]);
+        assert_eq!(data[data.len() - 16..], [0xAB; 16]);
+    }
+
+    #[test]
+    fn rejects_other_structures() {
+        let original = serialized(&[0x1000, 0x4000]);
+
+        // a handle of an unknown structure
+        let mut data = original.clone();
+        assert!(!patch_bottom_level_handles(
+            &mut data,
+            &[0x1000, 0x2000],
+            &[0xA000, 0xB000]
+        ));
+
+        // a structure more or less than when it was cached
+        let mut data = original.clone();
+        assert!(!patch_bottom_level_handles(
+            &mut data,
+            &[0x1000, 0x4000],
+            &[0xA000]
+        ));
+
+        // a handle count past the end of the data
+        let mut data = original.clone();
+        data[HANDLE_COUNT_OFFSET..HANDLE_COUNT_OFFSET + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(!patch_bottom_level_handles(
+            &mut data,
+            &[0x1000, 0x4000],
+            &[0xA000, 0xB000]
+        ));
+
+        // not a serialized structure
+        let mut data = original[..HANDLES_OFFSET].to_vec();
+        assert!(!patch_bottom_level_handles(&mut data, &[], &[]));
+    }
+
+    #[test]
+    fn stores_the_bottom_level_addresses() {
+        let directory = std::env::temp_dir().join(format!("as_cache_test_{}", std::process::id()));
+        let cache = AccelerationStructureCache::new(&directory);
+        let key = AccelerationStructureCache::key("tlas", b"instances");
+
+        assert!(cache.load(&key).is_none());
+
+        let data = serialized(&[0x1000, 0x2000]);
+        cache.store(&key, &[0x2000, 0x1000], &data).unwrap();
+        let entry = cache.load(&key).unwrap();
+
+        assert_eq!(entry.bottom_level_addresses, [0x2000, 0x1000]);
+        assert_eq!(entry.serialized, data);
+
+        // truncated files are ignored
+        let path = cache.path(&key);
+        let file = fs::read(&path).unwrap();
+        fs::write(&path, &file[..file.len() - 1]).unwrap();
+        assert!(cache.load(&key).is_none());
+        fs::write(&path, &file[..FILE_HEADER_SIZE + 8]).unwrap();
+        assert!(cache.load(&key).is_none());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
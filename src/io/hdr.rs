@@ -0,0 +1,158 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail};
+
+/// Linear RGB image read from a Radiance `.hdr` file, rows from top to bottom.
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    /// RGB with an unused alpha of 1, to upload as `R32G32B32A32_SFLOAT`.
+    pub pixels: Vec<[f32; 4]>,
+}
+
+/// Reads a Radiance RGBE image, flat or with the run length encoded scanlines most tools write.
+pub fn load_hdr(path: impl AsRef<Path>) -> anyhow::Result<HdrImage> {
+    let data = fs::read(path.as_ref())?;
+    let mut position = 0;
+
+    let mut next_line = |data: &[u8]| -> anyhow::Result<String> {
+        let end = data[position..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .ok_or_else(|| anyhow!("truncated header"))?;
+        let line = String::from_utf8_lossy(&data[position..position + end]).into_owned();
+        position += end + 1;
+        Ok(line)
+    };
+
+    let signature = next_line(&data)?;
+    if !signature.starts_with("#?") {
+        bail!("not a Radiance HDR file");
+    }
+
+    // variables until an empty line, then the resolution
+    loop {
+        let line = next_line(&data)?;
+
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                bail!("unsupported pixel format {}", format);
+            }
+        }
+    }
+
+    let resolution = next_line(&data)?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (height.parse::<u32>()?, width.parse::<u32>()?),
+        _ => bail!("unsupported image orientation {}", resolution),
+    };
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    let mut data = &data[position..];
+
+    for _ in 0..height {
+        data = read_scanline(data, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_rgba(rgbe)));
+    }
+
+    Ok(HdrImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Decodes one scanline into `scanline`, returns the data after it.
+fn read_scanline<'a>(data: &'a [u8], scanline: &mut [[u8; 4]]) -> anyhow::Result<&'a [u8]> {
+    let width = scanline.len();
+
+    let run_length_encoded = (8..0x8000).contains(&width)
+        && data.len() >= 4
+        && data[0] == 2
+        && data[1] == 2
+        && data[2] & 0x80 == 0;
+
+    if !run_length_encoded {
+        let size = width * 4;
+        if data.len() < size {
+            bail!("truncated pixel data");
+        }
+
+        for (pixel, rgbe) in scanline.iter_mut().zip(data.chunks_exact(4)) {
+            pixel.copy_from_slice(rgbe);
+        }
+
+        return Ok(&data[size..]);
+    }
+
+    if usize::from(data[2]) << 8 | usize::from(data[3]) != width {
+        bail!("scanline width mismatch");
+    }
+
+    // the four components are encoded one after the other, as runs or literal bytes
+    let mut data = &data[4..];
+
+    for component in 0..4 {
+        let mut x = 0;
+
+        while x < width {
+            let (&count, rest) = data
+                .split_first()
+                .ok_or_else(|| anyhow!("truncated pixel data"))?;
+
+            if count > 128 {
+                let count = usize::from(count - 128);
+                let &value = rest
+                    .first()
+                    .ok_or_else(|| anyhow!("truncated pixel data"))?;
+
+                if x + count > width {
+                    bail!("run past the end of the scanline");
+                }
+
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[component] = value;
+                }
+
+                x += count;
+                data = &rest[1..];
+            } else {
+                let count = usize::from(count);
+
+                if count == 0 || x + count > width || rest.len() < count {
+                    bail!("invalid literal run");
+                }
+
+                for (pixel, &value) in scanline[x..x + count].iter_mut().zip(rest) {
+                    pixel[component] = value;
+                }
+
+                x += count;
+                data = &rest[count..];
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+fn rgbe_to_rgba([r, g, b, e]: [u8; 4]) -> [f32; 4] {
+    if e == 0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+
+    // the mantissas are fixed point fractions of the shared exponent
+    let scale = 2f32.powi(i32::from(e) - 136);
+
+    [
+        f32::from(r) * scale,
+        f32::from(g) * scale,
+        f32::from(b) * scale,
+        1.0,
+    ]
+}
@@ -1 +1,4 @@
+pub mod as_cache;
+pub mod hdr;
+pub mod ppm;
 pub mod vox;
@@ -0,0 +1,70 @@
+use std::{fs, path::Path};
+
+use anyhow::bail;
+use ash::vk;
+
+/// RGB pixels of an image read back from a color image of `format`, 4 bytes per texel.
+pub fn rgb_pixels(data: &[u8], format: vk::Format) -> anyhow::Result<Vec<[u8; 3]>> {
+    let swizzle = match format {
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => [2, 1, 0],
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::A8B8G8R8_UNORM_PACK32
+        | vk::Format::A8B8G8R8_SRGB_PACK32 => [0, 1, 2],
+        _ => bail!("cannot write images of format {:?}", format),
+    };
+
+    Ok(data
+        .chunks_exact(4)
+        .map(|texel| swizzle.map(|channel| texel[channel]))
+        .collect())
+}
+
+/// Binary PPM of `pixels`, rows from top to bottom.
+pub fn encode_ppm(width: u32, height: u32, pixels: &[[u8; 3]]) -> Vec<u8> {
+    let mut data = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    data.extend(pixels.iter().flatten());
+    data
+}
+
+pub fn write_ppm(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    pixels: &[[u8; 3]],
+) -> anyhow::Result<()> {
+    if pixels.len() != (width * height) as usize {
+        bail!("{} pixels for a {}x{} image", pixels.len(), width, height);
+    }
+
+    fs::write(path, encode_ppm(width, height, pixels))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swizzles_bgra() {
+        let data = [1, 2, 3, 255, 4, 5, 6, 255];
+
+        assert_eq!(
+            rgb_pixels(&data, vk::Format::B8G8R8A8_SRGB).unwrap(),
+            [[3, 2, 1], [6, 5, 4]]
+        );
+        assert_eq!(
+            rgb_pixels(&data, vk::Format::R8G8B8A8_UNORM).unwrap(),
+            [[1, 2, 3], [4, 5, 6]]
+        );
+        assert!(rgb_pixels(&data, vk::Format::A2B10G10R10_UNORM_PACK32).is_err());
+    }
+
+    #[test]
+    fn encodes_the_header_and_the_pixels() {
+        let data = encode_ppm(2, 1, &[[255, 0, 0], [0, 0, 255]]);
+
+        assert_eq!(data, b"P6\n2 1\n255\n\xff\x00\x00\x00\x00\xff");
+    }
+}
@@ -5,11 +5,31 @@ use ash::{
     Device,
 };
 
+use std::collections::HashMap;
+
 use crate::{
-    uniform_types::VoxelInfos,
+    memory::{staging::StagingUploader, Allocator},
+    uniform_types::{Material, VoxelInfos},
     utils::{get_buffer_device_address, BufferResource},
+    vk_controller::TRANSLUCENT_SBT_RECORD_OFFSET,
 };
 
+/// Outward normals of the faces of the voxel cube, in the order of its triangles.
+const FACE_NORMALS: [(i32, i32, i32); 6] = [
+    (0, 0, 1),
+    (1, 0, 0),
+    (0, 0, -1),
+    (-1, 0, 0),
+    (0, -1, 0),
+    (0, 1, 0),
+];
+
+/// Instance mask of the voxels hit by the surface rays, `SURFACE_MASK` in `voxel.glsl`.
+pub const SURFACE_MASK: u8 = 0x01;
+/// Instance mask of the media voxels, only found by the fog march and crossed by the shadow rays,
+/// `MEDIA_MASK` in `voxel.glsl`.
+pub const MEDIA_MASK: u8 = 0x02;
+
 pub fn open_file(path: &str) -> dot_vox::DotVoxData {
     let vox_data = dot_vox::load(path).unwrap();
 
@@ -62,21 +82,60 @@ pub fn open_file(path: &str) -> dot_vox::DotVoxData {
 pub fn vox_to_tlas(
     as_device_handle: u64,
     input_voxels: Vec<dot_vox::Voxel>,
+    materials: &[Material; 256],
 ) -> (Vec<vk::AccelerationStructureInstanceKHR>, Vec<VoxelInfos>) {
     let mut instances = Vec::<vk::AccelerationStructureInstanceKHR>::new();
     let mut positions = Vec::<VoxelInfos>::new();
 
+    let occupied: HashMap<(i32, i32, i32), u8> = input_voxels
+        .iter()
+        .map(|vox| ((vox.x.into(), vox.z.into(), vox.y.into()), vox.i))
+        .collect();
+
     for vox in input_voxels {
         let x = f32::from(vox.x);
         let y = f32::from(vox.z);
         let z = f32::from(vox.y);
         let transform: [f32; 12] = [1.0, 0.0, 0.0, x, 0.0, 1.0, 0.0, y, 0.0, 0.0, 1.0, z];
 
-        // TODO palette support
+        let material = &materials[usize::from(vox.i)];
+
+        // translucent voxels go through the any-hit shaders, the others skip them
+        let sbt_record_offset_and_flags = if is_translucent(material) {
+            vk::Packed24_8::new(
+                TRANSLUCENT_SBT_RECORD_OFFSET,
+                vk::GeometryInstanceFlagsKHR::FORCE_NO_OPAQUE.as_raw() as u8,
+            )
+        } else {
+            vk::Packed24_8::new(0, 0)
+        };
+
+        // a block of glass only refracts at its outside, a block of media is only entered and
+        // left there
+        let mut interior_faces = 0;
+
+        if material.transmission > 0.0 || material.density > 0.0 {
+            let position = (i32::from(vox.x), i32::from(vox.z), i32::from(vox.y));
+
+            for (face, (dx, dy, dz)) in FACE_NORMALS.into_iter().enumerate() {
+                let neighbour = (position.0 + dx, position.1 + dy, position.2 + dz);
+
+                if occupied.get(&neighbour) == Some(&vox.i) {
+                    interior_faces |= 1 << face;
+                }
+            }
+        }
+
+        let mask = if material.density > 0.0 {
+            MEDIA_MASK
+        } else {
+            SURFACE_MASK
+        };
+
         let instance = vk::AccelerationStructureInstanceKHR {
             transform: vk::TransformMatrixKHR { matrix: transform },
-            instance_custom_index_and_mask: vk::Packed24_8::new(vox.i.into(), 0xff),
-            instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(0, 0),
+            instance_custom_index_and_mask: vk::Packed24_8::new(vox.i.into(), mask),
+            instance_shader_binding_table_record_offset_and_flags: sbt_record_offset_and_flags,
             acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
                 device_handle: as_device_handle,
             },
@@ -85,7 +144,8 @@ pub fn vox_to_tlas(
         instances.push(instance);
         positions.push(VoxelInfos {
             position: glm::Vec3::new(x, y, z),
-            palette_index: 0,
+            palette_index: vox.i.into(),
+            interior_faces,
         });
     }
 
@@ -97,7 +157,8 @@ pub fn vox_to_tlas(
 pub fn vox_to_blas<'a>(
     input_voxels: &Vec<dot_vox::Voxel>,
     device: &Device,
-    device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    allocator: &Allocator,
+    staging: &mut StagingUploader,
 ) -> vk::AccelerationStructureGeometryKHR<'a> {
     let aabb_buffer = {
         let mut corners = Vec::<AabbPositionsKHR>::new();
@@ -120,16 +181,17 @@ pub fn vox_to_blas<'a>(
         let aabb_stride = std::mem::size_of::<vk::AabbPositionsKHR>();
         let buffer_size = (aabb_stride * corners.len()) as vk::DeviceSize;
 
-        let mut aabb_buffer = BufferResource::new(
+        let aabb_buffer = BufferResource::new(
             buffer_size,
             vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
             device,
-            device_memory_properties,
+            allocator,
         );
 
-        aabb_buffer.store(&corners, device);
+        staging.upload(device, &aabb_buffer, 0, &corners);
 
         aabb_buffer
     };
@@ -153,7 +215,8 @@ pub fn vox_to_blas<'a>(
 pub fn vox_to_geometries<'a>(
     input_voxels: &Vec<dot_vox::Voxel>,
     device: &Device,
-    device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    allocator: &Allocator,
+    staging: &mut StagingUploader,
 ) -> (BufferResource, vk::AccelerationStructureGeometryKHR<'a>) {
     let voxel_count = input_voxels.len();
 
@@ -178,16 +241,17 @@ pub fn vox_to_geometries<'a>(
         let aabb_stride = std::mem::size_of::<vk::AabbPositionsKHR>();
         let buffer_size = (aabb_stride * corners.len()) as vk::DeviceSize;
 
-        let mut aabb_buffer = BufferResource::new(
+        let aabb_buffer = BufferResource::new(
             buffer_size,
             vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
             device,
-            device_memory_properties,
+            allocator,
         );
 
-        aabb_buffer.store(&corners, device);
+        staging.upload(device, &aabb_buffer, 0, &corners);
 
         aabb_buffer
     };
@@ -216,9 +280,9 @@ pub fn vox_to_geometries<'a>(
 
 pub fn get_palette(data: &dot_vox::DotVoxData) -> [glm::Vec3; 256] {
     let mut array = [glm::Vec3::zeros(); 256];
-    for i in 0..256 {
+    for (i, entry) in array.iter_mut().enumerate() {
         let color = data.palette.get(i).unwrap();
-        array[i] = glm::Vec3::new(
+        *entry = glm::Vec3::new(
             f32::from(color.r) / 255.0,
             f32::from(color.g) / 255.0,
             f32::from(color.b) / 255.0,
@@ -227,3 +291,72 @@ pub fn get_palette(data: &dot_vox::DotVoxData) -> [glm::Vec3; 256] {
 
     array
 }
+
+/// Materials of the palette entries, from the MagicaVoxel materials. Entries without one are
+/// diffuse.
+pub fn get_materials(data: &dot_vox::DotVoxData) -> [Material; 256] {
+    let palette = get_palette(data);
+    let mut materials = palette.map(|albedo| Material {
+        albedo,
+        roughness: 1.0,
+        emission: glm::Vec3::zeros(),
+        metalness: 0.0,
+        opacity: 1.0,
+        transmission: 0.0,
+        ior: 1.0,
+        density: 0.0,
+    });
+
+    for material in &data.materials {
+        // material ids start at 1 like the palette indices in the file, voxel indices start at 0
+        let Some(index) = (material.id as usize)
+            .checked_sub(1)
+            .filter(|index| *index < 256)
+        else {
+            continue;
+        };
+        let albedo = palette[index];
+
+        // older files store the transparency as `_alpha`
+        let transparency = material.transparency().or_else(|| material.opacity());
+
+        match material.material_type() {
+            Some("_metal") => {
+                materials[index].metalness = material.metalness().unwrap_or(1.0);
+                materials[index].roughness = material.roughness().unwrap_or(0.1);
+            }
+            Some("_emit") => {
+                // the flux is a power of two multiplier of the emission
+                let strength = material.emission().unwrap_or(0.0)
+                    * 2f32.powf(material.radiant_flux().unwrap_or(0.0));
+                materials[index].emission = albedo * strength;
+            }
+            Some("_glass") => {
+                // `_ior` is the index of refraction minus 1, water is 0.33, the full index is
+                // only stored again as `_ri`
+                materials[index].transmission = transparency.unwrap_or(1.0).clamp(0.0, 1.0);
+                materials[index].ior = 1.0 + material.refractive_index().unwrap_or(0.5).max(0.0);
+                materials[index].roughness = material.roughness().unwrap_or(0.0);
+            }
+            Some("_blend") => {
+                materials[index].opacity = 1.0 - transparency.unwrap_or(0.5).clamp(0.0, 1.0);
+            }
+            Some("_media") => {
+                // the voxels are fog volumes of the density stored as `_d`, scattering their color
+                let density = material
+                    .properties
+                    .get("_d")
+                    .and_then(|density| density.parse::<f32>().ok());
+                materials[index].density = density.unwrap_or(0.1).max(1e-4);
+            }
+            _ => (),
+        }
+    }
+
+    materials
+}
+
+/// Whether the rays of a material go through the any-hit shaders.
+pub fn is_translucent(material: &Material) -> bool {
+    material.opacity < 1.0 || material.transmission > 0.0 || material.density > 0.0
+}
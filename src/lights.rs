@@ -0,0 +1,138 @@
+use crate::uniform_types::{EmissiveLight, Material, VoxelInfos};
+
+/// Emissive voxels of the scene, sampled on the GPU in proportion to their power.
+pub struct LightList {
+    pub lights: Vec<EmissiveLight>,
+    /// Sum of the powers of the lights.
+    pub total_power: f32,
+}
+
+/// Luminance of a linear color, with the weights of the shaders.
+pub fn luminance(color: &glm::Vec3) -> f32 {
+    glm::dot(color, &glm::vec3(0.2126, 0.7152, 0.0722))
+}
+
+/// Collects the voxels whose material emits. Every voxel has the same area, so their power is
+/// the luminance of their emission.
+pub fn build_light_list(voxels: &[VoxelInfos], materials: &[Material; 256]) -> LightList {
+    let (indices, powers): (Vec<u32>, Vec<f32>) = voxels
+        .iter()
+        .enumerate()
+        .map(|(index, voxel)| {
+            let emission = &materials[voxel.palette_index as usize].emission;
+            (index as u32, luminance(emission))
+        })
+        .filter(|(_, power)| *power > 0.0)
+        .unzip();
+
+    let total_power = powers.iter().sum();
+
+    let lights = alias_table(&powers)
+        .into_iter()
+        .zip(indices)
+        .map(|((probability, alias), voxel_index)| EmissiveLight {
+            voxel_index,
+            probability,
+            alias,
+        })
+        .collect();
+
+    LightList {
+        lights,
+        total_power,
+    }
+}
+
+/// Alias table of the weights, Vose's method: an entry is drawn uniformly, then kept with its
+/// probability or replaced by its alias.
+fn alias_table(weights: &[f32]) -> Vec<(f32, u32)> {
+    let count = weights.len();
+    let total: f32 = weights.iter().sum();
+
+    // weights scaled to a mean of 1, the columns are filled up to 1 from the heavier entries
+    let mut scaled: Vec<f32> = weights
+        .iter()
+        .map(|weight| weight * count as f32 / total)
+        .collect();
+    let mut table: Vec<(f32, u32)> = (0..count as u32).map(|index| (1.0, index)).collect();
+
+    let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+        (0..count).partition(|&index| scaled[index] < 1.0);
+
+    while let (Some(less), Some(&more)) = (small.pop(), large.last()) {
+        table[less] = (scaled[less], more as u32);
+        scaled[more] -= 1.0 - scaled[less];
+
+        if scaled[more] < 1.0 {
+            large.pop();
+            small.push(more);
+        }
+    }
+
+    // the leftovers are 1 up to rounding errors
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that drawing an entry uniformly, then keeping it or taking its alias, picks every
+    /// entry in proportion to its weight.
+    fn check_alias_table(weights: &[f32]) {
+        let table = alias_table(weights);
+        let count = weights.len();
+        let total: f32 = weights.iter().sum();
+
+        assert_eq!(table.len(), count);
+
+        let mut probabilities = vec![0.0; count];
+        for (index, &(probability, alias)) in table.iter().enumerate() {
+            assert!((0.0..=1.0 + 1e-5).contains(&probability), "{:?}", table);
+            assert!((alias as usize) < count, "{:?}", table);
+
+            probabilities[index] += probability / count as f32;
+            probabilities[alias as usize] += (1.0 - probability) / count as f32;
+        }
+
+        for (probability, weight) in probabilities.iter().zip(weights) {
+            assert!(
+                (probability - weight / total).abs() < 1e-5,
+                "{:?} for {:?}",
+                probabilities,
+                weights
+            );
+        }
+    }
+
+    #[test]
+    fn alias_table_of_equal_weights() {
+        check_alias_table(&[1.0]);
+        check_alias_table(&[2.5; 7]);
+
+        // nothing to alias
+        assert!(alias_table(&[0.5; 4])
+            .iter()
+            .enumerate()
+            .all(|(index, &entry)| entry == (1.0, index as u32)));
+    }
+
+    #[test]
+    fn alias_table_of_a_dominant_light() {
+        check_alias_table(&[1000.0, 1.0, 1.0, 1.0]);
+        check_alias_table(&[0.01, 0.01, 500.0, 0.01, 0.01, 0.01]);
+        check_alias_table(&[1e6, 1e-3]);
+    }
+
+    #[test]
+    fn alias_table_with_float_leftovers() {
+        // scaled weights that do not sum to the count exactly
+        check_alias_table(&[0.1, 0.2, 0.7]);
+        check_alias_table(&[1.0 / 3.0, 0.1, 0.7, 0.3, 0.9, 1.0 / 7.0]);
+        check_alias_table(
+            &(1..=100)
+                .map(|i| (i as f32 * 0.37).sin().abs() + 0.01)
+                .collect::<Vec<_>>(),
+        );
+    }
+}
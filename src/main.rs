@@ -1,21 +1,29 @@
+mod animation;
 mod base;
+mod camera_path;
+mod config;
+mod headless;
 mod io;
+mod lights;
+mod memory;
 mod player_controller;
 mod random_generation;
 mod render;
+#[macro_use]
+mod shaders;
+mod sun;
 mod uniform_types;
 mod utils;
 mod vk_controller;
 
 extern crate nalgebra_glm as glm;
 
-use ash::vk::{self};
 use base::AppBase;
 use utils::{HEIGHT, WIDTH};
 use winit::{
     application::ApplicationHandler,
     event::{DeviceEvent, ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
-    event_loop::{ActiveEventLoop, EventLoop},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
     keyboard,
 };
 
@@ -25,33 +33,23 @@ struct App<'a> {
 }
 
 impl ApplicationHandler for App<'static> {
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
         let base = self.base.as_mut().unwrap();
-        if base.focused {
+
+        // the hidden window is never redrawn, the frames are rendered as fast as they can
+        if base.finished {
+            event_loop.exit();
+        } else if base.headless.is_some() {
+            event_loop.set_control_flow(ControlFlow::Poll);
+            base.main_loop();
+        } else if base.focused {
             base.vk_controller.window.request_redraw();
         }
     }
 
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         if self.base.is_none() {
-            let app = AppBase::new(&event_loop, WIDTH, HEIGHT);
-
-            unsafe {
-                let begin_info = vk::CommandBufferBeginInfo::default()
-                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-
-                app.vk_controller
-                    .device
-                    .begin_command_buffer(app.vk_controller.rt_command_buffer, &begin_info)
-                    .unwrap();
-            }
-
-            unsafe {
-                app.vk_controller
-                    .device
-                    .end_command_buffer(app.vk_controller.rt_command_buffer)
-                    .unwrap();
-            }
+            let app = AppBase::new(event_loop, WIDTH, HEIGHT);
 
             self.base = Some(app);
         }
@@ -86,6 +84,114 @@ impl ApplicationHandler for App<'static> {
             } => {
                 println!("position: {}", base.camera.transform.translation)
             }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: keyboard::PhysicalKey::Code(keyboard::KeyCode::KeyO),
+                        ..
+                    },
+                ..
+            } => base.cycle_ao_output(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: keyboard::PhysicalKey::Code(keyboard::KeyCode::KeyP),
+                        ..
+                    },
+                ..
+            } => base.toggle_path_tracing(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: keyboard::PhysicalKey::Code(keyboard::KeyCode::KeyL),
+                        ..
+                    },
+                ..
+            } => base.toggle_restir(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: keyboard::PhysicalKey::Code(keyboard::KeyCode::KeyN),
+                        ..
+                    },
+                ..
+            } => base.toggle_denoiser(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: keyboard::PhysicalKey::Code(keyboard::KeyCode::KeyT),
+                        ..
+                    },
+                ..
+            } => base.cycle_tonemapper(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: keyboard::PhysicalKey::Code(keyboard::KeyCode::KeyU),
+                        ..
+                    },
+                ..
+            } => base.cycle_upscaler(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: keyboard::PhysicalKey::Code(keyboard::KeyCode::KeyM),
+                        ..
+                    },
+                ..
+            } => base.cycle_anti_aliasing(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: keyboard::PhysicalKey::Code(keyboard::KeyCode::KeyF),
+                        ..
+                    },
+                ..
+            } => base.toggle_lens(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: keyboard::PhysicalKey::Code(keyboard::KeyCode::KeyG),
+                        ..
+                    },
+                ..
+            } => base.toggle_fog(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: keyboard::PhysicalKey::Code(keyboard::KeyCode::KeyV),
+                        ..
+                    },
+                ..
+            } => base.cycle_debug_view(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: keyboard::PhysicalKey::Code(keyboard::KeyCode::KeyI),
+                        ..
+                    },
+                ..
+            } => base.toggle_orbiting_voxel(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: keyboard::PhysicalKey::Code(keyboard::KeyCode::KeyC),
+                        ..
+                    },
+                ..
+            } => base.toggle_camera_path(),
             WindowEvent::Resized(_) => self.base.as_mut().unwrap().resized = true,
             WindowEvent::MouseInput {
                 state: ElementState::Pressed,
@@ -99,7 +205,7 @@ impl ApplicationHandler for App<'static> {
             WindowEvent::KeyboardInput { event, .. } => {
                 base.player_controller.handle_keyboard_event(event)
             }
-            WindowEvent::RedrawRequested => base.main_loop(),
+            WindowEvent::RedrawRequested if base.headless.is_none() => base.main_loop(),
             _ => (),
         };
     }
@@ -110,12 +216,9 @@ impl ApplicationHandler for App<'static> {
         _device_id: winit::event::DeviceId,
         event: winit::event::DeviceEvent,
     ) {
-        match event {
-            DeviceEvent::MouseMotion { delta } => {
-                self.base.as_mut().unwrap().update_look_position(delta)
-            }
-            _ => (),
-        };
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.base.as_mut().unwrap().update_look_position(delta)
+        }
     }
 }
 
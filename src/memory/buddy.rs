@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+/// Buddy allocator over a range of `size` bytes, split in power of two blocks of at least
+/// `min_block_size` bytes. Blocks are aligned to their own size, so any alignment up to the
+/// block size is satisfied for free.
+pub struct BuddyAllocator {
+    size: u64,
+    min_block_size: u64,
+    // offsets of the free blocks of every order, order 0 being `min_block_size`
+    free_lists: Vec<Vec<u64>>,
+    // order of every allocated block, by offset
+    allocated: HashMap<u64, usize>,
+    used: u64,
+}
+
+impl BuddyAllocator {
+    pub fn new(size: u64, min_block_size: u64) -> Self {
+        assert!(
+            size.is_power_of_two() && min_block_size.is_power_of_two() && min_block_size <= size,
+            "Buddy allocator sizes must be powers of two ({}, {}).",
+            size,
+            min_block_size
+        );
+
+        let order_count = (size / min_block_size).trailing_zeros() as usize + 1;
+        let mut free_lists = vec![vec![]; order_count];
+        free_lists[order_count - 1].push(0);
+
+        BuddyAllocator {
+            size,
+            min_block_size,
+            free_lists,
+            allocated: HashMap::new(),
+            used: 0,
+        }
+    }
+
+    fn block_size(&self, order: usize) -> u64 {
+        self.min_block_size << order
+    }
+
+    fn max_order(&self) -> usize {
+        self.free_lists.len() - 1
+    }
+
+    /// Size of the block that would be used for an allocation, `None` if it can never fit.
+    pub fn block_size_for(&self, size: u64, alignment: u64) -> Option<u64> {
+        let block_size = size
+            .max(alignment)
+            .max(self.min_block_size)
+            .checked_next_power_of_two()?;
+
+        (block_size <= self.size).then_some(block_size)
+    }
+
+    /// Returns the offset of a block of at least `size` bytes aligned to `alignment`.
+    pub fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        let block_size = self.block_size_for(size, alignment)?;
+        let order = (block_size / self.min_block_size).trailing_zeros() as usize;
+
+        let mut current_order =
+            (order..=self.max_order()).find(|order| !self.free_lists[*order].is_empty())?;
+        let offset = self.free_lists[current_order].pop().unwrap();
+
+        // split the block, keeping the lower half and freeing the upper one at every step
+        while current_order > order {
+            current_order -= 1;
+            let buddy = offset + self.block_size(current_order);
+            self.free_lists[current_order].push(buddy);
+        }
+
+        self.allocated.insert(offset, order);
+        self.used += block_size;
+
+        Some(offset)
+    }
+
+    pub fn free(&mut self, offset: u64) {
+        let mut order = self
+            .allocated
+            .remove(&offset)
+            .unwrap_or_else(|| panic!("Freeing unallocated block at offset {}.", offset));
+
+        self.used -= self.block_size(order);
+
+        // merge with the buddy as long as it is free
+        let mut offset = offset;
+        while order < self.max_order() {
+            let buddy = offset ^ self.block_size(order);
+
+            let Some(position) = self.free_lists[order]
+                .iter()
+                .position(|free| *free == buddy)
+            else {
+                break;
+            };
+
+            self.free_lists[order].swap_remove(position);
+            offset = offset.min(buddy);
+            order += 1;
+        }
+
+        self.free_lists[order].push(offset);
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Bytes taken by allocated blocks, including the rounding to powers of two.
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    pub fn allocation_count(&self) -> usize {
+        self.allocated.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocated.is_empty()
+    }
+
+    pub fn largest_free_block(&self) -> u64 {
+        (0..=self.max_order())
+            .rev()
+            .find(|order| !self.free_lists[*order].is_empty())
+            .map_or(0, |order| self.block_size(order))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_blocks_in_halves() {
+        let mut buddy = BuddyAllocator::new(1024, 64);
+
+        assert_eq!(buddy.allocate(64, 1), Some(0));
+        assert_eq!(buddy.allocate(64, 1), Some(64));
+        assert_eq!(buddy.allocate(128, 1), Some(128));
+        assert_eq!(buddy.allocate(100, 1), Some(256));
+
+        assert_eq!(buddy.allocation_count(), 4);
+        assert_eq!(buddy.used(), 64 + 64 + 128 + 128);
+        assert_eq!(buddy.largest_free_block(), 512);
+    }
+
+    #[test]
+    fn merges_free_buddies() {
+        let mut buddy = BuddyAllocator::new(1024, 64);
+        let offsets: Vec<u64> = (0..4).map(|_| buddy.allocate(256, 1).unwrap()).collect();
+
+        // 256 and 512 are not buddies, they stay apart
+        buddy.free(offsets[1]);
+        buddy.free(offsets[2]);
+        assert_eq!(buddy.largest_free_block(), 256);
+
+        buddy.free(offsets[0]);
+        assert_eq!(buddy.largest_free_block(), 512);
+
+        buddy.free(offsets[3]);
+        assert!(buddy.is_empty());
+        assert_eq!(buddy.used(), 0);
+        assert_eq!(buddy.largest_free_block(), 1024);
+        assert_eq!(buddy.allocate(1024, 1), Some(0));
+    }
+
+    #[test]
+    fn aligns_blocks() {
+        let mut buddy = BuddyAllocator::new(1024, 64);
+
+        assert_eq!(buddy.block_size_for(100, 1), Some(128));
+        assert_eq!(buddy.block_size_for(10, 512), Some(512));
+        assert_eq!(buddy.block_size_for(1, 1), Some(64));
+
+        assert_eq!(buddy.allocate(64, 1), Some(0));
+        assert_eq!(buddy.allocate(64, 256), Some(256));
+        assert_eq!(buddy.allocate(32, 128), Some(128));
+    }
+
+    #[test]
+    fn runs_out_of_blocks() {
+        let mut buddy = BuddyAllocator::new(1024, 64);
+
+        assert_eq!(buddy.block_size_for(2048, 1), None);
+        assert_eq!(buddy.allocate(2048, 1), None);
+        assert_eq!(buddy.allocate(64, 2048), None);
+
+        assert_eq!(buddy.allocate(512, 1), Some(0));
+        assert_eq!(buddy.allocate(1024, 1), None);
+        assert_eq!(buddy.allocate(512, 1), Some(512));
+        assert_eq!(buddy.allocate(64, 1), None);
+        assert_eq!(buddy.largest_free_block(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Freeing unallocated block")]
+    fn rejects_double_free() {
+        let mut buddy = BuddyAllocator::new(1024, 64);
+        let offset = buddy.allocate(64, 1).unwrap();
+
+        buddy.free(offset);
+        buddy.free(offset);
+    }
+}
@@ -0,0 +1,932 @@
+pub mod buddy;
+pub mod staging;
+
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use ash::{vk, Device};
+
+use buddy::BuddyAllocator;
+
+const BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+const MIN_ALLOCATION_SIZE: u64 = 256;
+
+#[derive(Debug)]
+pub enum AllocationError {
+    NoCompatibleMemoryType {
+        type_bits: u32,
+        properties: vk::MemoryPropertyFlags,
+    },
+    OutOfMemory {
+        size: vk::DeviceSize,
+        properties: vk::MemoryPropertyFlags,
+        result: vk::Result,
+    },
+    MapFailed(vk::Result),
+}
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocationError::NoCompatibleMemoryType {
+                type_bits,
+                properties,
+            } => write!(
+                f,
+                "no memory type in {:#b} has properties {:?}",
+                type_bits, properties
+            ),
+            AllocationError::OutOfMemory {
+                size,
+                properties,
+                result,
+            } => write!(
+                f,
+                "out of memory allocating {} bytes of {:?} memory ({})",
+                size, properties, result
+            ),
+            AllocationError::MapFailed(result) => write!(f, "failed to map memory ({})", result),
+        }
+    }
+}
+
+impl std::error::Error for AllocationError {}
+
+/// Source of the memory blocks the allocator splits. `DeviceBackend` in practice, anything
+/// handing out fake handles is enough to exercise the allocation logic without a GPU.
+pub trait MemoryBackend {
+    fn allocate(
+        &mut self,
+        memory_type_index: u32,
+        size: vk::DeviceSize,
+    ) -> Result<vk::DeviceMemory, vk::Result>;
+
+    fn map(
+        &mut self,
+        memory: vk::DeviceMemory,
+        size: vk::DeviceSize,
+    ) -> Result<*mut u8, vk::Result>;
+
+    fn free(&mut self, memory: vk::DeviceMemory);
+}
+
+pub struct DeviceBackend {
+    device: Device,
+}
+
+impl DeviceBackend {
+    pub fn new(device: Device) -> Self {
+        DeviceBackend { device }
+    }
+}
+
+impl MemoryBackend for DeviceBackend {
+    fn allocate(
+        &mut self,
+        memory_type_index: u32,
+        size: vk::DeviceSize,
+    ) -> Result<vk::DeviceMemory, vk::Result> {
+        // every block may hold buffers used through their device address
+        let mut memory_allocate_flags_info =
+            vk::MemoryAllocateFlagsInfo::default().flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+
+        let allocate_info = vk::MemoryAllocateInfo::default()
+            .push_next(&mut memory_allocate_flags_info)
+            .allocation_size(size)
+            .memory_type_index(memory_type_index);
+
+        unsafe { self.device.allocate_memory(&allocate_info, None) }
+    }
+
+    fn map(
+        &mut self,
+        memory: vk::DeviceMemory,
+        size: vk::DeviceSize,
+    ) -> Result<*mut u8, vk::Result> {
+        unsafe {
+            self.device
+                .map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+                .map(|ptr| ptr as *mut u8)
+        }
+    }
+
+    fn free(&mut self, memory: vk::DeviceMemory) {
+        unsafe { self.device.free_memory(memory, None) };
+    }
+}
+
+/// Buffers and optimal tiling images are kept in separate blocks so that
+/// `bufferImageGranularity` never has to be taken into account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocationKind {
+    Buffer,
+    Image,
+}
+
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    mapped: *mut u8,
+    // None for dedicated allocations
+    buddy: Option<BuddyAllocator>,
+    requested: vk::DeviceSize,
+}
+
+struct MemoryPool {
+    memory_type_index: u32,
+    kind: AllocationKind,
+    block_size: vk::DeviceSize,
+    blocks: Vec<Option<MemoryBlock>>,
+}
+
+struct AllocatorState {
+    backend: Box<dyn MemoryBackend>,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pools: Vec<MemoryPool>,
+}
+
+/// Sub-allocates device memory from large blocks, one set of blocks per memory type.
+/// Cloning only clones the handle.
+#[derive(Clone)]
+pub struct Allocator {
+    state: Rc<RefCell<AllocatorState>>,
+}
+
+/// A range of device memory owned by a resource, returned with `free`.
+pub struct Allocation {
+    allocator: Allocator,
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    mapped: *mut u8,
+    pool: usize,
+    block: usize,
+}
+
+impl Allocation {
+    /// Host pointer to the start of the allocation, for host visible memory only.
+    pub fn mapped_ptr(&self) -> Option<*mut u8> {
+        (!self.mapped.is_null()).then(|| unsafe { self.mapped.add(self.offset as usize) })
+    }
+
+    pub fn free(self) {
+        self.allocator
+            .free(self.pool, self.block, self.offset, self.size);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HeapStats {
+    pub memory_type_index: u32,
+    pub properties: vk::MemoryPropertyFlags,
+    pub block_count: usize,
+    pub dedicated_count: usize,
+    pub allocation_count: usize,
+    /// Bytes allocated from the device.
+    pub reserved: vk::DeviceSize,
+    /// Bytes taken by allocations, including the rounding of block sizes.
+    pub used: vk::DeviceSize,
+    /// Bytes actually requested by the allocations.
+    pub requested: vk::DeviceSize,
+    pub largest_free_block: vk::DeviceSize,
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mib = |bytes: vk::DeviceSize| bytes as f64 / (1024.0 * 1024.0);
+
+        write!(
+            f,
+            "memory type {} ({:?}): {} blocks + {} dedicated, {:.1} MiB reserved, {:.1} MiB used \
+             ({:.1} MiB requested) by {} allocations, largest free block {:.1} MiB",
+            self.memory_type_index,
+            self.properties,
+            self.block_count,
+            self.dedicated_count,
+            mib(self.reserved),
+            mib(self.used),
+            mib(self.requested),
+            self.allocation_count,
+            mib(self.largest_free_block),
+        )
+    }
+}
+
+/// A block whose allocations could be moved elsewhere so that it can be released.
+#[derive(Clone, Copy, Debug)]
+pub struct DefragmentationCandidate {
+    pub memory_type_index: u32,
+    pub block: usize,
+    pub used: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+}
+
+impl fmt::Display for DefragmentationCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "block {} of memory type {} is {:.1}% used, its allocations could be moved",
+            self.block,
+            self.memory_type_index,
+            self.used as f64 / self.size as f64 * 100.0,
+        )
+    }
+}
+
+impl Allocator {
+    pub fn new(
+        backend: Box<dyn MemoryBackend>,
+        memory_properties: vk::PhysicalDeviceMemoryProperties,
+    ) -> Self {
+        Allocator {
+            state: Rc::new(RefCell::new(AllocatorState {
+                backend,
+                memory_properties,
+                pools: vec![],
+            })),
+        }
+    }
+
+    pub fn allocate(
+        &self,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+        kind: AllocationKind,
+    ) -> Result<Allocation, AllocationError> {
+        let mut state = self.state.borrow_mut();
+        let memory_properties = state.memory_properties;
+
+        let mut result = Err(AllocationError::NoCompatibleMemoryType {
+            type_bits: requirements.memory_type_bits,
+            properties,
+        });
+
+        // fall back to the next compatible memory type when a heap is full
+        for memory_type_index in memory_type_indices(
+            &memory_properties,
+            requirements.memory_type_bits,
+            properties,
+        ) {
+            result = state.allocate_from_type(memory_type_index, kind, requirements, properties);
+
+            if result.is_ok() {
+                break;
+            }
+        }
+
+        result.map(|(memory, offset, mapped, pool, block)| Allocation {
+            allocator: self.clone(),
+            memory,
+            offset,
+            size: requirements.size,
+            mapped,
+            pool,
+            block,
+        })
+    }
+
+    fn free(&self, pool: usize, block: usize, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        let slot = &mut state.pools[pool].blocks[block];
+        let memory_block = slot.as_mut().unwrap();
+
+        memory_block.requested -= size;
+
+        match &mut memory_block.buddy {
+            Some(buddy) => buddy.free(offset),
+            None => {
+                state.backend.free(memory_block.memory);
+                *slot = None;
+            }
+        }
+    }
+
+    /// Gives the blocks that no longer hold any allocation back to the device.
+    pub fn release_empty_blocks(&self) {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+
+        for pool in &mut state.pools {
+            for slot in &mut pool.blocks {
+                if slot
+                    .as_ref()
+                    .is_some_and(|block| block.buddy.as_ref().is_some_and(|buddy| buddy.is_empty()))
+                {
+                    state.backend.free(slot.take().unwrap().memory);
+                }
+            }
+        }
+    }
+
+    /// Blocks using at most `max_usage` of their size. Moving their allocations to new ones
+    /// and calling `release_empty_blocks` reduces the memory reserved by the allocator.
+    pub fn defragmentation_candidates(&self, max_usage: f32) -> Vec<DefragmentationCandidate> {
+        let state = self.state.borrow();
+
+        state
+            .pools
+            .iter()
+            .flat_map(|pool| {
+                pool.blocks
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(index, block)| {
+                        let block = block.as_ref()?;
+                        let buddy = block.buddy.as_ref()?;
+
+                        (!buddy.is_empty()
+                            && (buddy.used() as f32) <= buddy.size() as f32 * max_usage)
+                            .then_some(DefragmentationCandidate {
+                                memory_type_index: pool.memory_type_index,
+                                block: index,
+                                used: buddy.used(),
+                                size: buddy.size(),
+                            })
+                    })
+            })
+            .collect()
+    }
+
+    pub fn stats(&self) -> Vec<HeapStats> {
+        let state = self.state.borrow();
+        let mut stats: Vec<HeapStats> = vec![];
+
+        for pool in &state.pools {
+            let index = match stats
+                .iter()
+                .position(|stats| stats.memory_type_index == pool.memory_type_index)
+            {
+                Some(index) => index,
+                None => {
+                    stats.push(HeapStats {
+                        memory_type_index: pool.memory_type_index,
+                        properties: state.memory_properties.memory_types
+                            [pool.memory_type_index as usize]
+                            .property_flags,
+                        block_count: 0,
+                        dedicated_count: 0,
+                        allocation_count: 0,
+                        reserved: 0,
+                        used: 0,
+                        requested: 0,
+                        largest_free_block: 0,
+                    });
+                    stats.len() - 1
+                }
+            };
+            let heap_stats = &mut stats[index];
+
+            for block in pool.blocks.iter().flatten() {
+                heap_stats.reserved += block.size;
+                heap_stats.requested += block.requested;
+
+                match &block.buddy {
+                    Some(buddy) => {
+                        heap_stats.block_count += 1;
+                        heap_stats.allocation_count += buddy.allocation_count();
+                        heap_stats.used += buddy.used();
+                        heap_stats.largest_free_block = heap_stats
+                            .largest_free_block
+                            .max(buddy.largest_free_block());
+                    }
+                    None => {
+                        heap_stats.dedicated_count += 1;
+                        heap_stats.allocation_count += 1;
+                        heap_stats.used += block.size;
+                    }
+                }
+            }
+        }
+
+        stats.sort_by_key(|stats| stats.memory_type_index);
+        stats
+    }
+
+    /// Frees every block, all resources using them must have been destroyed.
+    pub fn destroy(&self) {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+
+        for pool in state.pools.drain(..) {
+            for block in pool.blocks.into_iter().flatten() {
+                let allocation_count = block
+                    .buddy
+                    .as_ref()
+                    .map_or(1, |buddy| buddy.allocation_count());
+
+                if allocation_count > 0 {
+                    println!(
+                        "{} allocations still alive in memory type {}",
+                        allocation_count, pool.memory_type_index
+                    );
+                }
+
+                state.backend.free(block.memory);
+            }
+        }
+    }
+}
+
+impl AllocatorState {
+    fn allocate_from_type(
+        &mut self,
+        memory_type_index: u32,
+        kind: AllocationKind,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Result<(vk::DeviceMemory, vk::DeviceSize, *mut u8, usize, usize), AllocationError> {
+        let host_visible = self.memory_properties.memory_types[memory_type_index as usize]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+
+        let pool_index = match self
+            .pools
+            .iter()
+            .position(|pool| pool.memory_type_index == memory_type_index && pool.kind == kind)
+        {
+            Some(index) => index,
+            None => {
+                let heap_index =
+                    self.memory_properties.memory_types[memory_type_index as usize].heap_index;
+                let heap_size = self.memory_properties.memory_heaps[heap_index as usize].size;
+
+                // small heaps, like the host visible part of VRAM, get smaller blocks
+                let block_size = BLOCK_SIZE
+                    .min(prev_power_of_two(heap_size / 8))
+                    .max(MIN_ALLOCATION_SIZE);
+
+                self.pools.push(MemoryPool {
+                    memory_type_index,
+                    kind,
+                    block_size,
+                    blocks: vec![],
+                });
+                self.pools.len() - 1
+            }
+        };
+
+        let backend = &mut self.backend;
+        let pool = &mut self.pools[pool_index];
+
+        let out_of_memory = |result| AllocationError::OutOfMemory {
+            size: requirements.size,
+            properties,
+            result,
+        };
+
+        let mut new_block = |size: vk::DeviceSize, buddy: Option<BuddyAllocator>| {
+            let memory = backend
+                .allocate(memory_type_index, size)
+                .map_err(out_of_memory)?;
+
+            let mapped = if host_visible {
+                match backend.map(memory, vk::WHOLE_SIZE) {
+                    Ok(mapped) => mapped,
+                    Err(result) => {
+                        backend.free(memory);
+                        return Err(AllocationError::MapFailed(result));
+                    }
+                }
+            } else {
+                std::ptr::null_mut()
+            };
+
+            Ok(MemoryBlock {
+                memory,
+                size,
+                mapped,
+                buddy,
+                requested: 0,
+            })
+        };
+
+        // large resources get their own memory instead of wasting half a block
+        if requirements.size > pool.block_size / 2 {
+            let mut block = new_block(requirements.size, None)?;
+            block.requested = requirements.size;
+
+            let (memory, mapped) = (block.memory, block.mapped);
+            let block_index = insert_block(&mut pool.blocks, block);
+
+            return Ok((memory, 0, mapped, pool_index, block_index));
+        }
+
+        for (block_index, block) in pool.blocks.iter_mut().enumerate() {
+            let Some(block) = block else {
+                continue;
+            };
+            let Some(buddy) = &mut block.buddy else {
+                continue;
+            };
+
+            if let Some(offset) = buddy.allocate(requirements.size, requirements.alignment) {
+                block.requested += requirements.size;
+                return Ok((block.memory, offset, block.mapped, pool_index, block_index));
+            }
+        }
+
+        let mut block = new_block(
+            pool.block_size,
+            Some(BuddyAllocator::new(pool.block_size, MIN_ALLOCATION_SIZE)),
+        )?;
+
+        let offset = block
+            .buddy
+            .as_mut()
+            .unwrap()
+            .allocate(requirements.size, requirements.alignment)
+            .expect("Allocation does not fit in an empty block.");
+        block.requested = requirements.size;
+
+        let (memory, mapped) = (block.memory, block.mapped);
+        let block_index = insert_block(&mut pool.blocks, block);
+
+        Ok((memory, offset, mapped, pool_index, block_index))
+    }
+}
+
+fn insert_block(blocks: &mut Vec<Option<MemoryBlock>>, block: MemoryBlock) -> usize {
+    match blocks.iter().position(|block| block.is_none()) {
+        Some(index) => {
+            blocks[index] = Some(block);
+            index
+        }
+        None => {
+            blocks.push(Some(block));
+            blocks.len() - 1
+        }
+    }
+}
+
+fn prev_power_of_two(value: u64) -> u64 {
+    if value == 0 {
+        0
+    } else {
+        1 << (63 - value.leading_zeros())
+    }
+}
+
+fn memory_type_indices(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    properties: vk::MemoryPropertyFlags,
+) -> impl Iterator<Item = u32> + '_ {
+    memory_properties.memory_types[..memory_properties.memory_type_count as usize]
+        .iter()
+        .enumerate()
+        .filter(move |(index, memory_type)| {
+            (1 << index) & type_bits != 0 && memory_type.property_flags.contains(properties)
+        })
+        .map(|(index, _)| index as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ash::vk::Handle;
+
+    use super::*;
+
+    const MIB: vk::DeviceSize = 1024 * 1024;
+
+    // memory types of the fake heap, the block sizes follow from the heap sizes
+    const DEVICE_LOCAL: u32 = 0;
+    const HOST_VISIBLE: u32 = 1;
+    const DEVICE_LOCAL_HOST_VISIBLE: u32 = 2;
+    const DEVICE_BLOCK_SIZE: vk::DeviceSize = MIB;
+    const HOST_BLOCK_SIZE: vk::DeviceSize = MIB / 4;
+
+    #[derive(Default)]
+    struct FakeHeap {
+        next_handle: u64,
+        // memory type and host copy of every live allocation
+        live: HashMap<vk::DeviceMemory, (u32, Vec<u8>)>,
+        // bytes each memory type can still hand out, unlimited when missing
+        capacity: HashMap<u32, vk::DeviceSize>,
+    }
+
+    struct FakeBackend(Rc<RefCell<FakeHeap>>);
+
+    impl MemoryBackend for FakeBackend {
+        fn allocate(
+            &mut self,
+            memory_type_index: u32,
+            size: vk::DeviceSize,
+        ) -> Result<vk::DeviceMemory, vk::Result> {
+            let mut heap = self.0.borrow_mut();
+
+            if let Some(capacity) = heap.capacity.get_mut(&memory_type_index) {
+                if *capacity < size {
+                    return Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY);
+                }
+                *capacity -= size;
+            }
+
+            heap.next_handle += 1;
+            let memory = vk::DeviceMemory::from_raw(heap.next_handle);
+            heap.live
+                .insert(memory, (memory_type_index, vec![0; size as usize]));
+
+            Ok(memory)
+        }
+
+        fn map(
+            &mut self,
+            memory: vk::DeviceMemory,
+            _size: vk::DeviceSize,
+        ) -> Result<*mut u8, vk::Result> {
+            let mut heap = self.0.borrow_mut();
+            Ok(heap.live.get_mut(&memory).unwrap().1.as_mut_ptr())
+        }
+
+        fn free(&mut self, memory: vk::DeviceMemory) {
+            let mut heap = self.0.borrow_mut();
+            let (memory_type_index, bytes) = heap.live.remove(&memory).expect("Double free.");
+
+            if let Some(capacity) = heap.capacity.get_mut(&memory_type_index) {
+                *capacity += bytes.len() as vk::DeviceSize;
+            }
+        }
+    }
+
+    fn fake_allocator() -> (Allocator, Rc<RefCell<FakeHeap>>) {
+        let mut memory_properties = vk::PhysicalDeviceMemoryProperties {
+            memory_type_count: 3,
+            memory_heap_count: 2,
+            ..Default::default()
+        };
+        memory_properties.memory_heaps[0].size = DEVICE_BLOCK_SIZE * 8;
+        memory_properties.memory_heaps[1].size = HOST_BLOCK_SIZE * 8;
+
+        let host = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        memory_properties.memory_types[DEVICE_LOCAL as usize] = vk::MemoryType {
+            property_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            heap_index: 0,
+        };
+        memory_properties.memory_types[HOST_VISIBLE as usize] = vk::MemoryType {
+            property_flags: host,
+            heap_index: 1,
+        };
+        memory_properties.memory_types[DEVICE_LOCAL_HOST_VISIBLE as usize] = vk::MemoryType {
+            property_flags: host | vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            heap_index: 1,
+        };
+
+        let heap = Rc::new(RefCell::new(FakeHeap::default()));
+        let allocator = Allocator::new(Box::new(FakeBackend(heap.clone())), memory_properties);
+
+        (allocator, heap)
+    }
+
+    fn requirements(size: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::MemoryRequirements {
+        vk::MemoryRequirements {
+            size,
+            alignment,
+            memory_type_bits: !0,
+        }
+    }
+
+    fn allocate_device_local(allocator: &Allocator, size: vk::DeviceSize) -> Allocation {
+        allocator
+            .allocate(
+                requirements(size, 1),
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                AllocationKind::Buffer,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn allocations_are_aligned_and_disjoint() {
+        let (allocator, heap) = fake_allocator();
+
+        let allocations: Vec<Allocation> =
+            [(100, 1), (256, 256), (1000, 4096), (300, 64), (8, 512)]
+                .into_iter()
+                .map(|(size, alignment)| {
+                    let allocation = allocator
+                        .allocate(
+                            requirements(size, alignment),
+                            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                            AllocationKind::Buffer,
+                        )
+                        .unwrap();
+                    assert_eq!(allocation.offset % alignment, 0);
+                    allocation
+                })
+                .collect();
+
+        // all in one block, without overlaps
+        assert_eq!(heap.borrow().live.len(), 1);
+        for (i, a) in allocations.iter().enumerate() {
+            assert_eq!(a.memory, allocations[0].memory);
+            for b in &allocations[i + 1..] {
+                assert!(a.offset + a.size <= b.offset || b.offset + b.size <= a.offset);
+            }
+        }
+
+        let stats = allocator.stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].memory_type_index, DEVICE_LOCAL);
+        assert_eq!(stats[0].block_count, 1);
+        assert_eq!(stats[0].allocation_count, 5);
+        assert_eq!(stats[0].reserved, DEVICE_BLOCK_SIZE);
+        assert_eq!(stats[0].requested, 100 + 256 + 1000 + 300 + 8);
+        assert_eq!(stats[0].used, 256 + 256 + 4096 + 512 + 512);
+    }
+
+    #[test]
+    fn buffers_and_images_use_separate_blocks() {
+        let (allocator, heap) = fake_allocator();
+
+        let buffer = allocate_device_local(&allocator, 1024);
+        let image = allocator
+            .allocate(
+                requirements(1024, 1),
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                AllocationKind::Image,
+            )
+            .unwrap();
+
+        assert_ne!(buffer.memory, image.memory);
+        assert_eq!(heap.borrow().live.len(), 2);
+    }
+
+    #[test]
+    fn large_allocations_are_dedicated() {
+        let (allocator, heap) = fake_allocator();
+
+        let small = allocate_device_local(&allocator, DEVICE_BLOCK_SIZE / 2);
+        let large = allocate_device_local(&allocator, DEVICE_BLOCK_SIZE / 2 + 1);
+
+        assert_ne!(small.memory, large.memory);
+        assert_eq!(large.offset, 0);
+        assert_eq!(
+            heap.borrow().live[&large.memory].1.len() as vk::DeviceSize,
+            large.size
+        );
+
+        let stats = allocator.stats();
+        assert_eq!(stats[0].block_count, 1);
+        assert_eq!(stats[0].dedicated_count, 1);
+
+        // dedicated memory goes back to the device right away
+        let memory = large.memory;
+        large.free();
+        assert!(!heap.borrow().live.contains_key(&memory));
+        assert_eq!(allocator.stats()[0].dedicated_count, 0);
+
+        small.free();
+    }
+
+    #[test]
+    fn host_visible_allocations_are_mapped() {
+        let (allocator, heap) = fake_allocator();
+
+        let device_local = allocate_device_local(&allocator, 64);
+        assert!(device_local.mapped_ptr().is_none());
+
+        let first = allocator
+            .allocate(
+                requirements(64, 1),
+                vk::MemoryPropertyFlags::HOST_VISIBLE,
+                AllocationKind::Buffer,
+            )
+            .unwrap();
+        let second = allocator
+            .allocate(
+                requirements(64, 1),
+                vk::MemoryPropertyFlags::HOST_VISIBLE,
+                AllocationKind::Buffer,
+            )
+            .unwrap();
+
+        // the pointers are offset into the mapping of the block
+        unsafe { *second.mapped_ptr().unwrap() = 42 };
+        assert_eq!(first.memory, second.memory);
+        assert_eq!(
+            heap.borrow().live[&second.memory].1[second.offset as usize],
+            42
+        );
+    }
+
+    #[test]
+    fn exhausted_memory_types_fall_back_then_fail() {
+        let (allocator, heap) = fake_allocator();
+        heap.borrow_mut().capacity.insert(HOST_VISIBLE, 0);
+        heap.borrow_mut()
+            .capacity
+            .insert(DEVICE_LOCAL_HOST_VISIBLE, HOST_BLOCK_SIZE);
+
+        let allocate = || {
+            allocator.allocate(
+                requirements(HOST_BLOCK_SIZE / 2, 1),
+                vk::MemoryPropertyFlags::HOST_VISIBLE,
+                AllocationKind::Buffer,
+            )
+        };
+
+        // both fit in one block of the second compatible type
+        let first = allocate().unwrap();
+        let second = allocate().unwrap();
+        assert_eq!(first.memory, second.memory);
+        assert_eq!(
+            heap.borrow().live[&first.memory].0,
+            DEVICE_LOCAL_HOST_VISIBLE
+        );
+
+        match allocate() {
+            Err(AllocationError::OutOfMemory {
+                size,
+                properties,
+                result,
+            }) => {
+                assert_eq!(size, HOST_BLOCK_SIZE / 2);
+                assert_eq!(properties, vk::MemoryPropertyFlags::HOST_VISIBLE);
+                assert_eq!(result, vk::Result::ERROR_OUT_OF_DEVICE_MEMORY);
+            }
+            _ => panic!("Expected the allocation to run out of memory."),
+        }
+
+        // freeing makes room again
+        second.free();
+        allocate().unwrap();
+    }
+
+    #[test]
+    fn incompatible_requirements_fail() {
+        let (allocator, _) = fake_allocator();
+
+        let result = allocator.allocate(
+            vk::MemoryRequirements {
+                memory_type_bits: 1 << HOST_VISIBLE,
+                ..requirements(64, 1)
+            },
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            AllocationKind::Buffer,
+        );
+
+        assert!(matches!(
+            result,
+            Err(AllocationError::NoCompatibleMemoryType {
+                type_bits: 0b10,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn empty_blocks_are_released() {
+        let (allocator, heap) = fake_allocator();
+
+        let first = allocate_device_local(&allocator, 1024);
+        let second = allocate_device_local(&allocator, 1024);
+
+        // the block is reused after it was emptied
+        first.free();
+        let third = allocate_device_local(&allocator, 1024);
+        assert_eq!(third.memory, second.memory);
+
+        second.free();
+        allocator.release_empty_blocks();
+        assert_eq!(heap.borrow().live.len(), 1);
+
+        third.free();
+        allocator.release_empty_blocks();
+        assert!(heap.borrow().live.is_empty());
+        assert_eq!(allocator.stats()[0].reserved, 0);
+    }
+
+    #[test]
+    fn sparse_blocks_are_defragmentation_candidates() {
+        let (allocator, heap) = fake_allocator();
+
+        // fill a block and start a second one
+        let mut allocations: Vec<Allocation> = (0..5)
+            .map(|_| allocate_device_local(&allocator, DEVICE_BLOCK_SIZE / 4))
+            .collect();
+        assert_eq!(heap.borrow().live.len(), 2);
+        assert_eq!(allocator.defragmentation_candidates(0.25).len(), 1);
+
+        // leaves a single quarter of the first block
+        for allocation in allocations.drain(1..4) {
+            allocation.free();
+        }
+
+        let candidates = allocator.defragmentation_candidates(0.25);
+        assert_eq!(candidates.len(), 2);
+        assert!(candidates.iter().all(|candidate| {
+            candidate.memory_type_index == DEVICE_LOCAL
+                && candidate.used == DEVICE_BLOCK_SIZE / 4
+                && candidate.size == DEVICE_BLOCK_SIZE
+        }));
+        assert!(allocator.defragmentation_candidates(0.2).is_empty());
+
+        // empty blocks are left to `release_empty_blocks`
+        for allocation in allocations {
+            allocation.free();
+        }
+        assert!(allocator.defragmentation_candidates(1.0).is_empty());
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::VecDeque;
+
+use ash::{vk, Device};
+
+use crate::utils::BufferResource;
+
+use super::Allocator;
+
+const RING_SIZE: vk::DeviceSize = 32 * 1024 * 1024;
+const COPY_ALIGNMENT: vk::DeviceSize = 16;
+
+/// Stages that may consume uploaded data, submissions using uploads wait for them there.
+pub fn upload_dst_stages() -> vk::PipelineStageFlags {
+    vk::PipelineStageFlags::TRANSFER
+        | vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR
+        | vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR
+        | vk::PipelineStageFlags::COMPUTE_SHADER
+}
+
+/// Records the queue family ownership acquisition of buffers uploaded on a dedicated transfer
+/// queue, the submission must also wait for the uploader timeline.
+pub fn record_acquire_barriers(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    barriers: &[vk::BufferMemoryBarrier<'static>],
+) {
+    if barriers.is_empty() {
+        return;
+    }
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            upload_dst_stages(),
+            vk::DependencyFlags::empty(),
+            &[],
+            barriers,
+            &[],
+        );
+    }
+}
+
+struct PendingCopy {
+    dst: vk::Buffer,
+    concurrent: bool,
+    region: vk::BufferCopy,
+}
+
+struct Batch {
+    command_buffer: vk::CommandBuffer,
+    timeline_value: u64,
+    ring_end: vk::DeviceSize,
+}
+
+/// Copies CPU data to device local buffers through a host visible ring buffer. Writes are
+/// batched until `flush`, which submits them to the transfer queue and signals a timeline
+/// semaphore that consumers wait on.
+///
+/// With a dedicated transfer queue, exclusive destination buffers are released to the graphics
+/// queue after their copies. They must not have been used on the graphics queue before, which
+/// would have to release them first, and their previous contents are not preserved. Buffers
+/// uploaded again after the graphics queue read them, like the instance buffers, are created
+/// with `BufferResource::new_shared` and `queue_family_indices` and change no owner.
+pub struct StagingUploader {
+    ring: BufferResource,
+    head: vk::DeviceSize,
+    tail: vk::DeviceSize,
+
+    queue: vk::Queue,
+    queue_family_index: u32,
+    graphics_queue_family_index: u32,
+    command_pool: vk::CommandPool,
+    free_command_buffers: Vec<vk::CommandBuffer>,
+
+    pending: Vec<PendingCopy>,
+    in_flight: VecDeque<Batch>,
+    acquire_barriers: Vec<vk::BufferMemoryBarrier<'static>>,
+
+    timeline: vk::Semaphore,
+    submitted_value: u64,
+    waited_value: u64,
+}
+
+impl StagingUploader {
+    pub fn new(
+        device: &Device,
+        allocator: &Allocator,
+        queue: vk::Queue,
+        queue_family_index: u32,
+        graphics_queue_family_index: u32,
+    ) -> Self {
+        let ring = BufferResource::new(
+            RING_SIZE,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            device,
+            allocator,
+        );
+
+        let pool_create_info = vk::CommandPoolCreateInfo::default()
+            .flags(
+                vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER
+                    | vk::CommandPoolCreateFlags::TRANSIENT,
+            )
+            .queue_family_index(queue_family_index);
+
+        let command_pool = unsafe { device.create_command_pool(&pool_create_info, None) }.unwrap();
+
+        let mut semaphore_type_create_info = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+
+        let semaphore_create_info =
+            vk::SemaphoreCreateInfo::default().push_next(&mut semaphore_type_create_info);
+
+        let timeline = unsafe { device.create_semaphore(&semaphore_create_info, None) }
+            .expect("Create upload timeline semaphore failed.");
+
+        StagingUploader {
+            ring,
+            head: 0,
+            tail: 0,
+            queue,
+            queue_family_index,
+            graphics_queue_family_index,
+            command_pool,
+            free_command_buffers: vec![],
+            pending: vec![],
+            in_flight: VecDeque::new(),
+            acquire_barriers: vec![],
+            timeline,
+            submitted_value: 0,
+            waited_value: 0,
+        }
+    }
+
+    pub fn timeline(&self) -> vk::Semaphore {
+        self.timeline
+    }
+
+    /// Queue families sharing the buffers uploaded again once the graphics queue used them, a
+    /// single one without a dedicated transfer queue.
+    pub fn queue_family_indices(&self) -> Vec<u32> {
+        let mut indices = vec![self.graphics_queue_family_index, self.queue_family_index];
+        indices.dedup();
+        indices
+    }
+
+    /// Queues a copy of `data` to `dst` at `dst_offset`, executed on the next `flush`.
+    pub fn upload<T: Copy>(
+        &mut self,
+        device: &Device,
+        dst: &BufferResource,
+        dst_offset: vk::DeviceSize,
+        data: &[T],
+    ) {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data))
+        };
+
+        assert!(
+            dst_offset + bytes.len() as vk::DeviceSize <= dst.size,
+            "Upload is larger than the destination buffer ({}, {}).",
+            dst.size,
+            dst_offset + bytes.len() as vk::DeviceSize
+        );
+
+        // data larger than the ring goes through in several batches
+        let mut chunk_offset = 0;
+        for chunk in bytes.chunks((RING_SIZE / 2) as usize) {
+            let size = chunk.len() as vk::DeviceSize;
+            let src_offset = self.reserve(device, size);
+
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    chunk.as_ptr(),
+                    self.ring.mapped_ptr().add(src_offset as usize),
+                    chunk.len(),
+                );
+            }
+
+            self.pending.push(PendingCopy {
+                dst: dst.buffer,
+                concurrent: dst.concurrent,
+                region: vk::BufferCopy {
+                    src_offset,
+                    dst_offset: dst_offset + chunk_offset,
+                    size,
+                },
+            });
+
+            chunk_offset += size;
+        }
+    }
+
+    /// Returns the ring offset of `size` free bytes, submitting and waiting for earlier batches
+    /// when the ring is full.
+    fn reserve(&mut self, device: &Device, size: vk::DeviceSize) -> vk::DeviceSize {
+        loop {
+            if let Some(offset) = self.try_reserve(size) {
+                return offset;
+            }
+
+            if !self.pending.is_empty() {
+                self.flush(device);
+            }
+
+            let oldest_value = self.in_flight.front().unwrap().timeline_value;
+            self.wait(device, oldest_value);
+            self.reclaim(device);
+        }
+    }
+
+    fn try_reserve(&mut self, size: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        if self.in_flight.is_empty() && self.pending.is_empty() {
+            self.head = 0;
+            self.tail = 0;
+        }
+
+        let offset = self.head.next_multiple_of(COPY_ALIGNMENT);
+
+        if self.head >= self.tail {
+            // live data lies between tail and head
+            if offset + size <= RING_SIZE {
+                self.head = offset + size;
+                Some(offset)
+            } else if size < self.tail {
+                self.head = size;
+                Some(0)
+            } else {
+                None
+            }
+        } else if offset + size < self.tail {
+            // wrapped, live data lies after tail and before head
+            self.head = offset + size;
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    fn wait(&self, device: &Device, value: u64) {
+        let semaphores = [self.timeline];
+        let values = [value];
+        let wait_info = vk::SemaphoreWaitInfo::default()
+            .semaphores(&semaphores)
+            .values(&values);
+
+        unsafe { device.wait_semaphores(&wait_info, u64::MAX) }
+            .expect("Wait for upload timeline failed.");
+    }
+
+    /// Releases the ring space and command buffers of completed batches.
+    fn reclaim(&mut self, device: &Device) {
+        let completed_value = unsafe { device.get_semaphore_counter_value(self.timeline) }
+            .expect("Get upload timeline value failed.");
+
+        while let Some(batch) = self
+            .in_flight
+            .front()
+            .filter(|batch| batch.timeline_value <= completed_value)
+        {
+            self.tail = batch.ring_end;
+            self.free_command_buffers.push(batch.command_buffer);
+            self.in_flight.pop_front();
+        }
+    }
+
+    /// Submits every queued copy, returns the timeline value signaled once they complete.
+    pub fn flush(&mut self, device: &Device) -> u64 {
+        if self.pending.is_empty() {
+            return self.submitted_value;
+        }
+
+        self.reclaim(device);
+
+        let command_buffer = match self.free_command_buffers.pop() {
+            Some(command_buffer) => command_buffer,
+            None => {
+                let allocate_info = vk::CommandBufferAllocateInfo::default()
+                    .command_buffer_count(1)
+                    .command_pool(self.command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY);
+
+                unsafe { device.allocate_command_buffers(&allocate_info) }.unwrap()[0]
+            }
+        };
+
+        let pending = std::mem::take(&mut self.pending);
+        // exclusive buffers whose ownership moves to the graphics queue
+        let mut dst_buffers: Vec<vk::Buffer> = vec![];
+
+        unsafe {
+            device
+                .begin_command_buffer(
+                    command_buffer,
+                    &vk::CommandBufferBeginInfo::default()
+                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )
+                .unwrap();
+
+            // consecutive copies to the same buffer share a command
+            for copies in pending.chunk_by(|a, b| a.dst == b.dst) {
+                let regions: Vec<vk::BufferCopy> = copies.iter().map(|copy| copy.region).collect();
+
+                device.cmd_copy_buffer(command_buffer, self.ring.buffer, copies[0].dst, &regions);
+
+                if !copies[0].concurrent && !dst_buffers.contains(&copies[0].dst) {
+                    dst_buffers.push(copies[0].dst);
+                }
+            }
+
+            if self.queue_family_index != self.graphics_queue_family_index
+                && !dst_buffers.is_empty()
+            {
+                let ownership_barrier = |buffer: vk::Buffer| {
+                    vk::BufferMemoryBarrier::default()
+                        .src_queue_family_index(self.queue_family_index)
+                        .dst_queue_family_index(self.graphics_queue_family_index)
+                        .buffer(buffer)
+                        .size(vk::WHOLE_SIZE)
+                };
+
+                let release_barriers: Vec<vk::BufferMemoryBarrier> = dst_buffers
+                    .iter()
+                    .map(|buffer| {
+                        ownership_barrier(*buffer).src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    })
+                    .collect();
+
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &release_barriers,
+                    &[],
+                );
+
+                self.acquire_barriers
+                    .extend(dst_buffers.iter().map(|buffer| {
+                        ownership_barrier(*buffer).dst_access_mask(vk::AccessFlags::MEMORY_READ)
+                    }));
+            }
+
+            device.end_command_buffer(command_buffer).unwrap();
+
+            let timeline_value = self.submitted_value + 1;
+
+            let command_buffers = [command_buffer];
+            let signal_semaphores = [self.timeline];
+            let signal_values = [timeline_value];
+
+            let mut timeline_submit_info =
+                vk::TimelineSemaphoreSubmitInfo::default().signal_semaphore_values(&signal_values);
+
+            let submit_info = vk::SubmitInfo::default()
+                .command_buffers(&command_buffers)
+                .signal_semaphores(&signal_semaphores)
+                .push_next(&mut timeline_submit_info);
+
+            device
+                .queue_submit(self.queue, &[submit_info], vk::Fence::null())
+                .expect("Upload queue submit failed.");
+
+            self.in_flight.push_back(Batch {
+                command_buffer,
+                timeline_value,
+                ring_end: self.head,
+            });
+            self.submitted_value = timeline_value;
+        }
+
+        self.submitted_value
+    }
+
+    /// Ownership acquisitions for the buffers uploaded since the last call, to be recorded with
+    /// `record_acquire_barriers` on the graphics queue.
+    pub fn take_acquire_barriers(&mut self) -> Vec<vk::BufferMemoryBarrier<'static>> {
+        std::mem::take(&mut self.acquire_barriers)
+    }
+
+    /// Timeline value the next graphics submission has to wait for, if uploads were submitted
+    /// since the last one.
+    pub fn take_wait_value(&mut self) -> Option<u64> {
+        (self.submitted_value > self.waited_value).then(|| {
+            self.waited_value = self.submitted_value;
+            self.submitted_value
+        })
+    }
+
+    /// Blocks until every submitted upload has completed.
+    pub fn wait_idle(&mut self, device: &Device) {
+        self.wait(device, self.submitted_value);
+        self.reclaim(device);
+        self.waited_value = self.submitted_value;
+    }
+
+    pub fn destroy(self, device: &Device) {
+        self.wait(device, self.submitted_value);
+
+        unsafe {
+            device.destroy_command_pool(self.command_pool, None);
+            device.destroy_semaphore(self.timeline, None);
+            self.ring.destroy(device);
+        }
+    }
+}
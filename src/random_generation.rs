@@ -22,7 +22,7 @@ pub fn create_cube_instances(
         let y = (i / n) % n;
         let z = i % n;
 
-        if x % 16 == 0 && y == 0 && z == 0 {
+        if x.is_multiple_of(16) && y == 0 && z == 0 {
             #[cfg(debug_assertions)]
             println!("{:.1}%", (i as f32 / (n * n * n) as f32) * 100.0);
         }
@@ -48,6 +48,7 @@ pub fn create_cube_instances(
             positions.push(VoxelInfos {
                 position: glm::Vec3::new(x as f32, y as f32, z as f32),
                 palette_index: 0,
+                interior_faces: 0,
             });
         }
     }
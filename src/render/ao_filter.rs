@@ -0,0 +1,454 @@
+use ash::{vk, Device};
+use bytemuck::bytes_of;
+
+use crate::{
+    shaders::reflect::{reflect, ExpectedBinding, PipelineInterface},
+    uniform_types::{FilterPushConstants, GlobalUniforms},
+    utils::{create_descriptor_set_layout_and_pool, create_shader_module},
+};
+
+use super::{frame::FRAMES_IN_FLIGHT, tonemap::HDR_FORMAT};
+
+/// Formats of the G-buffer traced by the ray generation shader, and of the occlusion between the
+/// two filter passes. The traced occlusion holds the accumulated visibility and history length.
+pub const AO_FORMAT: vk::Format = vk::Format::R32G32_SFLOAT;
+pub const DEPTH_NORMAL_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+pub const ALBEDO_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+pub const FILTERED_AO_FORMAT: vk::Format = vk::Format::R16_SFLOAT;
+pub const REFLECTION_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+pub const FOG_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Index of the horizontal and vertical pass in the per pass arrays.
+pub const HORIZONTAL_PASS: usize = 0;
+pub const VERTICAL_PASS: usize = 1;
+/// Index of the set of the vertical pass reading the denoised occlusion, which it composites
+/// without filtering it again.
+pub const DENOISED_VERTICAL_SET: usize = 2;
+
+const FILTER_SHADERS: [(&str, &[u8]); 2] = [
+    (
+        "shaders/AO/filter.vert.glsl",
+        include_bytes!(concat!(env!("OUT_DIR"), "/filter_vert.spv")),
+    ),
+    (
+        "shaders/AO/filter.frag.glsl",
+        include_bytes!(concat!(env!("OUT_DIR"), "/filter_frag.spv")),
+    ),
+];
+
+/// Descriptors written by `write_descriptor_sets`, checked against the shaders.
+const FILTER_BINDINGS: [ExpectedBinding; 7] = [
+    ExpectedBinding {
+        set: 0,
+        binding: 0,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "depth and normal image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 1,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "ao image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 2,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "albedo image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 3,
+        descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+        name: "uniforms buffer",
+        size: Some(std::mem::size_of::<GlobalUniforms>() as u32),
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 4,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "environment map",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 5,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "reflection image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 6,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "fog image",
+        size: None,
+    },
+];
+
+/// Depth and normal aware low-pass filter of the ambient occlusion, run as a horizontal then a
+/// vertical fullscreen pass. The vertical pass also lights the albedo with the sun and the
+/// occluded sky, adds the reflections, and composites the fog over them into the HDR image.
+pub struct AoFilter {
+    render_passes: [vk::RenderPass; 2],
+    pipelines: [vk::Pipeline; 2],
+    pipeline_layout: vk::PipelineLayout,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    sampler: vk::Sampler,
+}
+
+impl AoFilter {
+    pub fn new(device: &Device) -> anyhow::Result<Self> {
+        let mut reflections = vec![];
+        for (path, code) in FILTER_SHADERS {
+            reflections.push((path, reflect(code).map_err(anyhow::Error::msg)?));
+        }
+
+        let interface = PipelineInterface::new(&reflections)?;
+        interface.check(&FILTER_BINDINGS)?;
+        interface.check_push_constants(Some(std::mem::size_of::<FilterPushConstants>() as u32))?;
+
+        // one set per pass, plus the denoised one, and frame in flight
+        let (descriptor_set_layout, descriptor_pool) = create_descriptor_set_layout_and_pool(
+            device,
+            &interface,
+            0,
+            3 * FRAMES_IN_FLIGHT as u32,
+        )?;
+
+        let layouts = [descriptor_set_layout];
+        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&layouts)
+            .push_constant_ranges(&interface.push_constant_ranges);
+
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&layout_create_info, None) }?;
+
+        let render_passes = [
+            Self::create_render_pass(device, FILTERED_AO_FORMAT)?,
+            Self::create_render_pass(device, HDR_FORMAT)?,
+        ];
+
+        let vertex_module = unsafe { create_shader_module(device, FILTER_SHADERS[0].1) }?;
+        let fragment_module = unsafe { create_shader_module(device, FILTER_SHADERS[1].1) }?;
+
+        let pipelines = Self::create_pipelines(
+            device,
+            pipeline_layout,
+            render_passes,
+            vertex_module,
+            fragment_module,
+        );
+
+        unsafe {
+            device.destroy_shader_module(vertex_module, None);
+            device.destroy_shader_module(fragment_module, None);
+        }
+
+        // the filter reads exact texels, taps outside of the image repeat the border
+        let sampler_create_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(vk::LOD_CLAMP_NONE);
+
+        let sampler = unsafe { device.create_sampler(&sampler_create_info, None) }?;
+
+        Ok(AoFilter {
+            render_passes,
+            pipelines: pipelines?,
+            pipeline_layout,
+            descriptor_set_layout,
+            descriptor_pool,
+            sampler,
+        })
+    }
+
+    /// The graph transitions the target before and after the pass, the render pass keeps its
+    /// layout.
+    fn create_render_pass(device: &Device, format: vk::Format) -> anyhow::Result<vk::RenderPass> {
+        let attachments = [vk::AttachmentDescription {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            store_op: vk::AttachmentStoreOp::STORE,
+            initial_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ..Default::default()
+        }];
+        let color_attachment_refs = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+
+        let subpass = vk::SubpassDescription::default()
+            .color_attachments(&color_attachment_refs)
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+
+        let render_pass_create_info = vk::RenderPassCreateInfo::default()
+            .attachments(&attachments)
+            .subpasses(std::slice::from_ref(&subpass));
+
+        Ok(unsafe { device.create_render_pass(&render_pass_create_info, None) }?)
+    }
+
+    fn create_pipelines(
+        device: &Device,
+        pipeline_layout: vk::PipelineLayout,
+        render_passes: [vk::RenderPass; 2],
+        vertex_module: vk::ShaderModule,
+        fragment_module: vk::ShaderModule,
+    ) -> anyhow::Result<[vk::Pipeline; 2]> {
+        let shader_stages = [
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vertex_module)
+                .name(c"main"),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(fragment_module)
+                .name(c"main"),
+        ];
+
+        // fullscreen triangle generated from the vertex index
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default();
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .line_width(1.0);
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::default()
+            .color_write_mask(vk::ColorComponentFlags::RGBA)];
+        let color_blend_state =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&color_blend_attachments);
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+        let create_infos = render_passes.map(|render_pass| {
+            vk::GraphicsPipelineCreateInfo::default()
+                .stages(&shader_stages)
+                .vertex_input_state(&vertex_input_state)
+                .input_assembly_state(&input_assembly_state)
+                .viewport_state(&viewport_state)
+                .rasterization_state(&rasterization_state)
+                .multisample_state(&multisample_state)
+                .color_blend_state(&color_blend_state)
+                .dynamic_state(&dynamic_state)
+                .layout(pipeline_layout)
+                .render_pass(render_pass)
+        });
+
+        let pipelines = unsafe {
+            device.create_graphics_pipelines(vk::PipelineCache::null(), &create_infos, None)
+        }
+        .map_err(|(_, result)| result)?;
+
+        Ok([pipelines[0], pipelines[1]])
+    }
+
+    /// Allocates the descriptor sets of the two passes of a frame, and of the vertical pass
+    /// after the denoiser.
+    pub fn allocate_descriptor_sets(
+        &self,
+        device: &Device,
+    ) -> anyhow::Result<[vk::DescriptorSet; 3]> {
+        let layouts = [self.descriptor_set_layout; 3];
+
+        let descriptor_sets = unsafe {
+            device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(self.descriptor_pool)
+                    .set_layouts(&layouts),
+            )
+        }?;
+
+        Ok([descriptor_sets[0], descriptor_sets[1], descriptor_sets[2]])
+    }
+
+    /// `hdr_view` is the HDR image the vertical pass renders to.
+    pub fn create_framebuffers(
+        &self,
+        device: &Device,
+        extent: vk::Extent2D,
+        filtered_ao_view: vk::ImageView,
+        hdr_view: vk::ImageView,
+    ) -> anyhow::Result<[vk::Framebuffer; 2]> {
+        let mut framebuffers = [vk::Framebuffer::null(); 2];
+
+        for (pass, view) in [filtered_ao_view, hdr_view].into_iter().enumerate() {
+            let attachments = [view];
+            let framebuffer_create_info = vk::FramebufferCreateInfo::default()
+                .render_pass(self.render_passes[pass])
+                .attachments(&attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+
+            framebuffers[pass] =
+                unsafe { device.create_framebuffer(&framebuffer_create_info, None) }?;
+        }
+
+        Ok(framebuffers)
+    }
+
+    /// The horizontal pass filters `ao_view`, the vertical pass filters its result in
+    /// `filtered_ao_view`, or the output of the denoiser in `denoised_view`. The uniforms and the
+    /// environment map give the sky light.
+    #[allow(clippy::too_many_arguments)]
+    pub fn write_descriptor_sets(
+        &self,
+        device: &Device,
+        descriptor_sets: [vk::DescriptorSet; 3],
+        depth_normal_view: vk::ImageView,
+        ao_view: vk::ImageView,
+        filtered_ao_view: vk::ImageView,
+        denoised_view: vk::ImageView,
+        albedo_view: vk::ImageView,
+        reflection_view: vk::ImageView,
+        fog_view: vk::ImageView,
+        uniforms_buffer: vk::Buffer,
+        environment_map_info: vk::DescriptorImageInfo,
+    ) {
+        let image_info = |view| {
+            [vk::DescriptorImageInfo::default()
+                .sampler(self.sampler)
+                .image_view(view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]
+        };
+
+        let depth_normal_info = image_info(depth_normal_view);
+        let ao_infos = [
+            image_info(ao_view),
+            image_info(filtered_ao_view),
+            image_info(denoised_view),
+        ];
+        let albedo_info = image_info(albedo_view);
+        let reflection_info = image_info(reflection_view);
+        let fog_info = image_info(fog_view);
+        let environment_map_info = [environment_map_info];
+        let uniforms_buffer_info = [vk::DescriptorBufferInfo::default()
+            .buffer(uniforms_buffer)
+            .range(vk::WHOLE_SIZE)];
+
+        let writes: Vec<vk::WriteDescriptorSet> = descriptor_sets
+            .iter()
+            .zip(&ao_infos)
+            .flat_map(|(descriptor_set, ao_info)| {
+                [
+                    (0, &depth_normal_info),
+                    (1, ao_info),
+                    (2, &albedo_info),
+                    (4, &environment_map_info),
+                    (5, &reflection_info),
+                    (6, &fog_info),
+                ]
+                .map(|(binding, info)| {
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(*descriptor_set)
+                        .dst_binding(binding)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(info)
+                })
+                .into_iter()
+                .chain([vk::WriteDescriptorSet::default()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(3)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&uniforms_buffer_info)])
+            })
+            .collect();
+
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+
+    /// Records one fullscreen pass, `pass` is `HORIZONTAL_PASS` or `VERTICAL_PASS`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_pass(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pass: usize,
+        framebuffer: vk::Framebuffer,
+        descriptor_set: vk::DescriptorSet,
+        extent: vk::Extent2D,
+        push_constants: &FilterPushConstants,
+    ) {
+        let render_area = vk::Rect2D::default().extent(extent);
+        let viewport = vk::Viewport::default()
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .max_depth(1.0);
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo::default()
+            .render_pass(self.render_passes[pass])
+            .framebuffer(framebuffer)
+            .render_area(render_area);
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipelines[pass],
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytes_of(push_constants),
+            );
+            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            device.cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            for pipeline in self.pipelines {
+                device.destroy_pipeline(pipeline, None);
+            }
+            for render_pass in self.render_passes {
+                device.destroy_render_pass(render_pass, None);
+            }
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
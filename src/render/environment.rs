@@ -0,0 +1,39 @@
+use ash::{vk, Device};
+
+use crate::memory::Allocation;
+
+/// Format of the environment map, the radiance of bright skies overflows half floats.
+pub const ENVIRONMENT_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+
+/// Equirectangular image of the sky radiance, read by the sky of every render mode. A single
+/// black texel stands in for it when no map is configured.
+pub struct EnvironmentMap {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub allocation: Option<Allocation>,
+    pub sampler: vk::Sampler,
+    /// False for the stand-in, the shaders then use the analytic sky.
+    pub loaded: bool,
+}
+
+impl EnvironmentMap {
+    /// The image stays in `SHADER_READ_ONLY_OPTIMAL` after its upload.
+    pub fn descriptor_info(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo::default()
+            .sampler(self.sampler)
+            .image_view(self.view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+        }
+
+        if let Some(allocation) = self.allocation.take() {
+            allocation.free();
+        }
+    }
+}
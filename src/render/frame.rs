@@ -0,0 +1,170 @@
+use ash::{vk, Device};
+
+use crate::{memory::Allocation, utils::BufferResource};
+
+/// Frames the CPU may record ahead of the GPU.
+pub const FRAMES_IN_FLIGHT: usize = 2;
+
+/// Format of the path traced radiance.
+pub const ACCUMULATION_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+
+/// Format of the light reservoirs: the light sample packed in the first two channels, then the
+/// bits of its contribution weight and of the sample count.
+pub const RESERVOIR_FORMAT: vk::Format = vk::Format::R32G32B32A32_UINT;
+
+/// Format of the debug views, colors already in the display range.
+pub const DEBUG_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+/// An image sized like the render resolution, or like the swapchain.
+pub struct FrameImage {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub allocation: Option<Allocation>,
+}
+
+impl FrameImage {
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+        }
+
+        if let Some(allocation) = self.allocation.take() {
+            allocation.free();
+        }
+    }
+}
+
+/// Images of a frame that depend on the swapchain size and on the render scale, recreated with
+/// them. Their content does not outlive the frame. Only the output has the size of the
+/// swapchain, the others have the render resolution.
+pub struct FrameImages {
+    /// Final image of the frame, copied to the swapchain.
+    pub output: FrameImage,
+    /// Radiance rendered by the current mode, exposed and tonemapped.
+    pub hdr: FrameImage,
+    /// Frame encoded for the display, upscaled into the output.
+    pub tonemapped: FrameImage,
+    /// G-buffer traced by the ray generation shader.
+    pub ao: FrameImage,
+    pub depth_normal: FrameImage,
+    pub albedo: FrameImage,
+    /// Occlusion after the horizontal filter pass.
+    pub filtered_ao: FrameImage,
+    /// Reflected radiance weighted by the Fresnel term, and the weight of the diffuse light.
+    pub reflection: FrameImage,
+    /// Light scattered by the fog along the primary rays, and the share of the light behind it
+    /// let through.
+    pub fog: FrameImage,
+    /// Mean of the path traced samples, carried over from the previous frame.
+    pub accumulation: FrameImage,
+    /// Light reservoirs after the temporal reuse.
+    pub reservoir: FrameImage,
+    /// Light reservoirs after the spatial reuse, carried over to the next frame.
+    pub spatial_reservoir: FrameImage,
+    /// Signal accumulated by the denoiser and the moments of its luminance, carried over to the
+    /// next frame.
+    pub svgf_history: FrameImage,
+    pub svgf_moments: FrameImage,
+    /// Signal and variance between the wavelet iterations of the denoiser.
+    pub svgf_filtered: [FrameImage; 2],
+    /// Output of the denoiser.
+    pub denoised: FrameImage,
+    /// Motion of the primary hits since the previous frame, in pixels.
+    pub motion: FrameImage,
+    /// HDR image resolved by the TAA, carried over to the next frame as its history.
+    pub taa_resolved: FrameImage,
+    /// Debug view of the primary hits, traced instead of the frame when one is selected.
+    pub debug: FrameImage,
+    /// Targets of the horizontal and vertical filter passes.
+    pub filter_framebuffers: [vk::Framebuffer; 2],
+    /// Target of the tonemapping pass.
+    pub tonemap_framebuffer: vk::Framebuffer,
+    /// Target of the pass writing the output.
+    pub upscale_framebuffer: vk::Framebuffer,
+}
+
+impl FrameImages {
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for framebuffer in self.filter_framebuffers {
+                device.destroy_framebuffer(framebuffer, None);
+            }
+            device.destroy_framebuffer(self.tonemap_framebuffer, None);
+            device.destroy_framebuffer(self.upscale_framebuffer, None);
+        }
+
+        self.output.destroy(device);
+        self.hdr.destroy(device);
+        self.tonemapped.destroy(device);
+        self.ao.destroy(device);
+        self.depth_normal.destroy(device);
+        self.albedo.destroy(device);
+        self.filtered_ao.destroy(device);
+        self.reflection.destroy(device);
+        self.fog.destroy(device);
+        self.accumulation.destroy(device);
+        self.reservoir.destroy(device);
+        self.spatial_reservoir.destroy(device);
+        self.svgf_history.destroy(device);
+        self.svgf_moments.destroy(device);
+        for image in &mut self.svgf_filtered {
+            image.destroy(device);
+        }
+        self.denoised.destroy(device);
+        self.motion.destroy(device);
+        self.taa_resolved.destroy(device);
+        self.debug.destroy(device);
+    }
+}
+
+/// Resources owned by one frame in flight. They are only touched again once `in_flight_fence`
+/// signals that the GPU is done with the previous frame using them.
+pub struct FrameResources {
+    /// Acceleration structure updates, tracing and filtering, recorded before the swapchain
+    /// image is known.
+    pub command_buffer: vk::CommandBuffer,
+    /// Copy of the output image to the acquired swapchain image.
+    pub present_command_buffer: vk::CommandBuffer,
+    pub image_available_semaphore: vk::Semaphore,
+    pub in_flight_fence: vk::Fence,
+    /// Timestamps of the start and the end of `command_buffer`, read once the frame is done.
+    pub timestamp_query_pool: vk::QueryPool,
+
+    pub uniforms_buffer: BufferResource,
+    pub images: FrameImages,
+
+    pub rt_descriptor_set: vk::DescriptorSet,
+    pub uniforms_descriptor_set: vk::DescriptorSet,
+    /// Sets of the horizontal and vertical filter passes, and of the vertical pass after the
+    /// denoiser.
+    pub filter_descriptor_sets: [vk::DescriptorSet; 3],
+    /// Sets of the denoiser filtering the path traced radiance and the occlusion.
+    pub svgf_descriptor_sets: [vk::DescriptorSet; 2],
+    pub taa_descriptor_set: vk::DescriptorSet,
+    /// Sets of the tonemapping reading the HDR image and its TAA resolve.
+    pub tonemap_descriptor_sets: [vk::DescriptorSet; 2],
+    pub upscale_descriptor_set: vk::DescriptorSet,
+    /// Shared resources bound in the descriptor sets were replaced while this frame was in
+    /// flight, the sets are rewritten before it records again.
+    pub descriptors_dirty: bool,
+
+    /// Resources replaced while this frame was recorded, destroyed when it comes around again.
+    pub retired_acceleration_structures: Vec<(vk::AccelerationStructureKHR, BufferResource)>,
+    pub retired_buffers: Vec<BufferResource>,
+}
+
+impl FrameResources {
+    /// Retired resources must have been destroyed beforehand.
+    pub fn destroy(mut self, device: &Device, pool: vk::CommandPool) {
+        self.images.destroy(device);
+
+        unsafe {
+            device.free_command_buffers(pool, &[self.command_buffer, self.present_command_buffer]);
+            device.destroy_semaphore(self.image_available_semaphore, None);
+            device.destroy_fence(self.in_flight_fence, None);
+            device.destroy_query_pool(self.timestamp_query_pool, None);
+            self.uniforms_buffer.destroy(device);
+        }
+    }
+}
@@ -0,0 +1,848 @@
+use ash::{vk, Device};
+
+use super::{
+    pass::{Access, Pass, ResourceId},
+    transient::TransientResources,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferId(usize);
+
+/// Last write to a resource before the graph runs, or the state it must be left in afterwards.
+/// Without an access, `stage` is where earlier submissions last read the resource, the first
+/// write then waits for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResourceState {
+    pub stage: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+    pub layout: vk::ImageLayout,
+}
+
+impl ResourceState {
+    /// No pending write, the previous frame is known to be done with the resource.
+    pub fn idle(layout: vk::ImageLayout) -> Self {
+        ResourceState {
+            stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+            access: vk::AccessFlags::empty(),
+            layout,
+        }
+    }
+}
+
+/// Description of a transient image, a single level 2D color image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub usage: vk::ImageUsageFlags,
+}
+
+enum ImageSource {
+    Imported {
+        image: vk::Image,
+        initial: ResourceState,
+    },
+    Transient(ImageDesc),
+}
+
+struct GraphImage {
+    source: ImageSource,
+    final_state: Option<ResourceState>,
+}
+
+struct GraphBuffer {
+    buffer: vk::Buffer,
+    initial: ResourceState,
+}
+
+/// A barrier computed by the graph, independent of the actual Vulkan handles so that graph
+/// compilation can run without a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Barrier {
+    pub resource: ResourceId,
+    pub src_stage: vk::PipelineStageFlags,
+    pub dst_stage: vk::PipelineStageFlags,
+    pub src_access: vk::AccessFlags,
+    pub dst_access: vk::AccessFlags,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+}
+
+#[derive(Debug)]
+pub struct CompiledGraph {
+    /// Name of every pass with the barriers recorded before it, in pass order.
+    pub pass_barriers: Vec<(&'static str, Vec<Barrier>)>,
+    /// Transitions of imported images to their final state.
+    pub final_barriers: Vec<Barrier>,
+    /// Memory slot of every transient image, images sharing a slot alias the same memory.
+    pub image_slots: Vec<Option<usize>>,
+    pub slot_count: usize,
+}
+
+#[derive(Clone, Copy)]
+struct TrackedState {
+    layout: vk::ImageLayout,
+    written: bool,
+    write_stage: vk::PipelineStageFlags,
+    write_access: vk::AccessFlags,
+    // stages that were made aware of the last write by a barrier
+    visible_stages: vk::PipelineStageFlags,
+    // stages that read since the last write, the next write has to wait for them
+    read_stages: vk::PipelineStageFlags,
+}
+
+impl TrackedState {
+    fn new(state: ResourceState) -> Self {
+        let written = !state.access.is_empty();
+
+        TrackedState {
+            layout: state.layout,
+            written,
+            write_stage: state.stage,
+            write_access: state.access,
+            visible_stages: vk::PipelineStageFlags::empty(),
+            read_stages: if written || state.stage == vk::PipelineStageFlags::TOP_OF_PIPE {
+                vk::PipelineStageFlags::empty()
+            } else {
+                state.stage
+            },
+        }
+    }
+
+    fn src_stage(&self) -> vk::PipelineStageFlags {
+        let stage = self.write_stage | self.read_stages;
+
+        if stage.is_empty() {
+            vk::PipelineStageFlags::TOP_OF_PIPE
+        } else {
+            stage
+        }
+    }
+
+    fn barrier(
+        &self,
+        resource: ResourceId,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+        new_layout: vk::ImageLayout,
+    ) -> Barrier {
+        Barrier {
+            resource,
+            src_stage: self.src_stage(),
+            dst_stage,
+            src_access: self.write_access,
+            dst_access,
+            old_layout: self.layout,
+            new_layout,
+        }
+    }
+
+    /// Updates the state for a new access and returns the barrier it requires, if any.
+    fn transition(&mut self, resource: ResourceId, access: Access) -> Option<Barrier> {
+        let stage = access.stage();
+        let access_mask = access.access_mask();
+        let layout = match resource {
+            ResourceId::Image(_) => access.image_layout(),
+            ResourceId::Buffer(_) => vk::ImageLayout::UNDEFINED,
+        };
+        let layout_change = self.layout != layout;
+
+        if access.is_write() {
+            let barrier = (layout_change || self.written || !self.read_stages.is_empty())
+                .then(|| self.barrier(resource, stage, access_mask, layout));
+
+            *self = TrackedState {
+                layout,
+                written: true,
+                write_stage: stage,
+                write_access: access_mask,
+                visible_stages: vk::PipelineStageFlags::empty(),
+                read_stages: vk::PipelineStageFlags::empty(),
+            };
+
+            barrier
+        } else if layout_change {
+            let barrier = self.barrier(resource, stage, access_mask, layout);
+
+            // the layout transition acts as a write that only the destination stage waited for
+            *self = TrackedState {
+                layout,
+                written: true,
+                write_stage: stage,
+                write_access: vk::AccessFlags::empty(),
+                visible_stages: stage,
+                read_stages: stage,
+            };
+
+            Some(barrier)
+        } else {
+            let barrier =
+                (self.written && !self.visible_stages.contains(stage)).then_some(Barrier {
+                    resource,
+                    src_stage: self.write_stage,
+                    dst_stage: stage,
+                    src_access: self.write_access,
+                    dst_access: access_mask,
+                    old_layout: layout,
+                    new_layout: layout,
+                });
+
+            self.visible_stages |= stage;
+            self.read_stages |= stage;
+
+            barrier
+        }
+    }
+}
+
+/// Frame description made of passes declaring the resources they use. Barriers and layout
+/// transitions are generated from those declarations when the graph is compiled.
+pub struct RenderGraph<'a> {
+    images: Vec<GraphImage>,
+    buffers: Vec<GraphBuffer>,
+    passes: Vec<Pass<'a>>,
+    transients: Option<&'a mut TransientResources>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        RenderGraph {
+            images: vec![],
+            buffers: vec![],
+            passes: vec![],
+            transients: None,
+        }
+    }
+
+    /// Graph whose transient images are created in `transients`, kept for the next graphs
+    /// using them as long as they declare the same transient images.
+    pub fn with_transients(transients: &'a mut TransientResources) -> Self {
+        RenderGraph {
+            transients: Some(transients),
+            ..Self::new()
+        }
+    }
+
+    pub fn import_image(
+        &mut self,
+        image: vk::Image,
+        initial: ResourceState,
+        final_state: Option<ResourceState>,
+    ) -> ImageId {
+        self.images.push(GraphImage {
+            source: ImageSource::Imported { image, initial },
+            final_state,
+        });
+
+        ImageId(self.images.len() - 1)
+    }
+
+    /// Declares an image that only lives during the graph execution, its memory is shared with
+    /// the other transient images whose passes do not overlap with its own. Its content starts
+    /// undefined.
+    pub fn create_image(&mut self, desc: ImageDesc) -> ImageId {
+        self.images.push(GraphImage {
+            source: ImageSource::Transient(desc),
+            final_state: None,
+        });
+
+        ImageId(self.images.len() - 1)
+    }
+
+    pub fn import_buffer(&mut self, buffer: vk::Buffer, initial: ResourceState) -> BufferId {
+        self.buffers.push(GraphBuffer { buffer, initial });
+
+        BufferId(self.buffers.len() - 1)
+    }
+
+    pub fn add_pass(&mut self, pass: Pass<'a>) {
+        self.passes.push(pass);
+    }
+
+    /// Assigns transient images to memory slots, reusing a slot once the last pass of its
+    /// previous image is over. Returns the slot of every image, the image that used the slot
+    /// before it, and the slot count.
+    fn assign_slots(&self) -> (Vec<Option<usize>>, Vec<Option<usize>>, usize) {
+        // first and last pass using every image
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.images.len()];
+
+        for (pass_index, pass) in self.passes.iter().enumerate() {
+            for (resource, _) in &pass.accesses {
+                if let ResourceId::Image(ImageId(index)) = resource {
+                    lifetimes[*index].get_or_insert((pass_index, pass_index)).1 = pass_index;
+                }
+            }
+        }
+
+        let mut transients: Vec<(usize, (usize, usize))> = lifetimes
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| matches!(self.images[*index].source, ImageSource::Transient(_)))
+            .filter_map(|(index, lifetime)| Some((index, lifetime?)))
+            .collect();
+        transients.sort_by_key(|(_, (first_pass, _))| *first_pass);
+
+        let mut slots = vec![None; self.images.len()];
+        let mut previous_owners = vec![None; self.images.len()];
+        // last pass and image of every slot
+        let mut slot_owners: Vec<(usize, usize)> = vec![];
+
+        for (index, (first_pass, last_pass)) in transients {
+            let free_slot = slot_owners
+                .iter()
+                .position(|(owner_last_pass, _)| *owner_last_pass < first_pass);
+
+            let slot = match free_slot {
+                Some(slot) => {
+                    previous_owners[index] = Some(slot_owners[slot].1);
+                    slot_owners[slot] = (last_pass, index);
+                    slot
+                }
+                None => {
+                    slot_owners.push((last_pass, index));
+                    slot_owners.len() - 1
+                }
+            };
+            slots[index] = Some(slot);
+        }
+
+        (slots, previous_owners, slot_owners.len())
+    }
+
+    /// Computes the barriers of every pass and the memory slots of the transient images,
+    /// without a device.
+    pub fn compile(&self) -> CompiledGraph {
+        let (image_slots, previous_owners, slot_count) = self.assign_slots();
+
+        let mut image_states: Vec<Option<TrackedState>> = self
+            .images
+            .iter()
+            .map(|image| match image.source {
+                ImageSource::Imported { initial, .. } => Some(TrackedState::new(initial)),
+                ImageSource::Transient(_) => None,
+            })
+            .collect();
+
+        let mut buffer_states: Vec<TrackedState> = self
+            .buffers
+            .iter()
+            .map(|buffer| TrackedState::new(buffer.initial))
+            .collect();
+
+        let mut pass_barriers = vec![];
+
+        for pass in &self.passes {
+            let mut barriers = vec![];
+
+            for (resource, access) in &pass.accesses {
+                let state = match resource {
+                    ResourceId::Image(ImageId(index)) => {
+                        if image_states[*index].is_none() {
+                            // aliased memory waits for the accesses of the previous image using it
+                            let mut state = match previous_owners[*index] {
+                                Some(owner) => image_states[owner].unwrap(),
+                                None => TrackedState::new(ResourceState::idle(
+                                    vk::ImageLayout::UNDEFINED,
+                                )),
+                            };
+                            state.layout = vk::ImageLayout::UNDEFINED;
+                            image_states[*index] = Some(state);
+                        }
+
+                        image_states[*index].as_mut().unwrap()
+                    }
+                    ResourceId::Buffer(BufferId(index)) => &mut buffer_states[*index],
+                };
+
+                barriers.extend(state.transition(*resource, *access));
+            }
+
+            pass_barriers.push((pass.name, barriers));
+        }
+
+        let final_barriers = self
+            .images
+            .iter()
+            .enumerate()
+            .filter_map(|(index, image)| {
+                let final_state = image.final_state?;
+                let state = image_states[index]?;
+
+                (state.layout != final_state.layout || state.written).then(|| {
+                    state.barrier(
+                        ResourceId::Image(ImageId(index)),
+                        final_state.stage,
+                        final_state.access,
+                        final_state.layout,
+                    )
+                })
+            })
+            .collect();
+
+        CompiledGraph {
+            pass_barriers,
+            final_barriers,
+            image_slots,
+            slot_count,
+        }
+    }
+
+    /// Compiles the graph, creates its transient images, then records every pass and its
+    /// barriers into `command_buffer`. The previous frame using the same transient resources
+    /// must have completed.
+    pub fn execute(self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let compiled = self.compile();
+
+        let transient_layout: Vec<(Option<usize>, Option<ImageDesc>)> = self
+            .images
+            .iter()
+            .zip(&compiled.image_slots)
+            .map(|(image, slot)| match image.source {
+                ImageSource::Transient(desc) => (*slot, slot.map(|_| desc)),
+                ImageSource::Imported { .. } => (None, None),
+            })
+            .collect();
+
+        let mut transients = self.transients;
+        if compiled.slot_count > 0 {
+            transients
+                .as_deref_mut()
+                .expect("transient images need a graph created with_transients")
+                .prepare(device, &transient_layout, compiled.slot_count);
+        }
+
+        let resources = GraphResources {
+            images: self
+                .images
+                .iter()
+                .zip(&compiled.image_slots)
+                .enumerate()
+                .map(|(index, (image, slot))| match image.source {
+                    ImageSource::Imported { image, .. } => (image, vk::ImageView::null()),
+                    // never used by a pass
+                    ImageSource::Transient(_) if slot.is_none() => {
+                        (vk::Image::null(), vk::ImageView::null())
+                    }
+                    ImageSource::Transient(_) => transients.as_deref().unwrap().image(index),
+                })
+                .collect(),
+            buffers: self.buffers.iter().map(|buffer| buffer.buffer).collect(),
+        };
+
+        for (pass, (_, barriers)) in self.passes.into_iter().zip(compiled.pass_barriers) {
+            resources.record_barriers(device, command_buffer, &barriers);
+
+            if let Some(record) = pass.record {
+                record(device, command_buffer, &resources);
+            }
+        }
+
+        resources.record_barriers(device, command_buffer, &compiled.final_barriers);
+    }
+}
+
+/// Vulkan handles of the graph resources, available to passes while recording.
+pub struct GraphResources {
+    images: Vec<(vk::Image, vk::ImageView)>,
+    buffers: Vec<vk::Buffer>,
+}
+
+impl GraphResources {
+    pub fn image(&self, id: ImageId) -> vk::Image {
+        self.images[id.0].0
+    }
+
+    /// View of a transient image, created with it. Imported images have none.
+    pub fn image_view(&self, id: ImageId) -> vk::ImageView {
+        self.images[id.0].1
+    }
+
+    pub fn buffer(&self, id: BufferId) -> vk::Buffer {
+        self.buffers[id.0]
+    }
+
+    fn record_barriers(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        barriers: &[Barrier],
+    ) {
+        if barriers.is_empty() {
+            return;
+        }
+
+        let mut src_stage = vk::PipelineStageFlags::empty();
+        let mut dst_stage = vk::PipelineStageFlags::empty();
+        let mut image_barriers = vec![];
+        let mut buffer_barriers = vec![];
+
+        for barrier in barriers {
+            src_stage |= barrier.src_stage;
+            dst_stage |= barrier.dst_stage;
+
+            match barrier.resource {
+                ResourceId::Image(id) => image_barriers.push(
+                    vk::ImageMemoryBarrier::default()
+                        .src_access_mask(barrier.src_access)
+                        .dst_access_mask(barrier.dst_access)
+                        .old_layout(barrier.old_layout)
+                        .new_layout(barrier.new_layout)
+                        .image(self.image(id))
+                        .subresource_range(
+                            vk::ImageSubresourceRange::default()
+                                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                .base_mip_level(0)
+                                .level_count(1)
+                                .base_array_layer(0)
+                                .layer_count(1),
+                        ),
+                ),
+                ResourceId::Buffer(id) => buffer_barriers.push(
+                    vk::BufferMemoryBarrier::default()
+                        .src_access_mask(barrier.src_access)
+                        .dst_access_mask(barrier.dst_access)
+                        .buffer(self.buffer(id))
+                        .size(vk::WHOLE_SIZE),
+                ),
+            }
+        }
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &buffer_barriers,
+                &image_barriers,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(graph: &mut RenderGraph, initial: ResourceState) -> ImageId {
+        graph.import_image(vk::Image::null(), initial, None)
+    }
+
+    fn barriers(graph: &RenderGraph) -> Vec<Vec<Barrier>> {
+        graph
+            .compile()
+            .pass_barriers
+            .into_iter()
+            .map(|(_, barriers)| barriers)
+            .collect()
+    }
+
+    #[test]
+    fn write_then_read() {
+        let mut graph = RenderGraph::new();
+        let traced = image(&mut graph, ResourceState::idle(vk::ImageLayout::UNDEFINED));
+        graph.add_pass(Pass::new("trace").image(traced, Access::RayTracingStorageWrite));
+        graph.add_pass(Pass::new("filter").image(traced, Access::FragmentSampledRead));
+
+        let resource = ResourceId::Image(traced);
+        assert_eq!(
+            barriers(&graph),
+            [
+                vec![Barrier {
+                    resource,
+                    src_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                    dst_stage: vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                    src_access: vk::AccessFlags::empty(),
+                    dst_access: vk::AccessFlags::SHADER_WRITE,
+                    old_layout: vk::ImageLayout::UNDEFINED,
+                    new_layout: vk::ImageLayout::GENERAL,
+                }],
+                vec![Barrier {
+                    resource,
+                    src_stage: vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                    dst_stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    src_access: vk::AccessFlags::SHADER_WRITE,
+                    dst_access: vk::AccessFlags::SHADER_READ,
+                    old_layout: vk::ImageLayout::GENERAL,
+                    new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                }],
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_of_a_stage_share_one_barrier() {
+        let mut graph = RenderGraph::new();
+        let uniforms = graph.import_buffer(
+            vk::Buffer::null(),
+            ResourceState::idle(vk::ImageLayout::UNDEFINED),
+        );
+        graph.add_pass(Pass::new("update").buffer(uniforms, Access::TransferWrite));
+        graph.add_pass(Pass::new("trace").buffer(uniforms, Access::RayTracingUniformRead));
+        graph.add_pass(Pass::new("trace again").buffer(uniforms, Access::RayTracingUniformRead));
+        graph.add_pass(Pass::new("filter").buffer(uniforms, Access::FragmentUniformRead));
+
+        let read_barrier = |dst_stage| Barrier {
+            resource: ResourceId::Buffer(uniforms),
+            src_stage: vk::PipelineStageFlags::TRANSFER,
+            dst_stage,
+            src_access: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access: vk::AccessFlags::UNIFORM_READ,
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::UNDEFINED,
+        };
+
+        // the first write of an idle buffer has nothing to wait for
+        assert_eq!(
+            barriers(&graph),
+            [
+                vec![],
+                vec![read_barrier(vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR)],
+                vec![],
+                vec![read_barrier(vk::PipelineStageFlags::FRAGMENT_SHADER)],
+            ]
+        );
+    }
+
+    #[test]
+    fn write_waits_for_the_reads_before_it() {
+        let mut graph = RenderGraph::new();
+        let signal = image(&mut graph, ResourceState::idle(vk::ImageLayout::UNDEFINED));
+        graph.add_pass(Pass::new("trace").image(signal, Access::RayTracingStorageWrite));
+        graph.add_pass(Pass::new("reuse").image(signal, Access::RayTracingStorageRead));
+        graph.add_pass(Pass::new("denoise").image(signal, Access::ComputeStorageWrite));
+
+        assert_eq!(
+            barriers(&graph)[2],
+            [Barrier {
+                resource: ResourceId::Image(signal),
+                src_stage: vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                src_access: vk::AccessFlags::SHADER_WRITE,
+                dst_access: vk::AccessFlags::SHADER_WRITE,
+                old_layout: vk::ImageLayout::GENERAL,
+                new_layout: vk::ImageLayout::GENERAL,
+            }]
+        );
+    }
+
+    #[test]
+    fn layout_changes_between_reads() {
+        let mut graph = RenderGraph::new();
+        let hdr = image(&mut graph, ResourceState::idle(vk::ImageLayout::UNDEFINED));
+        graph.add_pass(Pass::new("blit").image(hdr, Access::TransferWrite));
+        graph.add_pass(Pass::new("copy").image(hdr, Access::TransferRead));
+        graph.add_pass(Pass::new("tonemap").image(hdr, Access::FragmentSampledRead));
+
+        let resource = ResourceId::Image(hdr);
+        let compiled = barriers(&graph);
+
+        assert_eq!(
+            compiled[1],
+            [Barrier {
+                resource,
+                src_stage: vk::PipelineStageFlags::TRANSFER,
+                dst_stage: vk::PipelineStageFlags::TRANSFER,
+                src_access: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access: vk::AccessFlags::TRANSFER_READ,
+                old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            }]
+        );
+        // the second transition waits for the reads in the first layout, no write is pending
+        assert_eq!(
+            compiled[2],
+            [Barrier {
+                resource,
+                src_stage: vk::PipelineStageFlags::TRANSFER,
+                dst_stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                src_access: vk::AccessFlags::empty(),
+                dst_access: vk::AccessFlags::SHADER_READ,
+                old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }]
+        );
+    }
+
+    #[test]
+    fn imported_states() {
+        let mut graph = RenderGraph::new();
+        // written by an earlier submission, and read by the previous frame
+        let pending = image(
+            &mut graph,
+            ResourceState {
+                stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                access: vk::AccessFlags::SHADER_WRITE,
+                layout: vk::ImageLayout::GENERAL,
+            },
+        );
+        let history = image(
+            &mut graph,
+            ResourceState {
+                stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                access: vk::AccessFlags::empty(),
+                layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            },
+        );
+        let output = graph.import_image(
+            vk::Image::null(),
+            ResourceState::idle(vk::ImageLayout::UNDEFINED),
+            Some(ResourceState {
+                stage: vk::PipelineStageFlags::TRANSFER,
+                access: vk::AccessFlags::TRANSFER_READ,
+                layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            }),
+        );
+        // left as it is, no transition
+        graph.import_image(
+            vk::Image::null(),
+            ResourceState::idle(vk::ImageLayout::GENERAL),
+            Some(ResourceState::idle(vk::ImageLayout::GENERAL)),
+        );
+
+        graph.add_pass(
+            Pass::new("resolve")
+                .image(pending, Access::ComputeStorageRead)
+                .image(history, Access::ComputeStorageWrite)
+                .image(output, Access::ColorAttachmentWrite),
+        );
+
+        let compiled = graph.compile();
+
+        assert_eq!(
+            compiled.pass_barriers[0].1,
+            [
+                Barrier {
+                    resource: ResourceId::Image(pending),
+                    src_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                    dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                    src_access: vk::AccessFlags::SHADER_WRITE,
+                    dst_access: vk::AccessFlags::SHADER_READ,
+                    old_layout: vk::ImageLayout::GENERAL,
+                    new_layout: vk::ImageLayout::GENERAL,
+                },
+                Barrier {
+                    resource: ResourceId::Image(history),
+                    src_stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                    src_access: vk::AccessFlags::empty(),
+                    dst_access: vk::AccessFlags::SHADER_WRITE,
+                    old_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    new_layout: vk::ImageLayout::GENERAL,
+                },
+                Barrier {
+                    resource: ResourceId::Image(output),
+                    src_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                    dst_stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    src_access: vk::AccessFlags::empty(),
+                    dst_access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    old_layout: vk::ImageLayout::UNDEFINED,
+                    new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                },
+            ]
+        );
+        assert_eq!(
+            compiled.final_barriers,
+            [Barrier {
+                resource: ResourceId::Image(output),
+                src_stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                dst_stage: vk::PipelineStageFlags::TRANSFER,
+                src_access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access: vk::AccessFlags::TRANSFER_READ,
+                old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            }]
+        );
+    }
+
+    fn transient(graph: &mut RenderGraph) -> ImageId {
+        graph.create_image(ImageDesc {
+            format: vk::Format::R16G16B16A16_SFLOAT,
+            extent: vk::Extent2D {
+                width: 64,
+                height: 64,
+            },
+            usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        })
+    }
+
+    /// Passes of three transient images, the first one done before the third one starts.
+    fn aliasing_graph<'a>() -> (RenderGraph<'a>, [ImageId; 4]) {
+        let mut graph = RenderGraph::new();
+        let hdr = image(&mut graph, ResourceState::idle(vk::ImageLayout::UNDEFINED));
+        let traced = transient(&mut graph);
+        let filtered = transient(&mut graph);
+        let upscaled = transient(&mut graph);
+        // declared but never used
+        transient(&mut graph);
+
+        graph.add_pass(Pass::new("trace").image(traced, Access::RayTracingStorageWrite));
+        graph.add_pass(
+            Pass::new("filter")
+                .image(traced, Access::ComputeStorageRead)
+                .image(filtered, Access::ComputeStorageWrite),
+        );
+        graph.add_pass(
+            Pass::new("resolve")
+                .image(filtered, Access::ComputeStorageRead)
+                .image(hdr, Access::ComputeStorageWrite),
+        );
+        graph.add_pass(
+            Pass::new("upscale")
+                .image(hdr, Access::ComputeSampledRead)
+                .image(upscaled, Access::ComputeStorageWrite),
+        );
+        graph.add_pass(Pass::new("sharpen").image(upscaled, Access::FragmentSampledRead));
+
+        (graph, [hdr, traced, filtered, upscaled])
+    }
+
+    #[test]
+    fn transient_images_alias_when_their_passes_do_not_overlap() {
+        let (graph, _) = aliasing_graph();
+        let compiled = graph.compile();
+
+        // the filtered image is written while the traced one is read, the upscaled image comes
+        // after both
+        assert_eq!(
+            compiled.image_slots,
+            [None, Some(0), Some(1), Some(0), None]
+        );
+        assert_eq!(compiled.slot_count, 2);
+    }
+
+    #[test]
+    fn aliased_image_waits_for_the_previous_one() {
+        let (graph, [hdr, _, _, upscaled]) = aliasing_graph();
+
+        // the memory of the upscaled image was last written by the trace and read by the filter
+        assert_eq!(
+            barriers(&graph)[3],
+            [
+                Barrier {
+                    resource: ResourceId::Image(hdr),
+                    src_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                    dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                    src_access: vk::AccessFlags::SHADER_WRITE,
+                    dst_access: vk::AccessFlags::SHADER_READ,
+                    old_layout: vk::ImageLayout::GENERAL,
+                    new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                },
+                Barrier {
+                    resource: ResourceId::Image(upscaled),
+                    src_stage: vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR
+                        | vk::PipelineStageFlags::COMPUTE_SHADER,
+                    dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                    src_access: vk::AccessFlags::SHADER_WRITE,
+                    dst_access: vk::AccessFlags::SHADER_WRITE,
+                    old_layout: vk::ImageLayout::UNDEFINED,
+                    new_layout: vk::ImageLayout::GENERAL,
+                },
+            ]
+        );
+    }
+}
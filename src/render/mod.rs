@@ -1 +1,10 @@
+pub mod ao_filter;
+pub mod environment;
+pub mod frame;
+pub mod graph;
 pub mod pass;
+pub mod svgf;
+pub mod taa;
+pub mod tonemap;
+pub mod transient;
+pub mod upscale;
@@ -1,26 +1,155 @@
-use std::sync::Arc;
+use ash::{vk, Device};
 
-use ash::Device;
+use super::graph::{BufferId, GraphResources, ImageId};
 
-use crate::utils::BufferResource;
+/// How a pass uses a resource, determines the stages, access masks and image layouts used when
+/// generating barriers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    RayTracingStorageWrite,
+    RayTracingStorageRead,
+    /// Loads and stores of the same texels.
+    RayTracingStorageReadWrite,
+    RayTracingUniformRead,
+    ComputeStorageWrite,
+    ComputeStorageRead,
+    /// Atomics, or loads and stores of the same data.
+    ComputeStorageReadWrite,
+    ComputeSampledRead,
+    ComputeUniformRead,
+    FragmentStorageRead,
+    FragmentUniformRead,
+    FragmentSampledRead,
+    ColorAttachmentWrite,
+    TransferRead,
+    TransferWrite,
+}
+
+impl Access {
+    pub fn is_write(self) -> bool {
+        match self {
+            Access::RayTracingStorageWrite
+            | Access::RayTracingStorageReadWrite
+            | Access::ComputeStorageWrite
+            | Access::ComputeStorageReadWrite
+            | Access::ColorAttachmentWrite
+            | Access::TransferWrite => true,
+            Access::RayTracingStorageRead
+            | Access::RayTracingUniformRead
+            | Access::ComputeStorageRead
+            | Access::ComputeSampledRead
+            | Access::ComputeUniformRead
+            | Access::FragmentStorageRead
+            | Access::FragmentUniformRead
+            | Access::FragmentSampledRead
+            | Access::TransferRead => false,
+        }
+    }
 
-pub enum PipelineType {
-    GraphicsPipeline,
-    RayTracingPipeline,
+    pub fn stage(self) -> vk::PipelineStageFlags {
+        match self {
+            Access::RayTracingStorageWrite
+            | Access::RayTracingStorageRead
+            | Access::RayTracingStorageReadWrite
+            | Access::RayTracingUniformRead => vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+            Access::ComputeStorageWrite
+            | Access::ComputeStorageRead
+            | Access::ComputeStorageReadWrite
+            | Access::ComputeSampledRead
+            | Access::ComputeUniformRead => vk::PipelineStageFlags::COMPUTE_SHADER,
+            Access::FragmentStorageRead
+            | Access::FragmentUniformRead
+            | Access::FragmentSampledRead => vk::PipelineStageFlags::FRAGMENT_SHADER,
+            Access::ColorAttachmentWrite => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            Access::TransferRead | Access::TransferWrite => vk::PipelineStageFlags::TRANSFER,
+        }
+    }
+
+    pub fn access_mask(self) -> vk::AccessFlags {
+        match self {
+            Access::RayTracingStorageWrite | Access::ComputeStorageWrite => {
+                vk::AccessFlags::SHADER_WRITE
+            }
+            Access::RayTracingStorageRead
+            | Access::ComputeStorageRead
+            | Access::ComputeSampledRead => vk::AccessFlags::SHADER_READ,
+            Access::RayTracingStorageReadWrite | Access::ComputeStorageReadWrite => {
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE
+            }
+            Access::RayTracingUniformRead
+            | Access::ComputeUniformRead
+            | Access::FragmentUniformRead => vk::AccessFlags::UNIFORM_READ,
+            Access::FragmentStorageRead | Access::FragmentSampledRead => {
+                vk::AccessFlags::SHADER_READ
+            }
+            Access::ColorAttachmentWrite => vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            Access::TransferRead => vk::AccessFlags::TRANSFER_READ,
+            Access::TransferWrite => vk::AccessFlags::TRANSFER_WRITE,
+        }
+    }
+
+    /// Layout an image must be in for this access, buffers ignore it.
+    pub fn image_layout(self) -> vk::ImageLayout {
+        match self {
+            Access::RayTracingStorageWrite
+            | Access::RayTracingStorageRead
+            | Access::RayTracingStorageReadWrite
+            | Access::ComputeStorageWrite
+            | Access::ComputeStorageRead
+            | Access::ComputeStorageReadWrite
+            | Access::FragmentStorageRead => vk::ImageLayout::GENERAL,
+            Access::RayTracingUniformRead
+            | Access::ComputeUniformRead
+            | Access::FragmentUniformRead => vk::ImageLayout::UNDEFINED,
+            Access::ComputeSampledRead | Access::FragmentSampledRead => {
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            }
+            Access::ColorAttachmentWrite => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            Access::TransferRead => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            Access::TransferWrite => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        }
+    }
 }
 
-pub struct Pass {
-    pub device: Arc<Device>,
-    pub pipeline_type: PipelineType,
-    buffers: Vec<BufferResource>,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceId {
+    Image(ImageId),
+    Buffer(BufferId),
 }
 
-impl Pass {
-    fn new(device: Arc<Device>, pipeline_type: PipelineType) -> Self {
+pub type RecordFn<'a> = Box<dyn FnOnce(&Device, vk::CommandBuffer, &GraphResources) + 'a>;
+
+/// A node of the render graph: the resources it uses and the commands it records.
+pub struct Pass<'a> {
+    pub name: &'static str,
+    pub accesses: Vec<(ResourceId, Access)>,
+    pub record: Option<RecordFn<'a>>,
+}
+
+impl<'a> Pass<'a> {
+    pub fn new(name: &'static str) -> Self {
         Pass {
-            device,
-            pipeline_type,
-            buffers: vec![],
+            name,
+            accesses: vec![],
+            record: None,
         }
     }
+
+    pub fn image(mut self, image: ImageId, access: Access) -> Self {
+        self.accesses.push((ResourceId::Image(image), access));
+        self
+    }
+
+    pub fn buffer(mut self, buffer: BufferId, access: Access) -> Self {
+        self.accesses.push((ResourceId::Buffer(buffer), access));
+        self
+    }
+
+    pub fn record<F: FnOnce(&Device, vk::CommandBuffer, &GraphResources) + 'a>(
+        mut self,
+        record: F,
+    ) -> Self {
+        self.record = Some(Box::new(record));
+        self
+    }
 }
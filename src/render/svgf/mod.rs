@@ -0,0 +1,511 @@
+// only checked against the shaders, the application does not call it
+#[cfg(test)]
+mod reference;
+
+use ash::{vk, Device};
+use bytemuck::bytes_of;
+
+use crate::{
+    render::{
+        graph::{BufferId, ImageId, RenderGraph},
+        pass::{Access, Pass},
+    },
+    shaders::reflect::{reflect, ExpectedBinding, PipelineInterface},
+    uniform_types::{DenoisePushConstants, GlobalUniforms},
+    utils::{create_descriptor_set_layout_and_pool, create_shader_module},
+};
+
+use super::frame::FRAMES_IN_FLIGHT;
+
+/// Formats of the accumulated signal and history length, of the moments of its luminance, of
+/// the signal and variance between the wavelet iterations, and of the result.
+pub const HISTORY_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+pub const MOMENTS_FORMAT: vk::Format = vk::Format::R32G32_SFLOAT;
+pub const FILTERED_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+pub const DENOISED_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Index of the set denoising the path traced radiance and of the set denoising the occlusion
+/// in the per signal arrays.
+pub const RADIANCE_SIGNAL: usize = 0;
+pub const OCCLUSION_SIGNAL: usize = 1;
+
+/// Stages of the denoiser in dispatch order, the wavelet stage is dispatched once per iteration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SvgfStage {
+    TemporalAccumulation,
+    VarianceEstimation,
+    Wavelet,
+    Modulation,
+}
+
+const SVGF_SHADERS: [(&str, &[u8]); 4] = [
+    (
+        "shaders/SVGF/svgf_temporal.comp",
+        include_bytes!(concat!(env!("OUT_DIR"), "/svgf_temporal_comp.spv")),
+    ),
+    (
+        "shaders/SVGF/svgf_variance.comp",
+        include_bytes!(concat!(env!("OUT_DIR"), "/svgf_variance_comp.spv")),
+    ),
+    (
+        "shaders/SVGF/svgf_atrous.comp",
+        include_bytes!(concat!(env!("OUT_DIR"), "/svgf_atrous_comp.spv")),
+    ),
+    (
+        "shaders/SVGF/svgf_modulate.comp",
+        include_bytes!(concat!(env!("OUT_DIR"), "/svgf_modulate_comp.spv")),
+    ),
+];
+
+/// Descriptors written by `write_descriptor_sets`, checked against the shaders.
+const SVGF_BINDINGS: [ExpectedBinding; 12] = [
+    ExpectedBinding {
+        set: 0,
+        binding: 0,
+        descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+        name: "uniforms buffer",
+        size: Some(std::mem::size_of::<GlobalUniforms>() as u32),
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 1,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "signal image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 2,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "depth and normal image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 3,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "previous depth and normal image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 4,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "albedo image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 5,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "history image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 6,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "previous history image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 7,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "moments image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 8,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "previous moments image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 9,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "first filtered image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 10,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "second filtered image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 11,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "denoised image",
+        size: None,
+    },
+];
+
+/// Views of the images of a frame the denoiser reads and writes.
+pub struct SvgfViews {
+    pub depth_normal: vk::ImageView,
+    pub prev_depth_normal: vk::ImageView,
+    pub albedo: vk::ImageView,
+    pub history: vk::ImageView,
+    pub prev_history: vk::ImageView,
+    pub moments: vk::ImageView,
+    pub prev_moments: vk::ImageView,
+    pub filtered: [vk::ImageView; 2],
+    pub denoised: vk::ImageView,
+}
+
+/// Graph images the denoiser reads and writes, `signal` is the image it filters.
+#[derive(Clone, Copy)]
+pub struct SvgfImages {
+    pub signal: ImageId,
+    pub depth_normal: ImageId,
+    pub prev_depth_normal: ImageId,
+    pub albedo: ImageId,
+    pub history: ImageId,
+    pub prev_history: ImageId,
+    pub moments: ImageId,
+    pub prev_moments: ImageId,
+    pub filtered: [ImageId; 2],
+    pub denoised: ImageId,
+}
+
+/// Spatiotemporal variance-guided filter, run as compute stages: temporal accumulation of the
+/// signal and of its moments, variance estimation, à-trous wavelet iterations guided by the
+/// depth, the normals and the luminance, and modulation by the albedo. The same stages filter
+/// the path traced radiance and the occlusion of the AO preview.
+pub struct Svgf {
+    pipelines: [vk::Pipeline; 4],
+    pipeline_layout: vk::PipelineLayout,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    sampler: vk::Sampler,
+}
+
+impl Svgf {
+    pub fn new(device: &Device) -> anyhow::Result<Self> {
+        let mut reflections = vec![];
+        for (path, code) in SVGF_SHADERS {
+            reflections.push((path, reflect(code).map_err(anyhow::Error::msg)?));
+        }
+
+        let interface = PipelineInterface::new(&reflections)?;
+        interface.check(&SVGF_BINDINGS)?;
+        interface.check_push_constants(Some(std::mem::size_of::<DenoisePushConstants>() as u32))?;
+
+        // one set per signal and frame in flight
+        let (descriptor_set_layout, descriptor_pool) = create_descriptor_set_layout_and_pool(
+            device,
+            &interface,
+            0,
+            2 * FRAMES_IN_FLIGHT as u32,
+        )?;
+
+        let layouts = [descriptor_set_layout];
+        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&layouts)
+            .push_constant_ranges(&interface.push_constant_ranges);
+
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&layout_create_info, None) }?;
+
+        let mut modules = vec![];
+        for (_, code) in SVGF_SHADERS {
+            modules.push(unsafe { create_shader_module(device, code) }?);
+        }
+
+        let create_infos: Vec<vk::ComputePipelineCreateInfo> = modules
+            .iter()
+            .map(|module| {
+                vk::ComputePipelineCreateInfo::default()
+                    .stage(
+                        vk::PipelineShaderStageCreateInfo::default()
+                            .stage(vk::ShaderStageFlags::COMPUTE)
+                            .module(*module)
+                            .name(c"main"),
+                    )
+                    .layout(pipeline_layout)
+            })
+            .collect();
+
+        let pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &create_infos, None)
+        }
+        .map_err(|(_, result)| result);
+
+        unsafe {
+            for module in modules {
+                device.destroy_shader_module(module, None);
+            }
+        }
+
+        let pipelines = pipelines?;
+
+        // the stages read exact texels
+        let sampler_create_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(vk::LOD_CLAMP_NONE);
+
+        let sampler = unsafe { device.create_sampler(&sampler_create_info, None) }?;
+
+        Ok(Svgf {
+            pipelines: [pipelines[0], pipelines[1], pipelines[2], pipelines[3]],
+            pipeline_layout,
+            descriptor_set_layout,
+            descriptor_pool,
+            sampler,
+        })
+    }
+
+    /// Allocates the descriptor sets of the two signals of a frame.
+    pub fn allocate_descriptor_sets(
+        &self,
+        device: &Device,
+    ) -> anyhow::Result<[vk::DescriptorSet; 2]> {
+        let layouts = [self.descriptor_set_layout; 2];
+
+        let descriptor_sets = unsafe {
+            device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(self.descriptor_pool)
+                    .set_layouts(&layouts),
+            )
+        }?;
+
+        Ok([descriptor_sets[0], descriptor_sets[1]])
+    }
+
+    /// The sets only differ by their signal, `signal_views` are the path traced radiance and the
+    /// occlusion.
+    pub fn write_descriptor_sets(
+        &self,
+        device: &Device,
+        descriptor_sets: [vk::DescriptorSet; 2],
+        signal_views: [vk::ImageView; 2],
+        views: &SvgfViews,
+        uniforms_buffer: vk::Buffer,
+    ) {
+        let sampled_info = |view| {
+            [vk::DescriptorImageInfo::default()
+                .sampler(self.sampler)
+                .image_view(view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]
+        };
+        let storage_info = |view| {
+            [vk::DescriptorImageInfo::default()
+                .image_view(view)
+                .image_layout(vk::ImageLayout::GENERAL)]
+        };
+
+        let signal_infos = signal_views.map(sampled_info);
+        let sampled_infos = [
+            (2, sampled_info(views.depth_normal)),
+            (3, sampled_info(views.prev_depth_normal)),
+            (4, sampled_info(views.albedo)),
+        ];
+        let storage_infos = [
+            (5, storage_info(views.history)),
+            (6, storage_info(views.prev_history)),
+            (7, storage_info(views.moments)),
+            (8, storage_info(views.prev_moments)),
+            (9, storage_info(views.filtered[0])),
+            (10, storage_info(views.filtered[1])),
+            (11, storage_info(views.denoised)),
+        ];
+        let uniforms_buffer_info = [vk::DescriptorBufferInfo::default()
+            .buffer(uniforms_buffer)
+            .range(vk::WHOLE_SIZE)];
+
+        let writes: Vec<vk::WriteDescriptorSet> = descriptor_sets
+            .iter()
+            .zip(&signal_infos)
+            .flat_map(|(descriptor_set, signal_info)| {
+                let write = |binding, descriptor_type| {
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(*descriptor_set)
+                        .dst_binding(binding)
+                        .dst_array_element(0)
+                        .descriptor_type(descriptor_type)
+                };
+
+                std::iter::once(
+                    write(0, vk::DescriptorType::UNIFORM_BUFFER).buffer_info(&uniforms_buffer_info),
+                )
+                .chain(std::iter::once(
+                    write(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER).image_info(signal_info),
+                ))
+                .chain(sampled_infos.iter().map(move |(binding, info)| {
+                    write(*binding, vk::DescriptorType::COMBINED_IMAGE_SAMPLER).image_info(info)
+                }))
+                .chain(storage_infos.iter().map(move |(binding, info)| {
+                    write(*binding, vk::DescriptorType::STORAGE_IMAGE).image_info(info)
+                }))
+            })
+            .collect();
+
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+
+    /// Records one dispatch of `stage` over `extent`.
+    pub fn record_stage(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        stage: SvgfStage,
+        descriptor_set: vk::DescriptorSet,
+        extent: vk::Extent2D,
+        push_constants: &DenoisePushConstants,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipelines[stage as usize],
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytes_of(push_constants),
+            );
+            // 8x8 workgroups
+            device.cmd_dispatch(
+                command_buffer,
+                extent.width.div_ceil(8),
+                extent.height.div_ceil(8),
+                1,
+            );
+        }
+    }
+
+    /// Adds the stages to the graph, `settings.iteration` is the number of wavelet iterations.
+    /// The result is in `images.denoised`.
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        images: SvgfImages,
+        uniforms_buffer: BufferId,
+        descriptor_set: vk::DescriptorSet,
+        extent: vk::Extent2D,
+        settings: DenoisePushConstants,
+    ) {
+        let iterations = settings.iteration;
+
+        graph.add_pass(
+            Pass::new("denoise: temporal accumulation")
+                .buffer(uniforms_buffer, Access::ComputeUniformRead)
+                .image(images.signal, Access::ComputeSampledRead)
+                .image(images.depth_normal, Access::ComputeSampledRead)
+                .image(images.prev_depth_normal, Access::ComputeSampledRead)
+                .image(images.albedo, Access::ComputeSampledRead)
+                .image(images.prev_history, Access::ComputeStorageRead)
+                .image(images.prev_moments, Access::ComputeStorageRead)
+                .image(images.history, Access::ComputeStorageWrite)
+                .image(images.moments, Access::ComputeStorageWrite)
+                .record(move |device, command_buffer, _| {
+                    self.record_stage(
+                        device,
+                        command_buffer,
+                        SvgfStage::TemporalAccumulation,
+                        descriptor_set,
+                        extent,
+                        &settings,
+                    );
+                }),
+        );
+
+        graph.add_pass(
+            Pass::new("denoise: variance estimation")
+                .image(images.depth_normal, Access::ComputeSampledRead)
+                .image(images.history, Access::ComputeStorageRead)
+                .image(images.moments, Access::ComputeStorageRead)
+                .image(images.filtered[0], Access::ComputeStorageWrite)
+                .record(move |device, command_buffer, _| {
+                    self.record_stage(
+                        device,
+                        command_buffer,
+                        SvgfStage::VarianceEstimation,
+                        descriptor_set,
+                        extent,
+                        &settings,
+                    );
+                }),
+        );
+
+        for iteration in 0..iterations {
+            let input = images.filtered[iteration as usize % 2];
+            let output = images.filtered[(iteration as usize + 1) % 2];
+
+            graph.add_pass(
+                Pass::new("denoise: wavelet iteration")
+                    .image(images.depth_normal, Access::ComputeSampledRead)
+                    .image(images.albedo, Access::ComputeSampledRead)
+                    .image(input, Access::ComputeStorageRead)
+                    .image(output, Access::ComputeStorageWrite)
+                    .record(move |device, command_buffer, _| {
+                        self.record_stage(
+                            device,
+                            command_buffer,
+                            SvgfStage::Wavelet,
+                            descriptor_set,
+                            extent,
+                            &DenoisePushConstants {
+                                iteration,
+                                ..settings
+                            },
+                        );
+                    }),
+            );
+        }
+
+        graph.add_pass(
+            Pass::new("denoise: modulation")
+                .image(images.signal, Access::ComputeSampledRead)
+                .image(images.depth_normal, Access::ComputeSampledRead)
+                .image(images.albedo, Access::ComputeSampledRead)
+                .image(
+                    images.filtered[iterations as usize % 2],
+                    Access::ComputeStorageRead,
+                )
+                .image(images.denoised, Access::ComputeStorageWrite)
+                .record(move |device, command_buffer, _| {
+                    self.record_stage(
+                        device,
+                        command_buffer,
+                        SvgfStage::Modulation,
+                        descriptor_set,
+                        extent,
+                        &settings,
+                    );
+                }),
+        );
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            for pipeline in self.pipelines {
+                device.destroy_pipeline(pipeline, None);
+            }
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
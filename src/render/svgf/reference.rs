@@ -0,0 +1,714 @@
+//! CPU implementation of the kernels of the denoiser, texel for texel the same as the compute
+//! shaders, to check them against on small images. The temporal accumulation is the one of a
+//! still camera, whose history lies at the same pixel.
+
+use crate::{lights::luminance, uniform_types::DenoisePushConstants};
+
+/// Frames after which the temporal moments are trusted, as in `svgf_variance.comp`.
+const MIN_HISTORY: f32 = 4.0;
+
+/// Weights of the 5x5 B3 spline kernel of the wavelets.
+const KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Row major image of RGBA texels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub texels: Vec<glm::Vec4>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            texels: vec![glm::Vec4::zeros(); width * height],
+        }
+    }
+
+    /// The texel at `(x, y)`, or `None` outside the image.
+    pub fn get(&self, x: i32, y: i32) -> Option<&glm::Vec4> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+
+        self.texels.get(y as usize * self.width + x as usize)
+    }
+
+    fn set(&mut self, x: i32, y: i32, texel: glm::Vec4) {
+        self.texels[y as usize * self.width + x as usize] = texel;
+    }
+
+    fn pixels(&self) -> impl Iterator<Item = (i32, i32)> {
+        let width = self.width;
+        (0..self.width * self.height).map(move |i| ((i % width) as i32, (i / width) as i32))
+    }
+}
+
+/// G-buffer the stages are guided by: the normal and the depth, which is 0 on the background,
+/// and the albedo.
+pub struct Surfaces<'a> {
+    pub depth_normal: &'a Image,
+    pub albedo: &'a Image,
+}
+
+impl Surfaces<'_> {
+    fn demodulation_albedo(&self, x: i32, y: i32) -> glm::Vec3 {
+        self.albedo
+            .get(x, y)
+            .unwrap()
+            .xyz()
+            .sup(&glm::vec3(0.01, 0.01, 0.01))
+    }
+
+    /// Edge stopping weight of the depth and the normals between a pixel and a tap.
+    fn geometry_weight(
+        &self,
+        center: &glm::Vec4,
+        tap: &glm::Vec4,
+        settings: &DenoisePushConstants,
+    ) -> f32 {
+        let depth_weight =
+            (-(tap.w - center.w).abs() / (settings.phi_depth * center.w + 1e-4)).exp();
+        let normal_weight = glm::dot(&tap.xyz(), &center.xyz())
+            .max(0.0)
+            .powf(settings.phi_normal);
+        depth_weight * normal_weight
+    }
+}
+
+/// Accumulated signal and history length, and moments of the luminance.
+pub struct History {
+    pub signal: Image,
+    pub moments: Image,
+}
+
+/// Temporal accumulation of `signal` into the history of the previous frame.
+pub fn temporal_accumulation(
+    signal: &Image,
+    surfaces: &Surfaces,
+    prev_depth_normal: &Image,
+    prev_history: &History,
+    history_depth_tolerance: f32,
+    max_history: u32,
+    settings: &DenoisePushConstants,
+) -> History {
+    let mut history = History {
+        signal: Image::new(signal.width, signal.height),
+        moments: Image::new(signal.width, signal.height),
+    };
+
+    for (x, y) in signal.pixels() {
+        let depth_normal = surfaces.depth_normal.get(x, y).unwrap();
+
+        if depth_normal.w <= 0.0 {
+            continue;
+        }
+
+        let raw = signal.get(x, y).unwrap();
+        let demodulated = if settings.signal == 1 {
+            glm::vec3(raw.x, raw.x, raw.x)
+        } else {
+            raw.xyz().component_div(&surfaces.demodulation_albedo(x, y))
+        };
+        let signal_luminance = luminance(&demodulated);
+        let moments = glm::vec2(signal_luminance, signal_luminance * signal_luminance);
+
+        let prev_depth_normal = prev_depth_normal.get(x, y).unwrap();
+        let valid = prev_depth_normal.w > 0.0
+            && (prev_depth_normal.w - depth_normal.w).abs()
+                <= history_depth_tolerance * depth_normal.w
+            && glm::dot(&prev_depth_normal.xyz(), &depth_normal.xyz()) >= 0.9;
+
+        if !valid {
+            history.signal.set(
+                x,
+                y,
+                glm::vec4(demodulated.x, demodulated.y, demodulated.z, 1.0),
+            );
+            history
+                .moments
+                .set(x, y, glm::vec4(moments.x, moments.y, 0.0, 0.0));
+            continue;
+        }
+
+        let prev_signal = prev_history.signal.get(x, y).unwrap();
+        let prev_moments = prev_history.moments.get(x, y).unwrap().xy();
+
+        let history_length = (prev_signal.w + 1.0).min(max_history.max(1) as f32);
+        let alpha = settings.alpha.max(1.0 / history_length);
+        let moments_alpha = settings.moments_alpha.max(1.0 / history_length);
+
+        let accumulated = glm::mix(&prev_signal.xyz(), &demodulated, alpha);
+        let accumulated_moments = glm::mix(&prev_moments, &moments, moments_alpha);
+
+        history.signal.set(
+            x,
+            y,
+            glm::vec4(accumulated.x, accumulated.y, accumulated.z, history_length),
+        );
+        history.moments.set(
+            x,
+            y,
+            glm::vec4(accumulated_moments.x, accumulated_moments.y, 0.0, 0.0),
+        );
+    }
+
+    history
+}
+
+/// Signal and variance the first wavelet iteration filters.
+pub fn variance_estimation(
+    history: &History,
+    surfaces: &Surfaces,
+    settings: &DenoisePushConstants,
+) -> Image {
+    let mut filtered = Image::new(history.signal.width, history.signal.height);
+
+    for (x, y) in filtered.pixels() {
+        let depth_normal = surfaces.depth_normal.get(x, y).unwrap();
+        let signal = history.signal.get(x, y).unwrap();
+
+        if depth_normal.w <= 0.0 || signal.w >= MIN_HISTORY {
+            let moments = history.moments.get(x, y).unwrap();
+            let variance = (moments.y - moments.x * moments.x).max(0.0);
+            filtered.set(x, y, glm::vec4(signal.x, signal.y, signal.z, variance));
+            continue;
+        }
+
+        let mut signal_sum = glm::Vec3::zeros();
+        let mut moments_sum = glm::Vec2::zeros();
+        let mut weight_sum = 0.0;
+
+        for tap_y in y - 3..=y + 3 {
+            for tap_x in x - 3..=x + 3 {
+                let Some(tap_depth_normal) = surfaces.depth_normal.get(tap_x, tap_y) else {
+                    continue;
+                };
+
+                if tap_depth_normal.w <= 0.0 {
+                    continue;
+                }
+
+                let tap_signal = history.signal.get(tap_x, tap_y).unwrap().xyz();
+                let tap_luminance = luminance(&tap_signal);
+                let weight = surfaces.geometry_weight(depth_normal, tap_depth_normal, settings);
+
+                signal_sum += tap_signal * weight;
+                moments_sum += glm::vec2(tap_luminance, tap_luminance * tap_luminance) * weight;
+                weight_sum += weight;
+            }
+        }
+
+        signal_sum /= weight_sum;
+        moments_sum /= weight_sum;
+
+        let variance =
+            (moments_sum.y - moments_sum.x * moments_sum.x).max(0.0) * MIN_HISTORY / signal.w;
+        filtered.set(
+            x,
+            y,
+            glm::vec4(signal_sum.x, signal_sum.y, signal_sum.z, variance),
+        );
+    }
+
+    filtered
+}
+
+/// One wavelet iteration of `filtered`, whose taps are `1 << settings.iteration` pixels apart.
+pub fn atrous_iteration(
+    filtered: &Image,
+    surfaces: &Surfaces,
+    settings: &DenoisePushConstants,
+) -> Image {
+    let mut output = Image::new(filtered.width, filtered.height);
+    let step_size = 1 << settings.iteration;
+
+    for (x, y) in filtered.pixels() {
+        let depth_normal = surfaces.depth_normal.get(x, y).unwrap();
+        let center = filtered.get(x, y).unwrap();
+
+        if depth_normal.w <= 0.0 {
+            output.set(x, y, *center);
+            continue;
+        }
+
+        let albedo = surfaces.albedo.get(x, y).unwrap().xyz();
+        let center_luminance = luminance(&center.xyz());
+        let luminance_scale = settings.phi_color * blurred_variance(filtered, x, y).sqrt() + 1e-6;
+
+        let mut signal_sum = glm::Vec3::zeros();
+        let mut variance_sum = 0.0;
+        let mut weight_sum = 0.0;
+
+        for offset_y in -2..=2_i32 {
+            for offset_x in -2..=2_i32 {
+                let (tap_x, tap_y) = (x + offset_x * step_size, y + offset_y * step_size);
+
+                let Some(tap_depth_normal) = surfaces.depth_normal.get(tap_x, tap_y) else {
+                    continue;
+                };
+
+                if tap_depth_normal.w <= 0.0 {
+                    continue;
+                }
+
+                let tap_value = filtered.get(tap_x, tap_y).unwrap();
+                let tap_albedo = surfaces.albedo.get(tap_x, tap_y).unwrap().xyz();
+
+                let albedo_weight =
+                    (-glm::distance(&tap_albedo, &albedo) / settings.phi_albedo).exp();
+                let luminance_weight = (-(luminance(&tap_value.xyz()) - center_luminance).abs()
+                    / luminance_scale)
+                    .exp();
+                let weight = KERNEL[offset_x.unsigned_abs() as usize]
+                    * KERNEL[offset_y.unsigned_abs() as usize]
+                    * surfaces.geometry_weight(depth_normal, tap_depth_normal, settings)
+                    * albedo_weight
+                    * luminance_weight;
+
+                signal_sum += tap_value.xyz() * weight;
+                variance_sum += tap_value.w * weight * weight;
+                weight_sum += weight;
+            }
+        }
+
+        signal_sum /= weight_sum;
+        let variance = variance_sum / (weight_sum * weight_sum);
+        output.set(
+            x,
+            y,
+            glm::vec4(signal_sum.x, signal_sum.y, signal_sum.z, variance),
+        );
+    }
+
+    output
+}
+
+/// Variance of the pixel blurred over its 3x3 neighbourhood.
+fn blurred_variance(filtered: &Image, x: i32, y: i32) -> f32 {
+    const GAUSS: [f32; 2] = [1.0 / 4.0, 1.0 / 8.0];
+
+    let mut variance = 0.0;
+    let mut weight_sum = 0.0;
+
+    for offset_y in -1..=1_i32 {
+        for offset_x in -1..=1_i32 {
+            if let Some(tap) = filtered.get(x + offset_x, y + offset_y) {
+                let weight = GAUSS[offset_x.unsigned_abs() as usize]
+                    * GAUSS[offset_y.unsigned_abs() as usize];
+                variance += tap.w * weight;
+                weight_sum += weight;
+            }
+        }
+    }
+
+    variance / weight_sum
+}
+
+/// Puts the albedo back on the filtered radiance, the background keeps the traced signal.
+pub fn modulation(
+    filtered: &Image,
+    signal: &Image,
+    surfaces: &Surfaces,
+    settings: &DenoisePushConstants,
+) -> Image {
+    let mut denoised = Image::new(filtered.width, filtered.height);
+
+    for (x, y) in filtered.pixels() {
+        let value = filtered.get(x, y).unwrap();
+
+        let texel = if settings.signal == 1 {
+            glm::vec4(value.x, value.x, value.x, value.x)
+        } else if surfaces.depth_normal.get(x, y).unwrap().w <= 0.0 {
+            let raw = signal.get(x, y).unwrap();
+            glm::vec4(raw.x, raw.y, raw.z, 1.0)
+        } else {
+            let modulated = value
+                .xyz()
+                .component_mul(&surfaces.demodulation_albedo(x, y));
+            glm::vec4(modulated.x, modulated.y, modulated.z, 1.0)
+        };
+
+        denoised.set(x, y, texel);
+    }
+
+    denoised
+}
+
+/// Runs the variance estimation, `iterations` wavelet iterations and the modulation on an
+/// accumulated history, like a frame of the compute stages.
+pub fn filter(
+    history: &History,
+    signal: &Image,
+    surfaces: &Surfaces,
+    iterations: u32,
+    settings: &DenoisePushConstants,
+) -> Image {
+    let mut filtered = variance_estimation(history, surfaces, settings);
+
+    for iteration in 0..iterations {
+        filtered = atrous_iteration(
+            &filtered,
+            surfaces,
+            &DenoisePushConstants {
+                iteration,
+                ..*settings
+            },
+        );
+    }
+
+    modulation(&filtered, signal, surfaces, settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> DenoisePushConstants {
+        DenoisePushConstants {
+            signal: 0,
+            iteration: 0,
+            alpha: 0.1,
+            moments_alpha: 0.2,
+            phi_color: 4.0,
+            phi_normal: 128.0,
+            phi_depth: 1.0,
+            phi_albedo: 0.1,
+        }
+    }
+
+    fn image(width: usize, height: usize, texel: impl Fn(i32, i32) -> glm::Vec4) -> Image {
+        let mut image = Image::new(width, height);
+        for (x, y) in image.pixels() {
+            image.set(x, y, texel(x, y));
+        }
+        image
+    }
+
+    fn uniform(width: usize, height: usize, texel: glm::Vec4) -> Image {
+        image(width, height, |_, _| texel)
+    }
+
+    /// A plane facing the camera at a depth of 1.
+    fn plane(width: usize, height: usize) -> Image {
+        uniform(width, height, glm::vec4(0.0, 0.0, 1.0, 1.0))
+    }
+
+    fn white(width: usize, height: usize) -> Image {
+        uniform(width, height, glm::vec4(1.0, 1.0, 1.0, 0.0))
+    }
+
+    fn assert_close(a: &glm::Vec4, b: &glm::Vec4) {
+        assert!((a - b).abs().max() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn temporal_accumulation_starts_without_history() {
+        // a surface and the background
+        let depth_normal = image(2, 1, |x, _| glm::vec4(0.0, 0.0, 1.0, 1.0 - x as f32));
+        let albedo = uniform(2, 1, glm::vec4(0.5, 0.5, 0.5, 0.0));
+        let surfaces = Surfaces {
+            depth_normal: &depth_normal,
+            albedo: &albedo,
+        };
+        let signal = uniform(2, 1, glm::vec4(0.5, 0.25, 1.0, 0.0));
+        let prev_history = History {
+            signal: uniform(2, 1, glm::vec4(3.0, 3.0, 3.0, 8.0)),
+            moments: uniform(2, 1, glm::vec4(9.0, 81.0, 0.0, 0.0)),
+        };
+
+        let history = temporal_accumulation(
+            &signal,
+            &surfaces,
+            &Image::new(2, 1),
+            &prev_history,
+            0.1,
+            32,
+            &settings(),
+        );
+
+        let l = luminance(&glm::vec3(1.0, 0.5, 2.0));
+        assert_close(&history.signal.texels[0], &glm::vec4(1.0, 0.5, 2.0, 1.0));
+        assert_close(&history.moments.texels[0], &glm::vec4(l, l * l, 0.0, 0.0));
+        assert_close(&history.signal.texels[1], &glm::Vec4::zeros());
+        assert_close(&history.moments.texels[1], &glm::Vec4::zeros());
+    }
+
+    #[test]
+    fn temporal_accumulation_blends_with_the_history() {
+        let depth_normal = plane(1, 1);
+        let albedo = white(1, 1);
+        let surfaces = Surfaces {
+            depth_normal: &depth_normal,
+            albedo: &albedo,
+        };
+        let signal = Image::new(1, 1);
+
+        let accumulate = |history_length: f32, max_history: u32| {
+            let prev_history = History {
+                signal: uniform(1, 1, glm::vec4(1.0, 1.0, 1.0, history_length)),
+                moments: uniform(1, 1, glm::vec4(1.0, 1.0, 0.0, 0.0)),
+            };
+            let history = temporal_accumulation(
+                &signal,
+                &surfaces,
+                &depth_normal,
+                &prev_history,
+                0.1,
+                max_history,
+                &settings(),
+            );
+            (history.signal.texels[0], history.moments.texels[0])
+        };
+
+        // young histories are averaged
+        let (signal, moments) = accumulate(3.0, 32);
+        assert_close(&signal, &glm::vec4(0.75, 0.75, 0.75, 4.0));
+        assert_close(&moments, &glm::vec4(0.75, 0.75, 0.0, 0.0));
+
+        // the history length is clamped
+        let (signal, moments) = accumulate(3.0, 2);
+        assert_close(&signal, &glm::vec4(0.5, 0.5, 0.5, 2.0));
+        assert_close(&moments, &glm::vec4(0.5, 0.5, 0.0, 0.0));
+
+        // old ones blend with alpha and the moments alpha
+        let (signal, moments) = accumulate(20.0, 32);
+        assert_close(&signal, &glm::vec4(0.9, 0.9, 0.9, 21.0));
+        assert_close(&moments, &glm::vec4(0.8, 0.8, 0.0, 0.0));
+    }
+
+    #[test]
+    fn temporal_accumulation_rejects_disoccluded_history() {
+        let albedo = white(1, 1);
+        let signal = uniform(1, 1, glm::vec4(0.5, 0.5, 0.5, 0.0));
+        let prev_history = History {
+            signal: uniform(1, 1, glm::vec4(1.0, 1.0, 1.0, 8.0)),
+            moments: uniform(1, 1, glm::vec4(1.0, 1.0, 0.0, 0.0)),
+        };
+
+        for prev_depth_normal in [
+            glm::vec4(0.0, 0.0, 1.0, 1.5),
+            glm::vec4(0.0, 0.0, -1.0, 1.0),
+            glm::vec4(0.0, 0.0, 1.0, 0.0),
+        ] {
+            let depth_normal = plane(1, 1);
+            let history = temporal_accumulation(
+                &signal,
+                &Surfaces {
+                    depth_normal: &depth_normal,
+                    albedo: &albedo,
+                },
+                &uniform(1, 1, prev_depth_normal),
+                &prev_history,
+                0.1,
+                32,
+                &settings(),
+            );
+
+            assert_close(&history.signal.texels[0], &glm::vec4(0.5, 0.5, 0.5, 1.0));
+            assert_close(&history.moments.texels[0], &glm::vec4(0.5, 0.25, 0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn temporal_accumulation_of_the_occlusion_is_not_demodulated() {
+        let depth_normal = plane(1, 1);
+        let albedo = uniform(1, 1, glm::vec4(0.5, 0.25, 0.0, 0.0));
+
+        let history = temporal_accumulation(
+            &uniform(1, 1, glm::vec4(0.3, 9.0, 9.0, 9.0)),
+            &Surfaces {
+                depth_normal: &depth_normal,
+                albedo: &albedo,
+            },
+            &Image::new(1, 1),
+            &History {
+                signal: Image::new(1, 1),
+                moments: Image::new(1, 1),
+            },
+            0.1,
+            32,
+            &DenoisePushConstants {
+                signal: 1,
+                ..settings()
+            },
+        );
+
+        assert_close(&history.signal.texels[0], &glm::vec4(0.3, 0.3, 0.3, 1.0));
+    }
+
+    #[test]
+    fn variance_of_long_histories_comes_from_the_moments() {
+        let depth_normal = plane(2, 1);
+        let albedo = white(2, 1);
+        let history = History {
+            signal: uniform(2, 1, glm::vec4(0.5, 0.5, 0.5, MIN_HISTORY)),
+            moments: image(2, 1, |x, _| glm::vec4(0.5, 0.5 - x as f32 * 0.5, 0.0, 0.0)),
+        };
+
+        let filtered = variance_estimation(
+            &history,
+            &Surfaces {
+                depth_normal: &depth_normal,
+                albedo: &albedo,
+            },
+            &settings(),
+        );
+
+        // the second pixel has a negative variance, which is clamped
+        assert_close(&filtered.texels[0], &glm::vec4(0.5, 0.5, 0.5, 0.25));
+        assert_close(&filtered.texels[1], &glm::vec4(0.5, 0.5, 0.5, 0.0));
+    }
+
+    #[test]
+    fn variance_of_short_histories_is_estimated_spatially() {
+        let depth_normal = plane(3, 3);
+        let albedo = white(3, 3);
+        // a bright pixel in the middle of a dark plane, gray so that the luminance is the value
+        let history = History {
+            signal: image(3, 3, |x, y| {
+                let value = (x == 1 && y == 1) as u32 as f32;
+                glm::vec4(value, value, value, 1.0)
+            }),
+            moments: Image::new(3, 3),
+        };
+
+        let filtered = variance_estimation(
+            &history,
+            &Surfaces {
+                depth_normal: &depth_normal,
+                albedo: &albedo,
+            },
+            &settings(),
+        );
+
+        // the whole image is in the 7x7 window, with equal weights
+        let mean = 1.0 / 9.0;
+        let variance = (mean - mean * mean) * MIN_HISTORY;
+        for texel in &filtered.texels {
+            assert_close(texel, &glm::vec4(mean, mean, mean, variance));
+        }
+    }
+
+    #[test]
+    fn atrous_iteration_keeps_flat_signals() {
+        let depth_normal = plane(5, 5);
+        let albedo = white(5, 5);
+        let filtered = uniform(5, 5, glm::vec4(0.5, 0.5, 0.5, 0.5));
+
+        let output = atrous_iteration(
+            &filtered,
+            &Surfaces {
+                depth_normal: &depth_normal,
+                albedo: &albedo,
+            },
+            &settings(),
+        );
+
+        for texel in &output.texels {
+            assert_close(&texel.xyz().push(0.0), &glm::vec4(0.5, 0.5, 0.5, 0.0));
+        }
+
+        // the weights of the full kernel sum to 1, the variance shrinks by their squares
+        let squares: f32 = [KERNEL[2], KERNEL[1], KERNEL[0], KERNEL[1], KERNEL[2]]
+            .iter()
+            .map(|weight| weight * weight)
+            .sum();
+        assert!((output.get(2, 2).unwrap().w - 0.5 * squares * squares).abs() < 1e-6);
+    }
+
+    #[test]
+    fn atrous_iteration_stops_at_edges() {
+        // two faces at a right angle, one lit and one dark
+        let depth_normal = image(4, 4, |x, _| {
+            if x < 2 {
+                glm::vec4(0.0, 0.0, 1.0, 1.0)
+            } else {
+                glm::vec4(1.0, 0.0, 0.0, 1.0)
+            }
+        });
+        let albedo = white(4, 4);
+        let filtered = image(4, 4, |x, _| {
+            let value = (x < 2) as u32 as f32;
+            glm::vec4(value, value, value, 100.0)
+        });
+
+        let output = atrous_iteration(
+            &filtered,
+            &Surfaces {
+                depth_normal: &depth_normal,
+                albedo: &albedo,
+            },
+            &settings(),
+        );
+
+        for (x, y) in output.pixels() {
+            let value = (x < 2) as u32 as f32;
+            assert_close(
+                &output.get(x, y).unwrap().xyz().push(0.0),
+                &glm::vec4(value, value, value, 0.0),
+            );
+        }
+    }
+
+    #[test]
+    fn atrous_iterations_spread_their_taps() {
+        let depth_normal = plane(5, 5);
+        let albedo = white(5, 5);
+        let filtered = image(5, 5, |x, y| {
+            let value = (x == 0 && y == 0) as u32 as f32;
+            glm::vec4(value, value, value, 100.0)
+        });
+
+        let output = atrous_iteration(
+            &filtered,
+            &Surfaces {
+                depth_normal: &depth_normal,
+                albedo: &albedo,
+            },
+            &DenoisePushConstants {
+                iteration: 1,
+                ..settings()
+            },
+        );
+
+        // taps 2 pixels apart reach the corner from the center, but not from next to it
+        assert!(output.get(2, 2).unwrap().x > 0.0);
+        assert_eq!(output.get(1, 1).unwrap().x, 0.0);
+        assert_eq!(output.get(2, 1).unwrap().x, 0.0);
+    }
+
+    #[test]
+    fn filter_modulates_the_albedo_back() {
+        // a surface and the background
+        let depth_normal = image(2, 1, |x, _| glm::vec4(0.0, 0.0, 1.0, 1.0 - x as f32));
+        let albedo = uniform(2, 1, glm::vec4(0.5, 0.25, 1.0, 0.0));
+        let surfaces = Surfaces {
+            depth_normal: &depth_normal,
+            albedo: &albedo,
+        };
+        let signal = uniform(2, 1, glm::vec4(0.1, 0.2, 0.3, 0.0));
+        let history = History {
+            signal: uniform(2, 1, glm::vec4(1.0, 1.0, 1.0, MIN_HISTORY)),
+            moments: uniform(2, 1, glm::vec4(1.0, 1.0, 0.0, 0.0)),
+        };
+
+        let denoised = filter(&history, &signal, &surfaces, 2, &settings());
+        assert_close(&denoised.texels[0], &glm::vec4(0.5, 0.25, 1.0, 1.0));
+        assert_close(&denoised.texels[1], &glm::vec4(0.1, 0.2, 0.3, 1.0));
+
+        let occlusion = filter(
+            &history,
+            &signal,
+            &surfaces,
+            2,
+            &DenoisePushConstants {
+                signal: 1,
+                ..settings()
+            },
+        );
+        assert_close(&occlusion.texels[0], &glm::vec4(1.0, 1.0, 1.0, 1.0));
+    }
+}
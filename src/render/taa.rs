@@ -0,0 +1,256 @@
+use ash::{vk, Device};
+use bytemuck::bytes_of;
+
+use crate::{
+    render::{
+        graph::{ImageId, RenderGraph},
+        pass::{Access, Pass},
+    },
+    shaders::reflect::{reflect, ExpectedBinding, PipelineInterface},
+    uniform_types::TaaPushConstants,
+    utils::{create_descriptor_set_layout_and_pool, create_shader_module},
+};
+
+use super::frame::FRAMES_IN_FLIGHT;
+
+/// Format of the motion vectors written by the ray generation shaders, in pixels.
+pub const MOTION_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+
+/// Format of the resolved frame, which is also the history of the next one.
+pub const RESOLVED_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+const TAA_SHADER: (&str, &[u8]) = (
+    "shaders/Post/taa.comp",
+    include_bytes!(concat!(env!("OUT_DIR"), "/taa_comp.spv")),
+);
+
+/// Descriptors written by `write_descriptor_set`, checked against the shader.
+const TAA_BINDINGS: [ExpectedBinding; 4] = [
+    ExpectedBinding {
+        set: 0,
+        binding: 0,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "hdr image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 1,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "motion image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 2,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "previous resolved image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 3,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "resolved image",
+        size: None,
+    },
+];
+
+/// Temporal anti-aliasing of the HDR image. The primary rays are jittered every frame, a compute
+/// pass blends the frame with the history resolved by the previous one, reprojected along the
+/// motion vectors and clipped to the colors around the pixel.
+pub struct Taa {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    /// Texels of the frame and of the motion vectors are fetched as they are.
+    nearest_sampler: vk::Sampler,
+    /// The history is reprojected between its texels.
+    linear_sampler: vk::Sampler,
+}
+
+impl Taa {
+    pub fn new(device: &Device) -> anyhow::Result<Self> {
+        let (path, code) = TAA_SHADER;
+        let reflections = [(path, reflect(code).map_err(anyhow::Error::msg)?)];
+
+        let interface = PipelineInterface::new(&reflections)?;
+        interface.check(&TAA_BINDINGS)?;
+        interface.check_push_constants(Some(std::mem::size_of::<TaaPushConstants>() as u32))?;
+
+        // one set per frame in flight
+        let (descriptor_set_layout, descriptor_pool) =
+            create_descriptor_set_layout_and_pool(device, &interface, 0, FRAMES_IN_FLIGHT as u32)?;
+
+        let layouts = [descriptor_set_layout];
+        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&layouts)
+            .push_constant_ranges(&interface.push_constant_ranges);
+
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&layout_create_info, None) }?;
+
+        let module = unsafe { create_shader_module(device, code) }?;
+
+        let create_info = vk::ComputePipelineCreateInfo::default()
+            .stage(
+                vk::PipelineShaderStageCreateInfo::default()
+                    .stage(vk::ShaderStageFlags::COMPUTE)
+                    .module(module)
+                    .name(c"main"),
+            )
+            .layout(pipeline_layout);
+
+        let pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None)
+        };
+
+        unsafe { device.destroy_shader_module(module, None) };
+
+        let pipeline = pipelines.map_err(|(_, result)| result)?[0];
+
+        let sampler = |filter| {
+            let sampler_create_info = vk::SamplerCreateInfo::default()
+                .mag_filter(filter)
+                .min_filter(filter)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(vk::LOD_CLAMP_NONE);
+
+            unsafe { device.create_sampler(&sampler_create_info, None) }
+        };
+
+        Ok(Taa {
+            pipeline,
+            pipeline_layout,
+            descriptor_set_layout,
+            descriptor_pool,
+            nearest_sampler: sampler(vk::Filter::NEAREST)?,
+            linear_sampler: sampler(vk::Filter::LINEAR)?,
+        })
+    }
+
+    pub fn allocate_descriptor_set(&self, device: &Device) -> anyhow::Result<vk::DescriptorSet> {
+        let layouts = [self.descriptor_set_layout];
+
+        let descriptor_sets = unsafe {
+            device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(self.descriptor_pool)
+                    .set_layouts(&layouts),
+            )
+        }?;
+
+        Ok(descriptor_sets[0])
+    }
+
+    /// `prev_resolved_view` is the resolved image of the previous frame.
+    pub fn write_descriptor_set(
+        &self,
+        device: &Device,
+        descriptor_set: vk::DescriptorSet,
+        hdr_view: vk::ImageView,
+        motion_view: vk::ImageView,
+        prev_resolved_view: vk::ImageView,
+        resolved_view: vk::ImageView,
+    ) {
+        let sampled_info = |sampler, view| {
+            [vk::DescriptorImageInfo::default()
+                .sampler(sampler)
+                .image_view(view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]
+        };
+
+        let hdr_info = sampled_info(self.nearest_sampler, hdr_view);
+        let motion_info = sampled_info(self.nearest_sampler, motion_view);
+        let prev_resolved_info = sampled_info(self.linear_sampler, prev_resolved_view);
+        let resolved_info = [vk::DescriptorImageInfo::default()
+            .image_view(resolved_view)
+            .image_layout(vk::ImageLayout::GENERAL)];
+
+        let writes = [
+            (0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, &hdr_info),
+            (1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, &motion_info),
+            (
+                2,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                &prev_resolved_info,
+            ),
+            (3, vk::DescriptorType::STORAGE_IMAGE, &resolved_info),
+        ]
+        .map(|(binding, descriptor_type, image_info)| {
+            vk::WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(binding)
+                .dst_array_element(0)
+                .descriptor_type(descriptor_type)
+                .image_info(image_info)
+        });
+
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+
+    /// Adds the resolve of `hdr_image` against `prev_resolved_image` into `resolved_image`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_pass<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        hdr_image: ImageId,
+        motion_image: ImageId,
+        prev_resolved_image: ImageId,
+        resolved_image: ImageId,
+        descriptor_set: vk::DescriptorSet,
+        extent: vk::Extent2D,
+        push_constants: TaaPushConstants,
+    ) {
+        graph.add_pass(
+            Pass::new("taa resolve")
+                .image(hdr_image, Access::ComputeSampledRead)
+                .image(motion_image, Access::ComputeSampledRead)
+                .image(prev_resolved_image, Access::ComputeSampledRead)
+                .image(resolved_image, Access::ComputeStorageWrite)
+                .record(move |device, command_buffer, _| unsafe {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::COMPUTE,
+                        self.pipeline,
+                    );
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::COMPUTE,
+                        self.pipeline_layout,
+                        0,
+                        &[descriptor_set],
+                        &[],
+                    );
+                    device.cmd_push_constants(
+                        command_buffer,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::COMPUTE,
+                        0,
+                        bytes_of(&push_constants),
+                    );
+                    // 8x8 workgroups
+                    device.cmd_dispatch(
+                        command_buffer,
+                        extent.width.div_ceil(8),
+                        extent.height.div_ceil(8),
+                        1,
+                    );
+                }),
+        );
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.destroy_sampler(self.nearest_sampler, None);
+            device.destroy_sampler(self.linear_sampler, None);
+        }
+    }
+}
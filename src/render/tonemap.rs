@@ -0,0 +1,506 @@
+use ash::{vk, Device};
+use bytemuck::bytes_of;
+
+use crate::{
+    render::{
+        graph::{BufferId, ImageId, RenderGraph},
+        pass::{Access, Pass},
+    },
+    shaders::reflect::{reflect, ExpectedBinding, PipelineInterface},
+    uniform_types::{ExposureState, TonemapPushConstants},
+    utils::{create_descriptor_set_layout_and_pool, create_shader_module},
+};
+
+use super::frame::FRAMES_IN_FLIGHT;
+
+/// Format of the radiance the modes render, before the exposure and the tonemapping.
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Format of the tonemapped frame, encoded for the display, which the upscaler reads.
+pub const TONEMAPPED_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Value of `TonemapPushConstants::tonemap_operator` passing the debug views through untouched.
+pub const UNMAPPED_OPERATOR: u32 = 3;
+
+/// Bins of the luminance histogram, as in `histogram.glsl`.
+pub const HISTOGRAM_BINS: usize = 256;
+
+/// Index of the set reading the HDR image as the modes render it.
+pub const HDR_SET: usize = 0;
+/// Index of the set reading the HDR image resolved by the TAA.
+pub const RESOLVED_SET: usize = 1;
+
+const TONEMAP_SHADERS: [(&str, &[u8]); 4] = [
+    (
+        "shaders/Post/exposure_histogram.comp",
+        include_bytes!(concat!(env!("OUT_DIR"), "/exposure_histogram_comp.spv")),
+    ),
+    (
+        "shaders/Post/exposure_average.comp",
+        include_bytes!(concat!(env!("OUT_DIR"), "/exposure_average_comp.spv")),
+    ),
+    (
+        "shaders/AO/filter.vert.glsl",
+        include_bytes!(concat!(env!("OUT_DIR"), "/filter_vert.spv")),
+    ),
+    (
+        "shaders/Post/tonemap.frag.glsl",
+        include_bytes!(concat!(env!("OUT_DIR"), "/tonemap_frag.spv")),
+    ),
+];
+
+/// Descriptors written by `write_descriptor_set`, checked against the shaders.
+const TONEMAP_BINDINGS: [ExpectedBinding; 3] = [
+    ExpectedBinding {
+        set: 0,
+        binding: 0,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "hdr image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 1,
+        descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+        name: "histogram buffer",
+        size: Some((HISTOGRAM_BINS * std::mem::size_of::<u32>()) as u32),
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 2,
+        descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+        name: "exposure buffer",
+        size: Some(std::mem::size_of::<ExposureState>() as u32),
+    },
+];
+
+/// Automatic exposure and tonemapping of the HDR image. A compute pass builds the luminance
+/// histogram of the image, a second one averages it and adapts the exposure, then a fullscreen
+/// pass exposes, tonemaps and encodes the image for the display, at the render resolution.
+pub struct Tonemapper {
+    render_pass: vk::RenderPass,
+    histogram_pipeline: vk::Pipeline,
+    average_pipeline: vk::Pipeline,
+    tonemap_pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    push_constant_stages: vk::ShaderStageFlags,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    sampler: vk::Sampler,
+}
+
+impl Tonemapper {
+    pub fn new(device: &Device) -> anyhow::Result<Self> {
+        let mut reflections = vec![];
+        for (path, code) in TONEMAP_SHADERS {
+            reflections.push((path, reflect(code).map_err(anyhow::Error::msg)?));
+        }
+
+        let interface = PipelineInterface::new(&reflections)?;
+        interface.check(&TONEMAP_BINDINGS)?;
+        interface.check_push_constants(Some(std::mem::size_of::<TonemapPushConstants>() as u32))?;
+
+        // two sets per frame in flight
+        let (descriptor_set_layout, descriptor_pool) = create_descriptor_set_layout_and_pool(
+            device,
+            &interface,
+            0,
+            2 * FRAMES_IN_FLIGHT as u32,
+        )?;
+
+        let layouts = [descriptor_set_layout];
+        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&layouts)
+            .push_constant_ranges(&interface.push_constant_ranges);
+
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&layout_create_info, None) }?;
+
+        let render_pass = Self::create_render_pass(device, TONEMAPPED_FORMAT)?;
+
+        let mut modules = vec![];
+        for (_, code) in TONEMAP_SHADERS {
+            modules.push(unsafe { create_shader_module(device, code) }?);
+        }
+
+        let compute_pipelines =
+            Self::create_compute_pipelines(device, pipeline_layout, [modules[0], modules[1]]);
+        let tonemap_pipeline = Self::create_tonemap_pipeline(
+            device,
+            pipeline_layout,
+            render_pass,
+            modules[2],
+            modules[3],
+        );
+
+        unsafe {
+            for module in modules {
+                device.destroy_shader_module(module, None);
+            }
+        }
+
+        let [histogram_pipeline, average_pipeline] = compute_pipelines?;
+
+        // the output has the size of the image, texels are read as they are
+        let sampler_create_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(vk::LOD_CLAMP_NONE);
+
+        let sampler = unsafe { device.create_sampler(&sampler_create_info, None) }?;
+
+        Ok(Tonemapper {
+            render_pass,
+            histogram_pipeline,
+            average_pipeline,
+            tonemap_pipeline: tonemap_pipeline?,
+            pipeline_layout,
+            push_constant_stages: interface.push_constant_ranges[0].stage_flags,
+            descriptor_set_layout,
+            descriptor_pool,
+            sampler,
+        })
+    }
+
+    /// The graph transitions the output before and after the pass, the render pass keeps its
+    /// layout.
+    fn create_render_pass(device: &Device, format: vk::Format) -> anyhow::Result<vk::RenderPass> {
+        let attachments = [vk::AttachmentDescription {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            store_op: vk::AttachmentStoreOp::STORE,
+            initial_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ..Default::default()
+        }];
+        let color_attachment_refs = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+
+        let subpass = vk::SubpassDescription::default()
+            .color_attachments(&color_attachment_refs)
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+
+        let render_pass_create_info = vk::RenderPassCreateInfo::default()
+            .attachments(&attachments)
+            .subpasses(std::slice::from_ref(&subpass));
+
+        Ok(unsafe { device.create_render_pass(&render_pass_create_info, None) }?)
+    }
+
+    fn create_compute_pipelines(
+        device: &Device,
+        pipeline_layout: vk::PipelineLayout,
+        modules: [vk::ShaderModule; 2],
+    ) -> anyhow::Result<[vk::Pipeline; 2]> {
+        let create_infos = modules.map(|module| {
+            vk::ComputePipelineCreateInfo::default()
+                .stage(
+                    vk::PipelineShaderStageCreateInfo::default()
+                        .stage(vk::ShaderStageFlags::COMPUTE)
+                        .module(module)
+                        .name(c"main"),
+                )
+                .layout(pipeline_layout)
+        });
+
+        let pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &create_infos, None)
+        }
+        .map_err(|(_, result)| result)?;
+
+        Ok([pipelines[0], pipelines[1]])
+    }
+
+    fn create_tonemap_pipeline(
+        device: &Device,
+        pipeline_layout: vk::PipelineLayout,
+        render_pass: vk::RenderPass,
+        vertex_module: vk::ShaderModule,
+        fragment_module: vk::ShaderModule,
+    ) -> anyhow::Result<vk::Pipeline> {
+        let shader_stages = [
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vertex_module)
+                .name(c"main"),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(fragment_module)
+                .name(c"main"),
+        ];
+
+        // fullscreen triangle generated from the vertex index
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default();
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .line_width(1.0);
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::default()
+            .color_write_mask(vk::ColorComponentFlags::RGBA)];
+        let color_blend_state =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&color_blend_attachments);
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+        let create_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state)
+            .layout(pipeline_layout)
+            .render_pass(render_pass);
+
+        let pipelines = unsafe {
+            device.create_graphics_pipelines(vk::PipelineCache::null(), &[create_info], None)
+        }
+        .map_err(|(_, result)| result)?;
+
+        Ok(pipelines[0])
+    }
+
+    pub fn allocate_descriptor_sets(
+        &self,
+        device: &Device,
+    ) -> anyhow::Result<[vk::DescriptorSet; 2]> {
+        let layouts = [self.descriptor_set_layout; 2];
+
+        let descriptor_sets = unsafe {
+            device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(self.descriptor_pool)
+                    .set_layouts(&layouts),
+            )
+        }?;
+
+        Ok([descriptor_sets[0], descriptor_sets[1]])
+    }
+
+    /// `tonemapped_view` is the image the tonemapping renders to.
+    pub fn create_framebuffer(
+        &self,
+        device: &Device,
+        extent: vk::Extent2D,
+        tonemapped_view: vk::ImageView,
+    ) -> anyhow::Result<vk::Framebuffer> {
+        let attachments = [tonemapped_view];
+        let framebuffer_create_info = vk::FramebufferCreateInfo::default()
+            .render_pass(self.render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+
+        Ok(unsafe { device.create_framebuffer(&framebuffer_create_info, None) }?)
+    }
+
+    /// The histogram and the exposure are shared by the frames, the exposure adapts from one
+    /// frame to the next. `hdr_view` is the HDR image or its TAA resolve, see `HDR_SET`.
+    pub fn write_descriptor_set(
+        &self,
+        device: &Device,
+        descriptor_set: vk::DescriptorSet,
+        hdr_view: vk::ImageView,
+        histogram_buffer: vk::Buffer,
+        exposure_buffer: vk::Buffer,
+    ) {
+        let hdr_info = [vk::DescriptorImageInfo::default()
+            .sampler(self.sampler)
+            .image_view(hdr_view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let buffer_infos = [histogram_buffer, exposure_buffer].map(|buffer| {
+            [vk::DescriptorBufferInfo::default()
+                .buffer(buffer)
+                .range(vk::WHOLE_SIZE)]
+        });
+
+        let writes = [
+            vk::WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&hdr_info),
+            vk::WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(1)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&buffer_infos[0]),
+            vk::WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(2)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&buffer_infos[1]),
+        ];
+
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+
+    fn bind(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        pipeline: vk::Pipeline,
+        descriptor_set: vk::DescriptorSet,
+        push_constants: &TonemapPushConstants,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, bind_point, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                bind_point,
+                self.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                self.push_constant_stages,
+                0,
+                bytes_of(push_constants),
+            );
+        }
+    }
+
+    /// Adds the exposure passes and the tonemapping of `hdr_image` into `tonemapped_image`,
+    /// rendered through `framebuffer`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        hdr_image: ImageId,
+        tonemapped_image: ImageId,
+        histogram_buffer: BufferId,
+        exposure_buffer: BufferId,
+        descriptor_set: vk::DescriptorSet,
+        framebuffer: vk::Framebuffer,
+        extent: vk::Extent2D,
+        push_constants: TonemapPushConstants,
+    ) {
+        graph.add_pass(
+            Pass::new("build luminance histogram")
+                .image(hdr_image, Access::ComputeSampledRead)
+                .buffer(histogram_buffer, Access::ComputeStorageReadWrite)
+                .record(move |device, command_buffer, _| {
+                    self.bind(
+                        device,
+                        command_buffer,
+                        vk::PipelineBindPoint::COMPUTE,
+                        self.histogram_pipeline,
+                        descriptor_set,
+                        &push_constants,
+                    );
+                    // 16x16 workgroups
+                    unsafe {
+                        device.cmd_dispatch(
+                            command_buffer,
+                            extent.width.div_ceil(16),
+                            extent.height.div_ceil(16),
+                            1,
+                        )
+                    };
+                }),
+        );
+
+        graph.add_pass(
+            Pass::new("adapt exposure")
+                .buffer(histogram_buffer, Access::ComputeStorageReadWrite)
+                .buffer(exposure_buffer, Access::ComputeStorageReadWrite)
+                .record(move |device, command_buffer, _| {
+                    self.bind(
+                        device,
+                        command_buffer,
+                        vk::PipelineBindPoint::COMPUTE,
+                        self.average_pipeline,
+                        descriptor_set,
+                        &push_constants,
+                    );
+                    // one invocation per bin
+                    unsafe { device.cmd_dispatch(command_buffer, 1, 1, 1) };
+                }),
+        );
+
+        graph.add_pass(
+            Pass::new("tonemap")
+                .image(hdr_image, Access::FragmentSampledRead)
+                .buffer(exposure_buffer, Access::FragmentStorageRead)
+                .image(tonemapped_image, Access::ColorAttachmentWrite)
+                .record(move |device, command_buffer, _| {
+                    let render_area = vk::Rect2D::default().extent(extent);
+                    let viewport = vk::Viewport::default()
+                        .width(extent.width as f32)
+                        .height(extent.height as f32)
+                        .max_depth(1.0);
+
+                    let render_pass_begin_info = vk::RenderPassBeginInfo::default()
+                        .render_pass(self.render_pass)
+                        .framebuffer(framebuffer)
+                        .render_area(render_area);
+
+                    unsafe {
+                        device.cmd_begin_render_pass(
+                            command_buffer,
+                            &render_pass_begin_info,
+                            vk::SubpassContents::INLINE,
+                        );
+                    }
+
+                    self.bind(
+                        device,
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.tonemap_pipeline,
+                        descriptor_set,
+                        &push_constants,
+                    );
+
+                    unsafe {
+                        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+                        device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+                        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+                        device.cmd_end_render_pass(command_buffer);
+                    }
+                }),
+        );
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.histogram_pipeline, None);
+            device.destroy_pipeline(self.average_pipeline, None);
+            device.destroy_pipeline(self.tonemap_pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_render_pass(self.render_pass, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
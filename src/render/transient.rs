@@ -0,0 +1,157 @@
+use ash::{vk, Device};
+
+use crate::memory::{Allocation, AllocationKind, Allocator};
+
+use super::graph::ImageDesc;
+
+/// Images created by the render graph for its transient resources, one set per frame in flight.
+/// They are kept between frames and only recreated when the transient images of the graph
+/// change, images assigned to the same slot share the same memory.
+pub struct TransientResources {
+    allocator: Allocator,
+    layout: Vec<(Option<usize>, Option<ImageDesc>)>,
+    images: Vec<(vk::Image, vk::ImageView)>,
+    allocations: Vec<Allocation>,
+}
+
+impl TransientResources {
+    pub fn new(allocator: Allocator) -> Self {
+        TransientResources {
+            allocator,
+            layout: vec![],
+            images: vec![],
+            allocations: vec![],
+        }
+    }
+
+    pub fn image(&self, index: usize) -> (vk::Image, vk::ImageView) {
+        self.images[index]
+    }
+
+    /// Makes sure an image exists for every transient entry of `layout`, the slot and the
+    /// description of every image of a graph. Must only be called once the previous frame using
+    /// these images has completed.
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        layout: &[(Option<usize>, Option<ImageDesc>)],
+        slot_count: usize,
+    ) {
+        if self.layout == layout {
+            return;
+        }
+
+        self.destroy(device);
+
+        unsafe {
+            self.images = layout
+                .iter()
+                .map(|(_, desc)| match desc {
+                    Some(desc) => {
+                        let image_create_info = vk::ImageCreateInfo::default()
+                            .image_type(vk::ImageType::TYPE_2D)
+                            .format(desc.format)
+                            .extent(desc.extent.into())
+                            .mip_levels(1)
+                            .array_layers(1)
+                            .samples(vk::SampleCountFlags::TYPE_1)
+                            .tiling(vk::ImageTiling::OPTIMAL)
+                            .usage(desc.usage)
+                            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+                        let image = device.create_image(&image_create_info, None).unwrap();
+
+                        (image, vk::ImageView::null())
+                    }
+                    None => (vk::Image::null(), vk::ImageView::null()),
+                })
+                .collect();
+
+            let mut slot_requirements = vec![
+                vk::MemoryRequirements {
+                    size: 0,
+                    alignment: 1,
+                    memory_type_bits: u32::MAX,
+                };
+                slot_count
+            ];
+
+            for ((slot, _), (image, _)) in layout.iter().zip(&self.images) {
+                if let Some(slot) = slot {
+                    let requirements = device.get_image_memory_requirements(*image);
+                    let slot_requirements = &mut slot_requirements[*slot];
+
+                    slot_requirements.size = slot_requirements.size.max(requirements.size);
+                    slot_requirements.alignment =
+                        slot_requirements.alignment.max(requirements.alignment);
+                    slot_requirements.memory_type_bits &= requirements.memory_type_bits;
+                }
+            }
+
+            self.allocations = slot_requirements
+                .into_iter()
+                .map(|requirements| {
+                    self.allocator
+                        .allocate(
+                            requirements,
+                            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                            AllocationKind::Image,
+                        )
+                        .unwrap_or_else(|error| {
+                            panic!("Failed to allocate transient image memory: {}", error)
+                        })
+                })
+                .collect();
+
+            for ((slot, desc), (image, view)) in layout.iter().zip(&mut self.images) {
+                let (Some(slot), Some(desc)) = (slot, desc) else {
+                    continue;
+                };
+
+                let allocation = &self.allocations[*slot];
+
+                device
+                    .bind_image_memory(*image, allocation.memory, allocation.offset)
+                    .unwrap();
+
+                let image_view_create_info = vk::ImageViewCreateInfo::default()
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(desc.format)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: 0,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .image(*image);
+
+                *view = device
+                    .create_image_view(&image_view_create_info, None)
+                    .unwrap();
+            }
+        }
+
+        self.layout = layout.to_vec();
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for (image, view) in self.images.drain(..) {
+                if view != vk::ImageView::null() {
+                    device.destroy_image_view(view, None);
+                }
+                if image != vk::Image::null() {
+                    device.destroy_image(image, None);
+                }
+            }
+
+            for allocation in self.allocations.drain(..) {
+                allocation.free();
+            }
+        }
+
+        self.layout.clear();
+    }
+}
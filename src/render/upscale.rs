@@ -0,0 +1,497 @@
+use ash::{vk, Device};
+use bytemuck::bytes_of;
+
+use crate::{
+    render::{
+        graph::{ImageDesc, ImageId, RenderGraph},
+        pass::{Access, Pass},
+    },
+    shaders::reflect::{reflect, ExpectedBinding, PipelineInterface},
+    uniform_types::UpscalePushConstants,
+    utils::{create_descriptor_set_layout_and_pool, create_shader_module},
+};
+
+use super::frame::FRAMES_IN_FLIGHT;
+
+/// Format of the EASU output, at the output resolution.
+const UPSCALED_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Value of `UpscalePushConstants::upscale_filter` running EASU and RCAS.
+const FSR_FILTER: u32 = 1;
+/// Value of `UpscalePushConstants::upscale_filter` averaging the texels of a supersampled frame.
+pub const BOX_FILTER: u32 = 2;
+
+const UPSCALE_SHADERS: [(&str, &[u8]); 3] = [
+    (
+        "shaders/Post/easu.comp",
+        include_bytes!(concat!(env!("OUT_DIR"), "/easu_comp.spv")),
+    ),
+    (
+        "shaders/AO/filter.vert.glsl",
+        include_bytes!(concat!(env!("OUT_DIR"), "/filter_vert.spv")),
+    ),
+    (
+        "shaders/Post/upscale.frag.glsl",
+        include_bytes!(concat!(env!("OUT_DIR"), "/upscale_frag.spv")),
+    ),
+];
+
+/// Descriptors written by `write_descriptor_set`, checked against the shaders.
+const UPSCALE_BINDINGS: [ExpectedBinding; 3] = [
+    ExpectedBinding {
+        set: 0,
+        binding: 0,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "tonemapped image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 1,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "upscaled image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 2,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "upscaled texture",
+        size: None,
+    },
+];
+
+/// Upscaling of the tonemapped frame from the render resolution to the frame output. The
+/// bilinear filter samples it in a single fullscreen pass; FSR upscales it with EASU in a
+/// compute pass, then sharpens the result with RCAS while writing the output. A supersampled
+/// frame is downsampled by the box filter instead, also in a single pass.
+pub struct Upscaler {
+    render_pass: vk::RenderPass,
+    easu_pipeline: vk::Pipeline,
+    output_pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    push_constant_stages: vk::ShaderStageFlags,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    sampler: vk::Sampler,
+}
+
+impl Upscaler {
+    pub fn new(device: &Device, output_format: vk::Format) -> anyhow::Result<Self> {
+        let mut reflections = vec![];
+        for (path, code) in UPSCALE_SHADERS {
+            reflections.push((path, reflect(code).map_err(anyhow::Error::msg)?));
+        }
+
+        let interface = PipelineInterface::new(&reflections)?;
+        interface.check(&UPSCALE_BINDINGS)?;
+        interface.check_push_constants(Some(std::mem::size_of::<UpscalePushConstants>() as u32))?;
+
+        // one set per frame in flight
+        let (descriptor_set_layout, descriptor_pool) =
+            create_descriptor_set_layout_and_pool(device, &interface, 0, FRAMES_IN_FLIGHT as u32)?;
+
+        let layouts = [descriptor_set_layout];
+        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&layouts)
+            .push_constant_ranges(&interface.push_constant_ranges);
+
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&layout_create_info, None) }?;
+
+        let render_pass = Self::create_render_pass(device, output_format)?;
+
+        let mut modules = vec![];
+        for (_, code) in UPSCALE_SHADERS {
+            modules.push(unsafe { create_shader_module(device, code) }?);
+        }
+
+        let easu_pipeline = Self::create_easu_pipeline(device, pipeline_layout, modules[0]);
+        let output_pipeline = Self::create_output_pipeline(
+            device,
+            pipeline_layout,
+            render_pass,
+            modules[1],
+            modules[2],
+        );
+
+        unsafe {
+            for module in modules {
+                device.destroy_shader_module(module, None);
+            }
+        }
+
+        // bilinear taps of the tonemapped frame, EASU and RCAS fetch texels
+        let sampler_create_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(vk::LOD_CLAMP_NONE);
+
+        let sampler = unsafe { device.create_sampler(&sampler_create_info, None) }?;
+
+        Ok(Upscaler {
+            render_pass,
+            easu_pipeline: easu_pipeline?,
+            output_pipeline: output_pipeline?,
+            pipeline_layout,
+            push_constant_stages: interface.push_constant_ranges[0].stage_flags,
+            descriptor_set_layout,
+            descriptor_pool,
+            sampler,
+        })
+    }
+
+    /// The graph transitions the output before and after the pass, the render pass keeps its
+    /// layout.
+    fn create_render_pass(device: &Device, format: vk::Format) -> anyhow::Result<vk::RenderPass> {
+        let attachments = [vk::AttachmentDescription {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            store_op: vk::AttachmentStoreOp::STORE,
+            initial_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ..Default::default()
+        }];
+        let color_attachment_refs = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+
+        let subpass = vk::SubpassDescription::default()
+            .color_attachments(&color_attachment_refs)
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+
+        let render_pass_create_info = vk::RenderPassCreateInfo::default()
+            .attachments(&attachments)
+            .subpasses(std::slice::from_ref(&subpass));
+
+        Ok(unsafe { device.create_render_pass(&render_pass_create_info, None) }?)
+    }
+
+    fn create_easu_pipeline(
+        device: &Device,
+        pipeline_layout: vk::PipelineLayout,
+        module: vk::ShaderModule,
+    ) -> anyhow::Result<vk::Pipeline> {
+        let create_info = vk::ComputePipelineCreateInfo::default()
+            .stage(
+                vk::PipelineShaderStageCreateInfo::default()
+                    .stage(vk::ShaderStageFlags::COMPUTE)
+                    .module(module)
+                    .name(c"main"),
+            )
+            .layout(pipeline_layout);
+
+        let pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None)
+        }
+        .map_err(|(_, result)| result)?;
+
+        Ok(pipelines[0])
+    }
+
+    fn create_output_pipeline(
+        device: &Device,
+        pipeline_layout: vk::PipelineLayout,
+        render_pass: vk::RenderPass,
+        vertex_module: vk::ShaderModule,
+        fragment_module: vk::ShaderModule,
+    ) -> anyhow::Result<vk::Pipeline> {
+        let shader_stages = [
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vertex_module)
+                .name(c"main"),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(fragment_module)
+                .name(c"main"),
+        ];
+
+        // fullscreen triangle generated from the vertex index
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default();
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .line_width(1.0);
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::default()
+            .color_write_mask(vk::ColorComponentFlags::RGBA)];
+        let color_blend_state =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&color_blend_attachments);
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+        let create_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state)
+            .layout(pipeline_layout)
+            .render_pass(render_pass);
+
+        let pipelines = unsafe {
+            device.create_graphics_pipelines(vk::PipelineCache::null(), &[create_info], None)
+        }
+        .map_err(|(_, result)| result)?;
+
+        Ok(pipelines[0])
+    }
+
+    pub fn allocate_descriptor_set(&self, device: &Device) -> anyhow::Result<vk::DescriptorSet> {
+        let layouts = [self.descriptor_set_layout];
+
+        let descriptor_sets = unsafe {
+            device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(self.descriptor_pool)
+                    .set_layouts(&layouts),
+            )
+        }?;
+
+        Ok(descriptor_sets[0])
+    }
+
+    /// `output_view` is the frame output, sized like the swapchain.
+    pub fn create_framebuffer(
+        &self,
+        device: &Device,
+        extent: vk::Extent2D,
+        output_view: vk::ImageView,
+    ) -> anyhow::Result<vk::Framebuffer> {
+        let attachments = [output_view];
+        let framebuffer_create_info = vk::FramebufferCreateInfo::default()
+            .render_pass(self.render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+
+        Ok(unsafe { device.create_framebuffer(&framebuffer_create_info, None) }?)
+    }
+
+    /// The upscaled texture is the tonemapped image as well until EASU runs, the output shader
+    /// reads it whatever the filter.
+    pub fn write_descriptor_set(
+        &self,
+        device: &Device,
+        descriptor_set: vk::DescriptorSet,
+        tonemapped_view: vk::ImageView,
+    ) {
+        let tonemapped_info = [vk::DescriptorImageInfo::default()
+            .sampler(self.sampler)
+            .image_view(tonemapped_view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+
+        let writes = [0, 2].map(|binding| {
+            vk::WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(binding)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&tonemapped_info)
+        });
+
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+
+    /// The upscaled image is a transient image of the graph, written by EASU as a storage image
+    /// then sampled by RCAS. Its view is written when the EASU pass is recorded, before the set
+    /// is bound.
+    fn write_upscaled_view(
+        &self,
+        device: &Device,
+        descriptor_set: vk::DescriptorSet,
+        upscaled_view: vk::ImageView,
+    ) {
+        let upscaled_storage_info = [vk::DescriptorImageInfo::default()
+            .image_view(upscaled_view)
+            .image_layout(vk::ImageLayout::GENERAL)];
+        let upscaled_info = [vk::DescriptorImageInfo::default()
+            .sampler(self.sampler)
+            .image_view(upscaled_view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+
+        let writes = [
+            vk::WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(1)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .image_info(&upscaled_storage_info),
+            vk::WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(2)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&upscaled_info),
+        ];
+
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+
+    fn bind(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        pipeline: vk::Pipeline,
+        descriptor_set: vk::DescriptorSet,
+        push_constants: &UpscalePushConstants,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, bind_point, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                bind_point,
+                self.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                self.push_constant_stages,
+                0,
+                bytes_of(push_constants),
+            );
+        }
+    }
+
+    /// Adds the upscaling of `tonemapped_image` into `output_image`, rendered through
+    /// `framebuffer` at `output_extent`. `upscaled_image` holds the EASU output of FSR.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        tonemapped_image: ImageId,
+        output_image: ImageId,
+        descriptor_set: vk::DescriptorSet,
+        framebuffer: vk::Framebuffer,
+        output_extent: vk::Extent2D,
+        push_constants: UpscalePushConstants,
+    ) {
+        let upscaled_image = (push_constants.upscale_filter == FSR_FILTER).then(|| {
+            graph.create_image(ImageDesc {
+                format: UPSCALED_FORMAT,
+                extent: output_extent,
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            })
+        });
+
+        if let Some(upscaled_image) = upscaled_image {
+            graph.add_pass(
+                Pass::new("easu")
+                    .image(tonemapped_image, Access::ComputeSampledRead)
+                    .image(upscaled_image, Access::ComputeStorageWrite)
+                    .record(move |device, command_buffer, resources| {
+                        self.write_upscaled_view(
+                            device,
+                            descriptor_set,
+                            resources.image_view(upscaled_image),
+                        );
+                        self.bind(
+                            device,
+                            command_buffer,
+                            vk::PipelineBindPoint::COMPUTE,
+                            self.easu_pipeline,
+                            descriptor_set,
+                            &push_constants,
+                        );
+                        // 8x8 workgroups
+                        unsafe {
+                            device.cmd_dispatch(
+                                command_buffer,
+                                output_extent.width.div_ceil(8),
+                                output_extent.height.div_ceil(8),
+                                1,
+                            )
+                        };
+                    }),
+            );
+        }
+
+        let source_image = upscaled_image.unwrap_or(tonemapped_image);
+
+        let name = match push_constants.upscale_filter {
+            FSR_FILTER => "rcas",
+            BOX_FILTER => "downsample box",
+            _ => "upscale bilinear",
+        };
+
+        graph.add_pass(
+            Pass::new(name)
+                .image(source_image, Access::FragmentSampledRead)
+                .image(output_image, Access::ColorAttachmentWrite)
+                .record(move |device, command_buffer, _| {
+                    let render_area = vk::Rect2D::default().extent(output_extent);
+                    let viewport = vk::Viewport::default()
+                        .width(output_extent.width as f32)
+                        .height(output_extent.height as f32)
+                        .max_depth(1.0);
+
+                    let render_pass_begin_info = vk::RenderPassBeginInfo::default()
+                        .render_pass(self.render_pass)
+                        .framebuffer(framebuffer)
+                        .render_area(render_area);
+
+                    unsafe {
+                        device.cmd_begin_render_pass(
+                            command_buffer,
+                            &render_pass_begin_info,
+                            vk::SubpassContents::INLINE,
+                        );
+                    }
+
+                    self.bind(
+                        device,
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.output_pipeline,
+                        descriptor_set,
+                        &push_constants,
+                    );
+
+                    unsafe {
+                        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+                        device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+                        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+                        device.cmd_end_render_pass(command_buffer);
+                    }
+                }),
+        );
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.easu_pipeline, None);
+            device.destroy_pipeline(self.output_pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_render_pass(self.render_pass, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! GLSL to SPIR-V compilation, shared by the build script and runtime hot reload.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::layout::SHARED_TYPES_INCLUDE;
+use shaderc::{
+    CompileOptions, Compiler, EnvVersion, IncludeType, ResolvedInclude, ShaderKind, SpirvVersion,
+    TargetEnv,
+};
+
+pub const SHADER_DIRECTORY: &str = "shaders";
+
+// files without a stage extension, like `common.glsl`, are only compiled through includes
+const STAGES: [(&str, ShaderKind, &str); 9] = [
+    (".rgen", ShaderKind::RayGeneration, "rgen"),
+    (".rchit", ShaderKind::ClosestHit, "rchit"),
+    (".rahit", ShaderKind::AnyHit, "rahit"),
+    (".rmiss", ShaderKind::Miss, "rmiss"),
+    (".rint", ShaderKind::Intersection, "rint"),
+    (".rcall", ShaderKind::Callable, "rcall"),
+    (".comp", ShaderKind::Compute, "comp"),
+    (".vert.glsl", ShaderKind::Vertex, "vert"),
+    (".frag.glsl", ShaderKind::Fragment, "frag"),
+];
+
+/// Every file below `directory`, includes too.
+pub fn shader_files(directory: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            shader_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Output name and kind of a shader stage, `shaders/AO/ao.rgen` is compiled to `ao_rgen.spv`.
+pub fn stage(path: &Path) -> Option<(String, ShaderKind)> {
+    let file_name = path.file_name()?.to_str()?;
+
+    STAGES.iter().find_map(|(extension, kind, suffix)| {
+        let stem = file_name.strip_suffix(extension)?;
+        Some((format!("{}_{}.spv", stem, suffix), *kind))
+    })
+}
+
+/// Resolves `#include "..."` next to the including file first, then `#include <...>` and failed
+/// relative includes from the shader directory. `shared_types.glsl` resolves to the generated
+/// declarations of the shared structs.
+fn resolve_include(
+    requested: &str,
+    include_type: IncludeType,
+    requesting: &str,
+    shared_types: &str,
+) -> Result<ResolvedInclude, String> {
+    if requested == SHARED_TYPES_INCLUDE {
+        return Ok(ResolvedInclude {
+            resolved_name: String::from(SHARED_TYPES_INCLUDE),
+            content: String::from(shared_types),
+        });
+    }
+
+    let path = match include_type {
+        IncludeType::Relative => Path::new(requesting)
+            .parent()
+            .unwrap_or(Path::new(SHADER_DIRECTORY))
+            .join(requested),
+        IncludeType::Standard => Path::new(SHADER_DIRECTORY).join(requested),
+    };
+
+    let content = fs::read_to_string(&path)
+        .map_err(|error| format!("Cannot include {}: {}", path.display(), error))?;
+
+    Ok(ResolvedInclude {
+        resolved_name: path.to_string_lossy().replace('\\', "/"),
+        content,
+    })
+}
+
+pub struct ShaderCompiler {
+    compiler: Compiler,
+    options: CompileOptions<'static>,
+}
+
+impl ShaderCompiler {
+    /// `shared_types` is the content of the `shared_types.glsl` include.
+    pub fn new(shared_types: String) -> Result<Self, String> {
+        let compiler = Compiler::new().ok_or("Failed to create the shader compiler.")?;
+
+        let mut options = CompileOptions::new().ok_or("Failed to create the compile options.")?;
+        options.set_target_env(TargetEnv::Vulkan, EnvVersion::Vulkan1_2 as u32);
+        options.set_target_spirv(SpirvVersion::V1_5);
+        options.set_include_callback(move |requested, include_type, requesting, _depth| {
+            resolve_include(requested, include_type, requesting, &shared_types)
+        });
+
+        Ok(ShaderCompiler { compiler, options })
+    }
+
+    /// Compiles a shader stage to SPIR-V words. Warnings and errors are returned as lines
+    /// formatted like `shaders/rt.rchit:12: error: ...`.
+    pub fn compile(&mut self, path: &Path) -> Result<(Vec<u32>, Vec<String>), Vec<String>> {
+        let (_, kind) =
+            stage(path).ok_or_else(|| vec![format!("{}: not a shader stage", path.display())])?;
+
+        // forward slashes keep the names in messages the same on every platform
+        let name = path.to_string_lossy().replace('\\', "/");
+        let source =
+            fs::read_to_string(path).map_err(|error| vec![format!("{}: {}", name, error)])?;
+
+        match self
+            .compiler
+            .compile_into_spirv(&source, kind, &name, "main", Some(&self.options))
+        {
+            Ok(artifact) => Ok((
+                artifact.as_binary().to_vec(),
+                artifact
+                    .get_warning_messages()
+                    .lines()
+                    .map(String::from)
+                    .collect(),
+            )),
+            Err(shaderc::Error::CompilationError(_, messages)) => {
+                Err(messages.lines().map(String::from).collect())
+            }
+            Err(error) => Err(vec![format!("{}: {}", name, error)]),
+        }
+    }
+}
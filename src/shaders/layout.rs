@@ -0,0 +1,247 @@
+//! Structs shared between Rust and GLSL. They are declared once with `glsl_structs!`, which
+//! generates the Rust structs, their GLSL declarations and compile time checks that both agree on
+//! the memory layout. Also used by the build script, so it must not depend on other crates.
+
+/// Memory layout of the block a shared struct is used in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockLayout {
+    Std140,
+    // no shared struct is used in a std430 block yet
+    #[allow(dead_code)]
+    Std430,
+    /// `GL_EXT_scalar_block_layout`, every member is aligned to its component size.
+    Scalar,
+}
+
+#[derive(Debug)]
+pub struct GlslField {
+    pub name: &'static str,
+    pub glsl_type: &'static str,
+}
+
+#[derive(Debug)]
+pub struct GlslStruct {
+    pub name: &'static str,
+    pub layout: BlockLayout,
+    pub fields: &'static [GlslField],
+}
+
+/// Name of the include resolving to the generated declarations.
+pub const SHARED_TYPES_INCLUDE: &str = "shared_types.glsl";
+
+// name, size and base alignment in std140 and std430 blocks, components are all 4 bytes
+const GLSL_TYPES: [(&str, u32, u32); 13] = [
+    ("float", 4, 4),
+    ("int", 4, 4),
+    ("uint", 4, 4),
+    ("vec2", 8, 8),
+    ("vec3", 12, 16),
+    ("vec4", 16, 16),
+    ("ivec2", 8, 8),
+    ("ivec3", 12, 16),
+    ("ivec4", 16, 16),
+    ("uvec2", 8, 8),
+    ("uvec3", 12, 16),
+    ("uvec4", 16, 16),
+    ("mat4", 64, 16),
+];
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+
+    true
+}
+
+const fn glsl_type(glsl_type: &str) -> (u32, u32) {
+    let mut i = 0;
+    while i < GLSL_TYPES.len() {
+        let (name, size, align) = GLSL_TYPES[i];
+        if str_eq(name, glsl_type) {
+            return (size, align);
+        }
+        i += 1;
+    }
+
+    panic!("unsupported GLSL type in a shared struct")
+}
+
+const fn align_up(offset: u32, align: u32) -> u32 {
+    offset.div_ceil(align) * align
+}
+
+impl BlockLayout {
+    const fn align(self, base_align: u32) -> u32 {
+        match self {
+            BlockLayout::Std140 | BlockLayout::Std430 => base_align,
+            BlockLayout::Scalar => 4,
+        }
+    }
+}
+
+pub const fn glsl_type_size(glsl_type_name: &str) -> u32 {
+    glsl_type(glsl_type_name).0
+}
+
+/// Offset of the field `name` in a block using `layout`.
+pub const fn field_offset(layout: BlockLayout, fields: &[GlslField], name: &str) -> u32 {
+    let mut offset = 0;
+    let mut i = 0;
+    while i < fields.len() {
+        let (size, base_align) = glsl_type(fields[i].glsl_type);
+        offset = align_up(offset, layout.align(base_align));
+        if str_eq(fields[i].name, name) {
+            return offset;
+        }
+        offset += size;
+        i += 1;
+    }
+
+    panic!("no such field in the shared struct")
+}
+
+/// Size of the struct, padded to its alignment like in an array.
+pub const fn struct_size(layout: BlockLayout, fields: &[GlslField]) -> u32 {
+    let mut offset = 0;
+    let mut struct_align = 4;
+    let mut i = 0;
+    while i < fields.len() {
+        let (size, base_align) = glsl_type(fields[i].glsl_type);
+        let align = layout.align(base_align);
+        offset = align_up(offset, align) + size;
+        if align > struct_align {
+            struct_align = align;
+        }
+        i += 1;
+    }
+
+    if let BlockLayout::Std140 = layout {
+        struct_align = align_up(struct_align, 16);
+    }
+
+    align_up(offset, struct_align)
+}
+
+/// Content of the `shared_types.glsl` include.
+pub fn glsl_declarations(structs: &[GlslStruct]) -> String {
+    let mut source = String::from(
+        "// Generated from src/shaders/shared_types.rs, do not edit.\n\
+         #ifndef SHARED_TYPES_GLSL\n\
+         #define SHARED_TYPES_GLSL\n",
+    );
+
+    for glsl_struct in structs {
+        source.push_str(&format!(
+            "\n// checked against the Rust struct for {:?} blocks\nstruct {} {{\n",
+            glsl_struct.layout, glsl_struct.name
+        ));
+        for field in glsl_struct.fields {
+            source.push_str(&format!("    {} {};\n", field.glsl_type, field.name));
+        }
+        source.push_str("};\n");
+    }
+
+    source.push_str("\n#endif\n");
+
+    source
+}
+
+/// `glsl_structs!(rust; ...)` declares the Rust structs, checks their layout and defines
+/// `GLSL_STRUCTS`. `glsl_structs!(glsl; ...)` only defines `GLSL_STRUCTS`, leaving the Rust types
+/// unresolved for the build script. The items of this module must be in scope.
+macro_rules! glsl_structs {
+    (rust; $(
+        #[layout($layout:ident)]
+        $(#[$attr:meta])*
+        pub struct $name:ident {
+            $($(#[$field_attr:meta])* pub $field:ident: $ty:ty => $glsl:ident,)*
+        }
+    )*) => {
+        $(
+            $(#[$attr])*
+            #[repr(C)]
+            #[derive(Clone, Debug, Copy)]
+            pub struct $name {
+                $($(#[$field_attr])* pub $field: $ty,)*
+            }
+
+            // the derives of bytemuck generate dead items inside this macro, the checks below are
+            // the ones they would make: plain old data fields and no padding
+            unsafe impl ::bytemuck::Zeroable for $name {}
+            unsafe impl ::bytemuck::Pod for $name {}
+
+            const _: () = {
+                const fn assert_pod<T: ::bytemuck::Pod>() {}
+                $(assert_pod::<$ty>();)*
+
+                assert!(
+                    ::std::mem::size_of::<$name>() == 0 $(+ ::std::mem::size_of::<$ty>())*,
+                    concat!(stringify!($name), " has padding")
+                );
+
+                const FIELDS: &[GlslField] = &[
+                    $(GlslField { name: stringify!($field), glsl_type: stringify!($glsl) },)*
+                ];
+
+                $(
+                    assert!(
+                        ::std::mem::size_of::<$ty>() == glsl_type_size(stringify!($glsl)) as usize,
+                        concat!(
+                            "the size of ", stringify!($name), "::", stringify!($field),
+                            " does not match its GLSL type"
+                        )
+                    );
+                    assert!(
+                        ::std::mem::offset_of!($name, $field)
+                            == field_offset(BlockLayout::$layout, FIELDS, stringify!($field))
+                                as usize,
+                        concat!(
+                            "the offset of ", stringify!($name), "::", stringify!($field),
+                            " does not match its GLSL layout"
+                        )
+                    );
+                )*
+
+                assert!(
+                    ::std::mem::size_of::<$name>()
+                        == struct_size(BlockLayout::$layout, FIELDS) as usize,
+                    concat!("the size of ", stringify!($name), " does not match its GLSL layout")
+                );
+            };
+        )*
+
+        glsl_structs!(glsl; $(
+            #[layout($layout)]
+            pub struct $name {
+                $(pub $field: $ty => $glsl,)*
+            }
+        )*);
+    };
+
+    (glsl; $(
+        #[layout($layout:ident)]
+        $(#[$attr:meta])*
+        pub struct $name:ident {
+            $($(#[$field_attr:meta])* pub $field:ident: $ty:ty => $glsl:ident,)*
+        }
+    )*) => {
+        /// Structs shared with the shaders, in declaration order.
+        pub const GLSL_STRUCTS: &[GlslStruct] = &[$(
+            GlslStruct {
+                name: stringify!($name),
+                layout: BlockLayout::$layout,
+                fields: &[$(GlslField { name: stringify!($field), glsl_type: stringify!($glsl) },)*],
+            },
+        )*];
+    };
+}
@@ -0,0 +1,66 @@
+pub mod compiler;
+#[macro_use]
+pub mod layout;
+pub mod reflect;
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use compiler::shader_files;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Detects modified shader sources by polling their modification times.
+pub struct ShaderWatcher {
+    directory: PathBuf,
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new(directory: &Path) -> Self {
+        let mut watcher = ShaderWatcher {
+            directory: directory.to_path_buf(),
+            modified: HashMap::new(),
+            last_poll: Instant::now(),
+        };
+        watcher.modified = watcher.modification_times();
+
+        watcher
+    }
+
+    fn modification_times(&self) -> HashMap<PathBuf, SystemTime> {
+        let mut files = vec![];
+        if let Err(error) = shader_files(&self.directory, &mut files) {
+            println!("Cannot read {}: {}", self.directory.display(), error);
+        }
+
+        files
+            .into_iter()
+            .filter_map(|file| {
+                let modified = file.metadata().and_then(|metadata| metadata.modified());
+                modified.ok().map(|modified| (file, modified))
+            })
+            .collect()
+    }
+
+    /// Returns whether a file was added, removed or modified since the last change, at most
+    /// every `POLL_INTERVAL`.
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let modified = self.modification_times();
+        if modified == self.modified {
+            return false;
+        }
+
+        self.modified = modified;
+        true
+    }
+}
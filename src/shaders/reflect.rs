@@ -0,0 +1,948 @@
+//! Minimal SPIR-V reflection of the descriptor bindings and push constants of shader stages.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use ash::vk;
+
+const MAGIC_NUMBER: u32 = 0x0723_0203;
+
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+const IMAGE_DIM_BUFFER: u32 = 5;
+
+/// Errors found while reflecting or merging shader interfaces, one message per problem.
+#[derive(Debug)]
+pub struct ReflectionError(pub Vec<String>);
+
+impl fmt::Display for ReflectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join("\n"))
+    }
+}
+
+impl std::error::Error for ReflectionError {}
+
+#[derive(Clone, Debug)]
+enum Type {
+    Bool,
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32, format: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { storage_class: u32, pointee: u32 },
+    AccelerationStructure,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+    pub name: String,
+    /// Description of the block or image layout, compared between the stages using the binding.
+    pub layout: String,
+    /// Size of a block, without its trailing runtime array.
+    pub block_size: Option<u32>,
+    /// Stride of the trailing runtime array of a storage buffer block.
+    pub element_stride: Option<u32>,
+}
+
+pub struct ShaderReflection {
+    pub stage: vk::ShaderStageFlags,
+    pub bindings: Vec<DescriptorBinding>,
+    pub push_constant_size: Option<u32>,
+}
+
+#[derive(Default)]
+struct Module {
+    execution_model: Option<u32>,
+    names: HashMap<u32, String>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    variables: Vec<(u32, u32)>,
+    sets: HashMap<u32, u32>,
+    bindings: HashMap<u32, u32>,
+    blocks: HashSet<u32>,
+    buffer_blocks: HashSet<u32>,
+    array_strides: HashMap<u32, u32>,
+    member_offsets: HashMap<(u32, u32), u32>,
+}
+
+fn literal_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|byte| *byte != 0)
+        .collect();
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn stage_flags(execution_model: u32) -> Option<vk::ShaderStageFlags> {
+    Some(match execution_model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        5313 => vk::ShaderStageFlags::RAYGEN_KHR,
+        5314 => vk::ShaderStageFlags::INTERSECTION_KHR,
+        5315 => vk::ShaderStageFlags::ANY_HIT_KHR,
+        5316 => vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+        5317 => vk::ShaderStageFlags::MISS_KHR,
+        5318 => vk::ShaderStageFlags::CALLABLE_KHR,
+        _ => return None,
+    })
+}
+
+impl Module {
+    fn parse(code: &[u32]) -> Result<Self, String> {
+        if code.len() < 5 || code[0] != MAGIC_NUMBER {
+            return Err(String::from("not a SPIR-V module"));
+        }
+
+        let mut module = Module::default();
+        let mut words = &code[5..];
+
+        while !words.is_empty() {
+            let word_count = (words[0] >> 16) as usize;
+            let opcode = words[0] & 0xffff;
+
+            if word_count == 0 || word_count > words.len() {
+                return Err(String::from("truncated SPIR-V instruction"));
+            }
+
+            let operands = &words[1..word_count];
+            words = &words[word_count..];
+
+            match opcode {
+                OP_NAME => {
+                    module
+                        .names
+                        .insert(operands[0], literal_string(&operands[1..]));
+                }
+                OP_ENTRY_POINT => {
+                    module.execution_model.get_or_insert(operands[0]);
+                }
+                OP_TYPE_BOOL => {
+                    module.types.insert(operands[0], Type::Bool);
+                }
+                OP_TYPE_INT => {
+                    module.types.insert(
+                        operands[0],
+                        Type::Int {
+                            width: operands[1],
+                            signed: operands[2] != 0,
+                        },
+                    );
+                }
+                OP_TYPE_FLOAT => {
+                    module
+                        .types
+                        .insert(operands[0], Type::Float { width: operands[1] });
+                }
+                OP_TYPE_VECTOR => {
+                    module.types.insert(
+                        operands[0],
+                        Type::Vector {
+                            component: operands[1],
+                            count: operands[2],
+                        },
+                    );
+                }
+                OP_TYPE_MATRIX => {
+                    module.types.insert(
+                        operands[0],
+                        Type::Matrix {
+                            column: operands[1],
+                            count: operands[2],
+                        },
+                    );
+                }
+                OP_TYPE_IMAGE => {
+                    module.types.insert(
+                        operands[0],
+                        Type::Image {
+                            dim: operands[2],
+                            sampled: operands[6],
+                            format: operands[7],
+                        },
+                    );
+                }
+                OP_TYPE_SAMPLER => {
+                    module.types.insert(operands[0], Type::Sampler);
+                }
+                OP_TYPE_SAMPLED_IMAGE => {
+                    module.types.insert(operands[0], Type::SampledImage);
+                }
+                OP_TYPE_ARRAY => {
+                    module.types.insert(
+                        operands[0],
+                        Type::Array {
+                            element: operands[1],
+                            length: operands[2],
+                        },
+                    );
+                }
+                OP_TYPE_RUNTIME_ARRAY => {
+                    module.types.insert(
+                        operands[0],
+                        Type::RuntimeArray {
+                            element: operands[1],
+                        },
+                    );
+                }
+                OP_TYPE_STRUCT => {
+                    module.types.insert(
+                        operands[0],
+                        Type::Struct {
+                            members: operands[1..].to_vec(),
+                        },
+                    );
+                }
+                OP_TYPE_POINTER => {
+                    module.types.insert(
+                        operands[0],
+                        Type::Pointer {
+                            storage_class: operands[1],
+                            pointee: operands[2],
+                        },
+                    );
+                }
+                OP_TYPE_ACCELERATION_STRUCTURE => {
+                    module
+                        .types
+                        .insert(operands[0], Type::AccelerationStructure);
+                }
+                OP_CONSTANT => {
+                    if let Some(value) = operands.get(2) {
+                        module.constants.insert(operands[1], *value);
+                    }
+                }
+                OP_VARIABLE => {
+                    module.variables.push((operands[1], operands[0]));
+                }
+                OP_DECORATE => match operands[1] {
+                    DECORATION_BLOCK => {
+                        module.blocks.insert(operands[0]);
+                    }
+                    DECORATION_BUFFER_BLOCK => {
+                        module.buffer_blocks.insert(operands[0]);
+                    }
+                    DECORATION_ARRAY_STRIDE => {
+                        module.array_strides.insert(operands[0], operands[2]);
+                    }
+                    DECORATION_BINDING => {
+                        module.bindings.insert(operands[0], operands[2]);
+                    }
+                    DECORATION_DESCRIPTOR_SET => {
+                        module.sets.insert(operands[0], operands[2]);
+                    }
+                    _ => (),
+                },
+                OP_MEMBER_DECORATE if operands[2] == DECORATION_OFFSET => {
+                    module
+                        .member_offsets
+                        .insert((operands[0], operands[1]), operands[3]);
+                }
+                _ => (),
+            }
+        }
+
+        Ok(module)
+    }
+
+    fn array_length(&self, length: u32) -> u32 {
+        self.constants.get(&length).copied().unwrap_or(0)
+    }
+
+    /// Size in bytes, `None` for runtime arrays.
+    fn size(&self, type_id: u32) -> Option<u32> {
+        match self.types.get(&type_id)? {
+            Type::Bool => Some(4),
+            Type::Int { width, .. } | Type::Float { width } => Some(width / 8),
+            Type::Vector { component, count } => Some(self.size(*component)? * count),
+            Type::Matrix { column, count } => Some(self.size(*column)? * count),
+            Type::Array { element, length } => {
+                let stride = match self.array_strides.get(&type_id) {
+                    Some(stride) => *stride,
+                    None => self.size(*element)?,
+                };
+                Some(stride * self.array_length(*length))
+            }
+            Type::Struct { members } => Some(
+                members
+                    .iter()
+                    .enumerate()
+                    .map(|(index, member)| {
+                        let offset = self.member_offset(type_id, index);
+                        offset + self.size(*member).unwrap_or(0)
+                    })
+                    .max()
+                    .unwrap_or(0),
+            ),
+            _ => None,
+        }
+    }
+
+    fn member_offset(&self, struct_id: u32, index: usize) -> u32 {
+        self.member_offsets
+            .get(&(struct_id, index as u32))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Description of a type including offsets and strides, two stages declaring a binding with
+    /// different descriptions disagree on its memory layout.
+    fn describe(&self, type_id: u32) -> String {
+        let Some(ty) = self.types.get(&type_id) else {
+            return String::from("?");
+        };
+
+        match ty {
+            Type::Bool => String::from("bool"),
+            Type::Int { width, signed } => format!("{}{}", if *signed { "i" } else { "u" }, width),
+            Type::Float { width } => format!("f{}", width),
+            Type::Vector { component, count } => {
+                format!("vec{}<{}>", count, self.describe(*component))
+            }
+            Type::Matrix { column, count } => format!("mat{}<{}>", count, self.describe(*column)),
+            Type::Image {
+                dim,
+                sampled,
+                format,
+            } => format!("image(dim {}, sampled {}, format {})", dim, sampled, format),
+            Type::Sampler => String::from("sampler"),
+            Type::SampledImage => String::from("sampled image"),
+            Type::Array { element, length } => format!(
+                "[{}; {}] stride {}",
+                self.describe(*element),
+                self.array_length(*length),
+                self.array_strides.get(&type_id).copied().unwrap_or(0)
+            ),
+            Type::RuntimeArray { element } => format!(
+                "[{}] stride {}",
+                self.describe(*element),
+                self.array_strides.get(&type_id).copied().unwrap_or(0)
+            ),
+            Type::Struct { members } => {
+                let members: Vec<String> = members
+                    .iter()
+                    .enumerate()
+                    .map(|(index, member)| {
+                        format!(
+                            "{}: {}",
+                            self.member_offset(type_id, index),
+                            self.describe(*member)
+                        )
+                    })
+                    .collect();
+                format!("{{ {} }}", members.join(", "))
+            }
+            Type::Pointer { pointee, .. } => self.describe(*pointee),
+            Type::AccelerationStructure => String::from("acceleration structure"),
+        }
+    }
+
+    fn descriptor_type(
+        &self,
+        storage_class: u32,
+        type_id: u32,
+    ) -> Result<vk::DescriptorType, String> {
+        let descriptor_type = match (storage_class, self.types.get(&type_id)) {
+            (STORAGE_CLASS_UNIFORM, _) if self.buffer_blocks.contains(&type_id) => {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            (STORAGE_CLASS_UNIFORM, _) => vk::DescriptorType::UNIFORM_BUFFER,
+            (STORAGE_CLASS_STORAGE_BUFFER, _) => vk::DescriptorType::STORAGE_BUFFER,
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Some(Type::AccelerationStructure)) => {
+                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR
+            }
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Some(Type::Image { dim, sampled, .. })) => {
+                match (*dim == IMAGE_DIM_BUFFER, *sampled == 2) {
+                    (false, true) => vk::DescriptorType::STORAGE_IMAGE,
+                    (false, false) => vk::DescriptorType::SAMPLED_IMAGE,
+                    (true, true) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                    (true, false) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                }
+            }
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Some(Type::Sampler)) => vk::DescriptorType::SAMPLER,
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Some(Type::SampledImage)) => {
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+            }
+            _ => {
+                return Err(format!(
+                    "unsupported resource type {}",
+                    self.describe(type_id)
+                ))
+            }
+        };
+
+        Ok(descriptor_type)
+    }
+
+    fn binding(
+        &self,
+        stage: vk::ShaderStageFlags,
+        variable: u32,
+        storage_class: u32,
+        pointee: u32,
+    ) -> Result<DescriptorBinding, String> {
+        let name = self.names.get(&variable).cloned().unwrap_or_default();
+
+        let (Some(set), Some(binding)) = (self.sets.get(&variable), self.bindings.get(&variable))
+        else {
+            return Err(format!("{} has no descriptor set or binding", name));
+        };
+
+        // arrays of descriptors
+        let (count, type_id) = match self.types.get(&pointee) {
+            Some(Type::Array { element, length }) => (self.array_length(*length), *element),
+            Some(Type::RuntimeArray { .. }) => {
+                return Err(format!(
+                    "{} (set {}, binding {}) is an unbounded descriptor array",
+                    name, set, binding
+                ))
+            }
+            _ => (1, pointee),
+        };
+
+        let descriptor_type = self
+            .descriptor_type(storage_class, type_id)
+            .map_err(|error| format!("{} (set {}, binding {}): {}", name, set, binding, error))?;
+
+        let is_block = matches!(
+            descriptor_type,
+            vk::DescriptorType::UNIFORM_BUFFER | vk::DescriptorType::STORAGE_BUFFER
+        );
+
+        let element_stride = match self.types.get(&type_id) {
+            Some(Type::Struct { members }) if is_block => members
+                .last()
+                .filter(|member| matches!(self.types.get(member), Some(Type::RuntimeArray { .. })))
+                .and_then(|member| self.array_strides.get(member).copied()),
+            _ => None,
+        };
+
+        Ok(DescriptorBinding {
+            set: *set,
+            binding: *binding,
+            descriptor_type,
+            count,
+            stages: stage,
+            name,
+            layout: self.describe(type_id),
+            block_size: is_block.then(|| self.size(type_id)).flatten(),
+            element_stride,
+        })
+    }
+}
+
+/// Reflects the resources used by a shader stage from its SPIR-V.
+pub fn reflect(code: &[u8]) -> Result<ShaderReflection, String> {
+    // the bytes may come from `include_bytes!`, which does not align them
+    let words: Vec<u32> = code
+        .chunks_exact(4)
+        .map(|word| u32::from_ne_bytes(word.try_into().unwrap()))
+        .collect();
+
+    let module = Module::parse(&words)?;
+
+    let stage = module
+        .execution_model
+        .and_then(stage_flags)
+        .ok_or("unsupported or missing entry point")?;
+
+    let mut bindings = vec![];
+    let mut push_constant_size = None;
+
+    for (variable, pointer_type) in &module.variables {
+        let Some(Type::Pointer {
+            storage_class,
+            pointee,
+        }) = module.types.get(pointer_type)
+        else {
+            continue;
+        };
+
+        match *storage_class {
+            STORAGE_CLASS_PUSH_CONSTANT => {
+                push_constant_size = Some(module.size(*pointee).unwrap_or(0));
+            }
+            STORAGE_CLASS_UNIFORM_CONSTANT
+            | STORAGE_CLASS_UNIFORM
+            | STORAGE_CLASS_STORAGE_BUFFER => {
+                bindings.push(module.binding(stage, *variable, *storage_class, *pointee)?);
+            }
+            _ => (),
+        }
+    }
+
+    Ok(ShaderReflection {
+        stage,
+        bindings,
+        push_constant_size,
+    })
+}
+
+/// What the Rust side binds to a descriptor, checked against the shaders.
+pub struct ExpectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub name: &'static str,
+    /// Size of the bound block or, for storage buffers ending with a runtime array, of one
+    /// element.
+    pub size: Option<u32>,
+}
+
+/// Descriptor bindings and push constants of all the stages of a pipeline.
+#[derive(Clone, Debug)]
+pub struct PipelineInterface {
+    pub bindings: Vec<DescriptorBinding>,
+    pub push_constant_ranges: Vec<vk::PushConstantRange>,
+}
+
+impl PipelineInterface {
+    /// Merges the reflections of the stages of a pipeline, `stages` pairs each one with a name
+    /// for the error messages. Stages must agree on the type and layout of shared bindings.
+    pub fn new(stages: &[(&str, ShaderReflection)]) -> Result<Self, ReflectionError> {
+        let mut bindings: Vec<(&str, DescriptorBinding)> = vec![];
+        let mut errors = vec![];
+        let mut push_constants: Option<vk::PushConstantRange> = None;
+
+        for (stage_name, reflection) in stages {
+            for binding in &reflection.bindings {
+                let existing = bindings.iter_mut().find(|(_, existing)| {
+                    existing.set == binding.set && existing.binding == binding.binding
+                });
+
+                match existing {
+                    Some((existing_stage, existing)) => {
+                        if existing.descriptor_type != binding.descriptor_type
+                            || existing.count != binding.count
+                            || existing.layout != binding.layout
+                        {
+                            errors.push(format!(
+                                "set {} binding {} differs between {} ({:?} {} {}) and {} ({:?} {} {})",
+                                binding.set,
+                                binding.binding,
+                                existing_stage,
+                                existing.descriptor_type,
+                                existing.name,
+                                existing.layout,
+                                stage_name,
+                                binding.descriptor_type,
+                                binding.name,
+                                binding.layout,
+                            ));
+                        }
+                        existing.stages |= binding.stages;
+                    }
+                    None => bindings.push((stage_name, binding.clone())),
+                }
+            }
+
+            if let Some(size) = reflection.push_constant_size {
+                let range = push_constants.get_or_insert(vk::PushConstantRange::default());
+                range.stage_flags |= reflection.stage;
+                range.size = range.size.max(size);
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(ReflectionError(errors));
+        }
+
+        let mut bindings: Vec<DescriptorBinding> =
+            bindings.into_iter().map(|(_, binding)| binding).collect();
+        bindings.sort_by_key(|binding| (binding.set, binding.binding));
+
+        Ok(PipelineInterface {
+            bindings,
+            push_constant_ranges: push_constants.into_iter().collect(),
+        })
+    }
+
+    pub fn set_layout_bindings(&self, set: u32) -> Vec<vk::DescriptorSetLayoutBinding<'static>> {
+        self.bindings
+            .iter()
+            .filter(|binding| binding.set == set)
+            .map(|binding| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(binding.binding)
+                    .descriptor_type(binding.descriptor_type)
+                    .descriptor_count(binding.count)
+                    .stage_flags(binding.stages)
+            })
+            .collect()
+    }
+
+    /// Pool sizes for `set_count` descriptor sets of `set`.
+    pub fn pool_sizes(&self, set: u32, set_count: u32) -> Vec<vk::DescriptorPoolSize> {
+        let mut pool_sizes: Vec<vk::DescriptorPoolSize> = vec![];
+
+        for binding in self.bindings.iter().filter(|binding| binding.set == set) {
+            let count = binding.count * set_count;
+
+            match pool_sizes
+                .iter_mut()
+                .find(|pool_size| pool_size.ty == binding.descriptor_type)
+            {
+                Some(pool_size) => pool_size.descriptor_count += count,
+                None => pool_sizes.push(vk::DescriptorPoolSize {
+                    ty: binding.descriptor_type,
+                    descriptor_count: count,
+                }),
+            }
+        }
+
+        pool_sizes
+    }
+
+    /// Reports the bindings the shaders and the Rust side disagree on.
+    pub fn check(&self, expected: &[ExpectedBinding]) -> Result<(), ReflectionError> {
+        let mut errors = vec![];
+
+        for expected in expected {
+            let Some(binding) = self
+                .bindings
+                .iter()
+                .find(|binding| binding.set == expected.set && binding.binding == expected.binding)
+            else {
+                errors.push(format!(
+                    "{} (set {}, binding {}) is not used by any shader",
+                    expected.name, expected.set, expected.binding
+                ));
+                continue;
+            };
+
+            if binding.descriptor_type != expected.descriptor_type {
+                errors.push(format!(
+                    "{} (set {}, binding {}) is a {:?} in the shaders but bound as a {:?}",
+                    expected.name,
+                    expected.set,
+                    expected.binding,
+                    binding.descriptor_type,
+                    expected.descriptor_type
+                ));
+            }
+
+            let shader_size = binding.element_stride.or(binding.block_size);
+
+            if let (Some(size), Some(shader_size)) = (expected.size, shader_size) {
+                if size != shader_size {
+                    errors.push(format!(
+                        "{} (set {}, binding {}) is {} bytes in the shaders ({}) but {} bytes on the Rust side",
+                        expected.name,
+                        expected.set,
+                        expected.binding,
+                        shader_size,
+                        binding.layout,
+                        size
+                    ));
+                }
+            }
+        }
+
+        for binding in &self.bindings {
+            if !expected
+                .iter()
+                .any(|expected| expected.set == binding.set && expected.binding == binding.binding)
+            {
+                errors.push(format!(
+                    "{} (set {}, binding {}) is never bound",
+                    binding.name, binding.set, binding.binding
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ReflectionError(errors))
+        }
+    }
+
+    /// Reports a push constant block whose size differs from the `size` pushed by the Rust side,
+    /// `None` when nothing is pushed.
+    pub fn check_push_constants(&self, size: Option<u32>) -> Result<(), ReflectionError> {
+        let shader_size = self.push_constant_ranges.first().map(|range| range.size);
+
+        if shader_size == size {
+            return Ok(());
+        }
+
+        let describe = |size: Option<u32>| match size {
+            Some(size) => format!("{} bytes", size),
+            None => String::from("absent"),
+        };
+
+        Err(ReflectionError(vec![format!(
+            "push constants are {} in the shaders but {} on the Rust side",
+            describe(shader_size),
+            describe(size)
+        )]))
+    }
+
+    /// Whether pipelines of this interface can use the layouts created from `other`.
+    pub fn is_compatible_with(&self, other: &PipelineInterface) -> bool {
+        let bindings_compatible = self.bindings.len() == other.bindings.len()
+            && self.bindings.iter().zip(&other.bindings).all(|(a, b)| {
+                a.set == b.set
+                    && a.binding == b.binding
+                    && a.descriptor_type == b.descriptor_type
+                    && a.count == b.count
+                    && other.stages_contain(a)
+            });
+
+        let push_constants_compatible = self
+            .push_constant_ranges
+            .iter()
+            .zip(&other.push_constant_ranges)
+            .all(|(a, b)| b.stage_flags.contains(a.stage_flags) && a.size <= b.size)
+            && self.push_constant_ranges.len() == other.push_constant_ranges.len();
+
+        bindings_compatible && push_constants_compatible
+    }
+
+    fn stages_contain(&self, binding: &DescriptorBinding) -> bool {
+        self.bindings
+            .iter()
+            .find(|existing| existing.set == binding.set && existing.binding == binding.binding)
+            .is_some_and(|existing| existing.stages.contains(binding.stages))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uniform_types::{
+        DenoisePushConstants, EmissiveLight, GlobalUniforms, Material, TracePushConstants,
+        VoxelInfos,
+    };
+
+    const RESTIR_SPATIAL_RGEN: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/restir_spatial_rgen.spv"));
+    const SHADOW_RAHIT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/shadow_rahit.spv"));
+    const SVGF_TEMPORAL_COMP: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/svgf_temporal_comp.spv"));
+    const SVGF_ATROUS_COMP: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/svgf_atrous_comp.spv"));
+
+    fn byte_size<T>() -> Option<u32> {
+        Some(std::mem::size_of::<T>() as u32)
+    }
+
+    /// Set, binding and type of every binding, in order.
+    fn binding_types(bindings: &[DescriptorBinding]) -> Vec<(u32, u32, vk::DescriptorType)> {
+        let mut types: Vec<_> = bindings
+            .iter()
+            .map(|binding| (binding.set, binding.binding, binding.descriptor_type))
+            .collect();
+        types.sort_by_key(|(set, binding, _)| (*set, *binding));
+        types
+    }
+
+    fn find(bindings: &[DescriptorBinding], set: u32, binding: u32) -> &DescriptorBinding {
+        bindings
+            .iter()
+            .find(|found| found.set == set && found.binding == binding)
+            .unwrap_or_else(|| panic!("no binding {} in set {}", binding, set))
+    }
+
+    #[test]
+    fn reflects_a_ray_generation_shader() {
+        let reflection = reflect(RESTIR_SPATIAL_RGEN).unwrap();
+
+        assert_eq!(reflection.stage, vk::ShaderStageFlags::RAYGEN_KHR);
+        assert_eq!(
+            binding_types(&reflection.bindings),
+            [
+                (0, 0, vk::DescriptorType::ACCELERATION_STRUCTURE_KHR),
+                (0, 3, vk::DescriptorType::STORAGE_BUFFER),
+                (0, 6, vk::DescriptorType::STORAGE_IMAGE),
+                (0, 10, vk::DescriptorType::STORAGE_BUFFER),
+                (0, 14, vk::DescriptorType::STORAGE_IMAGE),
+                (0, 15, vk::DescriptorType::STORAGE_BUFFER),
+                (0, 16, vk::DescriptorType::STORAGE_IMAGE),
+                (0, 18, vk::DescriptorType::STORAGE_IMAGE),
+                (1, 0, vk::DescriptorType::UNIFORM_BUFFER),
+            ]
+        );
+        assert!(reflection.bindings.iter().all(
+            |binding| binding.count == 1 && binding.stages == vk::ShaderStageFlags::RAYGEN_KHR
+        ));
+
+        assert_eq!(find(&reflection.bindings, 0, 0).name, "scene_as");
+        assert_eq!(find(&reflection.bindings, 0, 6).name, "depth_normal_image");
+        assert_eq!(
+            find(&reflection.bindings, 0, 18).name,
+            "spatial_reservoir_image"
+        );
+
+        // the storage buffers are runtime arrays of the shared structs
+        for (binding, stride) in [
+            (3, byte_size::<VoxelInfos>()),
+            (10, byte_size::<Material>()),
+            (15, byte_size::<EmissiveLight>()),
+        ] {
+            let binding = find(&reflection.bindings, 0, binding);
+            assert_eq!(binding.element_stride, stride, "{}", binding.layout);
+            assert_eq!(binding.block_size, Some(0), "{}", binding.layout);
+        }
+
+        let globals = find(&reflection.bindings, 1, 0);
+        assert_eq!(globals.block_size, byte_size::<GlobalUniforms>());
+        assert_eq!(globals.element_stride, None);
+
+        assert_eq!(
+            reflection.push_constant_size,
+            byte_size::<TracePushConstants>()
+        );
+    }
+
+    #[test]
+    fn reflects_a_compute_shader() {
+        let reflection = reflect(SVGF_TEMPORAL_COMP).unwrap();
+
+        assert_eq!(reflection.stage, vk::ShaderStageFlags::COMPUTE);
+
+        let mut expected = vec![(0, 0, vk::DescriptorType::UNIFORM_BUFFER)];
+        expected.extend(
+            (1..=4).map(|binding| (0, binding, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)),
+        );
+        expected.extend((5..=11).map(|binding| (0, binding, vk::DescriptorType::STORAGE_IMAGE)));
+        assert_eq!(binding_types(&reflection.bindings), expected);
+
+        assert_eq!(find(&reflection.bindings, 0, 1).name, "signal_texture");
+        assert_eq!(find(&reflection.bindings, 0, 5).name, "history_image");
+        assert_eq!(find(&reflection.bindings, 0, 11).name, "denoised_image");
+        assert_eq!(
+            find(&reflection.bindings, 0, 0).block_size,
+            byte_size::<GlobalUniforms>()
+        );
+
+        // the images are described by their format, the moments are rg32f and the history rgba32f
+        assert_ne!(
+            find(&reflection.bindings, 0, 5).layout,
+            find(&reflection.bindings, 0, 7).layout
+        );
+
+        assert_eq!(
+            reflection.push_constant_size,
+            byte_size::<DenoisePushConstants>()
+        );
+    }
+
+    #[test]
+    fn merges_the_stages_of_a_pipeline() {
+        let interface = PipelineInterface::new(&[
+            ("restir_spatial.rgen", reflect(RESTIR_SPATIAL_RGEN).unwrap()),
+            ("shadow.rahit", reflect(SHADOW_RAHIT).unwrap()),
+        ])
+        .unwrap();
+
+        let both = vk::ShaderStageFlags::RAYGEN_KHR | vk::ShaderStageFlags::ANY_HIT_KHR;
+        assert_eq!(find(&interface.bindings, 0, 3).stages, both);
+        assert_eq!(find(&interface.bindings, 0, 10).stages, both);
+        assert_eq!(find(&interface.bindings, 1, 0).stages, both);
+        assert_eq!(
+            find(&interface.bindings, 0, 0).stages,
+            vk::ShaderStageFlags::RAYGEN_KHR
+        );
+
+        // only the ray generation shader has push constants
+        assert_eq!(interface.push_constant_ranges.len(), 1);
+        assert_eq!(
+            interface.push_constant_ranges[0].stage_flags,
+            vk::ShaderStageFlags::RAYGEN_KHR
+        );
+        assert_eq!(interface.push_constant_ranges[0].offset, 0);
+        assert_eq!(
+            Some(interface.push_constant_ranges[0].size),
+            byte_size::<TracePushConstants>()
+        );
+        interface
+            .check_push_constants(byte_size::<TracePushConstants>())
+            .unwrap();
+        assert!(interface.check_push_constants(None).is_err());
+
+        let set_0 = interface.set_layout_bindings(0);
+        assert_eq!(set_0.len(), 8);
+        assert_eq!(
+            interface
+                .pool_sizes(0, 2)
+                .iter()
+                .map(|pool_size| (pool_size.ty, pool_size.descriptor_count))
+                .collect::<Vec<_>>(),
+            [
+                (vk::DescriptorType::ACCELERATION_STRUCTURE_KHR, 2),
+                (vk::DescriptorType::STORAGE_BUFFER, 6),
+                (vk::DescriptorType::STORAGE_IMAGE, 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn stages_of_the_denoiser_share_one_layout() {
+        let temporal =
+            PipelineInterface::new(&[("svgf_temporal.comp", reflect(SVGF_TEMPORAL_COMP).unwrap())])
+                .unwrap();
+        let atrous =
+            PipelineInterface::new(&[("svgf_atrous.comp", reflect(SVGF_ATROUS_COMP).unwrap())])
+                .unwrap();
+
+        assert!(temporal.is_compatible_with(&atrous));
+        assert!(atrous.is_compatible_with(&temporal));
+
+        let ray_tracing =
+            PipelineInterface::new(&[("shadow.rahit", reflect(SHADOW_RAHIT).unwrap())]).unwrap();
+        assert!(!temporal.is_compatible_with(&ray_tracing));
+    }
+
+    #[test]
+    fn rejects_what_is_not_spir_v() {
+        assert!(reflect(&[]).is_err());
+        assert!(reflect(b"#version 460\nvoid main() {}\n").is_err());
+    }
+}
@@ -0,0 +1,290 @@
+// Structs shared with the shaders, see `layout.rs`. Included by `uniform_types.rs` and by the
+// build script, which generates `shared_types.glsl` from them.
+
+shared_structs! {
+    #[layout(Std140)]
+    pub struct GlobalUniforms {
+        /// Camera inverse view matrix
+        pub view_inverse: bevy_math::Mat4 => mat4,
+        /// Camera inverse projection matrix
+        pub proj_inverse: glm::Mat4 => mat4,
+        /// View matrix of the previous frame, to reproject into the history
+        pub prev_view: bevy_math::Mat4 => mat4,
+        /// Projection matrix of the previous frame
+        pub prev_proj: glm::Mat4 => mat4,
+        /// Incremented every frame, offsets the random sequences
+        pub frame_index: u32 => uint,
+        /// 0 when the history of the previous frame must be discarded
+        pub history_valid: u32 => uint,
+        /// Frames accumulated at most, 1 disables the accumulation
+        pub max_history: u32 => uint,
+        /// Relative depth difference above which a reprojected sample is rejected
+        pub history_depth_tolerance: f32 => float,
+        /// Unit vector towards the sun, or towards the moon at night
+        pub sun_direction: glm::Vec3 => vec3,
+        /// Hours since midnight, in [0, 24)
+        pub time_of_day: f32 => float,
+        /// Irradiance of the sun or moon on a surface facing it
+        pub sun_color: glm::Vec3 => vec3,
+        /// Scale of the light coming from the sky
+        pub sky_intensity: f32 => float,
+        /// Haziness of the analytic sky
+        pub sky_turbidity: f32 => float,
+        /// 1 when the sky is read from the environment map instead of the analytic model
+        pub use_environment_map: u32 => uint,
+        /// Scale of the environment map radiance
+        pub environment_intensity: f32 => float,
+        /// Rotation in radians of the environment map around the up axis
+        pub environment_rotation: f32 => float,
+        /// Offset in pixels of the primary rays of the AO preview from the pixel centers, 0
+        /// without TAA
+        pub jitter: glm::Vec2 => vec2,
+        /// Jitter of the previous frame, which the reprojection lands on
+        pub prev_jitter: glm::Vec2 => vec2,
+        /// Radius of the thin lens, 0 for a pinhole camera
+        pub aperture_radius: f32 => float,
+        /// Distance along the view axis of the plane in focus
+        pub focal_distance: f32 => float,
+        /// Sides of the polygonal aperture, below 3 for a round one
+        pub aperture_blades: u32 => uint,
+        /// Part of the interval since the previous frame the shutter is open for, 0 without
+        /// motion blur
+        pub shutter: f32 => float,
+        /// Color of the light scattered by the height fog
+        pub fog_albedo: glm::Vec3 => vec3,
+        /// Extinction per unit of length of the height fog below `fog_height`
+        pub fog_density: f32 => float,
+        /// Height up to which the density of the height fog is constant
+        pub fog_height: f32 => float,
+        /// Rate at which the density of the height fog decreases above `fog_height`
+        pub fog_height_falloff: f32 => float,
+        /// Henyey-Greenstein asymmetry of the scattering, positive forwards
+        pub fog_anisotropy: f32 => float,
+        /// Samples marched along every piece of the primary rays, 0 disables the fog
+        pub fog_steps: u32 => uint,
+    }
+
+    #[layout(Scalar)]
+    pub struct VoxelInfos {
+        pub position: glm::Vec3 => vec3,
+        pub palette_index: u32 => uint,
+        /// One bit per cube face, in the order of the face normals of the shaders, set for the
+        /// faces shared with a voxel of the same glass
+        pub interior_faces: u32 => uint,
+    }
+
+    #[layout(Scalar)]
+    /// Only used by the shaders.
+    #[allow(dead_code)]
+    pub struct RayPayload {
+        pub color: glm::Vec3 => vec3,
+        pub origin: glm::Vec3 => vec3,
+        pub direction: glm::Vec3 => vec3,
+        pub attenuation: f32 => float,
+        pub t: f32 => float,
+    }
+
+    #[layout(Scalar)]
+    /// Only used by the shaders.
+    #[allow(dead_code)]
+    pub struct MainPassPayload {
+        pub color: glm::Vec3 => vec3,
+        pub normal: glm::Vec3 => vec3,
+        pub t: f32 => float,
+        pub material_index: u32 => uint,
+        /// Index of the hit voxel in the voxels buffer
+        pub voxel_index: u32 => uint,
+        /// Index of the hit triangle in the voxel cube, two per face
+        pub primitive_index: u32 => uint,
+        /// Candidate hits found by the traversal, only counted by the any-hit shader for the rays
+        /// forced to be non-opaque
+        pub candidate_hits: u32 => uint,
+    }
+
+    #[layout(Scalar)]
+    /// Only used by the shaders.
+    #[allow(dead_code)]
+    pub struct AOPayload {
+        pub t: f32 => float,
+    }
+
+    #[layout(Scalar)]
+    /// Only used by the shaders.
+    #[allow(dead_code)]
+    pub struct ShadowPayload {
+        /// Light let through by the glass crossed so far, starts at 1
+        pub transmittance: glm::Vec3 => vec3,
+        /// Left at 0 by the shadow rays that hit something, set to 1 by the shadow miss shader
+        pub visibility: f32 => float,
+        /// Optical depth of the media crossed, summed over the distances to the faces of their
+        /// blocks, subtracted when the ray enters and added when it leaves
+        pub optical_depth: f32 => float,
+    }
+
+    #[layout(Scalar)]
+    /// Push constants of the ray generation shaders.
+    pub struct TracePushConstants {
+        /// Occlusion rays per pixel and frame
+        pub ao_samples_per_pixel: u32 => uint,
+        /// Length of the occlusion rays
+        pub ao_ray_length: f32 => float,
+        /// Path traced samples per pixel and frame, 0 only carries the accumulation over
+        pub pt_samples_per_pixel: u32 => uint,
+        /// Bounces before a path is cut
+        pub pt_max_bounces: u32 => uint,
+        /// Path traced samples per pixel already accumulated, 0 restarts the accumulation
+        pub pt_accumulated_samples: u32 => uint,
+        /// Reflection bounces traced after the primary hit, 0 disables the reflections
+        pub reflection_max_depth: u32 => uint,
+        /// Entries of the light list
+        pub light_count: u32 => uint,
+        /// Sum of the luminances of the emissive voxels, which the lights are picked in
+        /// proportion to
+        pub light_power: f32 => float,
+        /// Lights resampled per pixel and frame for the direct light of the AO preview
+        pub light_candidates: u32 => uint,
+        /// 1 to reuse the light samples over frames and neighbouring pixels
+        pub restir_enabled: u32 => uint,
+        /// Neighbouring reservoirs merged by the spatial reuse pass
+        pub restir_spatial_samples: u32 => uint,
+        /// Distance in pixels of the neighbours of the spatial reuse
+        pub restir_spatial_radius: f32 => float,
+        /// Cap of the sample count carried over by the temporal reuse, relative to the
+        /// candidates of a frame
+        pub restir_max_history: u32 => uint,
+        /// 1 when the denoiser filters the output of the mode, the path tracer then writes the
+        /// G-buffer and the AO preview leaves the accumulation of the occlusion to it
+        pub denoiser_enabled: u32 => uint,
+        /// 1 when the TAA resolve reads the motion vectors, the path tracer then writes the
+        /// G-buffer along with them
+        pub taa_enabled: u32 => uint,
+        /// Debug view the AO preview ray generation shader writes instead of tracing the frame, 0
+        /// for none
+        pub debug_view: u32 => uint,
+        /// Linear depth shown as white by the depth view
+        pub debug_max_depth: f32 => float,
+        /// Candidate hits shown as red by the traversal cost view, more are white
+        pub debug_max_cost: u32 => uint,
+    }
+
+    #[layout(Scalar)]
+    /// Surface properties of a palette entry.
+    pub struct Material {
+        pub albedo: glm::Vec3 => vec3,
+        pub roughness: f32 => float,
+        /// Emitted radiance
+        pub emission: glm::Vec3 => vec3,
+        pub metalness: f32 => float,
+        /// Coverage of the alpha voxels, rays go through the rest at random
+        pub opacity: f32 => float,
+        /// Share of the light refracted through glass, 0 for the opaque materials
+        pub transmission: f32 => float,
+        /// Index of refraction of the glass
+        pub ior: f32 => float,
+        /// Extinction per unit of length of the media voxels, which are not surfaces but fog
+        /// volumes, 0 for the others
+        pub density: f32 => float,
+    }
+
+    #[layout(Scalar)]
+    /// Entry of the light list, with its column of the alias table.
+    pub struct EmissiveLight {
+        /// Index of the emissive voxel in the voxels buffer
+        pub voxel_index: u32 => uint,
+        /// Chance of keeping this entry once its column is drawn
+        pub probability: f32 => float,
+        /// Entry picked instead
+        pub alias: u32 => uint,
+    }
+
+    #[layout(Scalar)]
+    /// Push constants of a pass of the AO filter.
+    pub struct FilterPushConstants {
+        /// Camera z axis in world space, to get the view space depth of normals
+        pub view_z: glm::Vec3 => vec3,
+        /// Distance in pixels between the filter taps
+        pub radius: f32 => float,
+        pub texel_size: glm::Vec2 => vec2,
+        /// (1, 0) for the horizontal pass, (0, 1) for the vertical one
+        pub direction: glm::Vec2 => vec2,
+        /// 0 outputs the occlusion, 1 the lit albedo, 2 the albedo
+        pub output_mode: u32 => uint,
+    }
+
+    #[layout(Scalar)]
+    /// Push constants of the stages of the denoiser.
+    pub struct DenoisePushConstants {
+        /// 0 for the path traced radiance, 1 for the occlusion
+        pub signal: u32 => uint,
+        /// Wavelet iteration, the taps are `1 << iteration` pixels apart. The modulation reads
+        /// the output of the last one.
+        pub iteration: u32 => uint,
+        /// Weight of the new frame in the accumulated signal
+        pub alpha: f32 => float,
+        /// Weight of the new frame in the accumulated moments
+        pub moments_alpha: f32 => float,
+        /// Edge stopping of the luminance, in standard deviations
+        pub phi_color: f32 => float,
+        /// Edge stopping of the normals, as an exponent of their cosine
+        pub phi_normal: f32 => float,
+        /// Edge stopping of the depth, relative to the depth
+        pub phi_depth: f32 => float,
+        /// Edge stopping of the albedo, in color distance
+        pub phi_albedo: f32 => float,
+    }
+
+    #[layout(Scalar)]
+    /// Exposure adapted over the frames, kept on the GPU.
+    pub struct ExposureState {
+        /// Average luminance the exposure has adapted to
+        pub average_luminance: f32 => float,
+        /// Scale of the radiance before the tonemapping
+        pub exposure: f32 => float,
+    }
+
+    #[layout(Scalar)]
+    /// Push constants of the exposure and tonemapping passes.
+    pub struct TonemapPushConstants {
+        /// Range of the luminance histogram, in log2 of the luminance
+        pub min_log_luminance: f32 => float,
+        pub log_luminance_range: f32 => float,
+        /// Fraction of the way to the measured luminance covered this frame, 1 snaps to it
+        pub adaptation: f32 => float,
+        /// 1 adapts the exposure to the histogram, 0 only applies the compensation
+        pub auto_exposure: u32 => uint,
+        /// Exposure compensation in stops
+        pub exposure_compensation: f32 => float,
+        /// 0 for ACES, 1 for AgX, 2 for Reinhard, 3 shows the colors of the debug views as they
+        /// are, without the exposure
+        pub tonemap_operator: u32 => uint,
+        /// 0 writes linear values, 1 encodes them to sRGB, 2 encodes them to HDR10
+        pub output_transfer: u32 => uint,
+        /// Brightness of a diffuse white and of the highlights in HDR10, in nits
+        pub paper_white: f32 => float,
+        pub peak_brightness: f32 => float,
+    }
+
+    #[layout(Scalar)]
+    /// Push constants of the upscaling passes.
+    pub struct UpscalePushConstants {
+        /// Sizes in pixels of the rendered frame and of the output
+        pub input_size: glm::Vec2 => vec2,
+        pub output_size: glm::Vec2 => vec2,
+        /// 0 samples the rendered frame bilinearly, 1 sharpens its EASU upscale with RCAS, 2
+        /// averages the texels of a supersampled frame under each pixel
+        pub upscale_filter: u32 => uint,
+        /// Sharpening of RCAS in stops, 0 is the sharpest
+        pub sharpness: f32 => float,
+        /// 1 when the output format encodes to sRGB itself, the encoded values are decoded first
+        pub srgb_output: u32 => uint,
+    }
+
+    #[layout(Scalar)]
+    /// Push constants of the TAA resolve.
+    pub struct TaaPushConstants {
+        /// Weight of the new frame in the history
+        pub alpha: f32 => float,
+        /// 0 when the history of the previous frame must be discarded
+        pub history_valid: u32 => uint,
+    }
+}
@@ -0,0 +1,67 @@
+use crate::config::SunConfig;
+
+/// Light of the sun, or of the moon once the sun is below the horizon.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SunLight {
+    /// Unit vector towards the light.
+    pub direction: glm::Vec3,
+    pub color: glm::Vec3,
+    /// Scale of the light coming from the sky.
+    pub sky_intensity: f32,
+}
+
+/// Hours since midnight after `delta_seconds` of real time.
+pub fn advance_time_of_day(time_of_day: f32, delta_seconds: f32, config: &SunConfig) -> f32 {
+    if config.day_duration <= 0.0 {
+        return time_of_day;
+    }
+
+    (time_of_day + delta_seconds / config.day_duration * 24.0).rem_euclid(24.0)
+}
+
+impl SunLight {
+    pub fn at_time(time_of_day: f32, config: &SunConfig) -> Self {
+        // the sun turns around a tilted axis: rises at 6, culminates at 12 and sets at 18
+        let angle = (time_of_day - 6.0) / 24.0 * glm::two_pi::<f32>();
+        let latitude = config.latitude.to_radians();
+        let sun_direction = glm::rotate_y_vec3(
+            &glm::vec3(
+                angle.cos(),
+                angle.sin() * latitude.cos(),
+                angle.sin() * latitude.sin(),
+            ),
+            config.azimuth.to_radians(),
+        );
+
+        let sun_height = sun_direction.y;
+
+        // fades out just after sunset, while the moon fades in
+        let sun_visibility = smoothstep(-0.05, 0.1, sun_height);
+        let moon_visibility = smoothstep(-0.05, 0.1, -sun_height);
+
+        // the long path through the atmosphere reddens the low sun
+        let sun_tint = glm::lerp(
+            &glm::vec3(1.0, 0.45, 0.2),
+            &glm::vec3(1.0, 0.96, 0.9),
+            smoothstep(0.0, 0.35, sun_height),
+        );
+        let moon_tint = glm::vec3(0.6, 0.7, 1.0) * 0.15;
+
+        let (direction, color) = if sun_height >= 0.0 {
+            (sun_direction, sun_tint * sun_visibility)
+        } else {
+            (-sun_direction, moon_tint * moon_visibility)
+        };
+
+        Self {
+            direction,
+            color: color * config.intensity,
+            sky_intensity: 0.05 + 0.95 * smoothstep(-0.2, 0.2, sun_height),
+        }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
@@ -1,20 +1,21 @@
 use bevy_transform::components::Transform;
-use bytemuck::{Pod, Zeroable};
 
-#[repr(C)]
-#[derive(Clone, Debug, Copy, Pod, Zeroable)]
-pub struct GlobalUniforms {
-    pub view_inverse: bevy_math::Mat4, // Camera inverse view matrix
-    pub proj_inverse: glm::Mat4,       // Camera inverse projection matrix
-}
+use crate::config::LensConfig;
+
+use crate::shaders::layout::{
+    field_offset, glsl_type_size, struct_size, BlockLayout, GlslField, GlslStruct,
+};
 
-#[repr(C)]
-#[derive(Clone, Debug, Copy, Pod, Zeroable)]
-pub struct VoxelInfos {
-    pub position: glm::Vec3,
-    pub palette_index: u32,
+macro_rules! shared_structs {
+    ($($tokens:tt)*) => {
+        glsl_structs!(rust; $($tokens)*);
+    };
 }
 
+include!("shaders/shared_types.rs");
+
+#[derive(Clone, Copy, Debug)]
 pub struct CameraTransform {
     pub transform: Transform,
+    pub lens: LensConfig,
 }
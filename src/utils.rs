@@ -6,6 +6,11 @@ use std::{
 
 use ash::{khr, prelude::VkResult, util, vk, Device, Instance};
 
+use crate::{
+    memory::{Allocation, AllocationKind, Allocator},
+    shaders::reflect::PipelineInterface,
+};
+
 #[cfg(debug_assertions)]
 use std::borrow::Cow;
 
@@ -40,7 +45,7 @@ pub fn record_submit_commandbuffer<F: FnOnce(&Device, vk::CommandBuffer)>(
 ) {
     unsafe {
         device
-            .wait_for_fences(&[command_buffer_reuse_fence], true, std::u64::MAX)
+            .wait_for_fences(&[command_buffer_reuse_fence], true, u64::MAX)
             .expect("Wait for fence failed.");
 
         device
@@ -110,21 +115,6 @@ pub unsafe extern "system" fn vulkan_debug_callback(
     vk::FALSE
 }
 
-pub fn find_memorytype_index(
-    memory_req: &vk::MemoryRequirements,
-    memory_prop: &vk::PhysicalDeviceMemoryProperties,
-    flags: vk::MemoryPropertyFlags,
-) -> Option<u32> {
-    memory_prop.memory_types[..memory_prop.memory_type_count as _]
-        .iter()
-        .enumerate()
-        .find(|(index, memory_type)| {
-            (1 << index) & memory_req.memory_type_bits != 0
-                && memory_type.property_flags & flags == flags
-        })
-        .map(|(index, _memory_type)| index as _)
-}
-
 pub fn pick_physical_device_and_queue_family_indices(
     instance: &Instance,
     surface: vk::SurfaceKHR,
@@ -174,28 +164,57 @@ pub fn pick_physical_device_and_queue_family_indices(
         }))
 }
 
-pub fn get_memory_type_index(
-    device_memory_properties: vk::PhysicalDeviceMemoryProperties,
-    mut type_bits: u32,
-    properties: vk::MemoryPropertyFlags,
-) -> u32 {
-    for i in 0..device_memory_properties.memory_type_count {
-        if (type_bits & 1) == 1
-            && (device_memory_properties.memory_types[i as usize].property_flags & properties)
-                == properties
-        {
-            return i;
-        }
-        type_bits >>= 1;
-    }
-    0
+/// A queue family supporting transfers only, usually backed by the DMA engines.
+pub fn find_transfer_queue_family_index(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Option<u32> {
+    unsafe { instance.get_physical_device_queue_family_properties(physical_device) }
+        .iter()
+        .position(|properties| {
+            properties.queue_count > 0
+                && properties.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                && !properties
+                    .queue_flags
+                    .intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+        })
+        .map(|index| index as u32)
+}
+
+/// Swapchain format, preferring HDR10 when `hdr10` is set and the surface supports it, then
+/// 8-bit sRGB formats that encode the output in hardware.
+pub fn choose_surface_format(
+    formats: &[vk::SurfaceFormatKHR],
+    hdr10: bool,
+) -> vk::SurfaceFormatKHR {
+    let hdr10_format = formats.iter().find(|format| {
+        format.color_space == vk::ColorSpaceKHR::HDR10_ST2084_EXT
+            && matches!(
+                format.format,
+                vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32
+            )
+    });
+    let srgb_format = formats.iter().find(|format| {
+        format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            && matches!(
+                format.format,
+                vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB
+            )
+    });
+
+    hdr10_format
+        .filter(|_| hdr10)
+        .or(srgb_format)
+        .copied()
+        .unwrap_or(formats[0])
 }
 
-#[derive(Clone)]
 pub struct BufferResource {
     pub buffer: vk::Buffer,
-    pub memory: vk::DeviceMemory,
+    pub allocation: Allocation,
     pub size: vk::DeviceSize,
+    /// Shared by several queue families, it never changes owner.
+    pub concurrent: bool,
 }
 
 impl BufferResource {
@@ -204,51 +223,60 @@ impl BufferResource {
         usage: vk::BufferUsageFlags,
         memory_properties: vk::MemoryPropertyFlags,
         device: &ash::Device,
-        device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+        allocator: &Allocator,
+    ) -> Self {
+        Self::new_shared(size, usage, memory_properties, &[], device, allocator)
+    }
+
+    /// Buffer used concurrently by `queue_family_indices`, exclusive to its queue family when
+    /// they are less than two.
+    pub fn new_shared(
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        memory_properties: vk::MemoryPropertyFlags,
+        queue_family_indices: &[u32],
+        device: &ash::Device,
+        allocator: &Allocator,
     ) -> Self {
+        let concurrent = queue_family_indices.len() > 1;
+
         unsafe {
             let buffer_info = vk::BufferCreateInfo::default()
                 .size(size)
                 .usage(usage)
-                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+                .sharing_mode(if concurrent {
+                    vk::SharingMode::CONCURRENT
+                } else {
+                    vk::SharingMode::EXCLUSIVE
+                })
+                .queue_family_indices(if concurrent {
+                    queue_family_indices
+                } else {
+                    &[]
+                });
 
             let buffer = device.create_buffer(&buffer_info, None).unwrap();
 
             let memory_req = device.get_buffer_memory_requirements(buffer);
 
-            let memory_index = get_memory_type_index(
-                device_memory_properties,
-                memory_req.memory_type_bits,
-                memory_properties,
-            );
-
-            let mut memory_allocate_flags_info = vk::MemoryAllocateFlagsInfo::default()
-                .flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+            let allocation = allocator
+                .allocate(memory_req, memory_properties, AllocationKind::Buffer)
+                .unwrap_or_else(|error| panic!("Failed to allocate buffer memory: {}", error));
 
-            let mut allocate_info_builder = vk::MemoryAllocateInfo::default();
-
-            if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
-                allocate_info_builder =
-                    allocate_info_builder.push_next(&mut memory_allocate_flags_info);
-            }
-
-            let allocate_info = allocate_info_builder
-                .allocation_size(memory_req.size)
-                .memory_type_index(memory_index);
-
-            let memory = device.allocate_memory(&allocate_info, None).unwrap();
-
-            device.bind_buffer_memory(buffer, memory, 0).unwrap();
+            device
+                .bind_buffer_memory(buffer, allocation.memory, allocation.offset)
+                .unwrap();
 
             BufferResource {
                 buffer,
-                memory,
+                allocation,
                 size,
+                concurrent,
             }
         }
     }
 
-    pub fn store<T: Copy>(&mut self, data: &[T], device: &ash::Device) {
+    pub fn store<T: Copy>(&mut self, data: &[T]) {
         unsafe {
             let size = std::mem::size_of_val(data) as u64;
             assert!(
@@ -257,35 +285,66 @@ impl BufferResource {
                 self.size,
                 size
             );
-            let mapped_ptr = self.map(size, device);
-            let mut mapped_slice =
-                util::Align::new(mapped_ptr, std::mem::align_of::<T>() as u64, size);
+            let mut mapped_slice = util::Align::new(
+                self.mapped_ptr() as *mut std::ffi::c_void,
+                std::mem::align_of::<T>() as u64,
+                size,
+            );
             mapped_slice.copy_from_slice(data);
-            self.unmap(device);
         }
     }
 
-    fn map(&mut self, size: vk::DeviceSize, device: &ash::Device) -> *mut std::ffi::c_void {
-        unsafe {
-            let data: *mut std::ffi::c_void = device
-                .map_memory(self.memory, 0, size, vk::MemoryMapFlags::empty())
-                .unwrap();
-            data
-        }
+    pub fn load_bytes(&self) -> Vec<u8> {
+        unsafe { std::slice::from_raw_parts(self.mapped_ptr(), self.size as usize).to_vec() }
     }
 
-    fn unmap(&mut self, device: &ash::Device) {
-        unsafe {
-            device.unmap_memory(self.memory);
-        }
+    pub fn mapped_ptr(&self) -> *mut u8 {
+        self.allocation
+            .mapped_ptr()
+            .expect("Buffer memory is not host visible.")
     }
 
     pub unsafe fn destroy(self, device: &ash::Device) {
         device.destroy_buffer(self.buffer, None);
-        device.free_memory(self.memory, None);
+        self.allocation.free();
     }
 }
 
+/// Creates the layout of a descriptor set from the bindings reflected from the shaders, and a pool
+/// holding `max_sets` such sets.
+pub fn create_descriptor_set_layout_and_pool(
+    device: &Device,
+    interface: &PipelineInterface,
+    set: u32,
+    max_sets: u32,
+) -> VkResult<(vk::DescriptorSetLayout, vk::DescriptorPool)> {
+    let bindings = interface.set_layout_bindings(set);
+
+    let binding_flags_inner = vec![vk::DescriptorBindingFlagsEXT::empty(); bindings.len()];
+
+    let mut binding_flags = vk::DescriptorSetLayoutBindingFlagsCreateInfoEXT::default()
+        .binding_flags(&binding_flags_inner);
+
+    let descriptor_set_layout = unsafe {
+        device.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::default()
+                .bindings(&bindings)
+                .push_next(&mut binding_flags),
+            None,
+        )
+    }?;
+
+    let descriptor_sizes = interface.pool_sizes(set, max_sets);
+
+    let descriptor_pool_info = vk::DescriptorPoolCreateInfo::default()
+        .pool_sizes(&descriptor_sizes)
+        .max_sets(max_sets);
+
+    let descriptor_pool = unsafe { device.create_descriptor_pool(&descriptor_pool_info, None) }?;
+
+    Ok((descriptor_set_layout, descriptor_pool))
+}
+
 pub unsafe fn create_shader_module(
     device: &ash::Device,
     code: &[u8],
@@ -306,6 +365,20 @@ pub fn aligned_size(value: u32, alignment: u32) -> u32 {
     (value + alignment - 1) & !(alignment - 1)
 }
 
+/// `index`-th point in [0, 1) of the Halton sequence of `base`, starting from 1.
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+
+    result
+}
+
 pub unsafe fn get_buffer_device_address(device: &ash::Device, buffer: vk::Buffer) -> u64 {
     let buffer_device_address_info = vk::BufferDeviceAddressInfo::default().buffer(buffer);
 
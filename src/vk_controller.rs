@@ -1,4 +1,4 @@
-use std::ffi::{c_char, CStr};
+use std::{ffi::c_char, path::Path};
 
 use ash::{
     ext, khr,
@@ -10,24 +10,324 @@ use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 use winit::{event_loop::ActiveEventLoop, window::Window};
 
 use crate::{
-    io::vox::{get_palette, open_file, vox_to_tlas},
-    uniform_types::{GlobalUniforms, VoxelInfos},
+    animation::transform_translation,
+    config::Config,
+    io::{
+        as_cache::{patch_bottom_level_handles, AccelerationStructureCache, SerializedHeader},
+        hdr::{load_hdr, HdrImage},
+        ppm::rgb_pixels,
+        vox::{get_materials, get_palette, open_file, vox_to_tlas},
+    },
+    lights::build_light_list,
+    memory::{
+        staging::{record_acquire_barriers, StagingUploader},
+        Allocation, AllocationKind, Allocator, DeviceBackend,
+    },
+    render::{
+        ao_filter::{
+            AoFilter, ALBEDO_FORMAT, AO_FORMAT, DEPTH_NORMAL_FORMAT, FILTERED_AO_FORMAT,
+            FOG_FORMAT, REFLECTION_FORMAT,
+        },
+        environment::{EnvironmentMap, ENVIRONMENT_FORMAT},
+        frame::{
+            FrameImage, FrameImages, FrameResources, ACCUMULATION_FORMAT, DEBUG_FORMAT,
+            FRAMES_IN_FLIGHT, RESERVOIR_FORMAT,
+        },
+        graph::{RenderGraph, ResourceState},
+        pass::{Access, Pass},
+        svgf::{
+            Svgf, SvgfViews, DENOISED_FORMAT, FILTERED_FORMAT, HISTORY_FORMAT, MOMENTS_FORMAT,
+            OCCLUSION_SIGNAL, RADIANCE_SIGNAL,
+        },
+        taa::{Taa, MOTION_FORMAT, RESOLVED_FORMAT},
+        tonemap::{
+            Tonemapper, HDR_FORMAT, HDR_SET, HISTOGRAM_BINS, RESOLVED_SET, TONEMAPPED_FORMAT,
+        },
+        upscale::Upscaler,
+    },
+    shaders::{
+        compiler::{ShaderCompiler, SHADER_DIRECTORY},
+        layout::glsl_declarations,
+        reflect::{reflect, ExpectedBinding, PipelineInterface, ReflectionError},
+        ShaderWatcher,
+    },
+    uniform_types::{
+        EmissiveLight, ExposureState, GlobalUniforms, Material, TracePushConstants, VoxelInfos,
+        GLSL_STRUCTS,
+    },
     utils::{
-        aligned_size, create_shader_module, find_memorytype_index, get_buffer_device_address,
-        get_memory_type_index, pick_physical_device_and_queue_family_indices,
-        record_submit_commandbuffer, BufferResource,
+        aligned_size, choose_surface_format, create_descriptor_set_layout_and_pool,
+        create_shader_module, find_transfer_queue_family_index, get_buffer_device_address,
+        pick_physical_device_and_queue_family_indices, record_submit_commandbuffer, BufferResource,
     },
 };
 
 #[cfg(debug_assertions)]
 use crate::utils::vulkan_debug_callback;
 
+const WINDOW_TITLE: &str = "RT";
+
+/// Sources of the ray tracing pipeline stages, and the SPIR-V compiled from them by the build
+/// script.
+const RT_PIPELINE_SHADERS: [(&str, &[u8]); 11] = [
+    (
+        "shaders/AO/ao.rgen",
+        include_bytes!(concat!(env!("OUT_DIR"), "/ao_rgen.spv")),
+    ),
+    (
+        "shaders/AO/main_pass.rchit",
+        include_bytes!(concat!(env!("OUT_DIR"), "/main_pass_rchit.spv")),
+    ),
+    (
+        "shaders/AO/ao_pass.rchit",
+        include_bytes!(concat!(env!("OUT_DIR"), "/ao_pass_rchit.spv")),
+    ),
+    (
+        "shaders/AO/ao.rmiss",
+        include_bytes!(concat!(env!("OUT_DIR"), "/ao_rmiss.spv")),
+    ),
+    (
+        "shaders/AO/ao_pass.rmiss",
+        include_bytes!(concat!(env!("OUT_DIR"), "/ao_pass_rmiss.spv")),
+    ),
+    (
+        "shaders/PT/path_tracing.rgen",
+        include_bytes!(concat!(env!("OUT_DIR"), "/path_tracing_rgen.spv")),
+    ),
+    (
+        "shaders/shadow.rmiss",
+        include_bytes!(concat!(env!("OUT_DIR"), "/shadow_rmiss.spv")),
+    ),
+    (
+        "shaders/AO/main_pass.rahit",
+        include_bytes!(concat!(env!("OUT_DIR"), "/main_pass_rahit.spv")),
+    ),
+    (
+        "shaders/AO/ao_pass.rahit",
+        include_bytes!(concat!(env!("OUT_DIR"), "/ao_pass_rahit.spv")),
+    ),
+    (
+        "shaders/shadow.rahit",
+        include_bytes!(concat!(env!("OUT_DIR"), "/shadow_rahit.spv")),
+    ),
+    (
+        "shaders/AO/restir_spatial.rgen",
+        include_bytes!(concat!(env!("OUT_DIR"), "/restir_spatial_rgen.spv")),
+    ),
+];
+
+/// Instance shader binding table offset of the translucent voxels. Their hit groups follow the
+/// main, occlusion and shadow hit groups of the opaque voxels.
+pub const TRANSLUCENT_SBT_RECORD_OFFSET: u32 = 3;
+
+/// Recursion depth of the ray tracing pipeline. Every ray is traced from the ray generation
+/// shaders, reflection bounces included, so the closest hit and miss shaders never trace.
+const RT_PIPELINE_RECURSION_DEPTH: u32 = 1;
+
+/// Descriptors written by `write_frame_descriptor_sets`, checked against the shaders.
+const RT_PIPELINE_BINDINGS: [ExpectedBinding; 23] = [
+    ExpectedBinding {
+        set: 0,
+        binding: 0,
+        descriptor_type: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+        name: "TLAS",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 1,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "ao image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 2,
+        descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+        name: "palette buffer",
+        size: Some(std::mem::size_of::<[glm::Vec3; 256]>() as u32),
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 3,
+        descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+        name: "voxels buffer",
+        size: Some(std::mem::size_of::<VoxelInfos>() as u32),
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 4,
+        descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+        name: "vertex buffer",
+        size: Some(std::mem::size_of::<glm::Vec3>() as u32),
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 5,
+        descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+        name: "index buffer",
+        size: Some(std::mem::size_of::<u32>() as u32),
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 6,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "depth and normal image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 7,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "albedo image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 8,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "previous ao image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 9,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "previous depth and normal image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 10,
+        descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+        name: "materials buffer",
+        size: Some(std::mem::size_of::<Material>() as u32),
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 11,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "accumulation image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 12,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "previous accumulation image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 13,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        name: "environment map",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 14,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "reflection image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 15,
+        descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+        name: "lights buffer",
+        size: Some(std::mem::size_of::<EmissiveLight>() as u32),
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 16,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "reservoir image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 17,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "previous spatial reservoir image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 18,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "spatial reservoir image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 19,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "motion image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 20,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "fog image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 0,
+        binding: 21,
+        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+        name: "debug image",
+        size: None,
+    },
+    ExpectedBinding {
+        set: 1,
+        binding: 0,
+        descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+        name: "uniforms buffer",
+        size: Some(std::mem::size_of::<GlobalUniforms>() as u32),
+    },
+];
+
+/// Reflects and merges the interfaces of the ray tracing pipeline stages, then checks them
+/// against what the Rust side binds.
+fn rt_pipeline_interface(code: [&[u8]; 11]) -> Result<PipelineInterface, ReflectionError> {
+    let mut reflections = vec![];
+    let mut errors = vec![];
+
+    for ((path, _), code) in RT_PIPELINE_SHADERS.iter().zip(code) {
+        match reflect(code) {
+            Ok(reflection) => reflections.push((*path, reflection)),
+            Err(error) => errors.push(format!("{}: {}", path, error)),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(ReflectionError(errors));
+    }
+
+    let interface = PipelineInterface::new(&reflections)?;
+    interface.check(&RT_PIPELINE_BINDINGS)?;
+    interface.check_push_constants(Some(std::mem::size_of::<TracePushConstants>() as u32))?;
+
+    Ok(interface)
+}
+
+/// `scale` times `resolution`, at least one pixel wide and high.
+fn scaled_resolution(resolution: vk::Extent2D, scale: f32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: ((resolution.width as f32 * scale).round() as u32).max(1),
+        height: ((resolution.height as f32 * scale).round() as u32).max(1),
+    }
+}
+
+// a slot is overwritten once the frame that last built from it has completed
+const INSTANCE_BUFFER_COUNT: usize = FRAMES_IN_FLIGHT;
+
 macro_rules! destroy_buffer {
     ($($buffer_option: expr, $device: expr), *) => {
         $(
-        let unwrap_result = $buffer_option.as_ref().unwrap();
-        $device.destroy_buffer(unwrap_result.buffer, None);
-        $device.free_memory(unwrap_result.memory, None);
+        $buffer_option.take().unwrap().destroy(&$device);
     )*
     };
 }
@@ -46,12 +346,21 @@ pub struct VkController<'a> {
     acceleration_structure_loader: khr::acceleration_structure::Device,
     pub window: winit::window::Window,
     physical_device: vk::PhysicalDevice,
-    device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pub allocator: Allocator,
+    pub staging: Option<StagingUploader>,
     pub present_queue: vk::Queue,
 
     surface: vk::SurfaceKHR,
     surface_format: vk::SurfaceFormatKHR,
     pub surface_resolution: vk::Extent2D,
+    /// Resolution the frame is rendered at before the upscaling, `render_scale` times the
+    /// surface resolution.
+    pub render_resolution: vk::Extent2D,
+    pub render_scale: f32,
+    /// Nanoseconds per timestamp tick, `None` when the queue cannot write timestamps.
+    timestamp_period: Option<f32>,
+    /// GPU time of the frame commands, in milliseconds, of the last completed frame.
+    pub gpu_frame_time: Option<f32>,
 
     desired_image_count: u32,
     pre_transform: vk::SurfaceTransformFlagsKHR,
@@ -63,26 +372,21 @@ pub struct VkController<'a> {
     present_image_views: Option<Vec<vk::ImageView>>,
     framebuffers: Vec<vk::Framebuffer>,
     setup_command_buffer: vk::CommandBuffer,
-    pub rt_command_buffer: vk::CommandBuffer,
     render_pass: Option<vk::RenderPass>,
 
     pub depth_image: Option<vk::Image>,
     pub depth_image_view: Option<vk::ImageView>,
-    pub depth_image_memory: Option<vk::DeviceMemory>,
+    pub depth_image_allocation: Option<Allocation>,
 
-    pub rt_image: vk::Image,
-    pub rt_image_view: vk::ImageView,
-    pub rt_image_memory: vk::DeviceMemory,
+    pub frames: Vec<FrameResources>,
+    pub frame_index: usize,
     pub rt_descriptor_pool: Option<vk::DescriptorPool>,
-    pub rt_descriptor_set: Option<vk::DescriptorSet>,
     pub rt_descriptor_set_layout: Option<vk::DescriptorSetLayout>,
 
-    pub present_complete_semaphore: vk::Semaphore,
-    pub rendering_complete_semaphore: vk::Semaphore,
+    /// One per swapchain image, a semaphore is only reused once its image was presented again.
+    pub rendering_complete_semaphores: Vec<vk::Semaphore>,
 
-    pub draw_commands_reuse_fence: vk::Fence,
     pub setup_commands_reuse_fence: vk::Fence,
-    pub swapchain_acquire_fence: vk::Fence,
 
     pub as_geometry: Option<vk::AccelerationStructureGeometryKHR<'a>>,
     // pub aabb_buffer: Option<BufferResource>,
@@ -91,38 +395,82 @@ pub struct VkController<'a> {
 
     pub bottom_as: Option<vk::AccelerationStructureKHR>,
     pub bottom_as_buffer: Option<BufferResource>,
+    bottom_as_cache_key: Option<String>,
 
     pub top_as: Option<vk::AccelerationStructureKHR>,
     pub top_as_buffer: Option<BufferResource>,
+    top_as_cache_key: Option<String>,
+
+    config: Config,
+    as_cache: Option<AccelerationStructureCache>,
 
     pub instance_count: Option<usize>,
-    pub instance_buffer: Option<BufferResource>,
+    pub instances: Vec<vk::AccelerationStructureInstanceKHR>,
+    instances_dirty: bool,
+    instance_buffers: Vec<BufferResource>,
+    instance_buffer_index: usize,
+    top_as_scratch_buffer: Option<BufferResource>,
     pub voxels_infos: Option<Vec<VoxelInfos>>,
+    voxels_dirty: bool,
+    /// Voxels moved by `set_instance_transform` since the last frame, written in place.
+    moved_voxels: Vec<usize>,
+    /// The accumulated history no longer matches the scene or the frame images, the next
+    /// frame starts over.
+    pub reset_history: bool,
 
     pub palette_buffer: Option<BufferResource>,
-    pub uniforms_buffer: Option<BufferResource>,
+    pub materials_buffer: Option<BufferResource>,
     pub voxels_buffer: Option<BufferResource>,
+    /// Emissive voxels, rebuilt with the voxels buffer.
+    pub lights_buffer: Option<BufferResource>,
+    /// Host visible copy of the output, for the headless renders.
+    readback_buffer: Option<BufferResource>,
+    pub light_count: u32,
+    /// Sum of the powers of the lights.
+    pub light_power: f32,
+    pub environment_map: Option<EnvironmentMap>,
 
     pub vox_model: dot_vox::DotVoxData,
 
     pub uniforms_descriptor_pool: Option<vk::DescriptorPool>,
-    pub uniforms_descriptor_set: Option<vk::DescriptorSet>,
     pub uniforms_descriptor_set_layout: Option<vk::DescriptorSetLayout>,
     pub pipeline: Option<vk::Pipeline>,
     pub pipeline_layout: Option<vk::PipelineLayout>,
     pub shader_group_count: Option<usize>,
+    pub ao_filter: Option<AoFilter>,
+    pub svgf: Option<Svgf>,
+    pub taa: Option<Taa>,
+    pub tonemapper: Option<Tonemapper>,
+    pub upscaler: Option<Upscaler>,
+    /// Luminance histogram and adapted exposure, shared by the frames.
+    pub histogram_buffer: Option<BufferResource>,
+    pub exposure_buffer: Option<BufferResource>,
+    pipeline_interface: Option<PipelineInterface>,
+    shader_watcher: Option<ShaderWatcher>,
+    shader_compiler: Option<ShaderCompiler>,
 
     pub shader_binding_table_buffer: Option<BufferResource>,
     pub sbt_raygen_region: Option<vk::StridedDeviceAddressRegionKHR>,
     pub sbt_miss_region: Option<vk::StridedDeviceAddressRegionKHR>,
     pub sbt_hit_region: Option<vk::StridedDeviceAddressRegionKHR>,
     pub sbt_call_region: Option<vk::StridedDeviceAddressRegionKHR>,
+    /// Replaces `sbt_raygen_region` in the path tracing mode.
+    pub sbt_path_tracing_raygen_region: Option<vk::StridedDeviceAddressRegionKHR>,
+    /// Spatial reuse of the light samples, after the AO preview trace.
+    pub sbt_restir_spatial_raygen_region: Option<vk::StridedDeviceAddressRegionKHR>,
 }
 
 impl<'a> VkController<'a> {
-    pub fn new(event_loop: &ActiveEventLoop, window_width: u32, window_height: u32) -> Self {
+    pub fn new(
+        event_loop: &ActiveEventLoop,
+        window_width: u32,
+        window_height: u32,
+        config: &Config,
+    ) -> Self {
         let window_attributes = Window::default_attributes()
-            .with_title("RT")
+            .with_title(WINDOW_TITLE)
+            // the headless renders only read the frames back
+            .with_visible(!config.headless.enabled)
             .with_inner_size(winit::dpi::PhysicalSize::new(
                 f64::from(window_width),
                 f64::from(window_height),
@@ -130,13 +478,9 @@ impl<'a> VkController<'a> {
 
         let window = event_loop.create_window(window_attributes).unwrap();
 
-        let app_name = unsafe { CStr::from_bytes_with_nul_unchecked(b"VulkanRT\0") };
+        let app_name = c"VulkanRT";
 
-        let layer_names = unsafe {
-            [CStr::from_bytes_with_nul_unchecked(
-                b"VK_LAYER_KHRONOS_validation\0",
-            )]
-        };
+        let layer_names = [c"VK_LAYER_KHRONOS_validation"];
         let layers_names_raw: Vec<*const c_char> = layer_names
             .iter()
             .map(|raw_name| raw_name.as_ptr())
@@ -148,6 +492,21 @@ impl<'a> VkController<'a> {
                 .to_vec();
         extension_names.push(ext::debug_utils::NAME.as_ptr());
 
+        let entry = Entry::linked();
+
+        // the HDR10 color space of the swapchain comes from an instance extension
+        let swapchain_colorspace_supported = config.display.hdr10
+            && unsafe { entry.enumerate_instance_extension_properties(None) }
+                .unwrap_or_default()
+                .iter()
+                .any(|extension| {
+                    extension.extension_name_as_c_str() == Ok(ext::swapchain_colorspace::NAME)
+                });
+
+        if swapchain_colorspace_supported {
+            extension_names.push(ext::swapchain_colorspace::NAME.as_ptr());
+        }
+
         let appinfo = vk::ApplicationInfo::default()
             .application_name(app_name)
             .application_version(vk::make_api_version(0, 1, 0, 0))
@@ -163,8 +522,6 @@ impl<'a> VkController<'a> {
             .enabled_extension_names(&extension_names)
             .flags(create_flags);
 
-        let entry = Entry::linked();
-
         let instance: Instance =
             unsafe { entry.create_instance(&create_info, None) }.expect("Instance creation error");
 
@@ -214,6 +571,9 @@ impl<'a> VkController<'a> {
         .unwrap()
         .unwrap();
 
+        let transfer_queue_family_index =
+            find_transfer_queue_family_index(&instance, physical_device);
+
         let device: Device = {
             let priorities = [1.0];
 
@@ -221,6 +581,16 @@ impl<'a> VkController<'a> {
                 .queue_family_index(queue_family_index)
                 .queue_priorities(&priorities);
 
+            let mut queue_create_infos = vec![queue_create_info];
+
+            if let Some(transfer_queue_family_index) = transfer_queue_family_index {
+                queue_create_infos.push(
+                    vk::DeviceQueueCreateInfo::default()
+                        .queue_family_index(transfer_queue_family_index)
+                        .queue_priorities(&priorities),
+                );
+            }
+
             let mut features2 = vk::PhysicalDeviceFeatures2::default();
             unsafe {
                 (instance.fp_v1_1().get_physical_device_features2)(physical_device, &mut features2)
@@ -229,6 +599,7 @@ impl<'a> VkController<'a> {
             let mut features12 = vk::PhysicalDeviceVulkan12Features::default()
                 .scalar_block_layout(true)
                 .buffer_device_address(true)
+                .timeline_semaphore(true)
                 .vulkan_memory_model(true);
 
             let mut as_feature = vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default()
@@ -248,8 +619,6 @@ impl<'a> VkController<'a> {
                 vk::KHR_GET_MEMORY_REQUIREMENTS2_NAME.as_ptr(),
             ];
 
-            let queue_create_infos = [queue_create_info];
-
             let device_create_info = vk::DeviceCreateInfo::default()
                 .push_next(&mut features2)
                 .push_next(&mut features12)
@@ -264,9 +633,16 @@ impl<'a> VkController<'a> {
 
         let present_queue = unsafe { device.get_device_queue(queue_family_index, 0) };
 
-        let surface_format =
+        let surface_formats =
             unsafe { surface_loader.get_physical_device_surface_formats(physical_device, surface) }
-                .unwrap()[0];
+                .unwrap();
+        let surface_format =
+            choose_surface_format(&surface_formats, swapchain_colorspace_supported);
+
+        if config.display.hdr10 && surface_format.color_space != vk::ColorSpaceKHR::HDR10_ST2084_EXT
+        {
+            println!("HDR10 output is not supported by the surface, presenting in SDR");
+        }
 
         let surface_capabilities = unsafe {
             surface_loader.get_physical_device_surface_capabilities(physical_device, surface)
@@ -287,6 +663,11 @@ impl<'a> VkController<'a> {
             _ => surface_capabilities.current_extent,
         };
 
+        let render_scale = config
+            .anti_aliasing
+            .render_scale(config.anti_aliasing.mode, config.render_scale.scale);
+        let render_resolution = scaled_resolution(surface_resolution, render_scale);
+
         let pre_transform = if surface_capabilities
             .supported_transforms
             .contains(vk::SurfaceTransformFlagsKHR::IDENTITY)
@@ -296,6 +677,10 @@ impl<'a> VkController<'a> {
             surface_capabilities.current_transform
         };
 
+        let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+        let timestamp_period =
+            (limits.timestamp_compute_and_graphics == vk::TRUE).then_some(limits.timestamp_period);
+
         let present_modes = unsafe {
             surface_loader.get_physical_device_surface_present_modes(physical_device, surface)
         }
@@ -325,22 +710,16 @@ impl<'a> VkController<'a> {
         let device_memory_properties =
             unsafe { instance.get_physical_device_memory_properties(physical_device) };
 
+        let allocator = Allocator::new(
+            Box::new(DeviceBackend::new(device.clone())),
+            device_memory_properties,
+        );
+
         let fence_create_info =
             vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
 
-        let draw_commands_reuse_fence = unsafe { device.create_fence(&fence_create_info, None) }
-            .expect("Create draw fence failed.");
         let setup_commands_reuse_fence = unsafe { device.create_fence(&fence_create_info, None) }
             .expect("Create setup fence failed.");
-        let swapchain_acquire_fence = unsafe { device.create_fence(&fence_create_info, None) }
-            .expect("Create swapchain acquire fence failed.");
-
-        let semaphore_create_info = vk::SemaphoreCreateInfo::default();
-
-        let present_complete_semaphore =
-            unsafe { device.create_semaphore(&semaphore_create_info, None) }.unwrap();
-        let rendering_complete_semaphore =
-            unsafe { device.create_semaphore(&semaphore_create_info, None) }.unwrap();
 
         let ray_tracing_pipeline_loader =
             khr::ray_tracing_pipeline::Device::new(&instance, &device);
@@ -350,18 +729,45 @@ impl<'a> VkController<'a> {
 
         let graphics_queue = unsafe { device.get_device_queue(queue_family_index, 0) };
 
-        let rt_command_buffer = {
-            let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
-                .command_buffer_count(1)
-                .command_pool(pool)
-                .level(vk::CommandBufferLevel::PRIMARY);
-
-            unsafe { device.allocate_command_buffers(&command_buffer_allocate_info) }
-                .expect("Failed to allocate Command Buffers!")[0]
+        // uploads go through the DMA engines when the device exposes them as a separate family
+        let staging = match transfer_queue_family_index {
+            Some(transfer_queue_family_index) => StagingUploader::new(
+                &device,
+                &allocator,
+                unsafe { device.get_device_queue(transfer_queue_family_index, 0) },
+                transfer_queue_family_index,
+                queue_family_index,
+            ),
+            None => StagingUploader::new(
+                &device,
+                &allocator,
+                graphics_queue,
+                queue_family_index,
+                queue_family_index,
+            ),
         };
 
         let vox_model = open_file("assets/monu1.vox");
 
+        let as_cache = config.acceleration_structures.cache.then(|| {
+            AccelerationStructureCache::new(&config.acceleration_structures.cache_directory)
+        });
+
+        let (shader_watcher, shader_compiler) = if config.shaders.hot_reload {
+            match ShaderCompiler::new(glsl_declarations(GLSL_STRUCTS)) {
+                Ok(compiler) => (
+                    Some(ShaderWatcher::new(Path::new(SHADER_DIRECTORY))),
+                    Some(compiler),
+                ),
+                Err(error) => {
+                    println!("Shader hot reload disabled: {}", error);
+                    (None, None)
+                }
+            }
+        } else {
+            (None, None)
+        };
+
         VkController {
             ray_tracing_pipeline_loader,
             acceleration_structure_loader,
@@ -369,21 +775,22 @@ impl<'a> VkController<'a> {
             device,
             graphics_queue,
             physical_device,
-            device_memory_properties,
+            allocator,
+            staging: Some(staging),
             window,
             surface_loader,
             surface_format,
             present_queue,
             surface_resolution,
+            render_resolution,
+            render_scale,
+            timestamp_period,
+            gpu_frame_time: None,
             swapchain_loader,
             pool,
             setup_command_buffer,
-            rt_command_buffer,
-            present_complete_semaphore,
-            rendering_complete_semaphore,
-            draw_commands_reuse_fence,
+            rendering_complete_semaphores: Vec::new(),
             setup_commands_reuse_fence,
-            swapchain_acquire_fence,
             surface,
             #[cfg(debug_assertions)]
             debug_call_back,
@@ -397,12 +804,10 @@ impl<'a> VkController<'a> {
             present_image_views: None,
             depth_image: None,
             depth_image_view: None,
-            depth_image_memory: None,
-            rt_image: vk::Image::null(),
-            rt_image_view: vk::ImageView::null(),
-            rt_image_memory: vk::DeviceMemory::null(),
+            depth_image_allocation: None,
+            frames: Vec::new(),
+            frame_index: 0,
             rt_descriptor_pool: None,
-            rt_descriptor_set: None,
             rt_descriptor_set_layout: None,
             render_pass: None,
             framebuffers: Vec::new(),
@@ -412,180 +817,217 @@ impl<'a> VkController<'a> {
             vertex_buffer: None,
             bottom_as: None,
             bottom_as_buffer: None,
+            bottom_as_cache_key: None,
             top_as: None,
             top_as_buffer: None,
+            top_as_cache_key: None,
+            config: config.clone(),
+            as_cache,
             instance_count: None,
-            instance_buffer: None,
+            instances: Vec::new(),
+            instances_dirty: false,
+            instance_buffers: Vec::new(),
+            instance_buffer_index: 0,
+            top_as_scratch_buffer: None,
             voxels_infos: None,
+            voxels_dirty: false,
+            moved_voxels: Vec::new(),
+            reset_history: true,
             palette_buffer: None,
-            uniforms_buffer: None,
+            materials_buffer: None,
+            environment_map: None,
             voxels_buffer: None,
+            lights_buffer: None,
+            readback_buffer: None,
+            light_count: 0,
+            light_power: 0.0,
             uniforms_descriptor_pool: None,
-            uniforms_descriptor_set: None,
             uniforms_descriptor_set_layout: None,
             pipeline: None,
             pipeline_layout: None,
             shader_group_count: None,
+            ao_filter: None,
+            svgf: None,
+            taa: None,
+            tonemapper: None,
+            upscaler: None,
+            histogram_buffer: None,
+            exposure_buffer: None,
+            pipeline_interface: None,
+            shader_watcher,
+            shader_compiler,
             shader_binding_table_buffer: None,
             sbt_raygen_region: None,
             sbt_hit_region: None,
             sbt_miss_region: None,
             sbt_call_region: None,
+            sbt_path_tracing_raygen_region: None,
+            sbt_restir_spatial_raygen_region: None,
 
             vox_model,
         }
     }
 
+    /// Value of `TonemapPushConstants::output_transfer`: the tonemapped frame is encoded for
+    /// the display, HDR10 or sRGB, before the upscaling.
+    pub fn output_transfer(&self) -> u32 {
+        if self.surface_format.color_space == vk::ColorSpaceKHR::HDR10_ST2084_EXT {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Whether the swapchain format encodes to sRGB in hardware, the upscaling then writes
+    /// linear values.
+    pub fn srgb_output(&self) -> bool {
+        matches!(
+            self.surface_format.format,
+            vk::Format::B8G8R8A8_SRGB
+                | vk::Format::R8G8B8A8_SRGB
+                | vk::Format::A8B8G8R8_SRGB_PACK32
+        )
+    }
+
     pub fn init(&mut self) {
         self.create_swapchain().unwrap();
         self.create_image_views().unwrap();
         // self.create_framebuffers().unwrap();
-        self.create_rt_image().unwrap();
+        self.ao_filter = Some(AoFilter::new(&self.device).unwrap());
+        self.svgf = Some(Svgf::new(&self.device).unwrap());
+        self.taa = Some(Taa::new(&self.device).unwrap());
+        self.tonemapper = Some(Tonemapper::new(&self.device).unwrap());
+        self.upscaler = Some(Upscaler::new(&self.device, self.surface_format.format).unwrap());
         self.create_data_structures();
+        self.create_frames().unwrap();
         self.create_descriptor_sets().unwrap();
         self.create_rt_sbt().unwrap();
+
+        // the temporary build and cache buffers are gone by now
+        self.allocator.release_empty_blocks();
+
+        for stats in self.allocator.stats() {
+            println!("{}", stats);
+        }
+        for candidate in self.allocator.defragmentation_candidates(0.25) {
+            println!("{}", candidate);
+        }
     }
 
     fn create_descriptor_sets(&mut self) -> anyhow::Result<()> {
-        let mut rt_count_allocate_info =
-            vk::DescriptorSetVariableDescriptorCountAllocateInfo::default().descriptor_counts(&[1]);
+        let interface = rt_pipeline_interface(RT_PIPELINE_SHADERS.map(|(_, code)| code))?;
 
-        let rt_descriptor_sizes = [
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR, // hardware TLAS
-                descriptor_count: 1,
-            },
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::STORAGE_IMAGE, // rt pass image
-                descriptor_count: 1,
-            },
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::UNIFORM_BUFFER, // voxels buffer
-                descriptor_count: 1,
-            },
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::UNIFORM_BUFFER, // palette buffer
-                descriptor_count: 1,
-            },
-        ];
+        let (rt_descriptor_set_layout, rt_descriptor_pool) = create_descriptor_set_layout_and_pool(
+            &self.device,
+            &interface,
+            0,
+            FRAMES_IN_FLIGHT as u32,
+        )?;
+        let (uniforms_descriptor_set_layout, uniforms_descriptor_pool) =
+            create_descriptor_set_layout_and_pool(
+                &self.device,
+                &interface,
+                1,
+                FRAMES_IN_FLIGHT as u32,
+            )?;
 
-        let rt_descriptor_pool_info = vk::DescriptorPoolCreateInfo::default()
-            .pool_sizes(&rt_descriptor_sizes)
-            .max_sets(1);
+        let layouts = vec![rt_descriptor_set_layout, uniforms_descriptor_set_layout];
+        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&layouts)
+            .push_constant_ranges(&interface.push_constant_ranges);
 
-        let rt_descriptor_pool = unsafe {
+        let pipeline_layout = unsafe {
             self.device
-                .create_descriptor_pool(&rt_descriptor_pool_info, None)
-        }
-        .unwrap();
-
-        let rt_binding_flags_inner = [
-            vk::DescriptorBindingFlagsEXT::empty(),
-            vk::DescriptorBindingFlagsEXT::empty(),
-            vk::DescriptorBindingFlagsEXT::empty(),
-            vk::DescriptorBindingFlagsEXT::empty(),
-            vk::DescriptorBindingFlagsEXT::empty(),
-            vk::DescriptorBindingFlagsEXT::empty(),
-        ];
-
-        let mut rt_binding_flags = vk::DescriptorSetLayoutBindingFlagsCreateInfoEXT::default()
-            .binding_flags(&rt_binding_flags_inner);
-
-        let rt_descriptor_set_layout = unsafe {
-            self.device.create_descriptor_set_layout(
-                &vk::DescriptorSetLayoutCreateInfo::default()
-                    .bindings(&[
-                        vk::DescriptorSetLayoutBinding::default() // TLAS buffer
-                            .descriptor_count(1)
-                            .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
-                            .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR)
-                            .binding(0),
-                        vk::DescriptorSetLayoutBinding::default() // full rt pass output image
-                            .descriptor_count(1)
-                            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-                            .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR)
-                            .binding(1),
-                        vk::DescriptorSetLayoutBinding::default() // colors buffer
-                            .descriptor_count(1)
-                            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                            .stage_flags(vk::ShaderStageFlags::CLOSEST_HIT_KHR)
-                            .binding(2),
-                        vk::DescriptorSetLayoutBinding::default() // voxels buffer
-                            .descriptor_count(1)
-                            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-                            .stage_flags(
-                                vk::ShaderStageFlags::INTERSECTION_KHR
-                                    | vk::ShaderStageFlags::CLOSEST_HIT_KHR,
-                            )
-                            .binding(3),
-                        vk::DescriptorSetLayoutBinding::default() // vertex buffer
-                            .descriptor_count(1)
-                            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-                            .stage_flags(vk::ShaderStageFlags::CLOSEST_HIT_KHR)
-                            .binding(4),
-                        vk::DescriptorSetLayoutBinding::default() // index buffer
-                            .descriptor_count(1)
-                            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-                            .stage_flags(vk::ShaderStageFlags::CLOSEST_HIT_KHR)
-                            .binding(5),
-                    ])
-                    .push_next(&mut rt_binding_flags),
-                None,
-            )
+                .create_pipeline_layout(&layout_create_info, None)
         }?;
 
-        let uniforms_descriptor_sizes = [vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: 1,
-        }];
+        let (pipeline, shader_group_count) =
+            self.create_rt_pipeline(pipeline_layout, RT_PIPELINE_SHADERS.map(|(_, code)| code))?;
 
-        const RGEN_SHADER: &[u8] = include_bytes!("..\\shaders\\spv\\ao_rgen.spv");
-        const MAIN_RCHIT_SHADER: &[u8] = include_bytes!("..\\shaders\\spv\\main_pass_rchit.spv");
-        const AO_RCHIT_SHADER: &[u8] = include_bytes!("..\\shaders\\spv\\ao_pass_rchit.spv");
-        const RMISS_SHADER: &[u8] = include_bytes!("..\\shaders\\spv\\ao_rmiss.spv");
-
-        let rgen_module = unsafe { create_shader_module(&self.device, RGEN_SHADER) }?;
-        let main_rchit_module = unsafe { create_shader_module(&self.device, MAIN_RCHIT_SHADER) }?;
-        let ao_rchit_module = unsafe { create_shader_module(&self.device, AO_RCHIT_SHADER) }?;
-        let rmiss_module = unsafe { create_shader_module(&self.device, RMISS_SHADER) }?;
-
-        let uniforms_binding_flags_inner = [vk::DescriptorBindingFlagsEXT::empty()];
-
-        let mut uniforms_binding_flags =
-            vk::DescriptorSetLayoutBindingFlagsCreateInfoEXT::default()
-                .binding_flags(&uniforms_binding_flags_inner);
-
-        let uniforms_descriptor_set_layout = unsafe {
-            self.device.create_descriptor_set_layout(
-                &vk::DescriptorSetLayoutCreateInfo::default()
-                    .bindings(&[vk::DescriptorSetLayoutBinding::default()
-                        .descriptor_count(1)
-                        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                        .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR)
-                        .binding(0)])
-                    .push_next(&mut uniforms_binding_flags),
-                None,
-            )
-        }?;
+        for frame_index in 0..FRAMES_IN_FLIGHT {
+            let mut rt_count_allocate_info =
+                vk::DescriptorSetVariableDescriptorCountAllocateInfo::default()
+                    .descriptor_counts(&[1]);
 
-        let uniforms_descriptor_pool_info = vk::DescriptorPoolCreateInfo::default()
-            .pool_sizes(&uniforms_descriptor_sizes)
-            .max_sets(1);
+            let rt_descriptor_set = unsafe {
+                self.device.allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::default()
+                        .descriptor_pool(rt_descriptor_pool)
+                        .set_layouts(&[rt_descriptor_set_layout])
+                        .push_next(&mut rt_count_allocate_info),
+                )
+            }
+            .unwrap()[0];
+
+            let mut uniforms_count_allocate_info =
+                vk::DescriptorSetVariableDescriptorCountAllocateInfo::default()
+                    .descriptor_counts(&[1]);
+
+            let uniforms_descriptor_set = unsafe {
+                self.device.allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::default()
+                        .descriptor_pool(uniforms_descriptor_pool)
+                        .set_layouts(&[uniforms_descriptor_set_layout])
+                        .push_next(&mut uniforms_count_allocate_info),
+                )
+            }
+            .unwrap()[0];
 
-        let uniforms_descriptor_pool = unsafe {
-            self.device
-                .create_descriptor_pool(&uniforms_descriptor_pool_info, None)
+            let frame = &mut self.frames[frame_index];
+            frame.rt_descriptor_set = rt_descriptor_set;
+            frame.uniforms_descriptor_set = uniforms_descriptor_set;
+
+            self.write_frame_descriptor_sets(frame_index);
         }
-        .unwrap();
 
-        let layouts = vec![rt_descriptor_set_layout, uniforms_descriptor_set_layout];
-        let layout_create_info = vk::PipelineLayoutCreateInfo::default().set_layouts(&layouts);
+        self.rt_descriptor_pool = Some(rt_descriptor_pool);
+        self.rt_descriptor_set_layout = Some(rt_descriptor_set_layout);
 
-        let pipeline_layout = unsafe {
-            self.device
-                .create_pipeline_layout(&layout_create_info, None)
-        }?;
+        self.uniforms_descriptor_pool = Some(uniforms_descriptor_pool);
+        self.uniforms_descriptor_set_layout = Some(uniforms_descriptor_set_layout);
 
+        self.pipeline = Some(pipeline);
+        self.pipeline_layout = Some(pipeline_layout);
+        self.shader_group_count = Some(shader_group_count);
+        self.pipeline_interface = Some(interface);
+
+        Ok(())
+    }
+
+    /// Creates the ray tracing pipeline from the SPIR-V of its stages, in `RT_PIPELINE_SHADERS`
+    /// order. Returns the pipeline and its shader group count.
+    fn create_rt_pipeline(
+        &self,
+        pipeline_layout: vk::PipelineLayout,
+        code: [&[u8]; 11],
+    ) -> anyhow::Result<(vk::Pipeline, usize)> {
+        let [rgen_code, main_rchit_code, ao_rchit_code, rmiss_code, ao_rmiss_code, pt_rgen_code, shadow_rmiss_code, main_rahit_code, ao_rahit_code, shadow_rahit_code, restir_spatial_rgen_code] =
+            code;
+
+        let max_ray_recursion_depth = self
+            .ray_tracing_pipeline_properties()
+            .max_ray_recursion_depth;
+        anyhow::ensure!(
+            RT_PIPELINE_RECURSION_DEPTH <= max_ray_recursion_depth,
+            "the device traces rays {} deep at most, the pipeline needs {}",
+            max_ray_recursion_depth,
+            RT_PIPELINE_RECURSION_DEPTH
+        );
+
+        let rgen_module = unsafe { create_shader_module(&self.device, rgen_code) }?;
+        let main_rchit_module = unsafe { create_shader_module(&self.device, main_rchit_code) }?;
+        let ao_rchit_module = unsafe { create_shader_module(&self.device, ao_rchit_code) }?;
+        let rmiss_module = unsafe { create_shader_module(&self.device, rmiss_code) }?;
+        let ao_rmiss_module = unsafe { create_shader_module(&self.device, ao_rmiss_code) }?;
+        let pt_rgen_module = unsafe { create_shader_module(&self.device, pt_rgen_code) }?;
+        let shadow_rmiss_module = unsafe { create_shader_module(&self.device, shadow_rmiss_code) }?;
+        let main_rahit_module = unsafe { create_shader_module(&self.device, main_rahit_code) }?;
+        let ao_rahit_module = unsafe { create_shader_module(&self.device, ao_rahit_code) }?;
+        let shadow_rahit_module = unsafe { create_shader_module(&self.device, shadow_rahit_code) }?;
+        let restir_spatial_rgen_module =
+            unsafe { create_shader_module(&self.device, restir_spatial_rgen_code) }?;
+
+        // in shader binding table order, the hit groups of the translucent voxels last
         let shader_groups = vec![
             // group0 = [ raygen ]
             vk::RayTracingShaderGroupCreateInfoKHR::default()
@@ -594,46 +1036,131 @@ impl<'a> VkController<'a> {
                 .closest_hit_shader(vk::SHADER_UNUSED_KHR)
                 .any_hit_shader(vk::SHADER_UNUSED_KHR)
                 .intersection_shader(vk::SHADER_UNUSED_KHR),
-            // group1 = [ chit main pass ]
+            // group1 = [ chit main pass, ahit main pass ], the opaque voxels only run the any-hit
+            // shader for the rays forcing them non-opaque, which the traversal cost view counts
             vk::RayTracingShaderGroupCreateInfoKHR::default()
                 .ty(vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
                 .general_shader(vk::SHADER_UNUSED_KHR)
                 .closest_hit_shader(1)
-                .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                .any_hit_shader(7)
                 .intersection_shader(vk::SHADER_UNUSED_KHR),
-            // group1 = [ chit ao pass ]
+            // group2 = [ chit ao pass ]
             vk::RayTracingShaderGroupCreateInfoKHR::default()
                 .ty(vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
                 .general_shader(vk::SHADER_UNUSED_KHR)
                 .closest_hit_shader(2)
                 .any_hit_shader(vk::SHADER_UNUSED_KHR)
                 .intersection_shader(vk::SHADER_UNUSED_KHR),
-            // group3 = [ miss ]
+            // group3 = [ shadow ], opaque voxels stop the shadow rays without any shader
+            vk::RayTracingShaderGroupCreateInfoKHR::default()
+                .ty(vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
+                .general_shader(vk::SHADER_UNUSED_KHR)
+                .closest_hit_shader(vk::SHADER_UNUSED_KHR)
+                .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                .intersection_shader(vk::SHADER_UNUSED_KHR),
+            // group4 = [ chit main pass, ahit main pass ]
+            vk::RayTracingShaderGroupCreateInfoKHR::default()
+                .ty(vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
+                .general_shader(vk::SHADER_UNUSED_KHR)
+                .closest_hit_shader(1)
+                .any_hit_shader(7)
+                .intersection_shader(vk::SHADER_UNUSED_KHR),
+            // group5 = [ chit ao pass, ahit ao pass ]
+            vk::RayTracingShaderGroupCreateInfoKHR::default()
+                .ty(vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
+                .general_shader(vk::SHADER_UNUSED_KHR)
+                .closest_hit_shader(2)
+                .any_hit_shader(8)
+                .intersection_shader(vk::SHADER_UNUSED_KHR),
+            // group6 = [ ahit shadow ]
+            vk::RayTracingShaderGroupCreateInfoKHR::default()
+                .ty(vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
+                .general_shader(vk::SHADER_UNUSED_KHR)
+                .closest_hit_shader(vk::SHADER_UNUSED_KHR)
+                .any_hit_shader(9)
+                .intersection_shader(vk::SHADER_UNUSED_KHR),
+            // group7 = [ miss ]
             vk::RayTracingShaderGroupCreateInfoKHR::default()
                 .ty(vk::RayTracingShaderGroupTypeKHR::GENERAL)
                 .general_shader(3)
                 .closest_hit_shader(vk::SHADER_UNUSED_KHR)
                 .any_hit_shader(vk::SHADER_UNUSED_KHR)
                 .intersection_shader(vk::SHADER_UNUSED_KHR),
+            // group8 = [ miss ao pass ]
+            vk::RayTracingShaderGroupCreateInfoKHR::default()
+                .ty(vk::RayTracingShaderGroupTypeKHR::GENERAL)
+                .general_shader(4)
+                .closest_hit_shader(vk::SHADER_UNUSED_KHR)
+                .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                .intersection_shader(vk::SHADER_UNUSED_KHR),
+            // group9 = [ miss shadow ]
+            vk::RayTracingShaderGroupCreateInfoKHR::default()
+                .ty(vk::RayTracingShaderGroupTypeKHR::GENERAL)
+                .general_shader(6)
+                .closest_hit_shader(vk::SHADER_UNUSED_KHR)
+                .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                .intersection_shader(vk::SHADER_UNUSED_KHR),
+            // group10 = [ raygen path tracing ]
+            vk::RayTracingShaderGroupCreateInfoKHR::default()
+                .ty(vk::RayTracingShaderGroupTypeKHR::GENERAL)
+                .general_shader(5)
+                .closest_hit_shader(vk::SHADER_UNUSED_KHR)
+                .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                .intersection_shader(vk::SHADER_UNUSED_KHR),
+            // group11 = [ raygen restir spatial reuse ]
+            vk::RayTracingShaderGroupCreateInfoKHR::default()
+                .ty(vk::RayTracingShaderGroupTypeKHR::GENERAL)
+                .general_shader(10)
+                .closest_hit_shader(vk::SHADER_UNUSED_KHR)
+                .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                .intersection_shader(vk::SHADER_UNUSED_KHR),
         ];
 
         let shader_stages = vec![
             vk::PipelineShaderStageCreateInfo::default()
                 .stage(vk::ShaderStageFlags::RAYGEN_KHR)
                 .module(rgen_module)
-                .name(std::ffi::CStr::from_bytes_with_nul(b"main\0")?),
+                .name(c"main"),
             vk::PipelineShaderStageCreateInfo::default()
                 .stage(vk::ShaderStageFlags::CLOSEST_HIT_KHR)
                 .module(main_rchit_module)
-                .name(std::ffi::CStr::from_bytes_with_nul(b"main\0")?),
+                .name(c"main"),
             vk::PipelineShaderStageCreateInfo::default()
                 .stage(vk::ShaderStageFlags::CLOSEST_HIT_KHR)
                 .module(ao_rchit_module)
-                .name(std::ffi::CStr::from_bytes_with_nul(b"main\0")?),
+                .name(c"main"),
             vk::PipelineShaderStageCreateInfo::default()
                 .stage(vk::ShaderStageFlags::MISS_KHR)
                 .module(rmiss_module)
-                .name(std::ffi::CStr::from_bytes_with_nul(b"main\0")?),
+                .name(c"main"),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::MISS_KHR)
+                .module(ao_rmiss_module)
+                .name(c"main"),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::RAYGEN_KHR)
+                .module(pt_rgen_module)
+                .name(c"main"),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::MISS_KHR)
+                .module(shadow_rmiss_module)
+                .name(c"main"),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::ANY_HIT_KHR)
+                .module(main_rahit_module)
+                .name(c"main"),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::ANY_HIT_KHR)
+                .module(ao_rahit_module)
+                .name(c"main"),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::ANY_HIT_KHR)
+                .module(shadow_rahit_module)
+                .name(c"main"),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::RAYGEN_KHR)
+                .module(restir_spatial_rgen_module)
+                .name(c"main"),
         ];
 
         let pipeline = unsafe {
@@ -644,46 +1171,120 @@ impl<'a> VkController<'a> {
                     &[vk::RayTracingPipelineCreateInfoKHR::default()
                         .stages(&shader_stages)
                         .groups(&shader_groups)
-                        .max_pipeline_ray_recursion_depth(1)
+                        .max_pipeline_ray_recursion_depth(RT_PIPELINE_RECURSION_DEPTH)
                         .layout(pipeline_layout)],
                     None,
                 )
         }
-        .unwrap()[0];
+        .map_err(|(_, result)| result);
 
         unsafe {
             self.device.destroy_shader_module(rgen_module, None);
             self.device.destroy_shader_module(main_rchit_module, None);
             self.device.destroy_shader_module(ao_rchit_module, None);
             self.device.destroy_shader_module(rmiss_module, None);
+            self.device.destroy_shader_module(ao_rmiss_module, None);
+            self.device.destroy_shader_module(pt_rgen_module, None);
+            self.device.destroy_shader_module(shadow_rmiss_module, None);
+            self.device.destroy_shader_module(main_rahit_module, None);
+            self.device.destroy_shader_module(ao_rahit_module, None);
+            self.device.destroy_shader_module(shadow_rahit_module, None);
+            self.device
+                .destroy_shader_module(restir_spatial_rgen_module, None);
         }
 
-        let rt_descriptor_sets = unsafe {
-            self.device.allocate_descriptor_sets(
-                &vk::DescriptorSetAllocateInfo::default()
-                    .descriptor_pool(rt_descriptor_pool)
-                    .set_layouts(&[rt_descriptor_set_layout])
-                    .push_next(&mut rt_count_allocate_info),
-            )
+        Ok((pipeline?[0], shader_groups.len()))
+    }
+
+    /// Recompiles the ray tracing shaders once a file of the shader directory changed, then
+    /// swaps in the new pipeline and shader binding table. On compile errors the current
+    /// pipeline keeps running.
+    pub fn reload_changed_shaders(&mut self) {
+        let (Some(watcher), Some(compiler)) =
+            (self.shader_watcher.as_mut(), self.shader_compiler.as_mut())
+        else {
+            return;
+        };
+
+        if !watcher.poll() {
+            return;
         }
-        .unwrap();
 
-        let rt_descriptor_set = rt_descriptor_sets[0];
+        let mut code = vec![];
+        let mut errors = vec![];
 
-        let mut uniforms_count_allocate_info =
-            vk::DescriptorSetVariableDescriptorCountAllocateInfo::default().descriptor_counts(&[1]);
+        for (path, _) in RT_PIPELINE_SHADERS {
+            match compiler.compile(Path::new(path)) {
+                Ok((stage_code, warnings)) => {
+                    warnings.iter().for_each(|warning| println!("{}", warning));
+                    code.push(stage_code);
+                }
+                Err(stage_errors) => errors.extend(stage_errors),
+            }
+        }
 
-        let uniforms_descriptor_sets = unsafe {
-            self.device.allocate_descriptor_sets(
-                &vk::DescriptorSetAllocateInfo::default()
-                    .descriptor_pool(uniforms_descriptor_pool)
-                    .set_layouts(&[uniforms_descriptor_set_layout])
-                    .push_next(&mut uniforms_count_allocate_info),
-            )
+        if !errors.is_empty() {
+            errors.iter().for_each(|error| println!("{}", error));
+            println!("Shader reload failed, keeping the previous pipeline.");
+            self.window
+                .set_title(&format!("{} - shader error", WINDOW_TITLE));
+            return;
         }
-        .unwrap();
 
-        let uniforms_descriptor_set = uniforms_descriptor_sets[0];
+        let code: [&[u8]; 11] = std::array::from_fn(|index| bytemuck::cast_slice(&code[index]));
+
+        // the descriptor set and pipeline layouts are kept, the new stages must fit them
+        let interface = match rt_pipeline_interface(code) {
+            Ok(interface) => interface,
+            Err(error) => {
+                println!("{}", error);
+                println!("Shader reload failed, keeping the previous pipeline.");
+                self.window
+                    .set_title(&format!("{} - shader error", WINDOW_TITLE));
+                return;
+            }
+        };
+
+        if !interface.is_compatible_with(self.pipeline_interface.as_ref().unwrap()) {
+            println!("The descriptor bindings of the shaders changed, restart to apply them.");
+            self.window
+                .set_title(&format!("{} - shader error", WINDOW_TITLE));
+            return;
+        }
+
+        let (pipeline, shader_group_count) =
+            match self.create_rt_pipeline(self.pipeline_layout.unwrap(), code) {
+                Ok(pipeline) => pipeline,
+                Err(error) => {
+                    println!("Failed to create the reloaded pipeline: {}", error);
+                    self.window
+                        .set_title(&format!("{} - shader error", WINDOW_TITLE));
+                    return;
+                }
+            };
+
+        // frames in flight still use the previous pipeline and binding table
+        unsafe {
+            self.device.device_wait_idle().unwrap();
+            self.device.destroy_pipeline(self.pipeline.unwrap(), None);
+            destroy_buffer!(self.shader_binding_table_buffer, self.device);
+        }
+
+        self.pipeline = Some(pipeline);
+        self.shader_group_count = Some(shader_group_count);
+        self.create_rt_sbt().unwrap();
+
+        self.reset_history = true;
+
+        self.window.set_title(WINDOW_TITLE);
+        println!("Reloaded shaders.");
+    }
+
+    /// Writes every binding of the descriptor sets of a frame, which must not be in flight.
+    fn write_frame_descriptor_sets(&mut self, frame_index: usize) {
+        let frame = &self.frames[frame_index];
+        // the history is read from the frame recorded before this one
+        let prev_frame = &self.frames[(frame_index + FRAMES_IN_FLIGHT - 1) % FRAMES_IN_FLIGHT];
 
         let accel_structs = [self.top_as.unwrap()];
 
@@ -691,30 +1292,77 @@ impl<'a> VkController<'a> {
             .acceleration_structures(&accel_structs);
 
         let accel_write = vk::WriteDescriptorSet::default()
-            .dst_set(rt_descriptor_set)
+            .dst_set(frame.rt_descriptor_set)
             .dst_binding(0)
             .dst_array_element(0)
             .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
             .descriptor_count(1)
             .push_next(&mut accel_info);
 
-        let image_info = [vk::DescriptorImageInfo::default()
-            .image_layout(vk::ImageLayout::GENERAL)
-            .image_view(self.rt_image_view)];
+        let storage_image_info = |view| {
+            [vk::DescriptorImageInfo::default()
+                .image_layout(vk::ImageLayout::GENERAL)
+                .image_view(view)]
+        };
 
-        let image_write = vk::WriteDescriptorSet::default()
-            .dst_set(rt_descriptor_set)
-            .dst_binding(1)
+        let ao_image_info = storage_image_info(frame.images.ao.view);
+        let depth_normal_image_info = storage_image_info(frame.images.depth_normal.view);
+        let albedo_image_info = storage_image_info(frame.images.albedo.view);
+        let prev_ao_image_info = storage_image_info(prev_frame.images.ao.view);
+        let prev_depth_normal_image_info = storage_image_info(prev_frame.images.depth_normal.view);
+
+        let reflection_image_info = storage_image_info(frame.images.reflection.view);
+        let accumulation_image_info = storage_image_info(frame.images.accumulation.view);
+        let prev_accumulation_image_info = storage_image_info(prev_frame.images.accumulation.view);
+        let reservoir_image_info = storage_image_info(frame.images.reservoir.view);
+        let prev_spatial_reservoir_image_info =
+            storage_image_info(prev_frame.images.spatial_reservoir.view);
+        let spatial_reservoir_image_info = storage_image_info(frame.images.spatial_reservoir.view);
+        let motion_image_info = storage_image_info(frame.images.motion.view);
+        let fog_image_info = storage_image_info(frame.images.fog.view);
+        let debug_image_info = storage_image_info(frame.images.debug.view);
+
+        let storage_image_writes = [
+            (1, &ao_image_info),
+            (6, &depth_normal_image_info),
+            (7, &albedo_image_info),
+            (8, &prev_ao_image_info),
+            (9, &prev_depth_normal_image_info),
+            (11, &accumulation_image_info),
+            (12, &prev_accumulation_image_info),
+            (14, &reflection_image_info),
+            (16, &reservoir_image_info),
+            (17, &prev_spatial_reservoir_image_info),
+            (18, &spatial_reservoir_image_info),
+            (19, &motion_image_info),
+            (20, &fog_image_info),
+            (21, &debug_image_info),
+        ]
+        .map(|(binding, image_info)| {
+            vk::WriteDescriptorSet::default()
+                .dst_set(frame.rt_descriptor_set)
+                .dst_binding(binding)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .image_info(image_info)
+        });
+
+        let environment_map = self.environment_map.as_ref().unwrap();
+        let environment_map_info = [environment_map.descriptor_info()];
+
+        let environment_map_write = vk::WriteDescriptorSet::default()
+            .dst_set(frame.rt_descriptor_set)
+            .dst_binding(13)
             .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-            .image_info(&image_info);
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&environment_map_info);
 
         let palette_buffer_info = [vk::DescriptorBufferInfo::default()
             .buffer(self.palette_buffer.as_ref().unwrap().buffer)
             .range(vk::WHOLE_SIZE)];
 
         let palette_buffer_write = vk::WriteDescriptorSet::default()
-            .dst_set(rt_descriptor_set)
+            .dst_set(frame.rt_descriptor_set)
             .dst_binding(2)
             .dst_array_element(0)
             .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
@@ -724,19 +1372,41 @@ impl<'a> VkController<'a> {
             .buffer(self.voxels_buffer.as_ref().unwrap().buffer)
             .range(vk::WHOLE_SIZE)];
 
+        let materials_buffer_info = [vk::DescriptorBufferInfo::default()
+            .buffer(self.materials_buffer.as_ref().unwrap().buffer)
+            .range(vk::WHOLE_SIZE)];
+
+        let materials_buffer_write = vk::WriteDescriptorSet::default()
+            .dst_set(frame.rt_descriptor_set)
+            .dst_binding(10)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&materials_buffer_info);
+
         let voxels_buffer_write = vk::WriteDescriptorSet::default()
-            .dst_set(rt_descriptor_set)
+            .dst_set(frame.rt_descriptor_set)
             .dst_binding(3)
             .dst_array_element(0)
             .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
             .buffer_info(&voxels_buffer_info);
 
+        let lights_buffer_info = [vk::DescriptorBufferInfo::default()
+            .buffer(self.lights_buffer.as_ref().unwrap().buffer)
+            .range(vk::WHOLE_SIZE)];
+
+        let lights_buffer_write = vk::WriteDescriptorSet::default()
+            .dst_set(frame.rt_descriptor_set)
+            .dst_binding(15)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&lights_buffer_info);
+
         let vertex_buffer_info = [vk::DescriptorBufferInfo::default()
             .buffer(self.vertex_buffer.as_ref().unwrap().buffer)
             .range(vk::WHOLE_SIZE)];
 
         let vertex_buffer_write = vk::WriteDescriptorSet::default()
-            .dst_set(rt_descriptor_set)
+            .dst_set(frame.rt_descriptor_set)
             .dst_binding(4)
             .dst_array_element(0)
             .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
@@ -747,116 +1417,196 @@ impl<'a> VkController<'a> {
             .range(vk::WHOLE_SIZE)];
 
         let index_buffer_write = vk::WriteDescriptorSet::default()
-            .dst_set(rt_descriptor_set)
-            .dst_binding(4)
+            .dst_set(frame.rt_descriptor_set)
+            .dst_binding(5)
             .dst_array_element(0)
             .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
             .buffer_info(&index_buffer_info);
 
-        unsafe {
-            self.device.update_descriptor_sets(
-                &[
-                    accel_write,
-                    image_write,
-                    palette_buffer_write,
-                    voxels_buffer_write,
-                    vertex_buffer_write,
-                    index_buffer_write,
-                ],
-                &[],
-            );
-        }
-
         let uniforms_buffer_info = [vk::DescriptorBufferInfo::default()
-            .buffer(self.uniforms_buffer.as_ref().unwrap().buffer)
+            .buffer(frame.uniforms_buffer.buffer)
             .range(vk::WHOLE_SIZE)];
 
         let uniforms_buffer_write = vk::WriteDescriptorSet::default()
-            .dst_set(uniforms_descriptor_set)
+            .dst_set(frame.uniforms_descriptor_set)
             .dst_binding(0)
             .dst_array_element(0)
             .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
             .buffer_info(&uniforms_buffer_info);
 
         unsafe {
-            self.device
-                .update_descriptor_sets(&[uniforms_buffer_write], &[]);
+            self.device.update_descriptor_sets(
+                &[
+                    &[
+                        accel_write,
+                        palette_buffer_write,
+                        voxels_buffer_write,
+                        lights_buffer_write,
+                        vertex_buffer_write,
+                        index_buffer_write,
+                        materials_buffer_write,
+                        environment_map_write,
+                        uniforms_buffer_write,
+                    ],
+                    &storage_image_writes[..],
+                ]
+                .concat(),
+                &[],
+            );
         }
 
-        self.rt_descriptor_pool = Some(rt_descriptor_pool);
-        self.rt_descriptor_set = Some(rt_descriptor_set);
-        self.rt_descriptor_set_layout = Some(rt_descriptor_set_layout);
-
-        self.uniforms_descriptor_pool = Some(uniforms_descriptor_pool);
-        self.uniforms_descriptor_set = Some(uniforms_descriptor_set);
-        self.uniforms_descriptor_set_layout = Some(uniforms_descriptor_set_layout);
+        self.ao_filter.as_ref().unwrap().write_descriptor_sets(
+            &self.device,
+            frame.filter_descriptor_sets,
+            frame.images.depth_normal.view,
+            frame.images.ao.view,
+            frame.images.filtered_ao.view,
+            frame.images.denoised.view,
+            frame.images.albedo.view,
+            frame.images.reflection.view,
+            frame.images.fog.view,
+            frame.uniforms_buffer.buffer,
+            environment_map.descriptor_info(),
+        );
 
-        self.pipeline = Some(pipeline);
-        self.pipeline_layout = Some(pipeline_layout);
-        self.shader_group_count = Some(shader_groups.len());
+        let mut signal_views = [vk::ImageView::null(); 2];
+        signal_views[RADIANCE_SIGNAL] = frame.images.accumulation.view;
+        signal_views[OCCLUSION_SIGNAL] = frame.images.ao.view;
 
-        Ok(())
+        self.svgf.as_ref().unwrap().write_descriptor_sets(
+            &self.device,
+            frame.svgf_descriptor_sets,
+            signal_views,
+            &SvgfViews {
+                depth_normal: frame.images.depth_normal.view,
+                prev_depth_normal: prev_frame.images.depth_normal.view,
+                albedo: frame.images.albedo.view,
+                history: frame.images.svgf_history.view,
+                prev_history: prev_frame.images.svgf_history.view,
+                moments: frame.images.svgf_moments.view,
+                prev_moments: prev_frame.images.svgf_moments.view,
+                filtered: [
+                    frame.images.svgf_filtered[0].view,
+                    frame.images.svgf_filtered[1].view,
+                ],
+                denoised: frame.images.denoised.view,
+            },
+            frame.uniforms_buffer.buffer,
+        );
+
+        self.taa.as_ref().unwrap().write_descriptor_set(
+            &self.device,
+            frame.taa_descriptor_set,
+            frame.images.hdr.view,
+            frame.images.motion.view,
+            prev_frame.images.taa_resolved.view,
+            frame.images.taa_resolved.view,
+        );
+
+        let mut tonemap_input_views = [vk::ImageView::null(); 2];
+        tonemap_input_views[HDR_SET] = frame.images.hdr.view;
+        tonemap_input_views[RESOLVED_SET] = frame.images.taa_resolved.view;
+
+        for (descriptor_set, hdr_view) in frame
+            .tonemap_descriptor_sets
+            .into_iter()
+            .zip(tonemap_input_views)
+        {
+            self.tonemapper.as_ref().unwrap().write_descriptor_set(
+                &self.device,
+                descriptor_set,
+                hdr_view,
+                self.histogram_buffer.as_ref().unwrap().buffer,
+                self.exposure_buffer.as_ref().unwrap().buffer,
+            );
+        }
+
+        self.upscaler.as_ref().unwrap().write_descriptor_set(
+            &self.device,
+            frame.upscale_descriptor_set,
+            frame.images.tonemapped.view,
+        );
+
+        self.frames[frame_index].descriptors_dirty = false;
+    }
+
+    /// Called when a resource bound in the descriptor sets is replaced. The sets of the frame
+    /// being recorded are updated right away, the others once their frame has completed.
+    fn invalidate_descriptor_sets(&mut self) {
+        for frame in &mut self.frames {
+            frame.descriptors_dirty = true;
+        }
+
+        self.write_frame_descriptor_sets(self.frame_index);
     }
 
     fn create_blas_geometry(&mut self) {
-        let vertex_buffer = {
-            // cube vertex buffer
-            let vertices = [
-                // front
-                glm::vec3::<f32>(-1.0, -1.0, 1.0),
-                glm::vec3::<f32>(1.0, -1.0, 1.0),
-                glm::vec3::<f32>(1.0, 1.0, 1.0),
-                glm::vec3::<f32>(-1.0, 1.0, 1.0),
-                // back
-                glm::vec3::<f32>(-1.0, -1.0, -1.0),
-                glm::vec3::<f32>(1.0, -1.0, -1.0),
-                glm::vec3::<f32>(1.0, 1.0, -1.0),
-                glm::vec3::<f32>(-1.0, 1.0, -1.0),
-            ];
+        // cube vertex buffer
+        let vertices = [
+            // front
+            glm::vec3::<f32>(-1.0, -1.0, 1.0),
+            glm::vec3::<f32>(1.0, -1.0, 1.0),
+            glm::vec3::<f32>(1.0, 1.0, 1.0),
+            glm::vec3::<f32>(-1.0, 1.0, 1.0),
+            // back
+            glm::vec3::<f32>(-1.0, -1.0, -1.0),
+            glm::vec3::<f32>(1.0, -1.0, -1.0),
+            glm::vec3::<f32>(1.0, 1.0, -1.0),
+            glm::vec3::<f32>(-1.0, 1.0, -1.0),
+        ];
 
+        let vertex_buffer = {
             let stride = std::mem::size_of::<glm::Vec3>();
             let buffer_size = (stride * vertices.len()) as vk::DeviceSize;
 
-            let mut buffer = BufferResource::new(
+            let buffer = BufferResource::new(
                 buffer_size,
                 vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
                     | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
-                    | vk::BufferUsageFlags::STORAGE_BUFFER,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    | vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
                 &self.device,
-                self.device_memory_properties,
+                &self.allocator,
             );
 
-            buffer.store(&vertices, &self.device);
+            self.staging
+                .as_mut()
+                .unwrap()
+                .upload(&self.device, &buffer, 0, &vertices);
 
             buffer
         };
 
-        let index_buffer = {
-            // cube index buffer
-            let indices: [u32; 36] = [
-                0, 1, 2, 2, 3, 0, // front OK
-                1, 5, 6, 6, 2, 1, // right OK
-                7, 6, 5, 5, 4, 7, // back OK
-                4, 0, 3, 3, 7, 4, // left NOT OK
-                4, 5, 1, 1, 0, 4, // bottom NOT OK
-                3, 2, 6, 6, 7, 3, // top NOT OK
-            ];
+        // cube index buffer
+        let indices: [u32; 36] = [
+            0, 1, 2, 2, 3, 0, // front OK
+            1, 5, 6, 6, 2, 1, // right OK
+            7, 6, 5, 5, 4, 7, // back OK
+            4, 0, 3, 3, 7, 4, // left NOT OK
+            4, 5, 1, 1, 0, 4, // bottom NOT OK
+            3, 2, 6, 6, 7, 3, // top NOT OK
+        ];
 
+        let index_buffer = {
             let stride = std::mem::size_of::<glm::Vec3>();
             let buffer_size = (stride * indices.len()) as vk::DeviceSize;
 
-            let mut buffer = BufferResource::new(
+            let buffer = BufferResource::new(
                 buffer_size,
                 vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
                     | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
-                    | vk::BufferUsageFlags::STORAGE_BUFFER,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    | vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
                 &self.device,
-                self.device_memory_properties,
+                &self.allocator,
             );
 
-            buffer.store(&indices, &self.device);
+            self.staging
+                .as_mut()
+                .unwrap()
+                .upload(&self.device, &buffer, 0, &indices);
 
             buffer
         };
@@ -880,24 +1630,337 @@ impl<'a> VkController<'a> {
                     })
                     .max_vertex(3),
             })
-            .flags(vk::GeometryFlagsKHR::OPAQUE);
+            // opaque unless an instance forces otherwise, the shadow any-hit shader must see
+            // each face once to absorb the light once
+            .flags(
+                vk::GeometryFlagsKHR::OPAQUE
+                    | vk::GeometryFlagsKHR::NO_DUPLICATE_ANY_HIT_INVOCATION,
+            );
+
+        let cache_inputs = [
+            bytemuck::cast_slice(&vertices),
+            bytemuck::cast_slice(&indices),
+            &geometry.flags.as_raw().to_le_bytes(),
+            &self
+                .acceleration_structure_build_flags(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+                .as_raw()
+                .to_le_bytes(),
+        ]
+        .concat();
 
         self.as_geometry = Some(geometry);
+        self.bottom_as_cache_key = Some(AccelerationStructureCache::key("blas", &cache_inputs));
         self.index_buffer = Some(index_buffer);
         self.vertex_buffer = Some(vertex_buffer);
     }
 
     fn create_blas(&mut self) {
-        let build_range_info =
-            vk::AccelerationStructureBuildRangeInfoKHR::default().primitive_count(12);
-
         let geometries: [vk::AccelerationStructureGeometryKHR<'a>; 1] = [self.as_geometry.unwrap()];
+        let ty = vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL;
+        let cache_key = self.bottom_as_cache_key.clone().unwrap();
+
+        let (bottom_as, bottom_as_buffer) = self
+            .load_cached_acceleration_structure("BLAS", ty, &cache_key, &[])
+            .unwrap_or_else(|| {
+                let (bottom_as, bottom_as_buffer) =
+                    self.build_acceleration_structure(ty, &geometries, 12);
+                self.finish_acceleration_structure(
+                    "BLAS",
+                    ty,
+                    &cache_key,
+                    &[],
+                    bottom_as,
+                    bottom_as_buffer,
+                )
+            });
+
+        self.bottom_as = Some(bottom_as);
+        self.bottom_as_buffer = Some(bottom_as_buffer);
+    }
+
+    /// Addresses of every BLAS the instances may reference, in a fixed order that the TLAS cache
+    /// maps the serialized references with.
+    fn bottom_level_addresses(&self) -> Vec<vk::DeviceAddress> {
+        vec![self.acceleration_structure_address(self.bottom_as.unwrap())]
+    }
+
+    fn create_tlas_instances(&mut self) {
+        let accel_handle = self.acceleration_structure_address(self.bottom_as.unwrap());
+
+        // let (instances, voxels) = create_cube_instances(accel_handle, 1024, 0.01);
+
+        let model = self.vox_model.models.first().unwrap().voxels.clone();
+        let materials = get_materials(&self.vox_model);
+        let (instances, voxels_infos) = vox_to_tlas(accel_handle, model, &materials);
+
+        // let instances = [vk::AccelerationStructureInstanceKHR {
+        //     transform: vk::TransformMatrixKHR {
+        //         matrix: [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+        //     },
+        //     instance_custom_index_and_mask: vk::Packed24_8::new(0, 0xff),
+        //     instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(0, 0),
+        //     acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
+        //         device_handle: accel_handle,
+        //     },
+        // }];
+
+        self.voxels_infos = Some(voxels_infos);
+
+        // the BLAS addresses change between runs, so the key has the BLAS set and the index of
+        // the BLAS of every instance instead
+        let bottom_level_addresses = self.bottom_level_addresses();
+        let cache_inputs: Vec<u8> = instances
+            .iter()
+            .flat_map(|instance| {
+                let address = unsafe { instance.acceleration_structure_reference.device_handle };
+                let bottom_level_index = bottom_level_addresses
+                    .iter()
+                    .position(|bottom_level_address| *bottom_level_address == address)
+                    .expect("Instance of an unknown BLAS.");
+
+                instance
+                    .transform
+                    .matrix
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes())
+                    .chain(
+                        instance
+                            .instance_custom_index_and_mask
+                            .low_24()
+                            .to_le_bytes(),
+                    )
+                    .chain(
+                        instance
+                            .instance_shader_binding_table_record_offset_and_flags
+                            .low_24()
+                            .to_le_bytes(),
+                    )
+                    .chain([instance
+                        .instance_shader_binding_table_record_offset_and_flags
+                        .high_8()])
+                    .chain((bottom_level_index as u32).to_le_bytes())
+            })
+            .chain(self.bottom_as_cache_key.as_ref().unwrap().bytes())
+            .chain(
+                self.acceleration_structure_build_flags(
+                    vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+                )
+                .as_raw()
+                .to_le_bytes(),
+            )
+            .collect();
+
+        self.instance_count = Some(instances.len());
+        self.instance_buffers = self.create_instance_buffers(instances.len());
+        self.instances = instances;
+        self.top_as_cache_key = Some(AccelerationStructureCache::key("tlas", &cache_inputs));
+    }
+
+    /// Device local instance buffers, one per slot so that an upload never overwrites the
+    /// instances of the structure currently being traced.
+    fn create_instance_buffers(&self, capacity: usize) -> Vec<BufferResource> {
+        let size = (std::mem::size_of::<vk::AccelerationStructureInstanceKHR>() * capacity.max(1))
+            as vk::DeviceSize;
+        // every refit uploads to a slot the graphics queue already built from
+        let queue_family_indices = self.staging.as_ref().unwrap().queue_family_indices();
+
+        (0..INSTANCE_BUFFER_COUNT)
+            .map(|_| {
+                BufferResource::new_shared(
+                    size,
+                    vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                        | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+                        | vk::BufferUsageFlags::TRANSFER_DST,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                    &queue_family_indices,
+                    &self.device,
+                    &self.allocator,
+                )
+            })
+            .collect()
+    }
+
+    fn upload_instances(&mut self, slot: usize) {
+        self.staging.as_mut().unwrap().upload(
+            &self.device,
+            &self.instance_buffers[slot],
+            0,
+            &self.instances,
+        );
+    }
+
+    /// Submits pending uploads and waits for them, for data used right away by one time
+    /// commands.
+    fn finish_uploads(&mut self) {
+        let staging = self.staging.as_mut().unwrap();
+        staging.flush(&self.device);
+        staging.wait_idle(&self.device);
+
+        let acquire_barriers = staging.take_acquire_barriers();
+
+        if !acquire_barriers.is_empty() {
+            self.submit_one_time_commands(|device, command_buffer| {
+                record_acquire_barriers(device, command_buffer, &acquire_barriers)
+            });
+        }
+    }
+
+    fn tlas_geometry(&self, slot: usize) -> vk::AccelerationStructureGeometryKHR<'a> {
+        let instances = vk::AccelerationStructureGeometryInstancesDataKHR::default()
+            .array_of_pointers(false)
+            .data(vk::DeviceOrHostAddressConstKHR {
+                device_address: unsafe {
+                    get_buffer_device_address(&self.device, self.instance_buffers[slot].buffer)
+                },
+            });
+
+        vk::AccelerationStructureGeometryKHR::default()
+            .geometry_type(vk::GeometryTypeKHR::INSTANCES)
+            .geometry(vk::AccelerationStructureGeometryDataKHR { instances })
+    }
+
+    fn create_tlas(&mut self) {
+        self.upload_instances(0);
+        self.finish_uploads();
+
+        let geometries = [self.tlas_geometry(0)];
+        let ty = vk::AccelerationStructureTypeKHR::TOP_LEVEL;
+        let cache_key = self.top_as_cache_key.clone().unwrap();
+        let bottom_level_addresses = self.bottom_level_addresses();
+
+        let (top_as, top_as_buffer) = self
+            .load_cached_acceleration_structure("TLAS", ty, &cache_key, &bottom_level_addresses)
+            .unwrap_or_else(|| {
+                let (top_as, top_as_buffer) = self.build_acceleration_structure(
+                    ty,
+                    &geometries,
+                    self.instance_count.unwrap() as u32,
+                );
+                self.finish_acceleration_structure(
+                    "TLAS",
+                    ty,
+                    &cache_key,
+                    &bottom_level_addresses,
+                    top_as,
+                    top_as_buffer,
+                )
+            });
+
+        self.top_as = Some(top_as);
+        self.top_as_buffer = Some(top_as_buffer);
+        self.instance_buffer_index = 0;
+    }
+
+    /// Replaces the transform of a single instance, the TLAS is refitted on the next frame and
+    /// only its entry of the voxels buffer is rewritten. The history is kept, the reprojection
+    /// rejects what the instance uncovered.
+    pub fn set_instance_transform(&mut self, index: usize, transform: vk::TransformMatrixKHR) {
+        self.instances[index].transform = transform;
+        // the lights are sampled at the positions of their voxels
+        self.voxels_infos.as_mut().unwrap()[index].position = transform_translation(&transform);
+        self.instances_dirty = true;
+
+        if !self.moved_voxels.contains(&index) {
+            self.moved_voxels.push(index);
+        }
+    }
+
+    /// Replaces every instance and their voxel infos, the TLAS is rebuilt on the next frame if
+    /// the instance count changed.
+    pub fn set_instances(
+        &mut self,
+        instances: Vec<vk::AccelerationStructureInstanceKHR>,
+        voxels_infos: Vec<VoxelInfos>,
+    ) {
+        self.instances = instances;
+        self.voxels_infos = Some(voxels_infos);
+        self.instances_dirty = true;
+        self.voxels_dirty = true;
+        self.reset_history = true;
+    }
+
+    /// Flushes pending uploads, then refits the TLAS with the new instances, or rebuilds it
+    /// when the instance count changed. Must be recorded in the command buffer of the current
+    /// frame after `begin_frame`, the submission has to wait for the staging timeline. Returns
+    /// whether the instances changed.
+    pub fn record_tlas_update(&mut self, command_buffer: vk::CommandBuffer) -> bool {
+        if self.voxels_dirty {
+            self.upload_voxels_infos();
+            self.voxels_dirty = false;
+            self.moved_voxels.clear();
+        }
+
+        let slot = (self.instance_buffer_index + 1) % INSTANCE_BUFFER_COUNT;
+        let instance_count = self.instances.len();
+
+        if self.instances_dirty {
+            if self.instance_buffers[slot].size
+                < std::mem::size_of_val(self.instances.as_slice()) as vk::DeviceSize
+            {
+                // grow every slot at once, the other slots may still be read by frames in flight
+                let instance_buffers = self.create_instance_buffers(instance_count * 2);
+
+                self.frames[self.frame_index]
+                    .retired_buffers
+                    .extend(std::mem::replace(
+                        &mut self.instance_buffers,
+                        instance_buffers,
+                    ));
+            }
+
+            self.upload_instances(slot);
+        }
+
+        let staging = self.staging.as_mut().unwrap();
+        staging.flush(&self.device);
+        record_acquire_barriers(
+            &self.device,
+            command_buffer,
+            &staging.take_acquire_barriers(),
+        );
+
+        self.record_moved_voxels(command_buffer);
+
+        if !self.instances_dirty {
+            return false;
+        }
+
+        unsafe {
+            // previous traces must be done reading the structure before it is overwritten, and
+            // the build of the previous frame done with the shared scratch buffer
+            let memory_barrier = vk::MemoryBarrier::default()
+                .src_access_mask(
+                    vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR
+                        | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+                )
+                .dst_access_mask(
+                    vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR
+                        | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+                );
+
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR
+                    | vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+        }
+
+        let ty = vk::AccelerationStructureTypeKHR::TOP_LEVEL;
+        let geometries = [self.tlas_geometry(slot)];
+        let rebuild = Some(instance_count) != self.instance_count;
+
+        let build_range_info = vk::AccelerationStructureBuildRangeInfoKHR::default()
+            .primitive_count(instance_count as u32);
 
         let mut build_info = vk::AccelerationStructureBuildGeometryInfoKHR::default()
-            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .flags(self.acceleration_structure_build_flags(ty))
             .geometries(&geometries)
-            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
-            .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL);
+            .ty(ty);
 
         let mut size_info = vk::AccelerationStructureBuildSizesInfoKHR::default();
 
@@ -906,358 +1969,1228 @@ impl<'a> VkController<'a> {
                 .get_acceleration_structure_build_sizes(
                     vk::AccelerationStructureBuildTypeKHR::DEVICE,
                     &build_info,
-                    &[12],
+                    &[instance_count as u32],
                     &mut size_info,
                 )
         };
 
-        let bottom_as_buffer = BufferResource::new(
-            size_info.acceleration_structure_size,
-            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
-                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                | vk::BufferUsageFlags::STORAGE_BUFFER,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            &self.device,
-            self.device_memory_properties,
-        );
+        if rebuild {
+            let (top_as, top_as_buffer) = self
+                .create_acceleration_structure_storage(ty, size_info.acceleration_structure_size);
 
-        let as_create_info = vk::AccelerationStructureCreateInfoKHR::default()
-            .ty(build_info.ty)
-            .size(size_info.acceleration_structure_size)
-            .buffer(bottom_as_buffer.buffer)
-            .offset(0);
+            let retired = (
+                self.top_as.replace(top_as).unwrap(),
+                self.top_as_buffer.replace(top_as_buffer).unwrap(),
+            );
+            self.frames[self.frame_index]
+                .retired_acceleration_structures
+                .push(retired);
 
-        let bottom_as = unsafe {
-            self.acceleration_structure_loader
-                .create_acceleration_structure(&as_create_info, None)
+            build_info = build_info
+                .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+                .dst_acceleration_structure(top_as);
+
+            self.instance_count = Some(instance_count);
+            self.invalidate_descriptor_sets();
+        } else {
+            build_info = build_info
+                .mode(vk::BuildAccelerationStructureModeKHR::UPDATE)
+                .src_acceleration_structure(self.top_as.unwrap())
+                .dst_acceleration_structure(self.top_as.unwrap());
         }
-        .unwrap();
 
-        build_info.dst_acceleration_structure = bottom_as;
+        let scratch_size = if rebuild {
+            size_info.build_scratch_size
+        } else {
+            size_info.update_scratch_size
+        };
+
+        if self
+            .top_as_scratch_buffer
+            .as_ref()
+            .is_none_or(|buffer| buffer.size < scratch_size)
+        {
+            let scratch_buffer = BufferResource::new(
+                scratch_size,
+                vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                &self.device,
+                &self.allocator,
+            );
 
-        let scratch_buffer = BufferResource::new(
-            size_info.build_scratch_size,
-            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            &self.device,
-            self.device_memory_properties,
-        );
+            if let Some(old_scratch_buffer) = self.top_as_scratch_buffer.replace(scratch_buffer) {
+                self.frames[self.frame_index]
+                    .retired_buffers
+                    .push(old_scratch_buffer);
+            }
+        }
 
         build_info.scratch_data = vk::DeviceOrHostAddressKHR {
             device_address: unsafe {
-                get_buffer_device_address(&self.device, scratch_buffer.buffer)
+                get_buffer_device_address(
+                    &self.device,
+                    self.top_as_scratch_buffer.as_ref().unwrap().buffer,
+                )
             },
         };
 
-        let build_command_buffer = {
-            let allocate_info = vk::CommandBufferAllocateInfo::default()
-                .command_buffer_count(1)
-                .command_pool(self.pool)
-                .level(vk::CommandBufferLevel::PRIMARY);
-
-            let command_buffers =
-                unsafe { self.device.allocate_command_buffers(&allocate_info) }.unwrap();
-            command_buffers[0]
-        };
-
         unsafe {
-            self.device
-                .begin_command_buffer(
-                    build_command_buffer,
-                    &vk::CommandBufferBeginInfo::default()
-                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
-                )
-                .unwrap();
-
             self.acceleration_structure_loader
                 .cmd_build_acceleration_structures(
-                    build_command_buffer,
+                    command_buffer,
                     &[build_info],
                     &[&[build_range_info]],
                 );
 
-            self.device
-                .end_command_buffer(build_command_buffer)
-                .unwrap();
+            let memory_barrier = vk::MemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR)
+                .dst_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR);
 
-            self.device
-                .queue_submit(
-                    self.graphics_queue,
-                    &[vk::SubmitInfo::default().command_buffers(&[build_command_buffer])],
-                    vk::Fence::null(),
-                )
-                .expect("queue submit failed.");
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+        }
 
-            self.device.queue_wait_idle(self.graphics_queue).unwrap();
+        self.instance_buffer_index = slot;
+        self.instances_dirty = false;
 
-            self.device
-                .free_command_buffers(self.pool, &[build_command_buffer]);
+        true
+    }
 
-            scratch_buffer.destroy(&self.device);
+    /// Writes the entries of the moved voxels into the voxels buffer, after the traces of the
+    /// previous frames and before the ones of this frame. The buffer and the descriptor sets
+    /// are kept, and so is the light list: it holds the indices of the emissive voxels, not
+    /// their positions, which the shaders read from the voxels buffer.
+    fn record_moved_voxels(&mut self, command_buffer: vk::CommandBuffer) {
+        if self.moved_voxels.is_empty() {
+            return;
         }
 
-        self.bottom_as = Some(bottom_as);
-        self.bottom_as_buffer = Some(bottom_as_buffer);
-    }
+        let voxels = self.voxels_infos.as_ref().unwrap();
+        let voxels_buffer = self.voxels_buffer.as_ref().unwrap().buffer;
+        let stride = std::mem::size_of::<VoxelInfos>();
 
-    fn create_tlas_instances(&mut self) {
-        let accel_handle = {
-            let as_addr_info = vk::AccelerationStructureDeviceAddressInfoKHR::default()
-                .acceleration_structure(self.bottom_as.unwrap());
-            unsafe {
-                self.acceleration_structure_loader
-                    .get_acceleration_structure_device_address(&as_addr_info)
-            }
-        };
+        unsafe {
+            let memory_barrier = vk::MemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::SHADER_READ)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
 
-        // let (instances, voxels) = create_cube_instances(accel_handle, 1024, 0.01);
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
 
-        let model = self.vox_model.models.get(0).unwrap().voxels.clone();
-        let (instances, _voxels) = vox_to_tlas(accel_handle, model);
+            for &index in &self.moved_voxels {
+                self.device.cmd_update_buffer(
+                    command_buffer,
+                    voxels_buffer,
+                    (index * stride) as vk::DeviceSize,
+                    bytes_of(&voxels[index]),
+                );
+            }
 
-        // let instances = [vk::AccelerationStructureInstanceKHR {
-        //     transform: vk::TransformMatrixKHR {
-        //         matrix: [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0],
-        //     },
-        //     instance_custom_index_and_mask: vk::Packed24_8::new(0, 0xff),
-        //     instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(0, 0),
-        //     acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
-        //         device_handle: accel_handle,
-        //     },
-        // }];
+            let memory_barrier = vk::MemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ);
 
-        let model_voxels = &self.vox_model.models.get(0).unwrap().voxels;
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+        }
 
-        let voxels_infos = {
-            let mut list = vec![];
+        self.moved_voxels.clear();
+    }
 
-            for v in model_voxels {
-                list.push(VoxelInfos {
-                    position: glm::vec3(f32::from(v.x), f32::from(v.z), f32::from(v.y)),
-                    palette_index: v.i.into(),
-                });
+    /// Uploads the voxels to a new buffer, the current one may still be read by frames in flight.
+    fn upload_voxels_infos(&mut self) {
+        let voxels = self.voxels_infos.as_ref().unwrap().as_slice();
+
+        let voxels_buffer = BufferResource::new(
+            std::mem::size_of_val(voxels) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &self.device,
+            &self.allocator,
+        );
+
+        self.staging
+            .as_mut()
+            .unwrap()
+            .upload(&self.device, &voxels_buffer, 0, voxels);
+
+        let old_voxels_buffer = self.voxels_buffer.replace(voxels_buffer).unwrap();
+        self.frames[self.frame_index]
+            .retired_buffers
+            .push(old_voxels_buffer);
+
+        // the light list indexes the voxels
+        let old_lights_buffer = self.create_lights_buffer();
+        self.frames[self.frame_index]
+            .retired_buffers
+            .extend(old_lights_buffer);
+
+        self.invalidate_descriptor_sets();
+    }
+
+    /// Collects the emissive voxels into the lights buffer, returns the buffer it replaces.
+    fn create_lights_buffer(&mut self) -> Option<BufferResource> {
+        let materials = get_materials(&self.vox_model);
+        let light_list = build_light_list(self.voxels_infos.as_ref().unwrap(), &materials);
+
+        // a buffer cannot be empty, the shaders skip the lights when there is none
+        let lights = if light_list.lights.is_empty() {
+            vec![EmissiveLight::zeroed()]
+        } else {
+            light_list.lights
+        };
+
+        let lights_buffer = BufferResource::new(
+            std::mem::size_of_val(lights.as_slice()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &self.device,
+            &self.allocator,
+        );
+
+        self.staging
+            .as_mut()
+            .unwrap()
+            .upload(&self.device, &lights_buffer, 0, lights.as_slice());
+
+        self.light_count = if light_list.total_power > 0.0 {
+            lights.len() as u32
+        } else {
+            0
+        };
+        self.light_power = light_list.total_power;
+
+        self.lights_buffer.replace(lights_buffer)
+    }
+
+    /// Frees the resources retired the last time the frame was recorded, its fence must have
+    /// been waited on.
+    fn destroy_retired_resources(&mut self, frame_index: usize) {
+        let frame = &mut self.frames[frame_index];
+
+        for (acceleration_structure, buffer) in frame.retired_acceleration_structures.drain(..) {
+            unsafe {
+                self.acceleration_structure_loader
+                    .destroy_acceleration_structure(acceleration_structure, None);
+                buffer.destroy(&self.device);
             }
+        }
 
-            list
+        for buffer in frame.retired_buffers.drain(..) {
+            unsafe { buffer.destroy(&self.device) };
+        }
+    }
+
+    fn acceleration_structure_build_flags(
+        &self,
+        ty: vk::AccelerationStructureTypeKHR,
+    ) -> vk::BuildAccelerationStructureFlagsKHR {
+        let mut flags = vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE;
+
+        if self.config.acceleration_structures.compaction {
+            flags |= vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION;
+        }
+
+        // instances are refitted every frame they move
+        if ty == vk::AccelerationStructureTypeKHR::TOP_LEVEL {
+            flags |= vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE;
+        }
+
+        flags
+    }
+
+    fn acceleration_structure_address(
+        &self,
+        acceleration_structure: vk::AccelerationStructureKHR,
+    ) -> vk::DeviceAddress {
+        let as_addr_info = vk::AccelerationStructureDeviceAddressInfoKHR::default()
+            .acceleration_structure(acceleration_structure);
+
+        unsafe {
+            self.acceleration_structure_loader
+                .get_acceleration_structure_device_address(&as_addr_info)
+        }
+    }
+
+    /// Records commands into a temporary command buffer, submits it and waits for the queue to
+    /// be idle.
+    fn submit_one_time_commands<F: FnOnce(&Device, vk::CommandBuffer)>(&self, record: F) {
+        let command_buffer = {
+            let allocate_info = vk::CommandBufferAllocateInfo::default()
+                .command_buffer_count(1)
+                .command_pool(self.pool)
+                .level(vk::CommandBufferLevel::PRIMARY);
+
+            let command_buffers =
+                unsafe { self.device.allocate_command_buffers(&allocate_info) }.unwrap();
+            command_buffers[0]
         };
 
-        self.voxels_infos = Some(voxels_infos);
+        unsafe {
+            self.device
+                .begin_command_buffer(
+                    command_buffer,
+                    &vk::CommandBufferBeginInfo::default()
+                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )
+                .unwrap();
+
+            record(&self.device, command_buffer);
+
+            self.device.end_command_buffer(command_buffer).unwrap();
+
+            self.device
+                .queue_submit(
+                    self.graphics_queue,
+                    &[vk::SubmitInfo::default().command_buffers(&[command_buffer])],
+                    vk::Fence::null(),
+                )
+                .expect("queue submit failed.");
+
+            self.device.queue_wait_idle(self.graphics_queue).unwrap();
+
+            self.device
+                .free_command_buffers(self.pool, &[command_buffer]);
+        }
+    }
+
+    fn create_acceleration_structure_storage(
+        &self,
+        ty: vk::AccelerationStructureTypeKHR,
+        size: vk::DeviceSize,
+    ) -> (vk::AccelerationStructureKHR, BufferResource) {
+        let buffer = BufferResource::new(
+            size,
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                | vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &self.device,
+            &self.allocator,
+        );
+
+        let as_create_info = vk::AccelerationStructureCreateInfoKHR::default()
+            .ty(ty)
+            .size(size)
+            .buffer(buffer.buffer)
+            .offset(0);
+
+        let acceleration_structure = unsafe {
+            self.acceleration_structure_loader
+                .create_acceleration_structure(&as_create_info, None)
+        }
+        .unwrap();
+
+        (acceleration_structure, buffer)
+    }
+
+    fn build_acceleration_structure(
+        &self,
+        ty: vk::AccelerationStructureTypeKHR,
+        geometries: &[vk::AccelerationStructureGeometryKHR<'a>],
+        primitive_count: u32,
+    ) -> (vk::AccelerationStructureKHR, BufferResource) {
+        let build_range_info =
+            vk::AccelerationStructureBuildRangeInfoKHR::default().primitive_count(primitive_count);
+
+        let mut build_info = vk::AccelerationStructureBuildGeometryInfoKHR::default()
+            .flags(self.acceleration_structure_build_flags(ty))
+            .geometries(geometries)
+            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+            .ty(ty);
+
+        let mut size_info = vk::AccelerationStructureBuildSizesInfoKHR::default();
+
+        unsafe {
+            self.acceleration_structure_loader
+                .get_acceleration_structure_build_sizes(
+                    vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                    &build_info,
+                    &[primitive_count],
+                    &mut size_info,
+                )
+        };
+
+        let (acceleration_structure, buffer) =
+            self.create_acceleration_structure_storage(ty, size_info.acceleration_structure_size);
+
+        build_info.dst_acceleration_structure = acceleration_structure;
+
+        let scratch_buffer = BufferResource::new(
+            size_info.build_scratch_size,
+            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &self.device,
+            &self.allocator,
+        );
+
+        build_info.scratch_data = vk::DeviceOrHostAddressKHR {
+            device_address: unsafe {
+                get_buffer_device_address(&self.device, scratch_buffer.buffer)
+            },
+        };
 
-        let instance_buffer_size =
-            std::mem::size_of::<vk::AccelerationStructureInstanceKHR>() * instances.len();
+        self.submit_one_time_commands(|device, command_buffer| unsafe {
+            let memory_barrier = vk::MemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR);
 
-        let mut instance_buffer = BufferResource::new(
-            instance_buffer_size as vk::DeviceSize,
-            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+
+            self.acceleration_structure_loader
+                .cmd_build_acceleration_structures(
+                    command_buffer,
+                    &[build_info],
+                    &[&[build_range_info]],
+                );
+        });
+
+        unsafe { scratch_buffer.destroy(&self.device) };
+
+        (acceleration_structure, buffer)
+    }
+
+    fn query_acceleration_structure_property(
+        &self,
+        acceleration_structure: vk::AccelerationStructureKHR,
+        query_type: vk::QueryType,
+    ) -> vk::DeviceSize {
+        let query_pool = unsafe {
+            self.device.create_query_pool(
+                &vk::QueryPoolCreateInfo::default()
+                    .query_type(query_type)
+                    .query_count(1),
+                None,
+            )
+        }
+        .unwrap();
+
+        self.submit_one_time_commands(|device, command_buffer| unsafe {
+            device.cmd_reset_query_pool(command_buffer, query_pool, 0, 1);
+
+            let memory_barrier = vk::MemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR)
+                .dst_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR);
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+
+            self.acceleration_structure_loader
+                .cmd_write_acceleration_structures_properties(
+                    command_buffer,
+                    &[acceleration_structure],
+                    query_type,
+                    query_pool,
+                    0,
+                );
+        });
+
+        let mut result = [0u64];
+
+        unsafe {
+            self.device
+                .get_query_pool_results(
+                    query_pool,
+                    0,
+                    &mut result,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                )
+                .unwrap();
+
+            self.device.destroy_query_pool(query_pool, None);
+        }
+
+        result[0]
+    }
+
+    /// Copies a structure built with `ALLOW_COMPACTION` into a buffer of its compacted size.
+    fn compact_acceleration_structure(
+        &self,
+        label: &str,
+        ty: vk::AccelerationStructureTypeKHR,
+        acceleration_structure: vk::AccelerationStructureKHR,
+        buffer: BufferResource,
+    ) -> (vk::AccelerationStructureKHR, BufferResource) {
+        let compacted_size = self.query_acceleration_structure_property(
+            acceleration_structure,
+            vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR,
+        );
+
+        let (compacted_as, compacted_buffer) =
+            self.create_acceleration_structure_storage(ty, compacted_size);
+
+        self.submit_one_time_commands(|_device, command_buffer| unsafe {
+            self.acceleration_structure_loader
+                .cmd_copy_acceleration_structure(
+                    command_buffer,
+                    &vk::CopyAccelerationStructureInfoKHR::default()
+                        .src(acceleration_structure)
+                        .dst(compacted_as)
+                        .mode(vk::CopyAccelerationStructureModeKHR::COMPACT),
+                );
+        });
+
+        println!(
+            "{} compacted: {} -> {} bytes ({:.1}% saved)",
+            label,
+            buffer.size,
+            compacted_size,
+            (1.0 - compacted_size as f64 / buffer.size as f64) * 100.0
+        );
+
+        unsafe {
+            self.acceleration_structure_loader
+                .destroy_acceleration_structure(acceleration_structure, None);
+            buffer.destroy(&self.device);
+        }
+
+        (compacted_as, compacted_buffer)
+    }
+
+    fn serialize_acceleration_structure(
+        &self,
+        acceleration_structure: vk::AccelerationStructureKHR,
+    ) -> Vec<u8> {
+        let serialized_size = self.query_acceleration_structure_property(
+            acceleration_structure,
+            vk::QueryType::ACCELERATION_STRUCTURE_SERIALIZATION_SIZE_KHR,
+        );
+
+        let serialized_buffer = BufferResource::new(
+            serialized_size,
+            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
             &self.device,
-            self.device_memory_properties,
+            &self.allocator,
         );
 
-        instance_buffer.store(&instances, &self.device);
+        let serialized_address =
+            unsafe { get_buffer_device_address(&self.device, serialized_buffer.buffer) };
 
-        self.instance_count = Some(instances.len());
-        self.instance_buffer = Some(instance_buffer);
+        self.submit_one_time_commands(|device, command_buffer| unsafe {
+            self.acceleration_structure_loader
+                .cmd_copy_acceleration_structure_to_memory(
+                    command_buffer,
+                    &vk::CopyAccelerationStructureToMemoryInfoKHR::default()
+                        .src(acceleration_structure)
+                        .dst(vk::DeviceOrHostAddressKHR {
+                            device_address: serialized_address,
+                        })
+                        .mode(vk::CopyAccelerationStructureModeKHR::SERIALIZE),
+                );
+
+            let memory_barrier = vk::MemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ);
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+        });
+
+        let serialized = serialized_buffer.load_bytes();
+
+        unsafe { serialized_buffer.destroy(&self.device) };
+
+        serialized
     }
 
-    fn create_tlas(&mut self) {
-        let build_range_info = vk::AccelerationStructureBuildRangeInfoKHR::default()
-            .primitive_count(self.instance_count.unwrap() as u32);
+    /// Returns `None` when the serialized data was produced by an incompatible device or driver.
+    fn deserialize_acceleration_structure(
+        &self,
+        ty: vk::AccelerationStructureTypeKHR,
+        serialized: &[u8],
+    ) -> Option<(vk::AccelerationStructureKHR, BufferResource)> {
+        let header = SerializedHeader::parse(serialized)?;
 
-        let build_command_buffer = {
-            let allocate_info = vk::CommandBufferAllocateInfo::default()
-                .command_buffer_count(1)
+        let compatibility = unsafe {
+            self.acceleration_structure_loader
+                .get_device_acceleration_structure_compatibility(
+                    &vk::AccelerationStructureVersionInfoKHR::default()
+                        .version_data(&header.version_data),
+                )
+        };
+
+        if compatibility != vk::AccelerationStructureCompatibilityKHR::COMPATIBLE {
+            return None;
+        }
+
+        let mut serialized_buffer = BufferResource::new(
+            serialized.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &self.device,
+            &self.allocator,
+        );
+
+        serialized_buffer.store(serialized);
+
+        let serialized_address =
+            unsafe { get_buffer_device_address(&self.device, serialized_buffer.buffer) };
+
+        let (acceleration_structure, buffer) =
+            self.create_acceleration_structure_storage(ty, header.deserialized_size);
+
+        self.submit_one_time_commands(|_device, command_buffer| unsafe {
+            self.acceleration_structure_loader
+                .cmd_copy_memory_to_acceleration_structure(
+                    command_buffer,
+                    &vk::CopyMemoryToAccelerationStructureInfoKHR::default()
+                        .src(vk::DeviceOrHostAddressConstKHR {
+                            device_address: serialized_address,
+                        })
+                        .dst(acceleration_structure)
+                        .mode(vk::CopyAccelerationStructureModeKHR::DESERIALIZE),
+                );
+        });
+
+        unsafe { serialized_buffer.destroy(&self.device) };
+
+        Some((acceleration_structure, buffer))
+    }
+
+    /// `bottom_level_addresses` are the structures a top level structure references, see
+    /// `bottom_level_addresses`, empty for bottom level structures.
+    fn load_cached_acceleration_structure(
+        &self,
+        label: &str,
+        ty: vk::AccelerationStructureTypeKHR,
+        cache_key: &str,
+        bottom_level_addresses: &[vk::DeviceAddress],
+    ) -> Option<(vk::AccelerationStructureKHR, BufferResource)> {
+        let mut entry = self.as_cache.as_ref()?.load(cache_key)?;
+
+        if !patch_bottom_level_handles(
+            &mut entry.serialized,
+            &entry.bottom_level_addresses,
+            bottom_level_addresses,
+        ) {
+            println!(
+                "{} cache entry does not reference the current bottom level structures",
+                label
+            );
+            return None;
+        }
+
+        match self.deserialize_acceleration_structure(ty, &entry.serialized) {
+            Some(loaded) => {
+                println!("{} loaded from cache ({} bytes)", label, loaded.1.size);
+                Some(loaded)
+            }
+            None => {
+                println!("{} cache entry is not compatible with this device", label);
+                None
+            }
+        }
+    }
+
+    /// Compacts and caches a freshly built structure depending on the configuration.
+    fn finish_acceleration_structure(
+        &self,
+        label: &str,
+        ty: vk::AccelerationStructureTypeKHR,
+        cache_key: &str,
+        bottom_level_addresses: &[vk::DeviceAddress],
+        acceleration_structure: vk::AccelerationStructureKHR,
+        buffer: BufferResource,
+    ) -> (vk::AccelerationStructureKHR, BufferResource) {
+        let (acceleration_structure, buffer) = if self.config.acceleration_structures.compaction {
+            self.compact_acceleration_structure(label, ty, acceleration_structure, buffer)
+        } else {
+            (acceleration_structure, buffer)
+        };
+
+        if let Some(cache) = self.as_cache.as_ref() {
+            let serialized = self.serialize_acceleration_structure(acceleration_structure);
+
+            if let Err(error) = cache.store(cache_key, bottom_level_addresses, &serialized) {
+                println!("Failed to cache {}: {}", label, error);
+            }
+        }
+
+        (acceleration_structure, buffer)
+    }
+
+    pub fn create_palette_buffer(&mut self) {
+        let palette = get_palette(&self.vox_model);
+        let data = bytes_of(&palette);
+
+        let palette_buffer = BufferResource::new(
+            std::mem::size_of_val(&palette) as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &self.device,
+            &self.allocator,
+        );
+        self.staging
+            .as_mut()
+            .unwrap()
+            .upload(&self.device, &palette_buffer, 0, data);
+
+        self.palette_buffer = Some(palette_buffer);
+    }
+
+    pub fn create_materials_buffer(&mut self) {
+        let materials = get_materials(&self.vox_model);
+
+        let materials_buffer = BufferResource::new(
+            std::mem::size_of_val(&materials) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &self.device,
+            &self.allocator,
+        );
+        self.staging
+            .as_mut()
+            .unwrap()
+            .upload(&self.device, &materials_buffer, 0, &materials);
+
+        self.materials_buffer = Some(materials_buffer);
+    }
+
+    /// Uploads the environment map of the sky config, or a black texel standing in for it when
+    /// none is configured or it fails to load.
+    pub fn create_environment_map(&mut self) -> anyhow::Result<()> {
+        let loaded = self.config.sky.environment_map.as_ref().and_then(|path| {
+            load_hdr(path)
+                .map_err(|error| println!("Failed to load the environment map {}: {}", path, error))
+                .ok()
+        });
+        let is_loaded = loaded.is_some();
+
+        let environment = loaded.unwrap_or(HdrImage {
+            width: 1,
+            height: 1,
+            pixels: vec![[0.0, 0.0, 0.0, 1.0]],
+        });
+        let extent = vk::Extent2D {
+            width: environment.width,
+            height: environment.height,
+        };
+
+        let (image, view, allocation) = self.create_image(
+            ENVIRONMENT_FORMAT,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            extent,
+        )?;
+
+        let mut staging_buffer = BufferResource::new(
+            std::mem::size_of_val(environment.pixels.as_slice()) as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &self.device,
+            &self.allocator,
+        );
+        staging_buffer.store(&environment.pixels);
+
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1);
+
+        self.submit_one_time_commands(|device, command_buffer| unsafe {
+            let to_transfer = vk::ImageMemoryBarrier::default()
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .image(image)
+                .subresource_range(subresource_range);
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer],
+            );
+
+            let region = vk::BufferImageCopy::default()
+                .image_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .layer_count(1),
+                )
+                .image_extent(vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                });
+
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_buffer.buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+
+            let to_shader_read = vk::ImageMemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image(image)
+                .subresource_range(subresource_range);
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_shader_read],
+            );
+        });
+
+        // the commands completed
+        unsafe { staging_buffer.destroy(&self.device) };
+
+        // wraps around horizontally, the poles clamp
+        let sampler_create_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::REPEAT)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+
+        let sampler = unsafe { self.device.create_sampler(&sampler_create_info, None) }?;
+
+        if is_loaded {
+            println!(
+                "Loaded a {}x{} environment map",
+                extent.width, extent.height
+            );
+        }
+
+        self.environment_map = Some(EnvironmentMap {
+            image,
+            view,
+            allocation: Some(allocation),
+            sampler,
+            loaded: is_loaded,
+        });
+
+        Ok(())
+    }
+
+    fn create_data_structures(&mut self) {
+        self.create_blas_geometry();
+        self.finish_uploads();
+        self.create_blas();
+        self.create_tlas_instances();
+        self.create_tlas();
+        self.create_palette_buffer();
+        self.create_materials_buffer();
+        self.create_environment_map().unwrap();
+
+        let voxels = self.voxels_infos.as_ref().unwrap().as_slice();
+
+        let voxels_buffer = BufferResource::new(
+            std::mem::size_of_val(voxels) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &self.device,
+            &self.allocator,
+        );
+        self.staging
+            .as_mut()
+            .unwrap()
+            .upload(&self.device, &voxels_buffer, 0, voxels);
+
+        self.voxels_buffer = Some(voxels_buffer);
+        self.create_lights_buffer();
+        println!("Collected {} emissive voxels", self.light_count);
+
+        self.finish_uploads();
+    }
+
+    fn create_frames(&mut self) -> anyhow::Result<()> {
+        let fence_create_info =
+            vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
+
+        self.create_exposure_buffers();
+
+        for _ in 0..FRAMES_IN_FLIGHT {
+            let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
+                .command_buffer_count(2)
                 .command_pool(self.pool)
                 .level(vk::CommandBufferLevel::PRIMARY);
 
-            let command_buffers =
-                unsafe { self.device.allocate_command_buffers(&allocate_info) }.unwrap();
-            command_buffers[0]
-        };
+            let command_buffers = unsafe {
+                self.device
+                    .allocate_command_buffers(&command_buffer_allocate_info)
+            }?;
+
+            let image_available_semaphore = unsafe {
+                self.device
+                    .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+            }?;
+
+            let in_flight_fence = unsafe { self.device.create_fence(&fence_create_info, None) }?;
+
+            let timestamp_query_pool = unsafe {
+                self.device.create_query_pool(
+                    &vk::QueryPoolCreateInfo::default()
+                        .query_type(vk::QueryType::TIMESTAMP)
+                        .query_count(2),
+                    None,
+                )
+            }?;
+            // reset queries read as not available until the frame writes them
+            self.submit_one_time_commands(|device, command_buffer| unsafe {
+                device.cmd_reset_query_pool(command_buffer, timestamp_query_pool, 0, 2);
+            });
+
+            let data = &[GlobalUniforms::zeroed()];
+
+            let uniforms_buffer = BufferResource::new(
+                std::mem::size_of_val(data) as u64,
+                vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                &self.device,
+                &self.allocator,
+            );
+            self.staging
+                .as_mut()
+                .unwrap()
+                .upload(&self.device, &uniforms_buffer, 0, data);
+
+            let images = self.create_frame_images()?;
+            let filter_descriptor_sets = self
+                .ao_filter
+                .as_ref()
+                .unwrap()
+                .allocate_descriptor_sets(&self.device)?;
+            let svgf_descriptor_sets = self
+                .svgf
+                .as_ref()
+                .unwrap()
+                .allocate_descriptor_sets(&self.device)?;
+            let taa_descriptor_set = self
+                .taa
+                .as_ref()
+                .unwrap()
+                .allocate_descriptor_set(&self.device)?;
+            let tonemap_descriptor_sets = self
+                .tonemapper
+                .as_ref()
+                .unwrap()
+                .allocate_descriptor_sets(&self.device)?;
+            let upscale_descriptor_set = self
+                .upscaler
+                .as_ref()
+                .unwrap()
+                .allocate_descriptor_set(&self.device)?;
+
+            self.frames.push(FrameResources {
+                command_buffer: command_buffers[0],
+                present_command_buffer: command_buffers[1],
+                image_available_semaphore,
+                in_flight_fence,
+                timestamp_query_pool,
+                uniforms_buffer,
+                images,
+                rt_descriptor_set: vk::DescriptorSet::null(),
+                uniforms_descriptor_set: vk::DescriptorSet::null(),
+                filter_descriptor_sets,
+                svgf_descriptor_sets,
+                taa_descriptor_set,
+                tonemap_descriptor_sets,
+                upscale_descriptor_set,
+                descriptors_dirty: false,
+                retired_acceleration_structures: Vec::new(),
+                retired_buffers: Vec::new(),
+            });
+        }
+
+        self.finish_uploads();
+
+        Ok(())
+    }
+
+    /// Waits until the GPU is done with the current frame resources, then frees what the frame
+    /// retired and brings its descriptor sets up to date. Returns the frame index.
+    pub fn begin_frame(&mut self) -> usize {
+        let frame_index = self.frame_index;
+        let in_flight_fence = self.frames[frame_index].in_flight_fence;
+
+        unsafe {
+            self.device
+                .wait_for_fences(&[in_flight_fence], true, u64::MAX)
+                .expect("Wait for fence failed.");
+
+            self.device
+                .reset_fences(&[in_flight_fence])
+                .expect("Reset fences failed.");
+        }
+
+        self.destroy_retired_resources(frame_index);
+        self.read_frame_timestamps(frame_index);
+
+        if self.frames[frame_index].descriptors_dirty {
+            self.write_frame_descriptor_sets(frame_index);
+        }
+
+        frame_index
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frame_index = (self.frame_index + 1) % FRAMES_IN_FLIGHT;
+    }
+
+    /// Signals the fence of the current frame without presenting it, its commands must have been
+    /// submitted.
+    pub fn skip_present(&self) {
+        unsafe {
+            self.device
+                .queue_submit(
+                    self.present_queue,
+                    &[],
+                    self.frames[self.frame_index].in_flight_fence,
+                )
+                .expect("queue submit failed.");
+        }
+    }
+
+    /// Copies the output of the current frame to the host instead of presenting it and waits for
+    /// the copy, its commands must have been submitted. Returns the RGB pixels, rows from top to
+    /// bottom.
+    pub fn read_back_output(&mut self) -> anyhow::Result<Vec<[u8; 3]>> {
+        let extent = self.surface_resolution;
+        // every supported output format has 4 bytes per texel
+        let size = (extent.width * extent.height * 4) as vk::DeviceSize;
+
+        if self
+            .readback_buffer
+            .as_ref()
+            .is_none_or(|buffer| buffer.size < size)
+        {
+            let readback_buffer = BufferResource::new(
+                size,
+                vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                &self.device,
+                &self.allocator,
+            );
+
+            // no frame reads it back asynchronously
+            if let Some(old_readback_buffer) = self.readback_buffer.replace(readback_buffer) {
+                unsafe { old_readback_buffer.destroy(&self.device) };
+            }
+        }
+
+        let frame = &self.frames[self.frame_index];
+        let command_buffer = frame.present_command_buffer;
+        let readback_buffer = self.readback_buffer.as_ref().unwrap();
 
         unsafe {
             self.device
-                .begin_command_buffer(
-                    build_command_buffer,
-                    &vk::CommandBufferBeginInfo::default()
-                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                .reset_command_buffer(
+                    command_buffer,
+                    vk::CommandBufferResetFlags::RELEASE_RESOURCES,
                 )
-                .unwrap();
+                .expect("Reset command buffer failed.");
+
+            let command_buffer_begin_info = vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+            self.device
+                .begin_command_buffer(command_buffer, &command_buffer_begin_info)
+                .expect("Begin commandbuffer");
+
+            let mut graph = RenderGraph::new();
+
+            // made visible to the copy at the end of the frame commands
+            let output_image = graph.import_image(
+                frame.images.output.image,
+                ResourceState {
+                    stage: vk::PipelineStageFlags::TRANSFER,
+                    access: vk::AccessFlags::empty(),
+                    layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                },
+                None,
+            );
+            let buffer = graph.import_buffer(
+                readback_buffer.buffer,
+                ResourceState::idle(vk::ImageLayout::UNDEFINED),
+            );
+
+            graph.add_pass(
+                Pass::new("read back output")
+                    .image(output_image, Access::TransferRead)
+                    .buffer(buffer, Access::TransferWrite)
+                    .record(|device, command_buffer, resources| {
+                        let copy_region = vk::BufferImageCopy::default()
+                            .image_subresource(
+                                vk::ImageSubresourceLayers::default()
+                                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                    .layer_count(1),
+                            )
+                            .image_extent(
+                                vk::Extent3D::default()
+                                    .width(extent.width)
+                                    .height(extent.height)
+                                    .depth(1),
+                            );
+
+                        device.cmd_copy_image_to_buffer(
+                            command_buffer,
+                            resources.image(output_image),
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            resources.buffer(buffer),
+                            &[copy_region],
+                        );
+                    }),
+            );
+
+            graph.execute(&self.device, command_buffer);
 
+            // the fence alone does not make the copy visible to the host
             let memory_barrier = vk::MemoryBarrier::default()
                 .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                .dst_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR);
+                .dst_access_mask(vk::AccessFlags::HOST_READ);
 
             self.device.cmd_pipeline_barrier(
-                build_command_buffer,
+                command_buffer,
                 vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::PipelineStageFlags::HOST,
                 vk::DependencyFlags::empty(),
                 &[memory_barrier],
                 &[],
                 &[],
             );
-        }
 
-        let instances = vk::AccelerationStructureGeometryInstancesDataKHR::default()
-            .array_of_pointers(false)
-            .data(vk::DeviceOrHostAddressConstKHR {
-                device_address: unsafe {
-                    get_buffer_device_address(
-                        &self.device,
-                        self.instance_buffer.as_ref().unwrap().buffer,
-                    )
-                },
-            });
+            self.device
+                .end_command_buffer(command_buffer)
+                .expect("End commandbuffer");
 
-        let geometry = vk::AccelerationStructureGeometryKHR::default()
-            .geometry_type(vk::GeometryTypeKHR::INSTANCES)
-            .geometry(vk::AccelerationStructureGeometryDataKHR { instances });
+            let command_buffers = [command_buffer];
+            let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
 
-        let geometries = [geometry];
+            self.device
+                .queue_submit(self.present_queue, &[submit_info], frame.in_flight_fence)
+                .expect("queue submit failed.");
 
-        let mut build_info = vk::AccelerationStructureBuildGeometryInfoKHR::default()
-            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
-            .geometries(&geometries)
-            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
-            .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL);
+            // left signaled for `begin_frame`
+            self.device
+                .wait_for_fences(&[frame.in_flight_fence], true, u64::MAX)
+                .expect("Wait for fence failed.");
 
-        let mut size_info = vk::AccelerationStructureBuildSizesInfoKHR::default();
+            let data = std::slice::from_raw_parts(readback_buffer.mapped_ptr(), size as usize);
 
-        unsafe {
-            self.acceleration_structure_loader
-                .get_acceleration_structure_build_sizes(
-                    vk::AccelerationStructureBuildTypeKHR::DEVICE,
-                    &build_info,
-                    &[build_range_info.primitive_count],
-                    &mut size_info,
-                )
+            rgb_pixels(data, self.surface_format.format)
         }
+    }
 
-        let top_as_buffer = BufferResource::new(
-            size_info.acceleration_structure_size,
-            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
-                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                | vk::BufferUsageFlags::STORAGE_BUFFER,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            &self.device,
-            self.device_memory_properties,
-        );
-
-        let as_create_info = vk::AccelerationStructureCreateInfoKHR::default()
-            .ty(build_info.ty)
-            .size(size_info.acceleration_structure_size)
-            .buffer(top_as_buffer.buffer)
-            .offset(0);
-
-        let top_as = unsafe {
-            self.acceleration_structure_loader
-                .create_acceleration_structure(&as_create_info, None)
+    /// Writes the timestamp of the start of the frame commands, after resetting those of the
+    /// previous use of the frame.
+    pub fn record_frame_start_timestamp(&self, command_buffer: vk::CommandBuffer) {
+        if self.timestamp_period.is_none() {
+            return;
         }
-        .unwrap();
 
-        build_info.dst_acceleration_structure = top_as;
-
-        let scratch_buffer = BufferResource::new(
-            size_info.build_scratch_size,
-            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            &self.device,
-            self.device_memory_properties,
-        );
-
-        build_info.scratch_data = vk::DeviceOrHostAddressKHR {
-            device_address: unsafe {
-                get_buffer_device_address(&self.device, scratch_buffer.buffer)
-            },
-        };
+        let query_pool = self.frames[self.frame_index].timestamp_query_pool;
 
         unsafe {
-            self.acceleration_structure_loader
-                .cmd_build_acceleration_structures(
-                    build_command_buffer,
-                    &[build_info],
-                    &[&[build_range_info]],
-                );
-            self.device
-                .end_command_buffer(build_command_buffer)
-                .unwrap();
-            self.device
-                .queue_submit(
-                    self.graphics_queue,
-                    &[vk::SubmitInfo::default().command_buffers(&[build_command_buffer])],
-                    vk::Fence::null(),
-                )
-                .expect("queue submit failed.");
-
-            self.device.queue_wait_idle(self.graphics_queue).unwrap();
             self.device
-                .free_command_buffers(self.pool, &[build_command_buffer]);
-            scratch_buffer.destroy(&self.device);
+                .cmd_reset_query_pool(command_buffer, query_pool, 0, 2);
+            self.device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                query_pool,
+                0,
+            );
         }
-
-        self.top_as = Some(top_as);
-        self.top_as_buffer = Some(top_as_buffer);
     }
 
-    pub fn create_palette_buffer(&mut self) {
-        let palette = get_palette(&self.vox_model);
-        let data = bytes_of(&palette);
-
-        let mut palette_buffer = BufferResource::new(
-            std::mem::size_of_val(&palette) as u64,
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            &self.device,
-            self.device_memory_properties,
-        );
-        palette_buffer.store(&data, &self.device);
-
-        self.palette_buffer = Some(palette_buffer);
-    }
-
-    pub fn create_uniforms_buffer(&mut self) {
-        let global_uniforms = GlobalUniforms::zeroed();
-
-        let data = &[global_uniforms];
-
-        let mut uniforms_buffer = BufferResource::new(
-            std::mem::size_of_val(data) as u64,
-            vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            &self.device,
-            self.device_memory_properties,
-        );
-        uniforms_buffer.store(data, &self.device);
+    pub fn record_frame_end_timestamp(&self, command_buffer: vk::CommandBuffer) {
+        if self.timestamp_period.is_none() {
+            return;
+        }
 
-        self.uniforms_buffer = Some(uniforms_buffer);
+        unsafe {
+            self.device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.frames[self.frame_index].timestamp_query_pool,
+                1,
+            );
+        }
     }
 
-    fn create_data_structures(&mut self) {
-        self.create_blas_geometry();
-        self.create_blas();
-        self.create_tlas_instances();
-        self.create_tlas();
-        self.create_palette_buffer();
-
-        let voxels = self.voxels_infos.as_ref().unwrap().as_slice();
+    /// Updates `gpu_frame_time` from the timestamps of the completed frame, if it wrote them.
+    fn read_frame_timestamps(&mut self, frame_index: usize) {
+        let Some(timestamp_period) = self.timestamp_period else {
+            return;
+        };
 
-        let mut voxels_buffer = BufferResource::new(
-            std::mem::size_of_val(voxels) as u64,
-            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            &self.device,
-            self.device_memory_properties,
-        );
-        voxels_buffer.store(voxels, &self.device);
+        let mut timestamps = [0u64; 2];
 
-        self.voxels_buffer = Some(voxels_buffer);
+        let result = unsafe {
+            self.device.get_query_pool_results(
+                self.frames[frame_index].timestamp_query_pool,
+                0,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
 
-        self.create_uniforms_buffer();
+        if result.is_ok() {
+            let ticks = timestamps[1].wrapping_sub(timestamps[0]);
+            self.gpu_frame_time = Some(ticks as f32 * timestamp_period / 1_000_000.0);
+        }
     }
 
-    fn create_rt_sbt(&mut self) -> anyhow::Result<()> {
+    fn ray_tracing_pipeline_properties(
+        &self,
+    ) -> vk::PhysicalDeviceRayTracingPipelinePropertiesKHR<'static> {
         let mut rt_pipeline_properties =
             vk::PhysicalDeviceRayTracingPipelinePropertiesKHR::default();
 
@@ -1273,6 +3206,12 @@ impl<'a> VkController<'a> {
             }
         }
 
+        rt_pipeline_properties
+    }
+
+    fn create_rt_sbt(&mut self) -> anyhow::Result<()> {
+        let rt_pipeline_properties = self.ray_tracing_pipeline_properties();
+
         let handle_size_aligned = aligned_size(
             rt_pipeline_properties.shader_group_handle_size,
             rt_pipeline_properties.shader_group_base_alignment,
@@ -1312,17 +3251,17 @@ impl<'a> VkController<'a> {
                     | vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR,
                 vk::MemoryPropertyFlags::HOST_VISIBLE,
                 &self.device,
-                self.device_memory_properties,
+                &self.allocator,
             );
 
-            shader_binding_table_buffer.store(&table_data, &self.device);
+            shader_binding_table_buffer.store(&table_data);
 
             shader_binding_table_buffer
         };
 
-        // |[ raygen shader ]|[ hit shader  ]|[ao hit shader]|[ miss shader ]|
-        // |                 |               |               |               |
-        // | 0               | 1             | 2             | 3             |
+        // |[ raygen ]|[ hit ]|[ao hit]|[shadow hit]|[ translucent hit ]|[translucent ao hit]|[translucent shadow hit]|[ miss ]|[ao miss]|[shadow miss]|[pt raygen]|[restir spatial raygen]|
+        // |           |       |        |            |                   |                   |                       |        |         |             |           |                       |
+        // | 0         | 1     | 2      | 3          | 4                 | 5                  | 6                     | 7      | 8       | 9           | 10        | 11                    |
 
         let sbt_address =
             unsafe { get_buffer_device_address(&self.device, shader_binding_table_buffer.buffer) };
@@ -1333,22 +3272,34 @@ impl<'a> VkController<'a> {
             .stride(handle_size_aligned);
 
         let sbt_miss_region = vk::StridedDeviceAddressRegionKHR::default()
-            .device_address(sbt_address + 3 * handle_size_aligned)
-            .size(handle_size_aligned)
+            .device_address(sbt_address + 7 * handle_size_aligned)
+            .size(handle_size_aligned * 3)
             .stride(handle_size_aligned);
 
         let sbt_hit_region = vk::StridedDeviceAddressRegionKHR::default()
             .device_address(sbt_address + handle_size_aligned)
-            .size(handle_size_aligned * 2)
+            .size(handle_size_aligned * 6)
             .stride(handle_size_aligned);
 
         let sbt_call_region = vk::StridedDeviceAddressRegionKHR::default();
 
+        let sbt_path_tracing_raygen_region = vk::StridedDeviceAddressRegionKHR::default()
+            .device_address(sbt_address + 10 * handle_size_aligned)
+            .size(handle_size_aligned)
+            .stride(handle_size_aligned);
+
+        let sbt_restir_spatial_raygen_region = vk::StridedDeviceAddressRegionKHR::default()
+            .device_address(sbt_address + 11 * handle_size_aligned)
+            .size(handle_size_aligned)
+            .stride(handle_size_aligned);
+
         self.shader_binding_table_buffer = Some(shader_binding_table_buffer);
         self.sbt_raygen_region = Some(sbt_raygen_region);
         self.sbt_miss_region = Some(sbt_miss_region);
         self.sbt_hit_region = Some(sbt_hit_region);
         self.sbt_call_region = Some(sbt_call_region);
+        self.sbt_path_tracing_raygen_region = Some(sbt_path_tracing_raygen_region);
+        self.sbt_restir_spatial_raygen_region = Some(sbt_restir_spatial_raygen_region);
 
         Ok(())
     }
@@ -1424,25 +3375,18 @@ impl<'a> VkController<'a> {
         let depth_image = unsafe { self.device.create_image(&depth_image_create_info, None) }?;
         let depth_image_memory_req =
             unsafe { self.device.get_image_memory_requirements(depth_image) };
-        let depth_image_memory_index = find_memorytype_index(
-            &depth_image_memory_req,
-            &self.device_memory_properties,
+        let depth_image_allocation = self.allocator.allocate(
+            depth_image_memory_req,
             vk::MemoryPropertyFlags::DEVICE_LOCAL,
-        )
-        .expect("Unable to find suitable memory index for depth image.");
-
-        let depth_image_allocate_info = vk::MemoryAllocateInfo::default()
-            .allocation_size(depth_image_memory_req.size)
-            .memory_type_index(depth_image_memory_index);
-
-        let depth_image_memory = unsafe {
-            self.device
-                .allocate_memory(&depth_image_allocate_info, None)
-        }?;
+            AllocationKind::Image,
+        )?;
 
         unsafe {
-            self.device
-                .bind_image_memory(depth_image, depth_image_memory, 0)
+            self.device.bind_image_memory(
+                depth_image,
+                depth_image_allocation.memory,
+                depth_image_allocation.offset,
+            )
         }
         .expect("Unable to bind depth image memory");
 
@@ -1498,12 +3442,20 @@ impl<'a> VkController<'a> {
         let depth_image_view =
             unsafe { self.device.create_image_view(&depth_image_view_info, None) }?;
 
+        self.rendering_complete_semaphores = present_images
+            .iter()
+            .map(|_| unsafe {
+                self.device
+                    .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+            })
+            .collect::<Result<_, _>>()?;
+
         self.present_images = Some(present_images);
         self.present_image_views = Some(present_image_views);
 
         self.depth_image = Some(depth_image);
         self.depth_image_view = Some(depth_image_view);
-        self.depth_image_memory = Some(depth_image_memory);
+        self.depth_image_allocation = Some(depth_image_allocation);
 
         Ok(())
     }
@@ -1588,153 +3540,208 @@ impl<'a> VkController<'a> {
         Ok(())
     }
 
-    fn update_rt_image_descriptor_set(&mut self) {
-        let image_info = [vk::DescriptorImageInfo::default()
-            .image_layout(vk::ImageLayout::GENERAL)
-            .image_view(self.rt_image_view)];
-
-        let image_write = vk::WriteDescriptorSet::default()
-            .dst_set(self.rt_descriptor_set.unwrap())
-            .dst_binding(1)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-            .image_info(&image_info);
-
-        unsafe {
-            self.device.update_descriptor_sets(&[image_write], &[]);
-        }
+    /// Creates an image of the frame, sized like the render resolution or like the swapchain.
+    /// Its layout is undefined, frames start by overwriting it.
+    fn create_frame_image(
+        &self,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        extent: vk::Extent2D,
+    ) -> anyhow::Result<FrameImage> {
+        let (image, view, allocation) = self.create_image(format, usage, extent)?;
+
+        Ok(FrameImage {
+            image,
+            view,
+            allocation: Some(allocation),
+        })
     }
 
-    pub fn create_rt_image(&mut self) -> anyhow::Result<()> {
-        let rt_image = {
-            let image_create_info = vk::ImageCreateInfo::default()
-                .image_type(vk::ImageType::TYPE_2D)
-                .format(self.surface_format.format)
-                .extent(
-                    vk::Extent3D::default()
-                        .width(self.surface_resolution.width)
-                        .height(self.surface_resolution.height)
-                        .depth(1),
-                )
-                .mip_levels(1)
-                .array_layers(1)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .tiling(vk::ImageTiling::OPTIMAL)
-                .usage(
-                    vk::ImageUsageFlags::COLOR_ATTACHMENT
-                        | vk::ImageUsageFlags::STORAGE
-                        | vk::ImageUsageFlags::TRANSFER_SRC,
-                );
-
-            unsafe { self.device.create_image(&image_create_info, None) }?
-        };
+    /// Creates a single level device local 2D image and a view of it.
+    fn create_image(
+        &self,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        extent: vk::Extent2D,
+    ) -> anyhow::Result<(vk::Image, vk::ImageView, Allocation)> {
+        let image_create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(
+                vk::Extent3D::default()
+                    .width(extent.width)
+                    .height(extent.height)
+                    .depth(1),
+            )
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage);
 
-        let rt_image_memory = {
-            let mem_reqs = unsafe { self.device.get_image_memory_requirements(rt_image) };
-            let mem_alloc_info = vk::MemoryAllocateInfo::default()
-                .allocation_size(mem_reqs.size)
-                .memory_type_index(get_memory_type_index(
-                    self.device_memory_properties,
-                    mem_reqs.memory_type_bits,
-                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
-                ));
+        let image = unsafe { self.device.create_image(&image_create_info, None) }?;
 
-            unsafe { self.device.allocate_memory(&mem_alloc_info, None) }?
-        };
+        let allocation = {
+            let mem_reqs = unsafe { self.device.get_image_memory_requirements(image) };
 
-        unsafe { self.device.bind_image_memory(rt_image, rt_image_memory, 0) }?;
-
-        let rt_image_view = {
-            let image_view_create_info = vk::ImageViewCreateInfo::default()
-                .view_type(vk::ImageViewType::TYPE_2D)
-                .format(self.surface_format.format)
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                })
-                .image(rt_image);
-
-            unsafe { self.device.create_image_view(&image_view_create_info, None) }?
+            self.allocator.allocate(
+                mem_reqs,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                AllocationKind::Image,
+            )?
         };
 
-        // rt image to general layout
-        {
-            let command_buffer = {
-                let allocate_info = vk::CommandBufferAllocateInfo::default()
-                    .command_buffer_count(1)
-                    .command_pool(self.pool)
-                    .level(vk::CommandBufferLevel::PRIMARY);
-
-                let command_buffers =
-                    unsafe { self.device.allocate_command_buffers(&allocate_info) }.unwrap();
-                command_buffers[0]
-            };
-
-            unsafe {
-                self.device.begin_command_buffer(
-                    command_buffer,
-                    &vk::CommandBufferBeginInfo::default()
-                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
-                )
-            }
-            .unwrap();
-
-            let image_barrier = vk::ImageMemoryBarrier::default()
-                .src_access_mask(vk::AccessFlags::empty())
-                .dst_access_mask(vk::AccessFlags::empty())
-                .old_layout(vk::ImageLayout::UNDEFINED)
-                .new_layout(vk::ImageLayout::GENERAL)
-                .image(rt_image)
-                .subresource_range(
-                    vk::ImageSubresourceRange::default()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .base_mip_level(0)
-                        .level_count(1)
-                        .base_array_layer(0)
-                        .layer_count(1),
-                );
-
-            unsafe {
-                self.device.cmd_pipeline_barrier(
-                    command_buffer,
-                    vk::PipelineStageFlags::ALL_COMMANDS,
-                    vk::PipelineStageFlags::ALL_COMMANDS,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[image_barrier],
-                );
+        unsafe {
+            self.device
+                .bind_image_memory(image, allocation.memory, allocation.offset)
+        }?;
 
-                self.device.end_command_buffer(command_buffer).unwrap();
-            }
+        let image_view_create_info = vk::ImageViewCreateInfo::default()
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image(image);
 
-            let command_buffers = [command_buffer];
+        let view = unsafe { self.device.create_image_view(&image_view_create_info, None) }?;
 
-            let submit_infos = [vk::SubmitInfo::default().command_buffers(&command_buffers)];
+        Ok((image, view, allocation))
+    }
 
-            unsafe {
-                self.device
-                    .queue_submit(self.graphics_queue, &submit_infos, vk::Fence::null())
-                    .expect("Failed to execute queue submit.");
+    /// The histogram starts empty and the exposure at 1, adapted from middle grey.
+    fn create_exposure_buffers(&mut self) {
+        let bins = [0u32; HISTOGRAM_BINS];
+        let exposure = [ExposureState {
+            average_luminance: 0.18,
+            exposure: 1.0,
+        }];
 
-                self.device.queue_wait_idle(self.graphics_queue).unwrap();
-                self.device
-                    .free_command_buffers(self.pool, &[command_buffer]);
-            }
-        }
+        let histogram_buffer = BufferResource::new(
+            std::mem::size_of_val(&bins) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &self.device,
+            &self.allocator,
+        );
+        let exposure_buffer = BufferResource::new(
+            std::mem::size_of_val(&exposure) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &self.device,
+            &self.allocator,
+        );
 
-        self.rt_image = rt_image;
-        self.rt_image_view = rt_image_view;
-        self.rt_image_memory = rt_image_memory;
+        let staging = self.staging.as_mut().unwrap();
+        staging.upload(&self.device, &histogram_buffer, 0, &bins);
+        staging.upload(&self.device, &exposure_buffer, 0, &exposure);
 
-        if self.rt_descriptor_set.is_some() {
-            self.update_rt_image_descriptor_set();
-        }
+        self.histogram_buffer = Some(histogram_buffer);
+        self.exposure_buffer = Some(exposure_buffer);
+    }
 
-        Ok(())
+    fn create_frame_images(&self) -> anyhow::Result<FrameImages> {
+        let traced = vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED;
+        let rendered = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED;
+        let render = self.render_resolution;
+        let display = self.surface_resolution;
+
+        let output = self.create_frame_image(
+            self.surface_format.format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            display,
+        )?;
+        // rendered by the AO filter, the path traced radiance is blitted to it
+        let hdr = self.create_frame_image(
+            HDR_FORMAT,
+            rendered | vk::ImageUsageFlags::TRANSFER_DST,
+            render,
+        )?;
+        let tonemapped = self.create_frame_image(TONEMAPPED_FORMAT, rendered, render)?;
+        let ao = self.create_frame_image(AO_FORMAT, traced, render)?;
+        let depth_normal = self.create_frame_image(DEPTH_NORMAL_FORMAT, traced, render)?;
+        let albedo = self.create_frame_image(ALBEDO_FORMAT, traced, render)?;
+        let filtered_ao = self.create_frame_image(FILTERED_AO_FORMAT, rendered, render)?;
+        let reflection = self.create_frame_image(REFLECTION_FORMAT, traced, render)?;
+        let fog = self.create_frame_image(FOG_FORMAT, traced, render)?;
+        // sampled by the denoiser
+        let accumulation = self.create_frame_image(
+            ACCUMULATION_FORMAT,
+            traced | vk::ImageUsageFlags::TRANSFER_SRC,
+            render,
+        )?;
+        let reservoir =
+            self.create_frame_image(RESERVOIR_FORMAT, vk::ImageUsageFlags::STORAGE, render)?;
+        let spatial_reservoir =
+            self.create_frame_image(RESERVOIR_FORMAT, vk::ImageUsageFlags::STORAGE, render)?;
+        let svgf_history =
+            self.create_frame_image(HISTORY_FORMAT, vk::ImageUsageFlags::STORAGE, render)?;
+        let svgf_moments =
+            self.create_frame_image(MOMENTS_FORMAT, vk::ImageUsageFlags::STORAGE, render)?;
+        let svgf_filtered = [
+            self.create_frame_image(FILTERED_FORMAT, vk::ImageUsageFlags::STORAGE, render)?,
+            self.create_frame_image(FILTERED_FORMAT, vk::ImageUsageFlags::STORAGE, render)?,
+        ];
+        // sampled by the AO filter, blitted to the output in path tracing
+        let denoised = self.create_frame_image(
+            DENOISED_FORMAT,
+            traced | vk::ImageUsageFlags::TRANSFER_SRC,
+            render,
+        )?;
+        let motion = self.create_frame_image(MOTION_FORMAT, traced, render)?;
+        let taa_resolved = self.create_frame_image(RESOLVED_FORMAT, traced, render)?;
+        // blitted to the HDR image in place of the frame
+        let debug = self.create_frame_image(
+            DEBUG_FORMAT,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC,
+            render,
+        )?;
+
+        let filter_framebuffers = self.ao_filter.as_ref().unwrap().create_framebuffers(
+            &self.device,
+            render,
+            filtered_ao.view,
+            hdr.view,
+        )?;
+        let tonemap_framebuffer = self.tonemapper.as_ref().unwrap().create_framebuffer(
+            &self.device,
+            render,
+            tonemapped.view,
+        )?;
+        let upscale_framebuffer = self.upscaler.as_ref().unwrap().create_framebuffer(
+            &self.device,
+            display,
+            output.view,
+        )?;
+
+        Ok(FrameImages {
+            output,
+            hdr,
+            tonemapped,
+            ao,
+            depth_normal,
+            albedo,
+            filtered_ao,
+            reflection,
+            fog,
+            accumulation,
+            reservoir,
+            spatial_reservoir,
+            svgf_history,
+            svgf_moments,
+            svgf_filtered,
+            denoised,
+            motion,
+            taa_resolved,
+            debug,
+            filter_framebuffers,
+            tonemap_framebuffer,
+            upscale_framebuffer,
+        })
     }
 
     pub fn recreate_swapchain(&mut self) -> anyhow::Result<()> {
@@ -1794,16 +3801,52 @@ impl<'a> VkController<'a> {
 
         self.create_image_views()?;
         // self.create_framebuffers()?;
-        self.create_rt_image()?;
+
+        self.render_resolution = scaled_resolution(self.surface_resolution, self.render_scale);
+        self.recreate_frame_images()
+    }
+
+    /// Renders at `scale` times the surface resolution from the next frame on. The frame images
+    /// are only recreated when the resolution changes.
+    pub fn set_render_scale(&mut self, scale: f32) -> anyhow::Result<()> {
+        self.render_scale = scale;
+
+        let render_resolution = scaled_resolution(self.surface_resolution, scale);
+
+        if render_resolution == self.render_resolution {
+            return Ok(());
+        }
+
+        unsafe { self.device.device_wait_idle() }?;
+
+        self.render_resolution = render_resolution;
+        self.recreate_frame_images()
+    }
+
+    /// The device must be idle.
+    fn recreate_frame_images(&mut self) -> anyhow::Result<()> {
+        for frame_index in 0..self.frames.len() {
+            let images = self.create_frame_images()?;
+
+            let frame = &mut self.frames[frame_index];
+            frame.images.destroy(&self.device);
+            frame.images = images;
+        }
+
+        // the sets also reference the images of the previous frame
+        for frame_index in 0..self.frames.len() {
+            // the device is idle, no frame is in flight
+            self.write_frame_descriptor_sets(frame_index);
+        }
+
+        self.reset_history = true;
 
         Ok(())
     }
 
     fn cleanup_swapchain(&mut self) -> anyhow::Result<()> {
-        unsafe {
-            self.device.destroy_image_view(self.rt_image_view, None);
-            self.device.destroy_image(self.rt_image, None);
-            self.device.free_memory(self.rt_image_memory, None);
+        for semaphore in self.rendering_complete_semaphores.drain(..) {
+            unsafe { self.device.destroy_semaphore(semaphore, None) };
         }
 
         for framebuffer in &self.framebuffers {
@@ -1824,8 +3867,8 @@ impl<'a> VkController<'a> {
         if let Some(depth_image_view) = self.depth_image_view {
             unsafe { self.device.destroy_image_view(depth_image_view, None) };
         }
-        if let Some(depth_image_memory) = self.depth_image_memory {
-            unsafe { self.device.free_memory(depth_image_memory, None) };
+        if let Some(depth_image_allocation) = self.depth_image_allocation.take() {
+            depth_image_allocation.free();
         }
 
         if let Some(swapchain) = self.swapchain {
@@ -1839,21 +3882,9 @@ impl<'a> VkController<'a> {
 impl Drop for VkController<'_> {
     fn drop(&mut self) {
         unsafe {
-            self.device
-                .wait_for_fences(&[self.swapchain_acquire_fence], true, std::u64::MAX)
-                .unwrap();
-
             self.device.device_wait_idle().unwrap();
-            self.device
-                .destroy_semaphore(self.present_complete_semaphore, None);
-            self.device
-                .destroy_semaphore(self.rendering_complete_semaphore, None);
-            self.device
-                .destroy_fence(self.draw_commands_reuse_fence, None);
             self.device
                 .destroy_fence(self.setup_commands_reuse_fence, None);
-            self.device
-                .destroy_fence(self.swapchain_acquire_fence, None);
 
             self.cleanup_swapchain().unwrap();
 
@@ -1869,6 +3900,11 @@ impl Drop for VkController<'_> {
 
             self.device
                 .destroy_pipeline_layout(self.pipeline_layout.unwrap(), None);
+            self.ao_filter.take().unwrap().destroy(&self.device);
+            self.svgf.take().unwrap().destroy(&self.device);
+            self.taa.take().unwrap().destroy(&self.device);
+            self.tonemapper.take().unwrap().destroy(&self.device);
+            self.upscaler.take().unwrap().destroy(&self.device);
 
             self.acceleration_structure_loader
                 .destroy_acceleration_structure(self.bottom_as.unwrap(), None);
@@ -1879,13 +3915,38 @@ impl Drop for VkController<'_> {
             destroy_buffer!(self.bottom_as_buffer, self.device);
             destroy_buffer!(self.top_as_buffer, self.device);
             destroy_buffer!(self.palette_buffer, self.device);
-            destroy_buffer!(self.instance_buffer, self.device);
+            destroy_buffer!(self.materials_buffer, self.device);
+            self.environment_map.take().unwrap().destroy(&self.device);
+            for frame_index in 0..self.frames.len() {
+                self.destroy_retired_resources(frame_index);
+            }
+
+            for frame in std::mem::take(&mut self.frames) {
+                frame.destroy(&self.device, self.pool);
+            }
+
+            for buffer in self
+                .instance_buffers
+                .drain(..)
+                .chain(self.top_as_scratch_buffer.take())
+            {
+                buffer.destroy(&self.device);
+            }
+
             // destroy_buffer!(self.aabb_buffer, self.device);
             destroy_buffer!(self.index_buffer, self.device);
             destroy_buffer!(self.vertex_buffer, self.device);
             destroy_buffer!(self.shader_binding_table_buffer, self.device);
-            destroy_buffer!(self.uniforms_buffer, self.device);
             destroy_buffer!(self.voxels_buffer, self.device);
+            destroy_buffer!(self.lights_buffer, self.device);
+            destroy_buffer!(self.histogram_buffer, self.device);
+            destroy_buffer!(self.exposure_buffer, self.device);
+            if let Some(readback_buffer) = self.readback_buffer.take() {
+                readback_buffer.destroy(&self.device);
+            }
+
+            self.staging.take().unwrap().destroy(&self.device);
+            self.allocator.destroy();
 
             self.device.destroy_command_pool(self.pool, None);
             self.device.destroy_device(None);